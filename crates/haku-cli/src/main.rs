@@ -1,12 +1,17 @@
 // NOTE: This is a very bad CLI. I only use it for debugging haku with LLDB.
 // Sorry that it doesn't actually do anything!
+// (Except for `haku-cli fmt`, which formats the code given on stdin.)
 
-use std::{error::Error, fmt::Display, io::BufRead};
+use std::{
+    error::Error,
+    io::{BufRead, Read},
+};
 
 use haku::{
     ast::{dump::dump, Ast},
+    format::format,
     lexer::{lex, Lexer},
-    parser::{expr, Parser, ParserLimits},
+    parser::{expr, toplevel, Parser, ParserLimits},
     source::SourceCode,
     token::Lexis,
     value::Value,
@@ -28,18 +33,42 @@ fn eval(code: &str) -> Result<Value, Box<dyn Error>> {
     Ok(Value::Nil)
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-struct DiagnosticsEmitted;
+fn fmt(code: &str) -> Result<String, Box<dyn Error>> {
+    let code = SourceCode::unlimited_len(code);
+    let mut lexer = Lexer::new(Lexis::with_trivia(65536), code);
+    lex(&mut lexer)?;
+
+    let mut parser = Parser::new(&lexer.lexis, &ParserLimits { max_events: 65536 });
+    toplevel(&mut parser);
 
-impl Display for DiagnosticsEmitted {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str("diagnostics were emitted")
+    let mut ast = Ast::new(65536);
+    let (root, mut parser_diagnostics) = parser.into_ast(&mut ast)?;
+
+    let mut diagnostics = lexer.diagnostics;
+    diagnostics.append(&mut parser_diagnostics);
+    if diagnostics.iter().any(|diagnostic| diagnostic.is_error()) {
+        for diagnostic in &diagnostics {
+            eprintln!(
+                "{}..{}: {}",
+                diagnostic.span().start,
+                diagnostic.span().end,
+                diagnostic.message()
+            );
+        }
+        return Err("code with syntax errors cannot be formatted".into());
     }
-}
 
-impl Error for DiagnosticsEmitted {}
+    Ok(format(code, &lexer.lexis, &ast, root))
+}
 
 fn main() -> Result<(), Box<dyn Error>> {
+    if std::env::args().nth(1).as_deref() == Some("fmt") {
+        let mut code = String::new();
+        std::io::stdin().read_to_string(&mut code)?;
+        print!("{}", fmt(&code)?);
+        return Ok(());
+    }
+
     let stdin = std::io::stdin();
     for line in stdin.lock().lines() {
         let line = line?;
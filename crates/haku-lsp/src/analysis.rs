@@ -0,0 +1,450 @@
+//! Analysis of a single brush's source code.
+//! Everything here works on byte offsets; conversion to LSP positions happens in `main`.
+
+use haku::{
+    ast::{scope::Scopes, Ast, NodeId, NodeKind},
+    bytecode::{Chunk, Defs},
+    compiler::{compile_expr, CompileError, Compiler, Source},
+    diagnostic::Diagnostic,
+    lexer::{lex, Lexer},
+    lint::lint,
+    module::{Loader, ModuleLimits, BUNDLED, PRELUDE},
+    parser::{self, IntoAstError, Parser, ParserLimits},
+    source::{SourceCode, Span},
+    system::{System, SystemFnArity},
+    token::Lexis,
+    types::{self, Types},
+};
+
+// These match the defaults in rkgk.toml, so that brushes which compile in the editor also
+// compile on the wall.
+const MAX_TOKENS: usize = 65536;
+const MAX_PARSER_EVENTS: usize = 65536;
+const AST_CAPACITY: usize = 65536;
+const MAX_DEFS: usize = 256;
+const CHUNK_CAPACITY: usize = 65536;
+pub const MAX_CHUNKS: usize = 8;
+
+const MODULE_LIMITS: ModuleLimits = ModuleLimits {
+    max_source_code_len: u32::MAX,
+    max_tokens: MAX_TOKENS,
+    max_parser_events: MAX_PARSER_EVENTS,
+    ast_capacity: AST_CAPACITY,
+    chunk_capacity: CHUNK_CAPACITY,
+};
+
+pub struct Analysis {
+    code: String,
+    ast: Ast,
+    root: NodeId,
+    types: Option<Types>,
+    diagnostics: Vec<Diagnostic>,
+    /// Identifier uses and the spans of the bindings they refer to.
+    references: Vec<(Span, Span)>,
+    bindings: Vec<Binding>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Binding {
+    name: Span,
+    /// The span of code where the binding is visible.
+    scope: Span,
+    is_def: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Hover {
+    pub span: Span,
+    /// Markdown.
+    pub contents: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionKind {
+    SystemFn,
+    Def,
+    Local,
+}
+
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub label: String,
+    pub kind: CompletionKind,
+    pub detail: Option<String>,
+    /// Markdown.
+    pub documentation: Option<String>,
+}
+
+impl Analysis {
+    pub fn new(system: &System, code: String) -> Self {
+        let mut analysis = Self {
+            code,
+            ast: Ast::new(AST_CAPACITY),
+            root: NodeId::NIL,
+            types: None,
+            diagnostics: Vec::new(),
+            references: Vec::new(),
+            bindings: Vec::new(),
+        };
+        if let Err(message) = analysis.analyze(system) {
+            analysis.diagnostics = vec![Diagnostic::error(Span::new(0, 0), message)];
+        }
+        analysis
+    }
+
+    /// Returns the message of a diagnostic spanning the whole file if the brush doesn't fit
+    /// within limits.
+    fn analyze(&mut self, system: &System) -> Result<(), &'static str> {
+        let code = SourceCode::unlimited_len(&self.code);
+
+        let mut lexer = Lexer::new(Lexis::new(MAX_TOKENS), code);
+        lex(&mut lexer).map_err(|_| "too many tokens")?;
+
+        let mut parser = Parser::new(
+            &lexer.lexis,
+            &ParserLimits {
+                max_events: MAX_PARSER_EVENTS,
+            },
+        );
+        parser::toplevel(&mut parser);
+        let (root, mut parser_diagnostics) =
+            parser
+                .into_ast(&mut self.ast)
+                .map_err(|error| match error {
+                    IntoAstError::NodeAlloc(_) => "too many AST nodes",
+                    IntoAstError::TooManyEvents => "too many parser events",
+                    IntoAstError::UnbalancedEvents => "parser produced unbalanced events",
+                })?;
+        self.root = root;
+
+        // Modules are compiled into the system's chunks, so each analysis works on its own copy.
+        let mut system = system.clone();
+        let mut defs = Defs::new(MAX_DEFS);
+        let prelude = Loader::new(BUNDLED, MODULE_LIMITS, None)
+            .load(&mut system, &mut defs, PRELUDE, Span::new(0, 0))
+            .expect("the prelude must compile");
+        let mut import_diagnostics = Loader::new(BUNDLED, MODULE_LIMITS, Some(prelude.namespace))
+            .import_all(&mut system, &mut defs, code, &self.ast, root);
+
+        let src = Source {
+            code,
+            ast: &self.ast,
+            system: &system,
+        };
+
+        let mut chunk = Chunk::new(CHUNK_CAPACITY).unwrap();
+        let mut compiler = Compiler::new(&mut defs, &mut chunk);
+        compile_expr(&mut compiler, &src, root).map_err(|error| match error {
+            CompileError::Emit => "brush is too big to compile",
+        })?;
+
+        let (types, mut type_diagnostics) = types::check(&src, root);
+        self.types = Some(types);
+
+        self.diagnostics = lexer.diagnostics;
+        self.diagnostics.append(&mut parser_diagnostics);
+        self.diagnostics.append(&mut import_diagnostics);
+        self.diagnostics.append(&mut compiler.diagnostics);
+        self.diagnostics.append(&mut type_diagnostics);
+        self.diagnostics.append(&mut lint(&src, root));
+
+        let mut resolver = Resolver {
+            code,
+            ast: &self.ast,
+            system: &system,
+            scopes: Scopes::new(),
+            references: Vec::new(),
+            bindings: Vec::new(),
+        };
+        resolver.resolve(root);
+        self.references = resolver.references;
+        self.bindings = resolver.bindings;
+
+        Ok(())
+    }
+
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    fn slice(&self, span: Span) -> &str {
+        span.slice(SourceCode::unlimited_len(&self.code))
+    }
+
+    /// Returns the path from the root to the innermost node containing the offset.
+    fn path_to(&self, offset: u32) -> Vec<NodeId> {
+        let mut path = vec![self.root];
+        'descend: loop {
+            let node = *path.last().unwrap();
+            for &child in self.ast.children(node) {
+                let span = self.ast.span(child);
+                if !matches!(self.ast.kind(child), NodeKind::Nil | NodeKind::Token)
+                    && span.start <= offset
+                    && offset <= span.end
+                {
+                    path.push(child);
+                    continue 'descend;
+                }
+            }
+            return path;
+        }
+    }
+
+    /// Resolves the system function called by the node at the end of the path, if any.
+    fn system_fn_at(&self, system: &System, path: &[NodeId]) -> Option<u8> {
+        let [.., parent, node] = path else {
+            return None;
+        };
+        let arity = match (self.ast.kind(*parent), self.ast.kind(*node)) {
+            (NodeKind::Unary, NodeKind::Op) => SystemFnArity::Unary,
+            (NodeKind::Binary, NodeKind::Op) => SystemFnArity::Binary,
+            // NOTE: This must match how the compiler decides whether a call is a system call.
+            (NodeKind::Call, NodeKind::Ident) if self.ast.walk(*parent).node() == Some(*node) => {
+                SystemFnArity::Nary
+            }
+            _ => return None,
+        };
+        (system.resolve_fn)(arity, self.slice(self.ast.span(*node)))
+    }
+
+    pub fn hover(&self, system: &System, offset: u32) -> Option<Hover> {
+        let path = self.path_to(offset);
+        let &node = path.last()?;
+        let span = self.ast.span(node);
+
+        if let Some(index) = self.system_fn_at(system, &path) {
+            return Some(Hover {
+                span,
+                contents: system_fn_docs(system, self.slice(span), index),
+            });
+        }
+
+        if matches!(self.ast.kind(node), NodeKind::Ident | NodeKind::Param) {
+            let types = self.types.as_ref()?;
+            let ty = types.type_of(node)?;
+            return Some(Hover {
+                span,
+                contents: format!("```haku\n{} : {}\n```", self.slice(span), types.display(ty)),
+            });
+        }
+
+        None
+    }
+
+    /// Returns the span of the binding the identifier at the offset refers to.
+    pub fn definition(&self, offset: u32) -> Option<Span> {
+        self.references
+            .iter()
+            .find(|(usage, _)| usage.start <= offset && offset <= usage.end)
+            .map(|&(_, binding)| binding)
+    }
+
+    pub fn completions(&self, system: &System, offset: u32) -> Vec<Completion> {
+        let mut completions: Vec<Completion> = Vec::new();
+
+        // Innermost bindings come first, so that they shadow outer ones.
+        let mut visible: Vec<_> = self
+            .bindings
+            .iter()
+            .filter(|binding| binding.scope.start <= offset && offset <= binding.scope.end)
+            .collect();
+        visible.sort_by_key(|binding| (binding.is_def, binding.scope.end - binding.scope.start));
+        for binding in visible {
+            let name = self.slice(binding.name);
+            if completions.iter().any(|c| c.label == name) {
+                continue;
+            }
+            let detail = self.types.as_ref().and_then(|types| {
+                let node = self.binding_node(binding.name)?;
+                Some(types.display(types.type_of(node)?).to_string())
+            });
+            completions.push(Completion {
+                label: name.to_owned(),
+                kind: if binding.is_def {
+                    CompletionKind::Def
+                } else {
+                    CompletionKind::Local
+                },
+                detail,
+                documentation: None,
+            });
+        }
+
+        for &(index, arity, name) in system.names {
+            let signatures = (system.signatures_fn)(index);
+            if arity != SystemFnArity::Nary || signatures.is_empty() {
+                continue;
+            }
+            completions.push(Completion {
+                label: name.to_owned(),
+                kind: CompletionKind::SystemFn,
+                detail: Some(signatures.join(" / ")),
+                documentation: Some(system_fn_docs(system, name, index)),
+            });
+        }
+
+        completions
+    }
+
+    fn binding_node(&self, name: Span) -> Option<NodeId> {
+        let path = self.path_to(name.start);
+        path.last()
+            .copied()
+            .filter(|&node| self.ast.span(node) == name)
+    }
+}
+
+/// Formats the documentation of a system function, with signatures laid out like in the system
+/// library reference.
+fn system_fn_docs(system: &System, name: &str, index: u8) -> String {
+    let mut docs = String::from("```haku\n");
+    for (i, signature) in (system.signatures_fn)(index).iter().enumerate() {
+        if i > 0 {
+            docs.push('\n');
+        }
+        docs.push_str(name);
+        docs.push('\n');
+        let (params, result) = signature.rsplit_once(" -> ").unwrap_or(("", signature));
+        for param in params.split(", ").filter(|param| !param.is_empty()) {
+            docs.push_str("  ");
+            docs.push_str(param);
+            docs.push('\n');
+        }
+        docs.push_str("  -> ");
+        docs.push_str(result);
+        docs.push('\n');
+    }
+    docs.push_str("```\n");
+    for line in (system.docs_fn)(index).lines() {
+        docs.push('\n');
+        docs.push_str(line.strip_prefix(' ').unwrap_or(line));
+    }
+    docs
+}
+
+/// Resolves identifiers to their bindings, following the same scoping rules as the compiler.
+struct Resolver<'a> {
+    code: &'a SourceCode,
+    ast: &'a Ast,
+    system: &'a System,
+    /// Bindings visible at the current point of the walk, innermost last.
+    scopes: Scopes<'a, Span>,
+    references: Vec<(Span, Span)>,
+    bindings: Vec<Binding>,
+}
+
+impl<'a> Resolver<'a> {
+    fn name(&self, node_id: NodeId) -> &'a str {
+        self.ast.span(node_id).slice(self.code)
+    }
+
+    fn bind(&mut self, name: NodeId, scope: Span, is_def: bool) {
+        let span = self.ast.span(name);
+        self.scopes.push(self.name(name), span);
+        self.bindings.push(Binding {
+            name: span,
+            scope,
+            is_def,
+        });
+    }
+
+    fn resolve(&mut self, node_id: NodeId) {
+        match self.ast.kind(node_id) {
+            NodeKind::Ident => {
+                let name = self.name(node_id);
+                if let Some(&binding) = self.scopes.get(name) {
+                    self.references.push((self.ast.span(node_id), binding));
+                }
+            }
+
+            NodeKind::Call => {
+                let mut walk = self.ast.walk(node_id);
+                if let Some(func) = walk.node() {
+                    let is_system_fn = self.ast.kind(func) == NodeKind::Ident
+                        && (self.system.resolve_fn)(SystemFnArity::Nary, self.name(func)).is_some();
+                    if !is_system_fn {
+                        self.resolve(func);
+                    }
+                }
+                while let Some(arg) = walk.node() {
+                    self.resolve(arg);
+                }
+            }
+
+            NodeKind::Lambda => {
+                let mut walk = self.ast.walk(node_id);
+                let (Some(params), Some(body)) = (walk.node(), walk.node()) else {
+                    return;
+                };
+                let scopes_len = self.scopes.len();
+                let mut params_walk = self.ast.walk(params);
+                while let Some(param) = params_walk.node() {
+                    self.bind(param, self.ast.span(body), false);
+                }
+                self.resolve(body);
+                self.scopes.truncate(scopes_len);
+            }
+
+            NodeKind::Let => {
+                let mut walk = self.ast.walk(node_id);
+                let (Some(ident), Some(expr), Some(then)) = (walk.node(), walk.node(), walk.node())
+                else {
+                    return;
+                };
+                self.resolve(expr);
+                self.bind(ident, self.ast.span(then), false);
+                self.resolve(then);
+                self.scopes.pop();
+            }
+
+            NodeKind::Toplevel => {
+                // Defs are visible in the entire file, regardless of order.
+                let whole_file = Span::new(0, self.code.len() as u32);
+                let mut walk = self.ast.walk(node_id);
+                while let Some(toplevel_expr) = walk.node() {
+                    if let Some(ident) = self
+                        .ast
+                        .def_ident(self.code, toplevel_expr)
+                        .or_else(|| self.ast.param_ident(toplevel_expr))
+                    {
+                        if !self.scopes.contains(self.name(ident)) {
+                            self.bind(ident, whole_file, true);
+                        }
+                    }
+                }
+
+                let mut walk = self.ast.walk(node_id);
+                while let Some(toplevel_expr) = walk.node() {
+                    if self.ast.kind(toplevel_expr) == NodeKind::ParamDecl {
+                        // Params only contain literals, so there's nothing to resolve in them.
+                        continue;
+                    }
+                    if self.ast.def_ident(self.code, toplevel_expr).is_some() {
+                        let mut walk = self.ast.walk(toplevel_expr);
+                        if let (Some(_ident), Some(_op), Some(expr)) =
+                            (walk.node(), walk.node(), walk.node())
+                        {
+                            self.resolve(expr);
+                        }
+                    } else {
+                        self.resolve(toplevel_expr);
+                    }
+                }
+            }
+
+            _ => {
+                let mut walk = self.ast.walk(node_id);
+                while let Some(child) = walk.node() {
+                    self.resolve(child);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
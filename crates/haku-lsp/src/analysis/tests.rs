@@ -0,0 +1,129 @@
+use haku::system::System;
+
+use super::{Analysis, CompletionKind, MAX_CHUNKS};
+
+fn analyze(code: &str) -> Analysis {
+    Analysis::new(&System::new(MAX_CHUNKS), code.to_owned())
+}
+
+/// Returns the byte offset of the `n`th occurrence of `pattern` in `code`.
+fn nth(code: &str, pattern: &str, n: usize) -> u32 {
+    code.match_indices(pattern).nth(n).unwrap().0 as u32
+}
+
+#[track_caller]
+fn assert_definition(code: &str, usage: (&str, usize), definition: (&str, usize)) {
+    let analysis = analyze(code);
+    let span = analysis
+        .definition(nth(code, usage.0, usage.1))
+        .expect("no definition found");
+    assert_eq!(span.start, nth(code, definition.0, definition.1));
+    assert_eq!(span.end as usize - span.start as usize, definition.0.len());
+}
+
+#[test]
+fn diagnostics() {
+    let system = System::new(MAX_CHUNKS);
+    let messages = |code: &str| -> Vec<String> {
+        Analysis::new(&system, code.to_owned())
+            .diagnostics()
+            .iter()
+            .map(|d| d.message().to_owned())
+            .collect()
+    };
+
+    assert!(messages("stroke 8 #000 (vec 0 0)").is_empty());
+    assert_eq!(messages("stroke 8 #000 x"), ["undefined variable"]);
+    assert_eq!(
+        messages("stroke 8 1 (vec 0 0)"),
+        ["type mismatch: expected `rgba`, found `number`"]
+    );
+    assert_eq!(messages("x = 1\n2"), ["unused def `x`"]);
+    assert!(
+        messages("import easing\nstroke (lerp 1 8 (easeInQuad 0.5)) #000 (vec 0 0)").is_empty()
+    );
+    assert_eq!(
+        messages("import nope\n1"),
+        ["no module named `nope` exists"]
+    );
+}
+
+#[test]
+fn hover_system_fn() {
+    let code = "stroke 8 #000 (vec 0 0)";
+    let hover = analyze(code).hover(&System::new(MAX_CHUNKS), 2).unwrap();
+    assert_eq!((hover.span.start, hover.span.end), (0, 6));
+    assert_eq!(
+        hover.contents,
+        "```haku\n\
+         stroke\n  thickness : number\n  color : rgba\n  shape : shapeLike\n  -> scribble\n\
+         ```\n\n\
+         Creates a stroke scribble, which outlines `shape` with the given `thickness` and `color`."
+    );
+
+    let code = "1 + 2";
+    let hover = analyze(code).hover(&System::new(MAX_CHUNKS), 2).unwrap();
+    assert!(hover
+        .contents
+        .starts_with("```haku\n+\n  a : number\n  b : number\n"));
+    assert!(hover.contents.ends_with("Adds two numbers together."));
+}
+
+#[test]
+fn hover_types() {
+    let code = "id = \\x -> x\nlet y = id 1\ny";
+    let analysis = analyze(code);
+    let system = System::new(MAX_CHUNKS);
+
+    let hover = analysis.hover(&system, nth(code, "id", 0)).unwrap();
+    assert_eq!(hover.contents, "```haku\nid : \\a -> a\n```");
+    let hover = analysis.hover(&system, nth(code, "y", 1)).unwrap();
+    assert_eq!(hover.contents, "```haku\ny : number\n```");
+    assert!(analysis.hover(&system, nth(code, "1", 0)).is_none());
+}
+
+#[test]
+fn definition() {
+    let code = "f = \\x -> x + g\ng = 1\nf 2";
+    assert_definition(code, ("x", 1), ("x", 0));
+    assert_definition(code, ("g", 0), ("g", 1));
+    assert_definition(code, ("f", 1), ("f", 0));
+
+    let code = "let x = 1\nlet x = x + 1\nx";
+    assert_definition(code, ("x", 2), ("x", 0));
+    assert_definition(code, ("x", 3), ("x", 1));
+
+    // System functions take precedence over variables in calls.
+    let code = "let vec = 1\nvec 1";
+    assert!(analyze(code).definition(nth(code, "vec", 1)).is_none());
+
+    let code = "param size = 4 [1, 8]\nstroke size #000 (vec 0 0)";
+    assert_definition(code, ("size", 1), ("size", 0));
+}
+
+#[test]
+fn completions() {
+    let code = "f = \\x -> x\ng = \\y ->\n  let z = y\n  z\n";
+    let analysis = analyze(code);
+    let system = System::new(MAX_CHUNKS);
+
+    let labels = |offset| {
+        analysis
+            .completions(&system, offset)
+            .into_iter()
+            .filter(|c| c.kind != CompletionKind::SystemFn)
+            .map(|c| c.label)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(labels(nth(code, "z", 1)), ["z", "y", "f", "g"]);
+    assert_eq!(labels(nth(code, "x", 1)), ["x", "f", "g"]);
+
+    let completions = analysis.completions(&system, 0);
+    let stroke = completions.iter().find(|c| c.label == "stroke").unwrap();
+    assert_eq!(stroke.kind, CompletionKind::SystemFn);
+    assert!(completions
+        .iter()
+        .all(|c| c.label != "+" && c.label != "list (unused)"));
+    let f = completions.iter().find(|c| c.label == "f").unwrap();
+    assert_eq!(f.detail.as_deref(), Some("\\a -> a"));
+}
@@ -0,0 +1,51 @@
+use lsp_types::Position;
+
+/// Converts between byte offsets used by haku and LSP positions, whose columns are counted in
+/// UTF-16 code units.
+pub struct LineIndex {
+    line_starts: Vec<u32>,
+}
+
+impl LineIndex {
+    pub fn new(text: &str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i as u32 + 1))
+            .collect();
+        Self { line_starts }
+    }
+
+    pub fn position(&self, text: &str, offset: u32) -> Position {
+        let offset = offset.min(text.len() as u32);
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let line_start = self.line_starts[line] as usize;
+        let character = text[line_start..offset as usize]
+            .chars()
+            .map(char::len_utf16)
+            .sum::<usize>();
+        Position::new(line as u32, character as u32)
+    }
+
+    /// Positions past the end of a line are clamped to the end of the line.
+    pub fn offset(&self, text: &str, position: Position) -> u32 {
+        let Some(&line_start) = self.line_starts.get(position.line as usize) else {
+            return text.len() as u32;
+        };
+        let line_end = self
+            .line_starts
+            .get(position.line as usize + 1)
+            .map(|&start| start - 1)
+            .unwrap_or(text.len() as u32);
+
+        let mut character = 0;
+        for (i, c) in text[line_start as usize..line_end as usize].char_indices() {
+            if character >= position.character as usize {
+                return line_start + i as u32;
+            }
+            character += c.len_utf16();
+        }
+        line_end
+    }
+}
+
+#[cfg(test)]
+mod tests;
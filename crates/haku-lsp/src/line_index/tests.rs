@@ -0,0 +1,23 @@
+use lsp_types::Position;
+
+use super::LineIndex;
+
+#[test]
+fn positions_count_utf16() {
+    let text = "a = 1\n-- żółć 𝄞\nb";
+    let index = LineIndex::new(text);
+
+    let b = text.find('b').unwrap() as u32;
+    assert_eq!(index.position(text, b), Position::new(2, 0));
+    assert_eq!(index.offset(text, Position::new(2, 0)), b);
+
+    // 𝄞 is two UTF-16 code units, and four UTF-8 bytes.
+    let clef = text.find('𝄞').unwrap() as u32;
+    assert_eq!(index.position(text, clef), Position::new(1, 8));
+    assert_eq!(index.position(text, clef + 4), Position::new(1, 10));
+    assert_eq!(index.offset(text, Position::new(1, 8)), clef);
+
+    // Positions past the end of a line are clamped.
+    assert_eq!(index.offset(text, Position::new(0, 100)), 5);
+    assert_eq!(index.offset(text, Position::new(100, 0)), text.len() as u32);
+}
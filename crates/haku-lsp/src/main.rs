@@ -0,0 +1,347 @@
+//! Language server for haku, communicating over stdio.
+
+use std::{collections::HashMap, error::Error};
+
+use haku::{
+    diagnostic::{Diagnostic, Severity},
+    source::Span,
+    system::System,
+};
+use lsp_server::{Connection, Message, Notification, Request, RequestId, Response};
+use lsp_types::{
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _,
+        PublishDiagnostics,
+    },
+    request::{CodeActionRequest, Completion, GotoDefinition, HoverRequest, Request as _},
+    CodeAction, CodeActionKind, CodeActionOrCommand, CodeActionParams,
+    CodeActionProviderCapability, CompletionItem, CompletionItemKind, CompletionParams,
+    CompletionResponse, DiagnosticRelatedInformation, DiagnosticSeverity,
+    DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    Documentation, GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents, HoverParams,
+    HoverProviderCapability, InitializeParams, Location, MarkupContent, MarkupKind, OneOf,
+    Position, PublishDiagnosticsParams, Range, ServerCapabilities, TextDocumentSyncCapability,
+    TextDocumentSyncKind, TextEdit, Uri, WorkspaceEdit,
+};
+
+use crate::{
+    analysis::{Analysis, CompletionKind},
+    line_index::LineIndex,
+};
+
+mod analysis;
+mod line_index;
+
+struct Document {
+    analysis: Analysis,
+    line_index: LineIndex,
+}
+
+impl Document {
+    fn new(system: &System, text: String) -> Self {
+        Self {
+            line_index: LineIndex::new(&text),
+            analysis: Analysis::new(system, text),
+        }
+    }
+
+    fn offset(&self, position: Position) -> u32 {
+        self.line_index.offset(self.analysis.code(), position)
+    }
+
+    fn range(&self, span: Span) -> Range {
+        let code = self.analysis.code();
+        Range::new(
+            self.line_index.position(code, span.start),
+            self.line_index.position(code, span.end),
+        )
+    }
+}
+
+struct Server {
+    connection: Connection,
+    system: System,
+    documents: HashMap<Uri, Document>,
+}
+
+impl Server {
+    fn run(&mut self) -> Result<(), Box<dyn Error + Sync + Send>> {
+        while let Ok(message) = self.connection.receiver.recv() {
+            match message {
+                Message::Request(request) => {
+                    if self.connection.handle_shutdown(&request)? {
+                        return Ok(());
+                    }
+                    self.request(request)?;
+                }
+                Message::Notification(notification) => self.notification(notification)?,
+                Message::Response(_) => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn respond<T: serde::Serialize>(
+        &self,
+        id: RequestId,
+        result: T,
+    ) -> Result<(), Box<dyn Error + Sync + Send>> {
+        let response = Response::new_ok(id, result);
+        self.connection.sender.send(Message::Response(response))?;
+        Ok(())
+    }
+
+    fn request(&mut self, request: Request) -> Result<(), Box<dyn Error + Sync + Send>> {
+        match request.method.as_str() {
+            HoverRequest::METHOD => {
+                let (id, params) = request.extract::<HoverParams>(HoverRequest::METHOD)?;
+                let position = params.text_document_position_params;
+                let hover = self
+                    .documents
+                    .get(&position.text_document.uri)
+                    .and_then(|document| {
+                        let offset = document.offset(position.position);
+                        let hover = document.analysis.hover(&self.system, offset)?;
+                        Some(Hover {
+                            contents: HoverContents::Markup(markdown(hover.contents)),
+                            range: Some(document.range(hover.span)),
+                        })
+                    });
+                self.respond(id, hover)
+            }
+
+            GotoDefinition::METHOD => {
+                let (id, params) =
+                    request.extract::<GotoDefinitionParams>(GotoDefinition::METHOD)?;
+                let position = params.text_document_position_params;
+                let uri = position.text_document.uri;
+                let definition = self.documents.get(&uri).and_then(|document| {
+                    let span = document
+                        .analysis
+                        .definition(document.offset(position.position))?;
+                    Some(GotoDefinitionResponse::Scalar(Location::new(
+                        uri.clone(),
+                        document.range(span),
+                    )))
+                });
+                self.respond(id, definition)
+            }
+
+            Completion::METHOD => {
+                let (id, params) = request.extract::<CompletionParams>(Completion::METHOD)?;
+                let position = params.text_document_position;
+                let completions = self
+                    .documents
+                    .get(&position.text_document.uri)
+                    .map(|document| {
+                        let offset = document.offset(position.position);
+                        let items = document
+                            .analysis
+                            .completions(&self.system, offset)
+                            .into_iter()
+                            .map(|completion| CompletionItem {
+                                label: completion.label,
+                                kind: Some(match completion.kind {
+                                    CompletionKind::SystemFn => CompletionItemKind::FUNCTION,
+                                    CompletionKind::Def => CompletionItemKind::CONSTANT,
+                                    CompletionKind::Local => CompletionItemKind::VARIABLE,
+                                }),
+                                detail: completion.detail,
+                                documentation: completion
+                                    .documentation
+                                    .map(|docs| Documentation::MarkupContent(markdown(docs))),
+                                ..Default::default()
+                            })
+                            .collect();
+                        CompletionResponse::Array(items)
+                    });
+                self.respond(id, completions)
+            }
+
+            CodeActionRequest::METHOD => {
+                let (id, params) =
+                    request.extract::<CodeActionParams>(CodeActionRequest::METHOD)?;
+                let uri = params.text_document.uri;
+                let actions = self.documents.get(&uri).map(|document| {
+                    let start = document.offset(params.range.start);
+                    let end = document.offset(params.range.end);
+                    document
+                        .analysis
+                        .diagnostics()
+                        .iter()
+                        .filter(|diagnostic| {
+                            diagnostic.span().start <= end && start <= diagnostic.span().end
+                        })
+                        .flat_map(|diagnostic| {
+                            diagnostic.suggestions().iter().map(|suggestion| {
+                                let edit = TextEdit::new(
+                                    document.range(suggestion.span),
+                                    suggestion.replacement.clone(),
+                                );
+                                CodeActionOrCommand::CodeAction(CodeAction {
+                                    title: suggestion.message.clone(),
+                                    kind: Some(CodeActionKind::QUICKFIX),
+                                    diagnostics: Some(vec![lsp_diagnostic(
+                                        &uri, document, diagnostic,
+                                    )]),
+                                    edit: Some(WorkspaceEdit {
+                                        changes: Some(HashMap::from([(uri.clone(), vec![edit])])),
+                                        ..Default::default()
+                                    }),
+                                    is_preferred: Some(true),
+                                    ..Default::default()
+                                })
+                            })
+                        })
+                        .collect::<Vec<_>>()
+                });
+                self.respond(id, actions)
+            }
+
+            _ => {
+                let response = Response::new_err(
+                    request.id,
+                    lsp_server::ErrorCode::MethodNotFound as i32,
+                    format!("unsupported request: {}", request.method),
+                );
+                self.connection.sender.send(Message::Response(response))?;
+                Ok(())
+            }
+        }
+    }
+
+    fn notification(
+        &mut self,
+        notification: Notification,
+    ) -> Result<(), Box<dyn Error + Sync + Send>> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams =
+                    serde_json::from_value(notification.params)?;
+                let document = params.text_document;
+                self.update(document.uri, document.text, Some(document.version))
+            }
+
+            DidChangeTextDocument::METHOD => {
+                let params: DidChangeTextDocumentParams =
+                    serde_json::from_value(notification.params)?;
+                // We only ask for full document sync, so the last change contains the entire text.
+                let Some(change) = params.content_changes.into_iter().last() else {
+                    return Ok(());
+                };
+                let document = params.text_document;
+                self.update(document.uri, change.text, Some(document.version))
+            }
+
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams =
+                    serde_json::from_value(notification.params)?;
+                let uri = params.text_document.uri;
+                self.documents.remove(&uri);
+                self.publish_diagnostics(uri, Vec::new(), None)
+            }
+
+            _ => Ok(()),
+        }
+    }
+
+    fn update(
+        &mut self,
+        uri: Uri,
+        text: String,
+        version: Option<i32>,
+    ) -> Result<(), Box<dyn Error + Sync + Send>> {
+        let document = Document::new(&self.system, text);
+        let diagnostics = document
+            .analysis
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| lsp_diagnostic(&uri, &document, diagnostic))
+            .collect();
+        self.documents.insert(uri.clone(), document);
+        self.publish_diagnostics(uri, diagnostics, version)
+    }
+
+    fn publish_diagnostics(
+        &self,
+        uri: Uri,
+        diagnostics: Vec<lsp_types::Diagnostic>,
+        version: Option<i32>,
+    ) -> Result<(), Box<dyn Error + Sync + Send>> {
+        let params = PublishDiagnosticsParams::new(uri, diagnostics, version);
+        let notification = Notification::new(PublishDiagnostics::METHOD.to_owned(), params);
+        self.connection
+            .sender
+            .send(Message::Notification(notification))?;
+        Ok(())
+    }
+}
+
+fn lsp_diagnostic(
+    uri: &Uri,
+    document: &Document,
+    diagnostic: &Diagnostic,
+) -> lsp_types::Diagnostic {
+    // LSP has no concept of notes, so they're appended to the message.
+    let mut message = diagnostic.message().to_owned();
+    for note in diagnostic.notes() {
+        message.push_str("\nnote: ");
+        message.push_str(note);
+    }
+
+    lsp_types::Diagnostic {
+        range: document.range(diagnostic.span()),
+        severity: Some(match diagnostic.severity() {
+            Severity::Error => DiagnosticSeverity::ERROR,
+            Severity::Warning => DiagnosticSeverity::WARNING,
+            Severity::Hint => DiagnosticSeverity::HINT,
+        }),
+        source: Some("haku".to_owned()),
+        message,
+        related_information: (!diagnostic.labels().is_empty()).then(|| {
+            diagnostic
+                .labels()
+                .iter()
+                .map(|label| DiagnosticRelatedInformation {
+                    location: Location::new(uri.clone(), document.range(label.span)),
+                    message: label.message.clone(),
+                })
+                .collect()
+        }),
+        ..Default::default()
+    }
+}
+
+fn markdown(value: String) -> MarkupContent {
+    MarkupContent {
+        kind: MarkupKind::Markdown,
+        value,
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error + Sync + Send>> {
+    let (connection, io_threads) = Connection::stdio();
+
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        completion_provider: Some(Default::default()),
+        code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+        ..Default::default()
+    };
+    let initialize_params = connection.initialize(serde_json::to_value(capabilities)?)?;
+    let _: InitializeParams = serde_json::from_value(initialize_params)?;
+
+    let mut server = Server {
+        connection,
+        system: System::new(analysis::MAX_CHUNKS),
+        documents: HashMap::new(),
+    };
+    server.run()?;
+
+    drop(server);
+    io_threads.join()?;
+
+    Ok(())
+}
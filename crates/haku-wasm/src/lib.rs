@@ -2,27 +2,41 @@
 
 extern crate alloc;
 
-use core::{alloc::Layout, slice};
+use core::{alloc::Layout, ffi::CStr, slice};
 
-use alloc::{boxed::Box, vec::Vec};
+use alloc::{boxed::Box, collections::BTreeMap, string::String, vec::Vec};
 use haku::{
     ast::Ast,
-    bytecode::{Chunk, Defs, DefsImage},
-    compiler::{compile_expr, ClosureSpec, CompileError, Compiler, Source},
-    diagnostic::Diagnostic,
+    bytecode::{Chunk, Defs, DefsImage, Namespace},
+    compiler::{compile_expr, incremental::DefCache, ClosureSpec, CompileError, Compiler, Source},
+    debug_info::DebugInfo,
+    diagnostic::{Diagnostic, Severity},
+    format::format,
     lexer::{lex, Lexer},
+    lint::lint,
+    module::{self, Loader, Module, ModuleLimits, BUNDLED, PRELUDE},
+    param::{self, Param, ParamKind, ParamValue},
     parser::{self, IntoAstError, Parser},
     render::{
+        accumulator::{Accumulator, Dither},
+        has_filters,
         tiny_skia::{Pixmap, PremultipliedColorU8},
         Renderer, RendererLimits,
     },
-    source::SourceCode,
-    system::{ChunkId, System, SystemImage},
-    token::Lexis,
-    value::{Closure, Ref, Value},
-    vm::{Exception, Vm, VmImage, VmLimits},
+    semantic::{classify, SemanticClass, SemanticToken},
+    source::{SourceCode, Span},
+    stroke::{self, EvalMode, Inputs},
+    system::{ChunkError, ChunkId, System, SystemImage},
+    token::{Lexis, TokenKind},
+    value::{BytecodeLoc, Ref, RefId, Rgba, Value, Vec2},
+    vm::{
+        debug::{DebugFrame, Debugged, Step},
+        profile::{FunctionProfile, Profile},
+        Exception, Vm, VmImage, VmLimits,
+    },
 };
 use log::{debug, info};
+use rkgk_image_ops::composite;
 
 pub mod logging;
 #[cfg(not(feature = "std"))]
@@ -57,13 +71,16 @@ struct Limits {
     memory: usize,
     pixmap_stack_capacity: usize,
     transform_stack_capacity: usize,
+    max_pixels: usize,
+    max_path_segments: usize,
+    strict_math: bool,
 }
 
 impl Default for Limits {
     fn default() -> Self {
         Self {
             max_source_code_len: 65536,
-            max_chunks: 2,
+            max_chunks: 8,
             max_defs: 256,
             max_tokens: 1024,
             max_parser_events: 1024,
@@ -76,6 +93,9 @@ impl Default for Limits {
             memory: 1024 * 1024,
             pixmap_stack_capacity: 4,
             transform_stack_capacity: 16,
+            max_pixels: 4 * 1024 * 1024,
+            max_path_segments: 8192,
+            strict_math: false,
         }
     }
 }
@@ -107,6 +127,18 @@ macro_rules! limit_setter {
     };
 }
 
+impl Limits {
+    fn module_limits(&self) -> ModuleLimits {
+        ModuleLimits {
+            max_source_code_len: self.max_source_code_len as u32,
+            max_tokens: self.max_tokens,
+            max_parser_events: self.max_parser_events,
+            ast_capacity: self.ast_capacity,
+            chunk_capacity: self.chunk_capacity,
+        }
+    }
+}
+
 limit_setter!(max_source_code_len);
 limit_setter!(max_chunks);
 limit_setter!(max_defs);
@@ -121,6 +153,16 @@ limit_setter!(fuel);
 limit_setter!(memory);
 limit_setter!(pixmap_stack_capacity);
 limit_setter!(transform_stack_capacity);
+limit_setter!(max_pixels);
+limit_setter!(max_path_segments);
+
+#[no_mangle]
+unsafe extern "C" fn haku_limits_set_strict_math(limits: *mut Limits, value: bool) {
+    debug!("set limit strict_math = {value}");
+
+    let limits = &mut *limits;
+    limits.strict_math = value;
+}
 
 #[derive(Debug, Clone)]
 struct Instance {
@@ -132,9 +174,40 @@ struct Instance {
     defs_image: DefsImage,
     vm: Vm,
     vm_image: VmImage,
+    prelude: Namespace,
+    inputs: Inputs,
+    /// Defs compiled from the last brush, so that recompiling it after an edit is fast.
+    def_cache: DefCache,
+    /// Modules added by `haku_instance_add_module`, importable by brushes alongside the bundled
+    /// ones.
+    host_modules: BTreeMap<String, String>,
+    /// The stroke started by `haku_begin_stroke`.
+    stroke: Option<Stroke>,
+
+    /// Whether brushes compiled by this instance can be debugged.
+    collect_debug_info: bool,
+    /// Breakpoints set in the brush being debugged.
+    breakpoints: Vec<BytecodeLoc>,
+    /// Call stack of the brush being debugged, captured each time it pauses.
+    /// Only frames running the brush's own code are included.
+    debug_frames: Vec<DebugFrame>,
+    /// Text returned by the last `haku_debug_format_*` call.
+    debug_text: String,
+    /// Resources used by the last `haku_profile_brush` call.
+    profile: Option<Profile>,
 
     value: Value,
     exception: Option<Exception>,
+    formatted: String,
+    highlight: Highlight,
+}
+
+#[derive(Debug, Clone)]
+struct Stroke {
+    /// Image of the VM after evaluating the stroke-constant part of the brush.
+    image: VmImage,
+    /// What the brush evaluated to, if it doesn't vary between points.
+    value: Option<Value>,
 }
 
 #[no_mangle]
@@ -142,10 +215,18 @@ unsafe extern "C" fn haku_instance_new(limits: *const Limits) -> *mut Instance {
     let limits = *limits;
     debug!("creating new instance with limits: {limits:?}");
 
-    let system = System::new(limits.max_chunks);
+    let mut system = System::new(limits.max_chunks);
+    let mut defs = Defs::new(limits.max_defs);
 
-    let defs = Defs::new(limits.max_defs);
-    let vm = Vm::new(
+    // The prelude is the same for all brushes, so it's compiled and run only once, before the
+    // images are taken.
+    let prelude = Loader::new(BUNDLED, limits.module_limits(), None)
+        .load(&mut system, &mut defs, PRELUDE, Span::new(0, 0))
+        .expect("the prelude must compile");
+    let inputs =
+        Inputs::declare(&mut defs, prelude.namespace).expect("the prelude must declare inputs");
+
+    let mut vm = Vm::new(
         &defs,
         &VmLimits {
             stack_capacity: limits.stack_capacity,
@@ -153,8 +234,11 @@ unsafe extern "C" fn haku_instance_new(limits: *const Limits) -> *mut Instance {
             ref_capacity: limits.ref_capacity,
             fuel: limits.fuel,
             memory: limits.memory,
+            strict_math: limits.strict_math,
         },
     );
+    module::init(&mut vm, &system, &[prelude]).expect("the prelude must run");
+    vm.set_fuel(limits.fuel);
 
     let system_image = system.image();
     let defs_image = defs.image();
@@ -168,8 +252,20 @@ unsafe extern "C" fn haku_instance_new(limits: *const Limits) -> *mut Instance {
         defs_image,
         vm,
         vm_image,
+        prelude: prelude.namespace,
+        inputs,
+        def_cache: DefCache::new(),
+        host_modules: BTreeMap::new(),
+        stroke: None,
+        collect_debug_info: false,
+        breakpoints: Vec::new(),
+        debug_frames: Vec::new(),
+        debug_text: String::new(),
+        profile: None,
         value: Value::Nil,
         exception: None,
+        formatted: String::new(),
+        highlight: Highlight::default(),
     });
 
     let ptr = Box::leak(instance) as *mut _;
@@ -183,12 +279,32 @@ unsafe extern "C" fn haku_instance_destroy(instance: *mut Instance) {
     drop(Box::from_raw(instance));
 }
 
+/// Makes a module available for brushes to import. Bundled modules take precedence over modules
+/// added with the same name.
+#[no_mangle]
+unsafe extern "C" fn haku_instance_add_module(
+    instance: *mut Instance,
+    name_len: u32,
+    name: *const u8,
+    code_len: u32,
+    code: *const u8,
+) {
+    let instance = &mut *instance;
+    let name = core::str::from_utf8(slice::from_raw_parts(name, name_len as usize))
+        .expect("invalid UTF-8");
+    let code = core::str::from_utf8(slice::from_raw_parts(code, code_len as usize))
+        .expect("invalid UTF-8");
+    debug!("adding module to instance: {name}");
+    instance.host_modules.insert(name.into(), code.into());
+}
+
 #[no_mangle]
 unsafe extern "C" fn haku_reset(instance: *mut Instance) {
     debug!("resetting instance: {instance:?}");
     let instance = &mut *instance;
     instance.system.restore_image(&instance.system_image);
     instance.defs.restore_image(&instance.defs_image);
+    instance.stroke = None;
 }
 
 #[no_mangle]
@@ -196,6 +312,7 @@ unsafe extern "C" fn haku_reset_vm(instance: *mut Instance) {
     debug!("resetting instance VM: {instance:?}");
     let instance = &mut *instance;
     instance.vm.restore_image(&instance.vm_image);
+    instance.stroke = None;
 }
 
 #[no_mangle]
@@ -225,6 +342,7 @@ enum StatusCode {
     ChunkTooBig,
     DiagnosticsEmitted,
     TooManyChunks,
+    InvalidBytecode,
     OutOfRefSlots,
     EvalException,
     RenderException,
@@ -260,6 +378,7 @@ extern "C" fn haku_status_string(code: StatusCode) -> *const i8 {
         StatusCode::ChunkTooBig => c"compiled bytecode is too large",
         StatusCode::DiagnosticsEmitted => c"diagnostics were emitted",
         StatusCode::TooManyChunks => c"too many registered bytecode chunks",
+        StatusCode::InvalidBytecode => c"compiled bytecode failed verification",
         StatusCode::OutOfRefSlots => c"out of ref slots (did you forget to restore the VM image?)",
         StatusCode::EvalException => c"an exception occurred while evaluating your code",
         StatusCode::RenderException => c"an exception occurred while rendering your brush",
@@ -278,6 +397,13 @@ enum BrushState {
 struct Brush {
     diagnostics: Vec<Diagnostic>,
     state: BrushState,
+    /// Modules imported by the brush, which need to be initialized before it's run.
+    modules: Vec<Module>,
+    params: Vec<Param>,
+    /// Values of params set by the user. Params without a value use their default.
+    param_values: Vec<Option<ParamValue>>,
+    /// Only collected if the instance that compiled the brush has debug info enabled.
+    debug_info: Option<DebugInfo>,
 }
 
 #[no_mangle]
@@ -300,22 +426,238 @@ unsafe extern "C" fn haku_num_diagnostics(brush: *const Brush) -> u32 {
 
 #[no_mangle]
 unsafe extern "C" fn haku_diagnostic_start(brush: *const Brush, index: u32) -> u32 {
-    (*brush).diagnostics[index as usize].span().start
+    (&(*brush).diagnostics)[index as usize].span().start
 }
 
 #[no_mangle]
 unsafe extern "C" fn haku_diagnostic_end(brush: *const Brush, index: u32) -> u32 {
-    (*brush).diagnostics[index as usize].span().end
+    (&(*brush).diagnostics)[index as usize].span().end
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_diagnostic_severity(brush: *const Brush, index: u32) -> *const i8 {
+    match (&(*brush).diagnostics)[index as usize].severity() {
+        Severity::Error => c"error",
+        Severity::Warning => c"warning",
+        Severity::Hint => c"hint",
+    }
+    .as_ptr()
 }
 
 #[no_mangle]
 unsafe extern "C" fn haku_diagnostic_message(brush: *const Brush, index: u32) -> *const u8 {
-    (*brush).diagnostics[index as usize].message().as_ptr()
+    (&(*brush).diagnostics)[index as usize].message().as_ptr()
 }
 
 #[no_mangle]
 unsafe extern "C" fn haku_diagnostic_message_len(brush: *const Brush, index: u32) -> u32 {
-    (*brush).diagnostics[index as usize].message().len() as u32
+    (&(*brush).diagnostics)[index as usize].message().len() as u32
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_diagnostic_num_labels(brush: *const Brush, index: u32) -> u32 {
+    (&(*brush).diagnostics)[index as usize].labels().len() as u32
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_diagnostic_label_start(
+    brush: *const Brush,
+    index: u32,
+    label: u32,
+) -> u32 {
+    (&(*brush).diagnostics)[index as usize].labels()[label as usize]
+        .span
+        .start
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_diagnostic_label_end(brush: *const Brush, index: u32, label: u32) -> u32 {
+    (&(*brush).diagnostics)[index as usize].labels()[label as usize]
+        .span
+        .end
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_diagnostic_label_message(
+    brush: *const Brush,
+    index: u32,
+    label: u32,
+) -> *const u8 {
+    (&(*brush).diagnostics)[index as usize].labels()[label as usize]
+        .message
+        .as_ptr()
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_diagnostic_label_message_len(
+    brush: *const Brush,
+    index: u32,
+    label: u32,
+) -> u32 {
+    (&(*brush).diagnostics)[index as usize].labels()[label as usize]
+        .message
+        .len() as u32
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_diagnostic_num_notes(brush: *const Brush, index: u32) -> u32 {
+    (&(*brush).diagnostics)[index as usize].notes().len() as u32
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_diagnostic_note(brush: *const Brush, index: u32, note: u32) -> *const u8 {
+    (&(*brush).diagnostics)[index as usize].notes()[note as usize].as_ptr()
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_diagnostic_note_len(brush: *const Brush, index: u32, note: u32) -> u32 {
+    (&(*brush).diagnostics)[index as usize].notes()[note as usize].len() as u32
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_diagnostic_num_suggestions(brush: *const Brush, index: u32) -> u32 {
+    (&(*brush).diagnostics)[index as usize].suggestions().len() as u32
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_diagnostic_suggestion_start(
+    brush: *const Brush,
+    index: u32,
+    suggestion: u32,
+) -> u32 {
+    (&(*brush).diagnostics)[index as usize].suggestions()[suggestion as usize]
+        .span
+        .start
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_diagnostic_suggestion_end(
+    brush: *const Brush,
+    index: u32,
+    suggestion: u32,
+) -> u32 {
+    (&(*brush).diagnostics)[index as usize].suggestions()[suggestion as usize]
+        .span
+        .end
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_diagnostic_suggestion_replacement(
+    brush: *const Brush,
+    index: u32,
+    suggestion: u32,
+) -> *const u8 {
+    (&(*brush).diagnostics)[index as usize].suggestions()[suggestion as usize]
+        .replacement
+        .as_ptr()
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_diagnostic_suggestion_replacement_len(
+    brush: *const Brush,
+    index: u32,
+    suggestion: u32,
+) -> u32 {
+    (&(*brush).diagnostics)[index as usize].suggestions()[suggestion as usize]
+        .replacement
+        .len() as u32
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_diagnostic_suggestion_message(
+    brush: *const Brush,
+    index: u32,
+    suggestion: u32,
+) -> *const u8 {
+    (&(*brush).diagnostics)[index as usize].suggestions()[suggestion as usize]
+        .message
+        .as_ptr()
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_diagnostic_suggestion_message_len(
+    brush: *const Brush,
+    index: u32,
+    suggestion: u32,
+) -> u32 {
+    (&(*brush).diagnostics)[index as usize].suggestions()[suggestion as usize]
+        .message
+        .len() as u32
+}
+
+/// Param values are passed in and out of JavaScript as four numbers: the number itself, the
+/// color's components, or 0 or 1 for booleans.
+fn param_value_components(value: ParamValue) -> [f32; 4] {
+    match value {
+        ParamValue::Number(x) => [x, 0.0, 0.0, 0.0],
+        ParamValue::Rgba(Rgba { r, g, b, a }) => [r, g, b, a],
+        ParamValue::Boolean(b) => [b as u8 as f32, 0.0, 0.0, 0.0],
+    }
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_num_params(brush: *const Brush) -> u32 {
+    (*brush).params.len() as u32
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_param_name(brush: *const Brush, index: u32) -> *const u8 {
+    (&(*brush).params)[index as usize].name.as_ptr()
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_param_name_len(brush: *const Brush, index: u32) -> u32 {
+    (&(*brush).params)[index as usize].name.len() as u32
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_param_kind(brush: *const Brush, index: u32) -> *const i8 {
+    match (&(*brush).params)[index as usize].kind() {
+        ParamKind::Number => c"number",
+        ParamKind::Rgba => c"rgba",
+        ParamKind::Boolean => c"boolean",
+    }
+    .as_ptr()
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_param_default(brush: *const Brush, index: u32, component: u32) -> f32 {
+    param_value_components((&(*brush).params)[index as usize].default)[component as usize]
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_param_has_range(brush: *const Brush, index: u32) -> bool {
+    (&(*brush).params)[index as usize].range.is_some()
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_param_min(brush: *const Brush, index: u32) -> f32 {
+    (&(*brush).params)[index as usize]
+        .range
+        .map_or(f32::NEG_INFINITY, |(min, _)| min)
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_param_max(brush: *const Brush, index: u32) -> f32 {
+    (&(*brush).params)[index as usize]
+        .range
+        .map_or(f32::INFINITY, |(_, max)| max)
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_param_set(brush: *mut Brush, index: u32, x: f32, y: f32, z: f32, w: f32) {
+    let brush = &mut *brush;
+    let value = match brush.params[index as usize].kind() {
+        ParamKind::Number => ParamValue::Number(x),
+        ParamKind::Rgba => ParamValue::Rgba(Rgba {
+            r: x,
+            g: y,
+            b: z,
+            a: w,
+        }),
+        ParamKind::Boolean => ParamValue::Boolean(x != 0.0),
+    };
+    brush.param_values[index as usize] = Some(value);
 }
 
 #[no_mangle]
@@ -382,6 +724,16 @@ unsafe extern "C" fn haku_compile_brush(
         ast.len()
     );
 
+    let resolver = (BUNDLED, &instance.host_modules);
+    let mut loader = Loader::new(
+        &resolver,
+        instance.limits.module_limits(),
+        Some(instance.prelude),
+    );
+    let mut import_diagnostics =
+        loader.import_all(&mut instance.system, &mut instance.defs, code, &ast, root);
+    brush.modules = loader.modules().collect();
+
     let src = Source {
         code,
         ast: &ast,
@@ -390,6 +742,8 @@ unsafe extern "C" fn haku_compile_brush(
 
     let mut chunk = Chunk::new(instance.limits.chunk_capacity).unwrap();
     let mut compiler = Compiler::new(&mut instance.defs, &mut chunk);
+    compiler.def_cache = Some(&mut instance.def_cache);
+    compiler.debug_info = instance.collect_debug_info.then(DebugInfo::new);
     if let Err(error) = compile_expr(&mut compiler, &src, root) {
         match error {
             CompileError::Emit => {
@@ -399,12 +753,21 @@ unsafe extern "C" fn haku_compile_brush(
         }
     }
     let closure_spec = compiler.closure_spec();
+    brush.param_values = Vec::from_iter(compiler.params.iter().map(|_| None));
+    brush.params = compiler.params;
+    brush.debug_info = compiler.debug_info.take();
 
     let mut diagnostics = lexer.diagnostics;
     diagnostics.append(&mut parser_diagnostics);
+    diagnostics.append(&mut import_diagnostics);
     diagnostics.append(&mut compiler.diagnostics);
-    if !diagnostics.is_empty() {
-        brush.diagnostics = diagnostics;
+    diagnostics.append(&mut lint(&src, root));
+    debug!("compiling: {:?}", instance.def_cache.stats());
+    // Warnings and hints are kept around for display, but they do not prevent the brush from
+    // being used.
+    let has_errors = diagnostics.iter().any(|diagnostic| diagnostic.is_error());
+    brush.diagnostics = diagnostics;
+    if has_errors {
         debug!("compiling failed: diagnostics were emitted");
         return StatusCode::DiagnosticsEmitted;
     }
@@ -415,9 +778,16 @@ unsafe extern "C" fn haku_compile_brush(
     );
     debug!("compiling: {closure_spec:?}");
 
-    let chunk_id = match instance.system.add_chunk(chunk) {
+    let chunk_id = match instance
+        .system
+        .add_chunk(chunk, &instance.defs, closure_spec)
+    {
         Ok(chunk_id) => chunk_id,
-        Err(_) => return StatusCode::TooManyChunks,
+        Err(ChunkError::TooManyChunks) => return StatusCode::TooManyChunks,
+        Err(ChunkError::Verify(error)) => {
+            info!("compiling failed: {error}");
+            return StatusCode::InvalidBytecode;
+        }
     };
     brush.state = BrushState::Ready(chunk_id, closure_spec);
 
@@ -426,6 +796,238 @@ unsafe extern "C" fn haku_compile_brush(
     StatusCode::Ok
 }
 
+#[no_mangle]
+unsafe extern "C" fn haku_format(
+    instance: *mut Instance,
+    code_len: u32,
+    code: *const u8,
+) -> StatusCode {
+    info!("formatting code");
+
+    let instance = &mut *instance;
+    instance.formatted.clear();
+
+    let code = core::str::from_utf8(slice::from_raw_parts(code, code_len as usize))
+        .expect("invalid UTF-8");
+    let Some(code) = SourceCode::limited_len(code, instance.limits.max_source_code_len as u32)
+    else {
+        return StatusCode::SourceCodeTooLong;
+    };
+
+    let mut lexer = Lexer::new(Lexis::with_trivia(instance.limits.max_tokens), code);
+    if lex(&mut lexer).is_err() {
+        info!("formatting failed: too many tokens");
+        return StatusCode::TooManyTokens;
+    };
+
+    let mut ast = Ast::new(instance.limits.ast_capacity);
+    let mut parser = Parser::new(
+        &lexer.lexis,
+        &haku::parser::ParserLimits {
+            max_events: instance.limits.max_parser_events,
+        },
+    );
+    parser::toplevel(&mut parser);
+    let (root, parser_diagnostics) = match parser.into_ast(&mut ast) {
+        Ok((r, d)) => (r, d),
+        Err(IntoAstError::NodeAlloc(_)) => return StatusCode::TooManyAstNodes,
+        Err(IntoAstError::TooManyEvents) => return StatusCode::TooManyParserEvents,
+        Err(IntoAstError::UnbalancedEvents) => return StatusCode::ParserUnbalancedEvents,
+    };
+
+    // Code with syntax errors cannot be formatted faithfully. The diagnostics themselves are
+    // reported by haku_compile_brush, so we don't duplicate them here.
+    if lexer
+        .diagnostics
+        .iter()
+        .chain(&parser_diagnostics)
+        .any(|diagnostic| diagnostic.is_error())
+    {
+        info!("formatting failed: code has syntax errors");
+        return StatusCode::DiagnosticsEmitted;
+    }
+
+    instance.formatted = format(code, &lexer.lexis, &ast, root);
+
+    StatusCode::Ok
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_formatted(instance: *const Instance) -> *const u8 {
+    let instance = &*instance;
+    instance.formatted.as_ptr()
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_formatted_len(instance: *const Instance) -> u32 {
+    let instance = &*instance;
+    instance.formatted.len() as u32
+}
+
+#[derive(Debug, Clone, Default)]
+struct Highlight {
+    tokens: Vec<(&'static CStr, Span)>,
+    semantic: Vec<SemanticToken>,
+}
+
+fn token_class(kind: TokenKind) -> Option<&'static CStr> {
+    match kind {
+        TokenKind::Eof | TokenKind::Newline => None,
+        TokenKind::Ident => Some(c"ident"),
+        TokenKind::Tag => Some(c"tag"),
+        TokenKind::Number => Some(c"number"),
+        TokenKind::Color => Some(c"color"),
+        TokenKind::Plus
+        | TokenKind::Minus
+        | TokenKind::Star
+        | TokenKind::Slash
+        | TokenKind::EqualEqual
+        | TokenKind::NotEqual
+        | TokenKind::Less
+        | TokenKind::LessEqual
+        | TokenKind::Greater
+        | TokenKind::GreaterEqual
+        | TokenKind::Not
+        | TokenKind::Equal
+        | TokenKind::RArrow
+        | TokenKind::Backslash => Some(c"operator"),
+        TokenKind::LParen
+        | TokenKind::RParen
+        | TokenKind::LBrack
+        | TokenKind::RBrack
+        | TokenKind::Comma => Some(c"punctuation"),
+        TokenKind::Underscore
+        | TokenKind::And
+        | TokenKind::Or
+        | TokenKind::If
+        | TokenKind::Else
+        | TokenKind::Let
+        | TokenKind::Import
+        | TokenKind::Param => Some(c"keyword"),
+        TokenKind::Error => Some(c"error"),
+    }
+}
+
+fn semantic_class(class: SemanticClass) -> &'static CStr {
+    match class {
+        SemanticClass::Def => c"def",
+        SemanticClass::Param => c"param",
+        SemanticClass::Local => c"local",
+        SemanticClass::SystemFn => c"systemFn",
+        SemanticClass::Tag => c"tag",
+        SemanticClass::Color => c"color",
+    }
+}
+
+/// Lexes and parses the code for syntax highlighting. Unlike compilation, this succeeds even if
+/// the code has errors, so that the code can be highlighted while it's being typed.
+#[no_mangle]
+unsafe extern "C" fn haku_highlight(
+    instance: *mut Instance,
+    code_len: u32,
+    code: *const u8,
+) -> StatusCode {
+    let instance = &mut *instance;
+    instance.highlight = Highlight::default();
+
+    let code = core::str::from_utf8(slice::from_raw_parts(code, code_len as usize))
+        .expect("invalid UTF-8");
+    let Some(code) = SourceCode::limited_len(code, instance.limits.max_source_code_len as u32)
+    else {
+        return StatusCode::SourceCodeTooLong;
+    };
+
+    let mut lexer = Lexer::new(Lexis::with_trivia(instance.limits.max_tokens), code);
+    if lex(&mut lexer).is_err() {
+        return StatusCode::TooManyTokens;
+    };
+
+    let mut tokens: Vec<_> = lexer
+        .lexis
+        .kinds
+        .iter()
+        .zip(&lexer.lexis.spans)
+        .filter_map(|(&kind, &span)| Some((token_class(kind)?, span)))
+        .collect();
+    tokens.extend(lexer.lexis.comments().map(|span| (c"comment", span)));
+    tokens.sort_by_key(|&(_, span)| span.start);
+    instance.highlight.tokens = tokens;
+
+    let mut ast = Ast::new(instance.limits.ast_capacity);
+    let mut parser = Parser::new(
+        &lexer.lexis,
+        &haku::parser::ParserLimits {
+            max_events: instance.limits.max_parser_events,
+        },
+    );
+    parser::toplevel(&mut parser);
+    let root = match parser.into_ast(&mut ast) {
+        Ok((root, _)) => root,
+        Err(IntoAstError::NodeAlloc(_)) => return StatusCode::TooManyAstNodes,
+        Err(IntoAstError::TooManyEvents) => return StatusCode::TooManyParserEvents,
+        Err(IntoAstError::UnbalancedEvents) => return StatusCode::ParserUnbalancedEvents,
+    };
+
+    let src = Source {
+        code,
+        ast: &ast,
+        system: &instance.system,
+    };
+    instance.highlight.semantic = classify(&src, root);
+
+    StatusCode::Ok
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_num_tokens(instance: *const Instance) -> u32 {
+    let instance = &*instance;
+    instance.highlight.tokens.len() as u32
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_token_class(instance: *const Instance, index: u32) -> *const i8 {
+    (&(*instance).highlight.tokens)[index as usize].0.as_ptr()
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_token_start(instance: *const Instance, index: u32) -> u32 {
+    (&(*instance).highlight.tokens)[index as usize].1.start
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_token_end(instance: *const Instance, index: u32) -> u32 {
+    (&(*instance).highlight.tokens)[index as usize].1.end
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_num_semantic_tokens(instance: *const Instance) -> u32 {
+    let instance = &*instance;
+    instance.highlight.semantic.len() as u32
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_semantic_token_class(instance: *const Instance, index: u32) -> *const i8 {
+    semantic_class((&(*instance).highlight.semantic)[index as usize].class).as_ptr()
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_semantic_token_start(instance: *const Instance, index: u32) -> u32 {
+    (&(*instance).highlight.semantic)[index as usize].span.start
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_semantic_token_end(instance: *const Instance, index: u32) -> u32 {
+    (&(*instance).highlight.semantic)[index as usize].span.end
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_semantic_token_is_definition(
+    instance: *const Instance,
+    index: u32,
+) -> bool {
+    (&(*instance).highlight.semantic)[index as usize].is_definition
+}
+
 struct PixmapLock {
     pixmap: Pixmap,
 }
@@ -458,26 +1060,113 @@ unsafe extern "C" fn haku_pixmap_clear(pixmap: *mut PixmapLock) {
 }
 
 #[no_mangle]
-unsafe extern "C" fn haku_eval_brush(instance: *mut Instance, brush: *const Brush) -> StatusCode {
-    let instance = &mut *instance;
-    let brush = &*brush;
+unsafe extern "C" fn haku_pixmap_copy(
+    dst: *mut PixmapLock,
+    src: *const PixmapLock,
+    x: i32,
+    y: i32,
+) {
+    composite::copy(&mut (*dst).pixmap.as_mut(), (*src).pixmap.as_ref(), x, y);
+}
+
+#[no_mangle]
+extern "C" fn haku_accumulator_new(width: u32, height: u32) -> *mut Accumulator {
+    let ptr = Box::leak(Box::new(Accumulator::new(width, height))) as *mut _;
+    debug!("created accumulator with size {width}x{height}: {ptr:?}");
+    ptr
+}
 
+#[no_mangle]
+unsafe extern "C" fn haku_accumulator_destroy(accumulator: *mut Accumulator) {
+    debug!("destroying accumulator: {accumulator:?}");
+    drop(Box::from_raw(accumulator))
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_accumulator_blend(
+    accumulator: *mut Accumulator,
+    pixmap: *const PixmapLock,
+    x: i32,
+    y: i32,
+) {
+    (*accumulator).blend(&(*pixmap).pixmap, x, y);
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_accumulator_composite(
+    accumulator: *const Accumulator,
+    pixmap: *mut PixmapLock,
+    dither: bool,
+) {
+    let dither = if dither {
+        Dither::Ordered
+    } else {
+        Dither::None
+    };
+    (*accumulator).composite(&mut (*pixmap).pixmap, dither);
+}
+
+/// Prepare the VM for running the given part of the brush, and return the closure to run.
+fn enter_brush(
+    instance: &mut Instance,
+    brush: &Brush,
+    mode: EvalMode,
+) -> Result<RefId, StatusCode> {
     let BrushState::Ready(chunk_id, closure_spec) = brush.state else {
         panic!("brush is not compiled and ready to be used");
     };
 
+    instance.vm.set_eval_mode(mode);
+
     debug!("applying defs");
     instance.vm.apply_defs(&instance.defs);
 
-    let Ok(closure_id) = instance
+    debug!("resetting exception");
+    instance.exception = None;
+
+    debug!("initializing modules");
+    if let Err(exn) = module::init(&mut instance.vm, &instance.system, &brush.modules) {
+        debug!("setting exception {exn:?}");
+        instance.exception = Some(exn);
+        return Err(StatusCode::EvalException);
+    }
+
+    // When evaluating a point, params are already set in the stroke image.
+    if mode != EvalMode::Point {
+        debug!("applying params");
+        let mut param_values = brush.param_values.iter();
+        if let Err(exn) = param::apply(&mut instance.vm, &brush.params, |_| {
+            param_values.next().copied().flatten()
+        }) {
+            debug!("setting exception {exn:?}");
+            instance.exception = Some(exn);
+            return Err(StatusCode::EvalException);
+        }
+    }
+
+    let closure = stroke::toplevel(chunk_id, closure_spec, mode)
+        .expect("the per-point part of a brush must only be run if the brush is split");
+    instance
         .vm
-        .create_ref(Ref::Closure(Closure::chunk(chunk_id, closure_spec)))
-    else {
-        return StatusCode::OutOfRefSlots;
+        .create_ref(Ref::Closure(closure))
+        .map_err(|_| StatusCode::OutOfRefSlots)
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_eval_brush(instance: *mut Instance, brush: *const Brush) -> StatusCode {
+    eval_brush(&mut *instance, &*brush)
+}
+
+fn eval_brush(instance: &mut Instance, brush: &Brush) -> StatusCode {
+    run_brush(instance, brush, EvalMode::Full)
+}
+
+fn run_brush(instance: &mut Instance, brush: &Brush, mode: EvalMode) -> StatusCode {
+    let closure_id = match enter_brush(instance, brush, mode) {
+        Ok(closure_id) => closure_id,
+        Err(status) => return status,
     };
 
-    debug!("resetting exception");
-    instance.exception = None;
     instance.value = match instance.vm.run(&instance.system, closure_id) {
         Ok(value) => value,
         Err(exn) => {
@@ -490,6 +1179,62 @@ unsafe extern "C" fn haku_eval_brush(instance: *mut Instance, brush: *const Brus
     StatusCode::Ok
 }
 
+/// Evaluate the stroke-constant part of the brush, so that it can then be evaluated at each point
+/// of a stroke with `haku_eval_point`.
+#[no_mangle]
+unsafe extern "C" fn haku_begin_stroke(instance: *mut Instance, brush: *const Brush) -> StatusCode {
+    let instance = &mut *instance;
+    let brush = &*brush;
+
+    instance.stroke = None;
+    let status = run_brush(instance, brush, EvalMode::Stroke);
+    if status != StatusCode::Ok {
+        return status;
+    }
+
+    let varies_per_point = matches!(
+        brush.state,
+        BrushState::Ready(_, closure_spec) if closure_spec.varies_per_point()
+    );
+    instance.stroke = Some(Stroke {
+        image: instance.vm.image(),
+        value: (!varies_per_point).then_some(instance.value),
+    });
+    StatusCode::Ok
+}
+
+/// Evaluate the brush at a point of the stroke started with `haku_begin_stroke`.
+/// If the brush doesn't vary between points, the value it evaluated to for the stroke is kept.
+#[no_mangle]
+unsafe extern "C" fn haku_eval_point(
+    instance: *mut Instance,
+    brush: *const Brush,
+    x: f32,
+    y: f32,
+) -> StatusCode {
+    let instance = &mut *instance;
+    let brush = &*brush;
+
+    let stroke = instance
+        .stroke
+        .as_ref()
+        .expect("a stroke must be started with haku_begin_stroke");
+    instance.vm.restore_image(&stroke.image);
+    if let Some(value) = stroke.value {
+        instance.value = value;
+        return StatusCode::Ok;
+    }
+
+    debug!("applying stroke inputs");
+    if let Err(exn) = instance.inputs.apply(&mut instance.vm, Vec2 { x, y }) {
+        debug!("setting exception {exn:?}");
+        instance.exception = Some(exn);
+        return StatusCode::EvalException;
+    }
+
+    run_brush(instance, brush, EvalMode::Point)
+}
+
 #[no_mangle]
 unsafe extern "C" fn haku_render_value(
     instance: *mut Instance,
@@ -510,6 +1255,8 @@ unsafe extern "C" fn haku_render_value(
         &RendererLimits {
             pixmap_stack_capacity: instance.limits.pixmap_stack_capacity,
             transform_stack_capacity: instance.limits.transform_stack_capacity,
+            max_pixels: instance.limits.max_pixels,
+            max_path_segments: instance.limits.max_path_segments,
         },
     );
     renderer.translate(translation_x, translation_y);
@@ -517,10 +1264,451 @@ unsafe extern "C" fn haku_render_value(
         Ok(()) => (),
         Err(exn) => {
             instance.exception = Some(exn);
-            instance.vm.restore_image(&instance.vm_image);
+            // Go back to the start of the stroke rather than the start of the brush, so that the
+            // rest of the stroke's points can still be drawn.
+            match &instance.stroke {
+                Some(stroke) => instance.vm.restore_image(&stroke.image),
+                None => instance.vm.restore_image(&instance.vm_image),
+            }
+            instance.value = Value::Nil;
             return StatusCode::RenderException;
         }
     }
 
     StatusCode::Ok
 }
+
+#[no_mangle]
+unsafe extern "C" fn haku_has_filters(instance: *const Instance) -> bool {
+    let instance = &*instance;
+    has_filters(&instance.vm, instance.value)
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_enable_debug_info(instance: *mut Instance) {
+    (*instance).collect_debug_info = true;
+}
+
+fn brush_debug_info(brush: &Brush) -> &DebugInfo {
+    brush
+        .debug_info
+        .as_ref()
+        .expect("brush must be compiled with debug info enabled")
+}
+
+fn brush_chunk_id(brush: &Brush) -> ChunkId {
+    let BrushState::Ready(chunk_id, _) = brush.state else {
+        panic!("brush is not compiled and ready to be used");
+    };
+    chunk_id
+}
+
+/// Capture the state of the brush being debugged after it's resumed.
+fn debugged(
+    instance: &mut Instance,
+    brush: &Brush,
+    result: Result<Debugged, Exception>,
+) -> StatusCode {
+    let chunk_id = brush_chunk_id(brush);
+    instance.debug_frames = instance
+        .vm
+        .debug_frames()
+        .into_iter()
+        .filter(|frame| frame.location.chunk_id == chunk_id)
+        .collect();
+
+    match result {
+        Ok(Debugged::Paused) => StatusCode::Ok,
+        Ok(Debugged::Finished(value)) => {
+            debug!("debugged brush finished with {value:?}");
+            instance.value = value;
+            StatusCode::Ok
+        }
+        Err(exn) => {
+            debug!("setting exception {exn:?}");
+            instance.exception = Some(exn);
+            instance.vm.debug_stop();
+            StatusCode::EvalException
+        }
+    }
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_debug_start(instance: *mut Instance, brush: *const Brush) -> StatusCode {
+    let instance = &mut *instance;
+    let brush = &*brush;
+
+    instance.vm.debug_stop();
+    let closure_id = match enter_brush(instance, brush, EvalMode::Full) {
+        Ok(closure_id) => closure_id,
+        Err(status) => return status,
+    };
+    let result = instance
+        .vm
+        .debug_start(closure_id)
+        .map(|_| Debugged::Paused);
+    debugged(instance, brush, result)
+}
+
+/// Run the brush until it starts evaluating a different expression.
+#[no_mangle]
+unsafe extern "C" fn haku_debug_step(instance: *mut Instance, brush: *const Brush) -> StatusCode {
+    let instance = &mut *instance;
+    let brush = &*brush;
+
+    let chunk_id = brush_chunk_id(brush);
+    let debug_info = brush_debug_info(brush);
+    let span_at = |frames: &[DebugFrame]| {
+        frames
+            .last()
+            .filter(|frame| frame.location.chunk_id == chunk_id)
+            .and_then(|frame| debug_info.span_at(frame.location.offset))
+    };
+
+    let frames = instance.vm.debug_frames();
+    let (start_span, start_depth) = (span_at(&frames), frames.len());
+    loop {
+        let result = instance
+            .vm
+            .debug_resume(&instance.system, &[], Step::Instruction);
+        if !matches!(result, Ok(Debugged::Paused)) {
+            return debugged(instance, brush, result);
+        }
+
+        // Code outside the brush (such as the prelude) has no debug info, so it's stepped over.
+        let frames = instance.vm.debug_frames();
+        let span = span_at(&frames);
+        if span.is_some() && (span != start_span || frames.len() != start_depth) {
+            return debugged(instance, brush, result);
+        }
+    }
+}
+
+/// Run the brush until it reaches a breakpoint.
+#[no_mangle]
+unsafe extern "C" fn haku_debug_continue(
+    instance: *mut Instance,
+    brush: *const Brush,
+) -> StatusCode {
+    let instance = &mut *instance;
+    let brush = &*brush;
+
+    let result = instance
+        .vm
+        .debug_resume(&instance.system, &instance.breakpoints, Step::Continue);
+    debugged(instance, brush, result)
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_debug_stop(instance: *mut Instance) {
+    let instance = &mut *instance;
+    instance.vm.debug_stop();
+    instance.debug_frames.clear();
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_debug_is_paused(instance: *const Instance) -> bool {
+    (*instance).vm.is_paused()
+}
+
+/// Set a breakpoint on the innermost expression at the given position in the brush's source
+/// code. Returns whether there was an expression to set the breakpoint on.
+#[no_mangle]
+unsafe extern "C" fn haku_debug_add_breakpoint(
+    instance: *mut Instance,
+    brush: *const Brush,
+    position: u32,
+) -> bool {
+    let instance = &mut *instance;
+    let brush = &*brush;
+
+    let Some(offset) = brush_debug_info(brush).breakpoint_at(position) else {
+        return false;
+    };
+    instance.breakpoints.push(BytecodeLoc {
+        chunk_id: brush_chunk_id(brush),
+        offset,
+    });
+    true
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_debug_clear_breakpoints(instance: *mut Instance) {
+    (*instance).breakpoints.clear();
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_debug_num_frames(instance: *const Instance) -> u32 {
+    (*instance).debug_frames.len() as u32
+}
+
+fn frame_span(instance: &Instance, brush: &Brush, index: u32) -> Span {
+    let frame = &instance.debug_frames[index as usize];
+    // Frames other than the innermost one are paused right after the call instruction, which
+    // belongs to the expression that's actually being evaluated.
+    let innermost = index as usize == instance.debug_frames.len() - 1;
+    let offset = match innermost {
+        true => frame.location.offset,
+        false => frame.location.offset - 1,
+    };
+    brush_debug_info(brush)
+        .span_at(offset)
+        .unwrap_or(Span::new(0, 0))
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_debug_frame_start(
+    instance: *const Instance,
+    brush: *const Brush,
+    frame: u32,
+) -> u32 {
+    frame_span(&*instance, &*brush, frame).start
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_debug_frame_end(
+    instance: *const Instance,
+    brush: *const Brush,
+    frame: u32,
+) -> u32 {
+    frame_span(&*instance, &*brush, frame).end
+}
+
+/// Returns the names of a frame's variables: its parameters and locals followed by its captures.
+fn frame_variables<'a>(
+    instance: &Instance,
+    brush: &'a Brush,
+    frame: u32,
+) -> impl Iterator<Item = &'a str> {
+    let frame = &instance.debug_frames[frame as usize];
+    let closure = instance.vm.get_ref(frame.closure_id).as_closure().unwrap();
+    let function = brush_debug_info(brush).function(closure.start.offset);
+    function
+        .into_iter()
+        .flat_map(|function| function.locals.iter().chain(&function.captures))
+        .map(|name| name.as_str())
+}
+
+fn frame_variable_value(instance: &Instance, frame: u32, index: u32) -> Value {
+    let frame = &instance.debug_frames[frame as usize];
+    let index = index as usize;
+    if index < frame.locals.len() {
+        instance.vm.stack()[frame.locals.start + index]
+    } else {
+        let closure = instance.vm.get_ref(frame.closure_id).as_closure().unwrap();
+        closure.captures[index - frame.locals.len()]
+    }
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_debug_num_variables(
+    instance: *const Instance,
+    brush: *const Brush,
+    frame: u32,
+) -> u32 {
+    frame_variables(&*instance, &*brush, frame).count() as u32
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_debug_variable_name(
+    instance: *const Instance,
+    brush: *const Brush,
+    frame: u32,
+    index: u32,
+) -> *const u8 {
+    frame_variables(&*instance, &*brush, frame)
+        .nth(index as usize)
+        .unwrap()
+        .as_ptr()
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_debug_variable_name_len(
+    instance: *const Instance,
+    brush: *const Brush,
+    frame: u32,
+    index: u32,
+) -> u32 {
+    frame_variables(&*instance, &*brush, frame)
+        .nth(index as usize)
+        .unwrap()
+        .len() as u32
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_debug_format_variable(instance: *mut Instance, frame: u32, index: u32) {
+    let instance = &mut *instance;
+    let value = frame_variable_value(instance, frame, index);
+    instance.debug_text = instance.vm.format_value(value);
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_debug_num_temporaries(instance: *const Instance, frame: u32) -> u32 {
+    let instance = &*instance;
+    instance.debug_frames[frame as usize].temporaries.len() as u32
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_debug_format_temporary(instance: *mut Instance, frame: u32, index: u32) {
+    let instance = &mut *instance;
+    let temporaries = &instance.debug_frames[frame as usize].temporaries;
+    let value = instance.vm.stack()[temporaries.start + index as usize];
+    instance.debug_text = instance.vm.format_value(value);
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_debug_num_defs(brush: *const Brush) -> u32 {
+    brush_debug_info(&*brush).defs.len() as u32
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_debug_def_name(brush: *const Brush, index: u32) -> *const u8 {
+    brush_debug_info(&*brush).defs[index as usize].0.as_ptr()
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_debug_def_name_len(brush: *const Brush, index: u32) -> u32 {
+    brush_debug_info(&*brush).defs[index as usize].0.len() as u32
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_debug_format_def(
+    instance: *mut Instance,
+    brush: *const Brush,
+    index: u32,
+) {
+    let instance = &mut *instance;
+    let (_, def_id) = brush_debug_info(&*brush).defs[index as usize];
+    let value = instance.vm.def_values()[def_id.to_u16() as usize];
+    instance.debug_text = instance.vm.format_value(value);
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_debug_text(instance: *const Instance) -> *const u8 {
+    (*instance).debug_text.as_ptr()
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_debug_text_len(instance: *const Instance) -> u32 {
+    let instance = &*instance;
+    instance.debug_text.len() as u32
+}
+
+/// Evaluate the brush like `haku_eval_brush`, while recording the resources it uses.
+/// The profile is kept even if evaluation fails.
+#[no_mangle]
+unsafe extern "C" fn haku_profile_brush(
+    instance: *mut Instance,
+    brush: *const Brush,
+) -> StatusCode {
+    let instance = &mut *instance;
+    let brush = &*brush;
+
+    instance.vm.start_profiling();
+    let status = eval_brush(instance, brush);
+    instance.profile = instance.vm.finish_profiling();
+    status
+}
+
+fn profile(instance: &Instance) -> &Profile {
+    instance
+        .profile
+        .as_ref()
+        .expect("brush must be profiled first")
+}
+
+macro_rules! profile_getter {
+    ($name:tt) => {
+        paste::paste! {
+            #[no_mangle]
+            unsafe extern "C" fn [<haku_profile_ $name>](instance: *const Instance) -> usize {
+                profile(&*instance).$name
+            }
+        }
+    };
+}
+
+profile_getter!(fuel_used);
+profile_getter!(fuel_limit);
+profile_getter!(peak_memory);
+profile_getter!(memory_limit);
+profile_getter!(peak_stack_depth);
+profile_getter!(stack_capacity);
+profile_getter!(peak_call_depth);
+profile_getter!(call_stack_capacity);
+
+#[no_mangle]
+unsafe extern "C" fn haku_profile_num_functions(instance: *const Instance) -> u32 {
+    profile(&*instance).functions.len() as u32
+}
+
+fn profile_function(instance: &Instance, index: u32) -> &FunctionProfile {
+    &profile(instance).functions[index as usize]
+}
+
+/// Returns the span of a profiled function in the brush's source code, or `None` if the
+/// function comes from outside the brush or the brush was compiled without debug info.
+fn profile_function_span(instance: &Instance, brush: &Brush, index: u32) -> Option<Span> {
+    let function = profile_function(instance, index);
+    let debug_info = brush.debug_info.as_ref()?;
+    let BrushState::Ready(chunk_id, _) = brush.state else {
+        return None;
+    };
+    if function.start.chunk_id != chunk_id {
+        return None;
+    }
+    debug_info
+        .function(function.start.offset)
+        .map(|function| function.span)
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_profile_function_in_brush(
+    instance: *const Instance,
+    brush: *const Brush,
+    index: u32,
+) -> bool {
+    profile_function_span(&*instance, &*brush, index).is_some()
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_profile_function_start(
+    instance: *const Instance,
+    brush: *const Brush,
+    index: u32,
+) -> u32 {
+    profile_function_span(&*instance, &*brush, index)
+        .unwrap()
+        .start
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_profile_function_end(
+    instance: *const Instance,
+    brush: *const Brush,
+    index: u32,
+) -> u32 {
+    profile_function_span(&*instance, &*brush, index)
+        .unwrap()
+        .end
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_profile_function_calls(instance: *const Instance, index: u32) -> usize {
+    profile_function(&*instance, index).calls
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_profile_function_fuel(instance: *const Instance, index: u32) -> usize {
+    profile_function(&*instance, index).fuel
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_profile_function_memory(instance: *const Instance, index: u32) -> usize {
+    profile_function(&*instance, index).memory
+}
+
+#[no_mangle]
+unsafe extern "C" fn haku_profile_function_refs(instance: *const Instance, index: u32) -> usize {
+    profile_function(&*instance, index).refs
+}
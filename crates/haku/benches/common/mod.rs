@@ -0,0 +1,72 @@
+//! Setup shared between the benchmarks, for taking brushes through the lexer, parser, and
+//! compiler with the same limits as rkgk.
+
+// Each benchmark is compiled as a crate of its own, and doesn't use everything in here.
+#![allow(dead_code)]
+
+use haku::{
+    ast::{Ast, NodeId},
+    bytecode::{Chunk, Defs},
+    compiler::{compile_expr, incremental::DefCache, ClosureSpec, Compiler, Source},
+    lexer::{lex, Lexer},
+    parser::{self, Parser, ParserLimits},
+    source::SourceCode,
+    system::System,
+    token::Lexis,
+};
+
+// Same as the defaults in rkgk.toml.
+pub const MAX_SOURCE_CODE_LEN: usize = 65536;
+pub const MAX_DEFS: usize = 256;
+pub const MAX_TOKENS: usize = 65536;
+pub const MAX_PARSER_EVENTS: usize = 65536;
+pub const AST_CAPACITY: usize = 65536;
+pub const CHUNK_CAPACITY: usize = 65536;
+
+pub struct Parsed<'a> {
+    pub code: &'a SourceCode,
+    pub ast: Ast,
+    pub root: NodeId,
+}
+
+pub fn parse(code: &str) -> Parsed<'_> {
+    let code = SourceCode::limited_len(code, MAX_SOURCE_CODE_LEN as u32).unwrap();
+    let mut lexer = Lexer::new(Lexis::new(MAX_TOKENS), code);
+    lex(&mut lexer).unwrap();
+
+    let mut ast = Ast::new(AST_CAPACITY);
+    let mut parser = Parser::new(
+        &lexer.lexis,
+        &ParserLimits {
+            max_events: MAX_PARSER_EVENTS,
+        },
+    );
+    parser::toplevel(&mut parser);
+    let (root, diagnostics) = parser.into_ast(&mut ast).unwrap();
+    assert!(diagnostics.is_empty());
+
+    Parsed { code, ast, root }
+}
+
+pub struct Compiled {
+    pub chunk: Chunk,
+    pub defs: Defs,
+    pub spec: ClosureSpec,
+}
+
+pub fn compile(system: &System, parsed: &Parsed, def_cache: Option<&mut DefCache>) -> Compiled {
+    let src = Source {
+        code: parsed.code,
+        ast: &parsed.ast,
+        system,
+    };
+    let mut defs = Defs::new(MAX_DEFS);
+    let mut chunk = Chunk::new(CHUNK_CAPACITY).unwrap();
+    let mut compiler = Compiler::new(&mut defs, &mut chunk);
+    compiler.def_cache = def_cache;
+    compile_expr(&mut compiler, &src, parsed.root).unwrap();
+    assert!(compiler.diagnostics.is_empty());
+    let spec = compiler.closure_spec();
+
+    Compiled { chunk, defs, spec }
+}
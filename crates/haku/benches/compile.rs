@@ -0,0 +1,92 @@
+//! Benchmarks for compiling brushes.
+//!
+//! Run with `cargo bench -p haku --bench compile`. The brush being compiled is as big as the
+//! default `max_source_code_len` in rkgk.toml allows, which is the worst case for recompiling it
+//! on every keystroke in the brush editor.
+
+use std::hint::black_box;
+
+use common::{compile, parse, MAX_SOURCE_CODE_LEN};
+use criterion::{criterion_group, criterion_main, Criterion};
+use haku::{compiler::incremental::DefCache, system::System};
+
+mod common;
+
+fn def(index: usize, scale: usize) -> String {
+    let callee = match index {
+        0 => "stroke".to_owned(),
+        _ => format!("shape{}", index - 1),
+    };
+    format!(
+        r#"
+shape{index} = \position, radius, thickness ->
+    let offset = vec (radius * {scale}) (thickness - {index})
+    let tint = rgba ({index} / 256) 0.5 0.25 1
+    if (radius > {index})
+        {callee} (position + offset) (radius - 1) thickness
+    else
+        stroke thickness tint (position + offset)
+"#
+    )
+}
+
+/// Generates a brush that's as long as possible, along with a copy with its first def edited, so
+/// that every def after it has to be moved.
+fn brushes() -> (String, String) {
+    let result = "\nshape0 (vec 0 0) 8 2\n";
+    let mut defs = Vec::new();
+    let mut len = result.len();
+    loop {
+        let def = def(defs.len(), 2);
+        if len + def.len() > MAX_SOURCE_CODE_LEN {
+            break;
+        }
+        len += def.len();
+        defs.push(def);
+    }
+    // Each def calls the one before it, so the result must call the last one.
+    let result = format!("\nshape{} (vec 0 0) 8 2\n", defs.len() - 1);
+
+    let original = defs.concat() + &result;
+    defs[0] = def(0, 3);
+    let edited = defs.concat() + &result;
+    (original, edited)
+}
+
+fn compile_64k(c: &mut Criterion) {
+    let system = System::new(1);
+    let (original, edited) = brushes();
+    let original = parse(&original);
+    let edited = parse(&edited);
+
+    let compiled = compile(&system, &original, None);
+    eprintln!(
+        "compile_64k: {} bytes of source code compile to {} bytes of bytecode",
+        original.code.len(),
+        compiled.chunk.bytecode.len()
+    );
+
+    c.bench_function("compile_64k/full", |b| {
+        b.iter(|| compile(&system, black_box(&original), None))
+    });
+
+    let mut def_cache = DefCache::new();
+    compile(&system, &original, Some(&mut def_cache));
+    c.bench_function("compile_64k/incremental_unchanged", |b| {
+        b.iter(|| compile(&system, black_box(&original), Some(&mut def_cache)))
+    });
+
+    // Alternate between the two versions, so that the first def is always different from the
+    // last compilation. The cache was last filled from the original, so start with the edit.
+    let mut versions = [&edited, &original].into_iter().cycle();
+    c.bench_function("compile_64k/incremental_edited", |b| {
+        b.iter(|| {
+            let parsed = versions.next().unwrap();
+            compile(&system, black_box(parsed), Some(&mut def_cache))
+        })
+    });
+    assert_eq!(def_cache.stats().compiled, 1);
+}
+
+criterion_group!(benches, compile_64k);
+criterion_main!(benches);
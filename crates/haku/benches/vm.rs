@@ -0,0 +1,106 @@
+//! Benchmarks for running brushes on the VM.
+//!
+//! Run with `cargo bench -p haku`. Besides timings, this prints how much of the VM's heap memory
+//! each brush uses up, since the size of values directly affects how much a brush can do before
+//! running into the memory limit.
+
+use std::hint::black_box;
+
+use common::{compile, parse};
+use criterion::{criterion_group, criterion_main, Criterion};
+use haku::{
+    system::System,
+    value::{Closure, Ref, RefId},
+    vm::{Vm, VmLimits},
+};
+
+mod common;
+
+// Same as the defaults in rkgk.toml.
+const LIMITS: VmLimits = VmLimits {
+    stack_capacity: 1024,
+    call_stack_capacity: 256,
+    ref_capacity: 2048,
+    fuel: 65536,
+    memory: 1048576,
+    strict_math: false,
+};
+
+struct Brush {
+    system: System,
+    vm: Vm,
+    closure: RefId,
+}
+
+fn load(code: &str) -> Brush {
+    let mut system = System::new(1);
+    let compiled = compile(&system, &parse(code), None);
+    let chunk_id = system
+        .add_chunk(compiled.chunk, &compiled.defs, compiled.spec)
+        .unwrap();
+    let mut vm = Vm::new(&compiled.defs, &LIMITS);
+    let closure = vm
+        .create_ref(Ref::Closure(Closure::chunk(chunk_id, compiled.spec)))
+        .unwrap();
+
+    Brush {
+        system,
+        vm,
+        closure,
+    }
+}
+
+fn bench_brush(c: &mut Criterion, name: &str, code: &str) {
+    let mut brush = load(code);
+    let image = brush.vm.image();
+
+    brush.vm.run(&brush.system, brush.closure).unwrap();
+    let used_memory = LIMITS.memory - brush.vm.remaining_memory();
+    let used_fuel = LIMITS.fuel - brush.vm.remaining_fuel();
+    eprintln!("{name}: uses {used_memory} bytes of heap memory and {used_fuel} fuel");
+    brush.vm.restore_image(&image);
+
+    c.bench_function(name, |b| {
+        b.iter(|| {
+            let result = brush.vm.run(&brush.system, black_box(brush.closure));
+            brush.vm.restore_image(&image);
+            result
+        })
+    });
+}
+
+fn brushes(c: &mut Criterion) {
+    // Lots of arithmetic on vectors, close to running out of fuel.
+    // There are no tail calls, so this recurses twice to stay within the call stack's capacity.
+    bench_brush(
+        c,
+        "vec_arithmetic",
+        r#"
+            walk = \p, n ->
+                if (n > 0)
+                    walk (walk (vec (vecX p + 1) (vecY p * 0.5)) (n - 1)) (n - 1)
+                else
+                    p
+
+            stroke 8 #000 (walk (vec 0 0) 11)
+        "#,
+    );
+
+    // Builds up a tree of lists, which puts pressure on the memory limit.
+    bench_brush(
+        c,
+        "lists",
+        r#"
+            tree = \n ->
+                if (n > 0)
+                    [tree (n - 1), tree (n - 1), n, vec n n]
+                else
+                    []
+
+            tree 9
+        "#,
+    );
+}
+
+criterion_group!(benches, brushes);
+criterion_main!(benches);
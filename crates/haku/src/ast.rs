@@ -2,13 +2,15 @@ use core::{error::Error, fmt::Display};
 
 use alloc::vec::Vec;
 
-use crate::source::Span;
+use crate::{source::Span, token::Trivia};
 
 pub mod dump;
+pub mod print;
+pub mod scope;
 pub mod walk;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub struct NodeId(u32);
+pub struct NodeId(pub(crate) u32);
 
 impl NodeId {
     pub const NIL: NodeId = NodeId(0);
@@ -37,6 +39,8 @@ pub enum NodeKind {
     Param,
     If,
     Let,
+    Import,
+    ParamDecl,
 
     Toplevel,
 
@@ -55,6 +59,9 @@ pub struct Ast {
     spans: Vec<Span>,
     children_spans: Vec<(u32, u32)>,
     children: Vec<NodeId>,
+    // Only filled in for nodes with trivia attached, so that ASTs without trivia don't pay for it.
+    trivia_spans: Vec<(u32, u32)>,
+    trivia: Vec<Trivia>,
 }
 
 impl Ast {
@@ -67,6 +74,8 @@ impl Ast {
             spans: Vec::with_capacity(capacity),
             children_spans: Vec::with_capacity(capacity),
             children: Vec::new(),
+            trivia_spans: Vec::new(),
+            trivia: Vec::new(),
         };
 
         ast.alloc(NodeKind::Nil, Span::new(0, 0)).unwrap();
@@ -103,8 +112,22 @@ impl Ast {
         self.children_spans[for_node.0 as usize] = (start as u32, end as u32);
     }
 
+    pub fn attach_trivia(&mut self, to_node: NodeId, trivia: &[Trivia]) {
+        let index = to_node.0 as usize;
+        if index >= self.trivia_spans.len() {
+            self.trivia_spans.resize(index + 1, (0, 0));
+        }
+        let start = self.trivia.len();
+        self.trivia.extend_from_slice(trivia);
+        let end = self.trivia.len();
+        self.trivia_spans[index] = (start as u32, end as u32);
+    }
+
     pub fn extend_span(&mut self, in_node: NodeId, end: u32) {
-        self.spans[in_node.0 as usize].end = end;
+        let span = &mut self.spans[in_node.0 as usize];
+        // Nodes without any tokens in them (which can appear during error recovery) end where
+        // they start, instead of before it.
+        span.end = end.max(span.start);
     }
 
     pub fn kind(&self, id: NodeId) -> NodeKind {
@@ -119,6 +142,20 @@ impl Ast {
         let (start, end) = self.children_spans[id.0 as usize];
         &self.children[start as usize..end as usize]
     }
+
+    /// Returns the trivia attached to a node, which is only present if the AST was produced from
+    /// a [`Lexis`][crate::token::Lexis] with trivia recorded.
+    ///
+    /// Token nodes have the trivia preceding the token attached. The root node has the trivia
+    /// following the last token in the source code attached.
+    pub fn trivia(&self, id: NodeId) -> &[Trivia] {
+        let (start, end) = self
+            .trivia_spans
+            .get(id.0 as usize)
+            .copied()
+            .unwrap_or((0, 0));
+        &self.trivia[start as usize..end as usize]
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -0,0 +1,34 @@
+use alloc::string::String;
+
+use crate::source::SourceCode;
+
+use super::{Ast, NodeId, NodeKind};
+
+/// Prints the AST back into source code.
+///
+/// If the AST was produced with trivia recorded (see [`Lexis::with_trivia`]), this reproduces
+/// the original source code exactly. Otherwise, tokens are printed without anything in between.
+///
+/// [`Lexis::with_trivia`]: crate::token::Lexis::with_trivia
+pub fn print(ast: &Ast, root: NodeId, code: &SourceCode) -> String {
+    let mut result = String::new();
+
+    fn rec(ast: &Ast, node: NodeId, code: &SourceCode, result: &mut String) {
+        if ast.kind(node) == NodeKind::Token {
+            for trivia in ast.trivia(node) {
+                result.push_str(trivia.span.slice(code));
+            }
+            result.push_str(ast.span(node).slice(code));
+        }
+        for &child in ast.children(node) {
+            rec(ast, child, code, result);
+        }
+    }
+
+    rec(ast, root, code, &mut result);
+    for trivia in ast.trivia(root) {
+        result.push_str(trivia.span.slice(code));
+    }
+
+    result
+}
@@ -0,0 +1,68 @@
+//! Tracking which variables are visible while walking the AST.
+
+use alloc::vec::Vec;
+
+/// Variables visible at the current point of a walk, innermost last.
+///
+/// Analyses which resolve names the same way the compiler does push variables as they enter
+/// lambdas and `let`s, and truncate them away as they leave. Each variable carries a value of
+/// type `T` with whatever the analysis needs to know about it.
+pub struct Scopes<'a, T> {
+    vars: Vec<(&'a str, T)>,
+}
+
+impl<'a, T> Scopes<'a, T> {
+    pub fn new() -> Self {
+        Self { vars: Vec::new() }
+    }
+
+    pub fn push(&mut self, name: &'a str, value: T) {
+        self.vars.push((name, value));
+    }
+
+    pub fn pop(&mut self) -> Option<(&'a str, T)> {
+        self.vars.pop()
+    }
+
+    /// The number of visible variables, which can be [`truncate`][Self::truncate]d back to when
+    /// leaving a scope.
+    pub fn len(&self) -> usize {
+        self.vars.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vars.is_empty()
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.vars.truncate(len);
+    }
+
+    /// Looks up the innermost variable with the given name.
+    pub fn get(&self, name: &str) -> Option<&T> {
+        self.vars
+            .iter()
+            .rev()
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Looks up the innermost variable with the given name.
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut T> {
+        self.vars
+            .iter_mut()
+            .rev()
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+}
+
+impl<T> Default for Scopes<'_, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
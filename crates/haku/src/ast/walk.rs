@@ -1,3 +1,5 @@
+use crate::source::SourceCode;
+
 use super::{Ast, NodeId, NodeKind};
 
 impl Ast {
@@ -15,6 +17,38 @@ impl Ast {
             index: 0,
         }
     }
+
+    /// Returns whether the node is a def, that is, a binary `=` expression.
+    ///
+    /// The left-hand side of a def is not guaranteed to be an identifier; the compiler reports an
+    /// error if it's not. Use [`def_ident`][Self::def_ident] to only accept well-formed defs.
+    pub fn is_def(&self, code: &SourceCode, node_id: NodeId) -> bool {
+        self.kind(node_id) == NodeKind::Binary
+            && self
+                .walk(node_id)
+                .get(NodeKind::Op)
+                .is_some_and(|op| self.span(op).slice(code) == "=")
+    }
+
+    /// Returns the identifier a def defines, if the node is a def.
+    pub fn def_ident(&self, code: &SourceCode, node_id: NodeId) -> Option<NodeId> {
+        if !self.is_def(code, node_id) {
+            return None;
+        }
+        self.walk(node_id)
+            .node()
+            .filter(|&ident| self.kind(ident) == NodeKind::Ident)
+    }
+
+    /// Returns the identifier a param declares, if the node is a param declaration.
+    pub fn param_ident(&self, node_id: NodeId) -> Option<NodeId> {
+        if self.kind(node_id) != NodeKind::ParamDecl {
+            return None;
+        }
+        self.walk(node_id)
+            .node()
+            .filter(|&ident| self.kind(ident) == NodeKind::Ident)
+    }
 }
 
 /// An iterator over a node's children, with convenience methods for accessing those children.
@@ -5,6 +5,10 @@ use core::{
 
 use alloc::{borrow::ToOwned, string::String, vec::Vec};
 
+use crate::snapshot::{Reader, SnapshotError, Writer};
+
+pub mod verify;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(u8)]
 pub enum Opcode {
@@ -14,6 +18,9 @@ pub enum Opcode {
     True,
     Number, // (float: f32)
     Rgba,   // (r: u8, g: u8, b: u8, a: u8)
+    /// Push a vec. Only components whose bit is set in `mask` are stored; the rest are zero.
+    /// This is only emitted by the compiler as a result of constant folding.
+    Vec4, // (mask: u8, components: [f32; popcount(mask)])
 
     // Duplicate existing values.
     /// Push a value relative to the bottom of the current stack window.
@@ -42,6 +49,10 @@ pub enum Opcode {
     /// This is a fast path for system calls, which are quite common (e.g. basic arithmetic.)
     System, // (index: u8, argc: u8)
 
+    /// Marks where the per-point part of toplevel code starts. When evaluating only the
+    /// stroke-constant part, this returns nil; otherwise it does nothing. See [`crate::stroke`].
+    PointStart,
+
     Return,
     // NOTE: There must be no more opcodes after this.
     // They will get treated as invalid.
@@ -56,9 +67,15 @@ pub struct Chunk {
     pub bytecode: Vec<u8>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Offset(u16);
 
+impl Offset {
+    pub fn to_u16(self) -> u16 {
+        self.0
+    }
+}
+
 impl Chunk {
     pub fn new(capacity: usize) -> Result<Chunk, ChunkSizeError> {
         if capacity <= (1 << 16) {
@@ -120,8 +137,14 @@ impl Chunk {
         self.patch_u16(offset, x.0);
     }
 
+    /// Remove all bytecode starting at the given offset.
+    pub fn truncate(&mut self, offset: Offset) {
+        self.bytecode.truncate(offset.0 as usize);
+    }
+
     // NOTE: I'm aware these aren't the fastest implementations since they validate quite a lot
     // during runtime, but this is just an MVP. It doesn't have to be blazingly fast.
+    // Chunks are verified before they're run anyways, so these should never fail in the VM.
 
     pub fn read_u8(&self, pc: &mut usize) -> Result<u8, ReadError> {
         let x = self.bytecode.get(*pc).copied();
@@ -130,21 +153,21 @@ impl Chunk {
     }
 
     pub fn read_u16(&self, pc: &mut usize) -> Result<u16, ReadError> {
-        let xs = &self.bytecode[*pc..*pc + 2];
+        let xs = self.bytecode.get(*pc..*pc + 2).ok_or(ReadError)?;
         *pc += 2;
-        Ok(u16::from_le_bytes(xs.try_into().map_err(|_| ReadError)?))
+        Ok(u16::from_le_bytes(xs.try_into().unwrap()))
     }
 
     pub fn read_u32(&self, pc: &mut usize) -> Result<u32, ReadError> {
-        let xs = &self.bytecode[*pc..*pc + 4];
+        let xs = self.bytecode.get(*pc..*pc + 4).ok_or(ReadError)?;
         *pc += 4;
-        Ok(u32::from_le_bytes(xs.try_into().map_err(|_| ReadError)?))
+        Ok(u32::from_le_bytes(xs.try_into().unwrap()))
     }
 
     pub fn read_f32(&self, pc: &mut usize) -> Result<f32, ReadError> {
-        let xs = &self.bytecode[*pc..*pc + 4];
+        let xs = self.bytecode.get(*pc..*pc + 4).ok_or(ReadError)?;
         *pc += 4;
-        Ok(f32::from_le_bytes(xs.try_into().map_err(|_| ReadError)?))
+        Ok(f32::from_le_bytes(xs.try_into().unwrap()))
     }
 
     pub fn read_opcode(&self, pc: &mut usize) -> Result<Opcode, ReadError> {
@@ -193,14 +216,32 @@ impl DefId {
     }
 }
 
+/// A group of defs which can only see each other, and the defs of namespaces they import.
+/// Every module is compiled into its own namespace, so that defs in different modules may share
+/// names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Namespace(u16);
+
 #[derive(Debug, Clone)]
 pub struct Defs {
     defs: Vec<String>,
+    /// The namespace each def belongs to.
+    namespaces: Vec<Namespace>,
+    /// Whether each def is varying, that is its value may differ between points of a stroke.
+    varying: Vec<bool>,
+    /// `(importer, imported)` pairs, in import order.
+    imports: Vec<(Namespace, Namespace)>,
+    namespace_count: u16,
+    /// The namespace new defs are added to, and names are looked up in.
+    current: Namespace,
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct DefsImage {
     defs: usize,
+    imports: usize,
+    namespace_count: u16,
+    current: Namespace,
 }
 
 impl Defs {
@@ -208,9 +249,52 @@ impl Defs {
         assert!(capacity < u16::MAX as usize + 1);
         Self {
             defs: Vec::with_capacity(capacity),
+            namespaces: Vec::with_capacity(capacity),
+            varying: Vec::with_capacity(capacity),
+            imports: Vec::new(),
+            namespace_count: 1,
+            current: Namespace::default(),
         }
     }
 
+    /// Creates a new, empty namespace and makes it current.
+    pub fn begin_namespace(&mut self) -> Namespace {
+        let namespace = Namespace(self.namespace_count);
+        self.namespace_count += 1;
+        self.current = namespace;
+        namespace
+    }
+
+    pub fn current_namespace(&self) -> Namespace {
+        self.current
+    }
+
+    /// Makes the defs of `namespace` visible in the current namespace. Defs in the current
+    /// namespace take priority over imported ones, and earlier imports take priority over later.
+    pub fn import(&mut self, namespace: Namespace) {
+        let import = (self.current, namespace);
+        if namespace != self.current && !self.imports.contains(&import) {
+            self.imports.push(import);
+        }
+    }
+
+    /// Namespaces visible from the current one, in lookup order.
+    fn visible(&self) -> impl Iterator<Item = Namespace> + '_ {
+        let imported = self
+            .imports
+            .iter()
+            .filter(|&&(importer, _)| importer == self.current)
+            .map(|&(_, imported)| imported);
+        [self.current].into_iter().chain(imported)
+    }
+
+    fn find(&self, namespace: Namespace, name: &str) -> Option<usize> {
+        self.defs
+            .iter()
+            .zip(&self.namespaces)
+            .position(|(n, &ns)| ns == namespace && *n == name)
+    }
+
     pub fn len(&self) -> u16 {
         self.defs.len() as u16
     }
@@ -219,15 +303,35 @@ impl Defs {
         self.len() != 0
     }
 
+    /// Looks up a def visible from the current namespace.
     pub fn get(&mut self, name: &str) -> Option<DefId> {
-        self.defs
-            .iter()
-            .position(|n| *n == name)
+        self.visible()
+            .find_map(|namespace| self.find(namespace, name))
             .map(|index| DefId(index as u16))
     }
 
+    /// Returns whether the def with the given ID is called `name` and belongs to the current
+    /// namespace, in which case [`get`][Self::get] would return it for `name`.
+    /// Unlike `get`, this does not have to search through all defs.
+    pub fn is_current(&self, def_id: DefId, name: &str) -> bool {
+        let index = def_id.0 as usize;
+        self.namespaces.get(index) == Some(&self.current) && self.defs[index] == name
+    }
+
+    /// Returns the names of all defs visible from the current namespace.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.visible().flat_map(move |namespace| {
+            self.defs
+                .iter()
+                .zip(&self.namespaces)
+                .filter(move |&(_, &ns)| ns == namespace)
+                .map(|(name, _)| name.as_str())
+        })
+    }
+
+    /// Adds a def to the current namespace.
     pub fn add(&mut self, name: &str) -> Result<DefId, DefError> {
-        if self.defs.iter().any(|n| n == name) {
+        if self.find(self.current, name).is_some() {
             Err(DefError::Exists)
         } else {
             if self.defs.len() >= self.defs.capacity() {
@@ -235,13 +339,34 @@ impl Defs {
             }
             let id = DefId(self.defs.len() as u16);
             self.defs.push(name.to_owned());
+            self.namespaces.push(self.current);
+            self.varying.push(false);
             Ok(id)
         }
     }
 
+    /// Looks up a def in the given namespace, regardless of which namespaces are visible from the
+    /// current one.
+    pub fn get_in(&self, namespace: Namespace, name: &str) -> Option<DefId> {
+        self.find(namespace, name).map(|index| DefId(index as u16))
+    }
+
+    pub fn is_varying(&self, def_id: DefId) -> bool {
+        self.varying[def_id.0 as usize]
+    }
+
+    /// Marks a def as varying. The compiler does this for defs depending on varying defs, and the
+    /// host does this for stroke inputs. See [`crate::stroke`].
+    pub fn set_varying(&mut self, def_id: DefId) {
+        self.varying[def_id.0 as usize] = true;
+    }
+
     pub fn image(&self) -> DefsImage {
         DefsImage {
             defs: self.defs.len(),
+            imports: self.imports.len(),
+            namespace_count: self.namespace_count,
+            current: self.current,
         }
     }
 
@@ -249,6 +374,66 @@ impl Defs {
         self.defs.resize_with(image.defs, || {
             panic!("image must be a subset of the current defs")
         });
+        self.namespaces.truncate(image.defs);
+        self.varying.truncate(image.defs);
+        self.imports.truncate(image.imports);
+        self.namespace_count = image.namespace_count;
+        self.current = image.current;
+    }
+
+    pub(crate) fn write_snapshot(&self, w: &mut Writer) {
+        w.len(self.defs.len());
+        for ((name, namespace), &varying) in
+            self.defs.iter().zip(&self.namespaces).zip(&self.varying)
+        {
+            w.str(name);
+            w.u16(namespace.0);
+            w.u8(varying.into());
+        }
+        w.len(self.imports.len());
+        for (importer, imported) in &self.imports {
+            w.u16(importer.0);
+            w.u16(imported.0);
+        }
+        w.u16(self.namespace_count);
+        w.u16(self.current.0);
+    }
+
+    pub(crate) fn read_snapshot(r: &mut Reader, capacity: usize) -> Result<Self, SnapshotError> {
+        let mut defs = Defs::new(capacity);
+
+        // Namespaces are checked once their count is known, at the end.
+        let len = r.len(capacity)?;
+        for _ in 0..len {
+            defs.defs.push(r.string()?);
+            defs.namespaces.push(Namespace(r.u16()?));
+            defs.varying.push(match r.u8()? {
+                0 => false,
+                1 => true,
+                _ => return Err(SnapshotError::Invalid),
+            });
+        }
+        let len = r.len(usize::MAX)?;
+        for _ in 0..len {
+            let importer = Namespace(r.u16()?);
+            let imported = Namespace(r.u16()?);
+            defs.imports.push((importer, imported));
+        }
+        defs.namespace_count = r.u16()?;
+        defs.current = Namespace(r.u16()?);
+
+        let is_valid = |namespace: &Namespace| namespace.0 < defs.namespace_count;
+        if !(defs.namespaces.iter().all(is_valid)
+            && defs
+                .imports
+                .iter()
+                .all(|(importer, imported)| is_valid(importer) && is_valid(imported))
+            && is_valid(&defs.current))
+        {
+            return Err(SnapshotError::Invalid);
+        }
+
+        Ok(defs)
     }
 }
 
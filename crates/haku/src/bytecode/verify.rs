@@ -0,0 +1,454 @@
+//! Static verification of bytecode chunks.
+//!
+//! Chunks are verified once, when they're added to a [`System`]. After that, the VM is free to
+//! assume that all instructions are well-formed: every opcode and operand is in bounds, jumps land
+//! on instruction boundaries, local, capture, def, and system function indices are valid, and the
+//! value stack never underflows.
+
+use core::{
+    error::Error,
+    fmt::{self, Display},
+};
+
+use alloc::vec::Vec;
+
+use crate::{compiler::ClosureSpec, system::System};
+
+use super::{Chunk, Defs, Opcode, CAPTURE_CAPTURE, CAPTURE_LOCAL};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyErrorKind {
+    InvalidOpcode,
+    InvalidVecMask,
+    Truncated,
+    InvalidFunction,
+    InvalidCaptureKind,
+    InvalidJumpTarget,
+    LocalOutOfBounds,
+    CaptureOutOfBounds,
+    DefOutOfBounds,
+    InvalidSystemFn,
+    StackUnderflow,
+    StackMismatch,
+    ReturnStackMismatch,
+    MissingReturn,
+    InvalidPointStart,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifyError {
+    /// Offset of the instruction that failed verification.
+    pub offset: usize,
+    pub kind: VerifyErrorKind,
+}
+
+impl Display for VerifyErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            VerifyErrorKind::InvalidOpcode => "invalid opcode",
+            VerifyErrorKind::InvalidVecMask => "invalid vec component mask",
+            VerifyErrorKind::Truncated => "instruction is cut off by the end of its function",
+            VerifyErrorKind::InvalidFunction => "function body is out of bounds",
+            VerifyErrorKind::InvalidCaptureKind => "invalid capture kind",
+            VerifyErrorKind::InvalidJumpTarget => "jump does not land on an instruction",
+            VerifyErrorKind::LocalOutOfBounds => "local variable index out of bounds",
+            VerifyErrorKind::CaptureOutOfBounds => "capture index out of bounds",
+            VerifyErrorKind::DefOutOfBounds => "def index out of bounds",
+            VerifyErrorKind::InvalidSystemFn => "invalid system function index",
+            VerifyErrorKind::StackUnderflow => "value stack underflow",
+            VerifyErrorKind::StackMismatch => {
+                "value stack height differs between paths reaching this instruction"
+            }
+            VerifyErrorKind::ReturnStackMismatch => {
+                "value stack must contain exactly one value when returning"
+            }
+            VerifyErrorKind::MissingReturn => "execution can run past the end of a function",
+            VerifyErrorKind::InvalidPointStart => {
+                "per-point code must start at the toplevel, with an empty value stack"
+            }
+        })
+    }
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid bytecode at offset {}: {}", self.offset, self.kind)
+    }
+}
+
+impl Error for VerifyError {}
+
+/// A place in a chunk where closures can start running code, and what closures starting there look
+/// like. These are collected during verification, so that closures which weren't created by the
+/// chunk's own code (such as ones read from VM snapshots) can be checked against them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    pub offset: u16,
+    pub param_count: u8,
+    pub local_count: u8,
+    pub capture_count: u8,
+}
+
+/// A function whose body still needs to be verified.
+#[derive(Debug, Clone, Copy)]
+struct Function {
+    start: usize,
+    end: usize,
+    params: usize,
+    /// Number of parameters and locals. These all live at the bottom of the function's stack
+    /// window.
+    locals: usize,
+    captures: usize,
+}
+
+impl Function {
+    fn entry(&self, offset: usize) -> Entry {
+        // Offsets fit in a u16 because chunks can't be larger than that, and counts fit in a u8
+        // because that's how they're encoded in bytecode.
+        Entry {
+            offset: offset as u16,
+            param_count: self.params as u8,
+            local_count: (self.locals - self.params) as u8,
+            capture_count: self.captures as u8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Flow {
+    Next,
+    Jump(usize),
+    Branch(usize),
+    /// Continues to the next instruction, but returns nil in stroke evaluation mode.
+    PointStart,
+    Return,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Instruction {
+    offset: usize,
+    pops: u32,
+    pushes: u32,
+    flow: Flow,
+}
+
+struct Verifier<'a> {
+    system: &'a System,
+    defs: &'a Defs,
+    chunk: &'a Chunk,
+    functions: Vec<Function>,
+}
+
+/// Verify a chunk, whose toplevel code is described by the given closure spec.
+/// Returns the chunk's entries, sorted by offset.
+///
+/// `defs` must contain all defs that the chunk refers to.
+pub fn verify(
+    system: &System,
+    defs: &Defs,
+    chunk: &Chunk,
+    spec: ClosureSpec,
+) -> Result<Vec<Entry>, VerifyError> {
+    let mut v = Verifier {
+        system,
+        defs,
+        chunk,
+        functions: Vec::from_iter([Function {
+            start: 0,
+            end: chunk.bytecode.len(),
+            params: 0,
+            locals: spec.local_count as usize,
+            captures: 0,
+        }]),
+    };
+    let mut entries = Vec::new();
+
+    // NOTE: Functions are verified iteratively rather than recursively, because lambdas can be
+    // nested pretty deeply and we don't want to overflow the native stack.
+    while let Some(function) = v.functions.pop() {
+        let instructions = v.decode(function)?;
+        check_flow(function, &instructions)?;
+        entries.push(function.entry(function.start));
+
+        if let (0, Some(point_start)) = (function.start, spec.point_start) {
+            let point_start = point_start as usize;
+            if !instructions
+                .iter()
+                .any(|i| i.offset == point_start && matches!(i.flow, Flow::PointStart))
+            {
+                return Err(Verifier::error(
+                    point_start,
+                    VerifyErrorKind::InvalidPointStart,
+                ));
+            }
+            entries.push(function.entry(point_start));
+        }
+    }
+
+    entries.sort_by_key(|entry| entry.offset);
+    Ok(entries)
+}
+
+impl Verifier<'_> {
+    fn error(offset: usize, kind: VerifyErrorKind) -> VerifyError {
+        VerifyError { offset, kind }
+    }
+
+    fn u8(&self, function: Function, at: usize, pc: &mut usize) -> Result<u8, VerifyError> {
+        if *pc < function.end {
+            let x = self.chunk.bytecode[*pc];
+            *pc += 1;
+            Ok(x)
+        } else {
+            Err(Self::error(at, VerifyErrorKind::Truncated))
+        }
+    }
+
+    fn u16(&self, function: Function, at: usize, pc: &mut usize) -> Result<u16, VerifyError> {
+        let lo = self.u8(function, at, pc)?;
+        let hi = self.u8(function, at, pc)?;
+        Ok(u16::from_le_bytes([lo, hi]))
+    }
+
+    /// Decode all instructions in the function's body, checking their operands along the way.
+    fn decode(&mut self, function: Function) -> Result<Vec<Instruction>, VerifyError> {
+        let mut instructions = Vec::new();
+
+        let mut pc = function.start;
+        while pc < function.end {
+            let at = pc;
+            let opcode = self
+                .chunk
+                .read_opcode(&mut pc)
+                .map_err(|_| Self::error(at, VerifyErrorKind::InvalidOpcode))?;
+
+            let (pops, pushes, flow) = match opcode {
+                Opcode::Nil | Opcode::False | Opcode::True => (0, 1, Flow::Next),
+
+                Opcode::Number | Opcode::Rgba => {
+                    // Both are followed by 4 bytes of data: an f32, or RGBA color channels.
+                    for _ in 0..4 {
+                        self.u8(function, at, &mut pc)?;
+                    }
+                    (0, 1, Flow::Next)
+                }
+
+                Opcode::Vec4 => {
+                    let mask = self.u8(function, at, &mut pc)?;
+                    if mask > 0b1111 {
+                        return Err(Self::error(at, VerifyErrorKind::InvalidVecMask));
+                    }
+                    for _ in 0..mask.count_ones() * 4 {
+                        self.u8(function, at, &mut pc)?;
+                    }
+                    (0, 1, Flow::Next)
+                }
+
+                Opcode::Local | Opcode::SetLocal => {
+                    let index = self.u8(function, at, &mut pc)? as usize;
+                    if index >= function.locals {
+                        return Err(Self::error(at, VerifyErrorKind::LocalOutOfBounds));
+                    }
+                    if opcode == Opcode::Local {
+                        (0, 1, Flow::Next)
+                    } else {
+                        (1, 0, Flow::Next)
+                    }
+                }
+
+                Opcode::Capture => {
+                    let index = self.u8(function, at, &mut pc)? as usize;
+                    if index >= function.captures {
+                        return Err(Self::error(at, VerifyErrorKind::CaptureOutOfBounds));
+                    }
+                    (0, 1, Flow::Next)
+                }
+
+                Opcode::Def | Opcode::SetDef => {
+                    let index = self.u16(function, at, &mut pc)?;
+                    if index >= self.defs.len() {
+                        return Err(Self::error(at, VerifyErrorKind::DefOutOfBounds));
+                    }
+                    if opcode == Opcode::Def {
+                        (0, 1, Flow::Next)
+                    } else {
+                        (1, 0, Flow::Next)
+                    }
+                }
+
+                Opcode::List => {
+                    let len = self.u16(function, at, &mut pc)?;
+                    (u32::from(len), 1, Flow::Next)
+                }
+
+                Opcode::Function => {
+                    let param_count = self.u8(function, at, &mut pc)? as usize;
+                    let then = self.u16(function, at, &mut pc)? as usize;
+                    if then <= pc || then >= function.end {
+                        return Err(Self::error(at, VerifyErrorKind::InvalidFunction));
+                    }
+                    let body_start = pc;
+
+                    pc = then;
+                    let local_count = self.u8(function, at, &mut pc)? as usize;
+                    let capture_count = self.u8(function, at, &mut pc)? as usize;
+                    for _ in 0..capture_count {
+                        let kind = self.u8(function, at, &mut pc)?;
+                        let index = self.u8(function, at, &mut pc)? as usize;
+                        match kind {
+                            CAPTURE_LOCAL if index < function.locals => (),
+                            CAPTURE_CAPTURE if index < function.captures => (),
+                            CAPTURE_LOCAL => {
+                                return Err(Self::error(at, VerifyErrorKind::LocalOutOfBounds))
+                            }
+                            CAPTURE_CAPTURE => {
+                                return Err(Self::error(at, VerifyErrorKind::CaptureOutOfBounds))
+                            }
+                            _ => return Err(Self::error(at, VerifyErrorKind::InvalidCaptureKind)),
+                        }
+                    }
+
+                    self.functions.push(Function {
+                        start: body_start,
+                        end: then,
+                        params: param_count,
+                        locals: param_count + local_count,
+                        captures: capture_count,
+                    });
+
+                    (0, 1, Flow::Next)
+                }
+
+                Opcode::Jump => {
+                    let offset = self.u16(function, at, &mut pc)?;
+                    (0, 0, Flow::Jump(offset as usize))
+                }
+
+                Opcode::JumpIfNot => {
+                    let offset = self.u16(function, at, &mut pc)?;
+                    (1, 0, Flow::Branch(offset as usize))
+                }
+
+                Opcode::Call => {
+                    let argument_count = self.u8(function, at, &mut pc)?;
+                    // +1 for the function itself, which is at the top of the stack.
+                    (u32::from(argument_count) + 1, 1, Flow::Next)
+                }
+
+                Opcode::System => {
+                    let index = self.u8(function, at, &mut pc)? as usize;
+                    let argument_count = self.u8(function, at, &mut pc)?;
+                    if self.system.fns[index].is_none() {
+                        return Err(Self::error(at, VerifyErrorKind::InvalidSystemFn));
+                    }
+                    (u32::from(argument_count), 1, Flow::Next)
+                }
+
+                Opcode::PointStart => {
+                    // Only toplevel code, which starts at the beginning of the chunk, is split
+                    // into parts.
+                    if function.start != 0 {
+                        return Err(Self::error(at, VerifyErrorKind::InvalidPointStart));
+                    }
+                    (0, 0, Flow::PointStart)
+                }
+
+                Opcode::Return => (1, 0, Flow::Return),
+            };
+
+            instructions.push(Instruction {
+                offset: at,
+                pops,
+                pushes,
+                flow,
+            });
+        }
+
+        Ok(instructions)
+    }
+}
+
+/// Check that all jumps land on instructions, and that the value stack is balanced along all
+/// paths through the function.
+fn check_flow(function: Function, instructions: &[Instruction]) -> Result<(), VerifyError> {
+    if instructions.is_empty() {
+        return Err(Verifier::error(
+            function.start,
+            VerifyErrorKind::MissingReturn,
+        ));
+    }
+
+    let find = |at: usize, target: usize| {
+        instructions
+            .binary_search_by_key(&target, |i| i.offset)
+            .map_err(|_| Verifier::error(at, VerifyErrorKind::InvalidJumpTarget))
+    };
+
+    // Stack heights are counted relative to the top of the function's locals.
+    let mut heights: Vec<Option<u32>> = Vec::from_iter(instructions.iter().map(|_| None));
+    let mut worklist = Vec::from_iter([0]);
+    heights[0] = Some(0);
+
+    while let Some(index) = worklist.pop() {
+        let instruction = instructions[index];
+        let at = instruction.offset;
+        let height = heights[index].unwrap();
+        let height = height
+            .checked_sub(instruction.pops)
+            .ok_or(Verifier::error(at, VerifyErrorKind::StackUnderflow))?
+            + instruction.pushes;
+
+        let next = || {
+            if index + 1 < instructions.len() {
+                Ok(index + 1)
+            } else {
+                Err(Verifier::error(at, VerifyErrorKind::MissingReturn))
+            }
+        };
+
+        match instruction.flow {
+            Flow::Next => enter(&mut heights, &mut worklist, at, next()?, height)?,
+            Flow::Jump(target) => {
+                enter(&mut heights, &mut worklist, at, find(at, target)?, height)?
+            }
+            Flow::Branch(target) => {
+                enter(&mut heights, &mut worklist, at, next()?, height)?;
+                enter(&mut heights, &mut worklist, at, find(at, target)?, height)?;
+            }
+            Flow::PointStart => {
+                if height != 0 {
+                    return Err(Verifier::error(at, VerifyErrorKind::InvalidPointStart));
+                }
+                enter(&mut heights, &mut worklist, at, next()?, height)?
+            }
+            Flow::Return => {
+                if height != 0 {
+                    return Err(Verifier::error(at, VerifyErrorKind::ReturnStackMismatch));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn enter(
+    heights: &mut [Option<u32>],
+    worklist: &mut Vec<usize>,
+    at: usize,
+    index: usize,
+    height: u32,
+) -> Result<(), VerifyError> {
+    match heights[index] {
+        None => {
+            heights[index] = Some(height);
+            worklist.push(index);
+            Ok(())
+        }
+        Some(h) if h == height => Ok(()),
+        Some(_) => Err(Verifier::error(at, VerifyErrorKind::StackMismatch)),
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,375 @@
+use crate::{
+    bytecode::{Chunk, Defs, Opcode, CAPTURE_LOCAL},
+    compiler::ClosureSpec,
+    system::System,
+};
+
+use super::{verify, Entry, VerifyError, VerifyErrorKind};
+
+fn chunk(f: impl FnOnce(&mut Chunk)) -> Chunk {
+    let mut chunk = Chunk::new(1024).unwrap();
+    f(&mut chunk);
+    chunk
+}
+
+fn check(chunk: &Chunk, local_count: u8) -> Result<(), VerifyError> {
+    check_split(chunk, local_count, None)
+}
+
+fn check_split(
+    chunk: &Chunk,
+    local_count: u8,
+    point_start: Option<u16>,
+) -> Result<(), VerifyError> {
+    let system = System::new(1);
+    let mut defs = Defs::new(4);
+    defs.add("x").unwrap();
+    verify(
+        &system,
+        &defs,
+        chunk,
+        ClosureSpec {
+            local_count,
+            point_start,
+        },
+    )
+    .map(|_| ())
+}
+
+#[track_caller]
+fn assert_fails(chunk: &Chunk, local_count: u8, offset: usize, kind: VerifyErrorKind) {
+    assert_eq!(
+        check(chunk, local_count),
+        Err(VerifyError { offset, kind }),
+        "bytecode: {:?}",
+        chunk.bytecode
+    );
+}
+
+#[test]
+fn valid() {
+    // x = 1
+    // \y -> if (y) x else let z = 2
+    //                     z
+    let c = chunk(|c| {
+        c.emit_opcode(Opcode::Number).unwrap();
+        c.emit_f32(1.0).unwrap();
+        c.emit_opcode(Opcode::SetDef).unwrap();
+        c.emit_u16(0).unwrap();
+        c.emit_opcode(Opcode::Function).unwrap();
+        c.emit_u8(1).unwrap();
+        let then = c.emit_u16(0).unwrap();
+        c.emit_opcode(Opcode::Local).unwrap();
+        c.emit_u8(0).unwrap();
+        c.emit_opcode(Opcode::JumpIfNot).unwrap();
+        let if_false = c.emit_u16(0).unwrap();
+        c.emit_opcode(Opcode::Def).unwrap();
+        c.emit_u16(0).unwrap();
+        c.emit_opcode(Opcode::Jump).unwrap();
+        let end = c.emit_u16(0).unwrap();
+        let if_false_target = c.offset();
+        c.emit_opcode(Opcode::Number).unwrap();
+        c.emit_f32(2.0).unwrap();
+        c.emit_opcode(Opcode::SetLocal).unwrap();
+        c.emit_u8(1).unwrap();
+        c.emit_opcode(Opcode::Local).unwrap();
+        c.emit_u8(1).unwrap();
+        let end_target = c.emit_opcode(Opcode::Return).unwrap();
+        let then_target = c.offset();
+        c.emit_u8(1).unwrap(); // local_count
+        c.emit_u8(0).unwrap(); // capture_count
+        c.emit_opcode(Opcode::Return).unwrap();
+
+        c.patch_offset(then, then_target);
+        c.patch_offset(if_false, if_false_target);
+        c.patch_offset(end, end_target);
+    });
+    assert_eq!(check(&c, 0), Ok(()));
+}
+
+#[test]
+fn empty() {
+    assert_fails(&chunk(|_| ()), 0, 0, VerifyErrorKind::MissingReturn);
+}
+
+#[test]
+fn invalid_opcode() {
+    let c = chunk(|c| {
+        c.emit_u8(0xFF).unwrap();
+    });
+    assert_fails(&c, 0, 0, VerifyErrorKind::InvalidOpcode);
+}
+
+#[test]
+fn truncated() {
+    let c = chunk(|c| {
+        c.emit_opcode(Opcode::Number).unwrap();
+        c.emit_u16(0).unwrap();
+    });
+    assert_fails(&c, 0, 0, VerifyErrorKind::Truncated);
+}
+
+#[test]
+fn missing_return() {
+    let c = chunk(|c| {
+        c.emit_opcode(Opcode::Nil).unwrap();
+    });
+    assert_fails(&c, 0, 0, VerifyErrorKind::MissingReturn);
+}
+
+#[test]
+fn stack_underflow() {
+    let c = chunk(|c| {
+        c.emit_opcode(Opcode::Nil).unwrap();
+        c.emit_opcode(Opcode::List).unwrap();
+        c.emit_u16(2).unwrap();
+        c.emit_opcode(Opcode::Return).unwrap();
+    });
+    assert_fails(&c, 0, 1, VerifyErrorKind::StackUnderflow);
+}
+
+#[test]
+fn return_stack_mismatch() {
+    let c = chunk(|c| {
+        c.emit_opcode(Opcode::Nil).unwrap();
+        c.emit_opcode(Opcode::Nil).unwrap();
+        c.emit_opcode(Opcode::Return).unwrap();
+    });
+    assert_fails(&c, 0, 2, VerifyErrorKind::ReturnStackMismatch);
+}
+
+#[test]
+fn local_out_of_bounds() {
+    let c = chunk(|c| {
+        c.emit_opcode(Opcode::Local).unwrap();
+        c.emit_u8(1).unwrap();
+        c.emit_opcode(Opcode::Return).unwrap();
+    });
+    assert_fails(&c, 1, 0, VerifyErrorKind::LocalOutOfBounds);
+    assert_eq!(check(&c, 2), Ok(()));
+}
+
+#[test]
+fn capture_out_of_bounds() {
+    let c = chunk(|c| {
+        c.emit_opcode(Opcode::Capture).unwrap();
+        c.emit_u8(0).unwrap();
+        c.emit_opcode(Opcode::Return).unwrap();
+    });
+    assert_fails(&c, 0, 0, VerifyErrorKind::CaptureOutOfBounds);
+}
+
+#[test]
+fn captured_local_out_of_bounds() {
+    let c = chunk(|c| {
+        c.emit_opcode(Opcode::Function).unwrap();
+        c.emit_u8(0).unwrap();
+        c.emit_u16(7).unwrap();
+        c.emit_opcode(Opcode::Capture).unwrap();
+        c.emit_u8(0).unwrap();
+        c.emit_opcode(Opcode::Return).unwrap();
+        c.emit_u8(0).unwrap();
+        c.emit_u8(1).unwrap();
+        c.emit_u8(CAPTURE_LOCAL).unwrap();
+        c.emit_u8(3).unwrap();
+        c.emit_opcode(Opcode::Return).unwrap();
+    });
+    assert_fails(&c, 3, 0, VerifyErrorKind::LocalOutOfBounds);
+    assert_eq!(check(&c, 4), Ok(()));
+}
+
+#[test]
+fn def_out_of_bounds() {
+    let c = chunk(|c| {
+        c.emit_opcode(Opcode::Def).unwrap();
+        c.emit_u16(1).unwrap();
+        c.emit_opcode(Opcode::Return).unwrap();
+    });
+    assert_fails(&c, 0, 0, VerifyErrorKind::DefOutOfBounds);
+}
+
+#[test]
+fn invalid_system_fn() {
+    let c = chunk(|c| {
+        c.emit_opcode(Opcode::System).unwrap();
+        c.emit_u8(0xFF).unwrap();
+        c.emit_u8(0).unwrap();
+        c.emit_opcode(Opcode::Return).unwrap();
+    });
+    assert_fails(&c, 0, 0, VerifyErrorKind::InvalidSystemFn);
+}
+
+#[test]
+fn jump_into_operand() {
+    let c = chunk(|c| {
+        c.emit_opcode(Opcode::Jump).unwrap();
+        c.emit_u16(4).unwrap();
+        c.emit_opcode(Opcode::Number).unwrap();
+        c.emit_f32(1.0).unwrap();
+        c.emit_opcode(Opcode::Return).unwrap();
+    });
+    assert_fails(&c, 0, 0, VerifyErrorKind::InvalidJumpTarget);
+}
+
+#[test]
+fn jump_out_of_function() {
+    let c = chunk(|c| {
+        c.emit_opcode(Opcode::Function).unwrap();
+        c.emit_u8(0).unwrap();
+        c.emit_u16(8).unwrap();
+        c.emit_opcode(Opcode::Jump).unwrap();
+        c.emit_u16(10).unwrap();
+        c.emit_opcode(Opcode::Return).unwrap();
+        c.emit_u8(0).unwrap();
+        c.emit_u8(0).unwrap();
+        c.emit_opcode(Opcode::Return).unwrap();
+    });
+    assert_fails(&c, 0, 4, VerifyErrorKind::InvalidJumpTarget);
+}
+
+#[test]
+fn stack_mismatch() {
+    let c = chunk(|c| {
+        c.emit_opcode(Opcode::Nil).unwrap();
+        c.emit_opcode(Opcode::True).unwrap();
+        c.emit_opcode(Opcode::JumpIfNot).unwrap();
+        c.emit_u16(6).unwrap();
+        c.emit_opcode(Opcode::Nil).unwrap();
+        c.emit_opcode(Opcode::Return).unwrap();
+    });
+    assert_fails(&c, 0, 5, VerifyErrorKind::StackMismatch);
+}
+
+#[test]
+fn function_out_of_bounds() {
+    let c = chunk(|c| {
+        c.emit_opcode(Opcode::Function).unwrap();
+        c.emit_u8(0).unwrap();
+        c.emit_u16(100).unwrap();
+        c.emit_opcode(Opcode::Return).unwrap();
+    });
+    assert_fails(&c, 0, 0, VerifyErrorKind::InvalidFunction);
+}
+
+#[test]
+fn vec4() {
+    let c = chunk(|c| {
+        c.emit_opcode(Opcode::Vec4).unwrap();
+        c.emit_u8(0b0101).unwrap();
+        c.emit_f32(1.0).unwrap();
+        c.emit_f32(2.0).unwrap();
+        c.emit_opcode(Opcode::Return).unwrap();
+    });
+    assert_eq!(check(&c, 0), Ok(()));
+
+    let c = chunk(|c| {
+        c.emit_opcode(Opcode::Vec4).unwrap();
+        c.emit_u8(0b10000).unwrap();
+        c.emit_opcode(Opcode::Return).unwrap();
+    });
+    assert_fails(&c, 0, 0, VerifyErrorKind::InvalidVecMask);
+}
+
+#[test]
+fn point_start() {
+    let c = chunk(|c| {
+        c.emit_opcode(Opcode::PointStart).unwrap();
+        c.emit_opcode(Opcode::Nil).unwrap();
+        c.emit_opcode(Opcode::Return).unwrap();
+    });
+    assert_eq!(check_split(&c, 0, Some(0)), Ok(()));
+    assert_eq!(
+        check_split(&c, 0, Some(1)),
+        Err(VerifyError {
+            offset: 1,
+            kind: VerifyErrorKind::InvalidPointStart
+        })
+    );
+}
+
+#[test]
+fn point_start_with_temporaries() {
+    let c = chunk(|c| {
+        c.emit_opcode(Opcode::Nil).unwrap();
+        c.emit_opcode(Opcode::PointStart).unwrap();
+        c.emit_opcode(Opcode::Return).unwrap();
+    });
+    assert_fails(&c, 0, 1, VerifyErrorKind::InvalidPointStart);
+}
+
+#[test]
+fn point_start_in_function() {
+    let c = chunk(|c| {
+        c.emit_opcode(Opcode::Function).unwrap();
+        c.emit_u8(0).unwrap();
+        let then = c.emit_u16(0).unwrap();
+        c.emit_opcode(Opcode::Return).unwrap();
+        let then_target = c.offset();
+        c.emit_u8(0).unwrap(); // local_count
+        c.emit_u8(0).unwrap(); // capture_count
+        c.emit_opcode(Opcode::PointStart).unwrap();
+        c.emit_opcode(Opcode::Nil).unwrap();
+        c.emit_opcode(Opcode::Return).unwrap();
+        c.patch_offset(then, then_target);
+    });
+    assert_fails(&c, 0, 7, VerifyErrorKind::InvalidPointStart);
+}
+
+#[test]
+fn entries() {
+    // f = \y -> y, with a capture of the toplevel's first local
+    // <point start>
+    // ()
+    let (mut body, mut point_start) = (0, 0);
+    let c = chunk(|c| {
+        c.emit_opcode(Opcode::Function).unwrap();
+        c.emit_u8(1).unwrap();
+        let then = c.emit_u16(0).unwrap();
+        body = c.offset().to_u16();
+        c.emit_opcode(Opcode::Local).unwrap();
+        c.emit_u8(0).unwrap();
+        c.emit_opcode(Opcode::Return).unwrap();
+        let then_target = c.offset();
+        c.emit_u8(2).unwrap(); // local_count
+        c.emit_u8(1).unwrap(); // capture_count
+        c.emit_u8(CAPTURE_LOCAL).unwrap();
+        c.emit_u8(0).unwrap();
+        c.emit_opcode(Opcode::SetLocal).unwrap();
+        c.emit_u8(0).unwrap();
+        point_start = c.emit_opcode(Opcode::PointStart).unwrap().to_u16();
+        c.emit_opcode(Opcode::Nil).unwrap();
+        c.emit_opcode(Opcode::Return).unwrap();
+        c.patch_offset(then, then_target);
+    });
+
+    let system = System::new(1);
+    let entries = verify(
+        &system,
+        &Defs::new(1),
+        &c,
+        ClosureSpec {
+            local_count: 1,
+            point_start: Some(point_start),
+        },
+    )
+    .unwrap();
+    let toplevel = |offset| Entry {
+        offset,
+        param_count: 0,
+        local_count: 1,
+        capture_count: 0,
+    };
+    assert_eq!(
+        entries,
+        [
+            toplevel(0),
+            Entry {
+                offset: body,
+                param_count: 1,
+                local_count: 2,
+                capture_count: 1,
+            },
+            toplevel(point_start),
+        ]
+    );
+}
@@ -3,16 +3,27 @@ use core::{
     fmt::{self, Display},
 };
 
-use alloc::vec::Vec;
+use alloc::{borrow::ToOwned, format, vec::Vec};
 
 use crate::{
     ast::{Ast, NodeId, NodeKind},
-    bytecode::{Chunk, DefError, Defs, EmitError, Opcode, CAPTURE_CAPTURE, CAPTURE_LOCAL},
-    diagnostic::Diagnostic,
-    source::SourceCode,
+    bytecode::{
+        Chunk, DefError, DefId, Defs, EmitError, Offset, Opcode, CAPTURE_CAPTURE, CAPTURE_LOCAL,
+    },
+    debug_info::{DebugInfo, FunctionInfo, SpanRange},
+    diagnostic::{similar_name, Diagnostic},
+    param::{Param, ParamKind, ParamValue},
+    source::{SourceCode, Span},
     system::{System, SystemFnArity},
+    value::{Rgba, Value},
+    vm::{Vm, VmLimits},
 };
 
+use self::incremental::DefCache;
+
+pub mod incremental;
+mod varying;
+
 pub struct Source<'a> {
     pub code: &'a SourceCode,
     pub ast: &'a Ast,
@@ -36,16 +47,80 @@ struct Scope<'a> {
     let_count: usize,
 }
 
+/// An operand that has to be adjusted when code is moved to a different place in a chunk, or into
+/// a different chunk. See the [`incremental`] module.
+#[derive(Debug, Clone, Copy)]
+enum Relocation<'a> {
+    /// An absolute offset in the chunk.
+    Offset(Offset),
+    /// The ID of the def with the given name.
+    Def(Offset, &'a str),
+}
+
+impl Relocation<'_> {
+    fn offset(&self) -> Offset {
+        match *self {
+            Relocation::Offset(offset) | Relocation::Def(offset, _) => offset,
+        }
+    }
+}
+
+/// A constant value pushed by the instructions in `start..end`.
+#[derive(Debug, Clone, Copy)]
+struct Constant {
+    start: Offset,
+    end: Offset,
+    value: Value,
+}
+
 pub struct Compiler<'a> {
     pub defs: &'a mut Defs,
     pub chunk: &'a mut Chunk,
     pub diagnostics: Vec<Diagnostic>,
+    /// Brush parameters declared by the program, in source order.
+    pub params: Vec<Param>,
+    /// Cache of defs compiled previously, which speeds up compiling the same program again after
+    /// it's edited.
+    pub def_cache: Option<&'a mut DefCache>,
+    /// Maps the emitted bytecode back to the source code, for debugging.
+    /// This is only collected if it's `Some`, since most compilations don't need it.
+    pub debug_info: Option<DebugInfo>,
     scopes: Vec<Scope<'a>>,
+
+    /// Relocations in the code emitted since the start of the current def.
+    /// These are only collected when there's a `def_cache` to store the def in.
+    relocations: Vec<Relocation<'a>>,
+    /// Names of defs referred to since the start of the current def, including ones in code that
+    /// was removed by constant folding. Only collected when there's a `def_cache`.
+    dependencies: Vec<&'a str>,
+
+    /// Constants pushed by the code at the very end of the chunk, used for constant folding.
+    /// These are always contiguous, so the constants pushed by the last N instructions are the
+    /// last N elements.
+    constants: Vec<Constant>,
+    /// VM used for evaluating system functions during constant folding.
+    /// It cannot allocate any refs, so only functions that produce plain values can be folded.
+    /// Vectors and colors in `constants` are stored inside this VM.
+    fold_vm: Vm,
+
+    /// Where the per-point part of the toplevel code starts, if it was split into parts.
+    point_start: Option<Offset>,
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct ClosureSpec {
     pub(crate) local_count: u8,
+    /// Offset of the [`Opcode::PointStart`] splitting the toplevel code into a stroke-constant
+    /// part and a per-point part, if it's split.
+    pub(crate) point_start: Option<u16>,
+}
+
+impl ClosureSpec {
+    /// Returns whether the toplevel code has to be evaluated separately for each point of a
+    /// stroke. See [`crate::stroke`].
+    pub fn varies_per_point(&self) -> bool {
+        self.point_start.is_some()
+    }
 }
 
 impl<'a> Compiler<'a> {
@@ -54,11 +129,34 @@ impl<'a> Compiler<'a> {
             defs,
             chunk,
             diagnostics: Vec::with_capacity(16),
+            params: Vec::new(),
+            def_cache: None,
+            debug_info: None,
             scopes: Vec::from_iter([Scope {
                 locals: Vec::new(),
                 captures: Vec::new(),
                 let_count: 0,
             }]),
+            relocations: Vec::new(),
+            dependencies: Vec::new(),
+            constants: Vec::new(),
+            fold_vm: Vm::new(
+                &Defs::new(0),
+                &VmLimits {
+                    // Enough to fit the maximum number of arguments to a system function.
+                    stack_capacity: u8::MAX as usize,
+                    call_stack_capacity: 0,
+                    ref_capacity: 0,
+                    fuel: 0,
+                    // Vectors are only created for constants, whose amount is already limited by
+                    // the chunk's capacity.
+                    memory: usize::MAX,
+                    // Calls resulting in NaN or infinity are not folded, so that they're left for
+                    // the VM running the code to deal with, according to its own limits.
+                    strict_math: true,
+                },
+            ),
+            point_start: None,
         }
     }
 
@@ -68,6 +166,18 @@ impl<'a> Compiler<'a> {
         }
     }
 
+    fn relocate(&mut self, relocation: Relocation<'a>) {
+        if self.def_cache.is_some() {
+            self.relocations.push(relocation);
+        }
+    }
+
+    fn depend_on(&mut self, def_name: &'a str) {
+        if self.def_cache.is_some() && !self.dependencies.contains(&def_name) {
+            self.dependencies.push(def_name);
+        }
+    }
+
     pub fn closure_spec(&self) -> ClosureSpec {
         ClosureSpec {
             local_count: self
@@ -78,6 +188,7 @@ impl<'a> Compiler<'a> {
                 .len()
                 .try_into()
                 .unwrap_or_default(),
+            point_start: self.point_start.map(|offset| offset.to_u16()),
         }
     }
 }
@@ -85,6 +196,7 @@ impl<'a> Compiler<'a> {
 type CompileResult<T = ()> = Result<T, CompileError>;
 
 pub fn compile_expr<'a>(c: &mut Compiler<'a>, src: &Source<'a>, node_id: NodeId) -> CompileResult {
+    let start = c.chunk.offset();
     match src.ast.kind(node_id) {
         // The nil node is special, as it inhabits node ID 0.
         NodeKind::Nil => {
@@ -116,20 +228,170 @@ pub fn compile_expr<'a>(c: &mut Compiler<'a>, src: &Source<'a>, node_id: NodeId)
         NodeKind::Let => compile_let(c, src, node_id),
 
         NodeKind::Toplevel => compile_toplevel(c, src, node_id),
+        // Imports are resolved by the host before compilation. See the `module` module.
+        NodeKind::Import => Ok(()),
+        NodeKind::ParamDecl => compile_param_decl(c, src, node_id),
 
         // Error nodes are ignored, because for each error node an appropriate parser
         // diagnostic is emitted anyways.
         NodeKind::Error => Ok(()),
+    }?;
+    record_span(c, start, src.ast.span(node_id));
+    Ok(())
+}
+
+/// Record that the code emitted since `start` belongs to the node spanning `span`.
+fn record_span(c: &mut Compiler, start: Offset, span: Span) {
+    let end = c.chunk.offset();
+    if let (Some(debug_info), true) = (&mut c.debug_info, end > start) {
+        debug_info.spans.push(SpanRange {
+            start: start.to_u16(),
+            end: end.to_u16(),
+            span,
+        });
+    }
+}
+
+fn compile_nil(c: &mut Compiler) -> CompileResult {
+    emit_constant(c, Value::Nil)
+}
+
+fn rgba_bytes(rgba: Rgba) -> Option<[u8; 4]> {
+    let byte = |x: f32| {
+        let byte = libm::roundf(x * 255.0);
+        // NOTE: This must match how the VM decodes Opcode::Rgba exactly.
+        ((0.0..=255.0).contains(&byte) && byte as u8 as f32 / 255.0 == x).then_some(byte as u8)
+    };
+    Some([byte(rgba.r)?, byte(rgba.g)?, byte(rgba.b)?, byte(rgba.a)?])
+}
+
+/// Returns whether the value can be emitted into bytecode using [`emit_constant`].
+fn is_emittable(c: &Compiler, value: Value) -> bool {
+    match value {
+        Value::Nil | Value::False | Value::True | Value::Number(_) | Value::Vec4(_) => true,
+        Value::Rgba(_) => c.fold_vm.get_rgba(value).and_then(rgba_bytes).is_some(),
+        Value::Ref(_) => false,
     }
 }
 
-fn unsupported(c: &mut Compiler, src: &Source, node_id: NodeId, message: &str) -> CompileResult {
-    c.emit(Diagnostic::error(src.ast.span(node_id), message));
+/// Emit an instruction that pushes a constant value, and remember it for constant folding.
+/// The value must be [emittable][is_emittable].
+fn emit_constant(c: &mut Compiler, value: Value) -> CompileResult {
+    let start = c.chunk.offset();
+    match value {
+        Value::Nil => {
+            c.chunk.emit_opcode(Opcode::Nil)?;
+        }
+        Value::False => {
+            c.chunk.emit_opcode(Opcode::False)?;
+        }
+        Value::True => {
+            c.chunk.emit_opcode(Opcode::True)?;
+        }
+        Value::Number(x) => {
+            c.chunk.emit_opcode(Opcode::Number)?;
+            c.chunk.emit_f32(x)?;
+        }
+        Value::Vec4(_) => {
+            let vec = c.fold_vm.get_vec4(value).unwrap();
+            let components = [vec.x, vec.y, vec.z, vec.w];
+            // NOTE: Comparing bits rather than floats, so that -0 is preserved.
+            let mask = components
+                .iter()
+                .enumerate()
+                .fold(0, |mask, (i, x)| mask | (((x.to_bits() != 0) as u8) << i));
+            c.chunk.emit_opcode(Opcode::Vec4)?;
+            c.chunk.emit_u8(mask)?;
+            for x in components {
+                if x.to_bits() != 0 {
+                    c.chunk.emit_f32(x)?;
+                }
+            }
+        }
+        Value::Rgba(_) => {
+            let rgba = c.fold_vm.get_rgba(value).unwrap();
+            let bytes = rgba_bytes(rgba).expect("color must be representable in bytecode");
+            c.chunk.emit_opcode(Opcode::Rgba)?;
+            c.chunk.emit_bytes(&bytes)?;
+        }
+        Value::Ref(_) => unreachable!("refs cannot be emitted as constants"),
+    }
+
+    if c.constants.last().is_some_and(|k| k.end != start) {
+        c.constants.clear();
+    }
+    c.constants.push(Constant {
+        start,
+        end: c.chunk.offset(),
+        value,
+    });
+
     Ok(())
 }
 
-fn compile_nil(c: &mut Compiler) -> CompileResult {
-    c.chunk.emit_opcode(Opcode::Nil)?;
+/// Remove all code emitted since the given offset.
+fn truncate(c: &mut Compiler, offset: Offset) {
+    c.chunk.truncate(offset);
+    c.constants.retain(|k| k.end <= offset);
+    c.relocations.retain(|r| r.offset() < offset);
+    if let Some(debug_info) = &mut c.debug_info {
+        debug_info.truncate(offset.to_u16());
+    }
+}
+
+/// Returns the current offset, to be used as a jump target.
+/// Constants emitted before a jump target cannot be folded together with code after it.
+fn label(c: &mut Compiler) -> Offset {
+    c.constants.clear();
+    c.chunk.offset()
+}
+
+/// Returns the constant pushed by the code emitted since the given offset, if there's exactly one.
+fn constant_since(c: &Compiler, offset: Offset) -> Option<Value> {
+    c.constants
+        .last()
+        .filter(|k| k.start == offset && k.end == c.chunk.offset())
+        .map(|k| k.value)
+}
+
+/// Try to evaluate a system function call at compile time.
+/// This succeeds if all of its arguments are constants at the end of the chunk, and the function
+/// returns an emittable value without raising an exception.
+fn fold_system_call(
+    c: &mut Compiler,
+    system: &System,
+    index: u8,
+    argument_count: u8,
+) -> Option<(Offset, Value)> {
+    let f = system.fns[index as usize]?;
+    let first = c.constants.len().checked_sub(argument_count as usize)?;
+    let args = &c.constants[first..];
+
+    let end = c.chunk.offset();
+    if args.last().is_some_and(|k| k.end != end) {
+        return None;
+    }
+    let start = args.first().map_or(end, |k| k.start);
+
+    let args: Vec<Value> = args.iter().map(|k| k.value).collect();
+    let value = c.fold_vm.call_system_fn(f, &args).ok()?;
+    is_emittable(c, value).then_some((start, value))
+}
+
+fn emit_system_call(
+    c: &mut Compiler,
+    system: &System,
+    index: u8,
+    argument_count: u8,
+) -> CompileResult {
+    if let Some((start, value)) = fold_system_call(c, system, index, argument_count) {
+        truncate(c, start);
+        return emit_constant(c, value);
+    }
+
+    c.chunk.emit_opcode(Opcode::System)?;
+    c.chunk.emit_u8(index)?;
+    c.chunk.emit_u8(argument_count)?;
 
     Ok(())
 }
@@ -185,9 +447,12 @@ fn compile_ident<'a>(c: &mut Compiler<'a>, src: &Source<'a>, node_id: NodeId) ->
         Ok(None) => {
             if let Some(def_id) = c.defs.get(name) {
                 c.chunk.emit_opcode(Opcode::Def)?;
-                c.chunk.emit_u16(def_id.to_u16())?;
+                let operand = c.chunk.emit_u16(def_id.to_u16())?;
+                c.relocate(Relocation::Def(operand, name));
+                c.depend_on(name);
             } else {
-                c.emit(Diagnostic::error(span, "undefined variable"));
+                let diagnostic = undefined_variable(c, src, span);
+                c.emit(diagnostic);
             }
         }
         Err(CaptureError) => {
@@ -201,15 +466,38 @@ fn compile_ident<'a>(c: &mut Compiler<'a>, src: &Source<'a>, node_id: NodeId) ->
     Ok(())
 }
 
+fn undefined_variable(c: &Compiler, src: &Source, span: Span) -> Diagnostic {
+    let name = span.slice(src.code);
+    let locals = c
+        .scopes
+        .iter()
+        .rev()
+        .flat_map(|scope| scope.locals.iter().rev().map(|local| local.name));
+    let system_fns = src
+        .system
+        .names
+        .iter()
+        .filter(|&&(_, arity, _)| arity == SystemFnArity::Nary)
+        .map(|&(_, _, name)| name);
+
+    let diagnostic = Diagnostic::error(span, "undefined variable");
+    match similar_name(name, locals.chain(c.defs.names()).chain(system_fns)) {
+        Some(similar) => {
+            diagnostic.with_suggestion(span, similar, format!("did you mean `{similar}`?"))
+        }
+        None => diagnostic,
+    }
+}
+
 fn compile_tag(c: &mut Compiler, src: &Source, node_id: NodeId) -> CompileResult {
     let tag = src.ast.span(node_id).slice(src.code);
 
     match tag {
         "False" => {
-            c.chunk.emit_opcode(Opcode::False)?;
+            emit_constant(c, Value::False)?;
         }
         "True" => {
-            c.chunk.emit_opcode(Opcode::True)?;
+            emit_constant(c, Value::True)?;
         }
         _ => {
             c.emit(Diagnostic::error(src.ast.span(node_id), "uppercased identifiers are reserved for future use; please start your identifiers with a lowercase letter instead"));
@@ -221,9 +509,17 @@ fn compile_tag(c: &mut Compiler, src: &Source, node_id: NodeId) -> CompileResult
 
 fn compile_number(c: &mut Compiler, src: &Source, node_id: NodeId) -> CompileResult {
     let literal = src.ast.span(node_id).slice(src.code);
-    if let Ok(float) = literal.parse() {
-        c.chunk.emit_opcode(Opcode::Number)?;
-        c.chunk.emit_f32(float)?;
+    if let Ok(float) = literal.parse::<f32>() {
+        // Literals too large to be represented would turn into infinity, which we never want
+        // in code, no matter whether the VM uses strict math.
+        if !float.is_finite() {
+            c.emit(
+                Diagnostic::error(src.ast.span(node_id), "number is too large")
+                    .with_note(format!("the largest possible number is {}", f32::MAX)),
+            );
+            return Ok(());
+        }
+        emit_constant(c, Value::Number(float))?;
     }
 
     Ok(())
@@ -231,7 +527,14 @@ fn compile_number(c: &mut Compiler, src: &Source, node_id: NodeId) -> CompileRes
 
 fn compile_color(c: &mut Compiler, src: &Source, node_id: NodeId) -> CompileResult {
     let literal = src.ast.span(node_id).slice(src.code);
+    let value = c
+        .fold_vm
+        .create_rgba(parse_color(literal))
+        .expect("constant folding VM should have unlimited memory");
+    emit_constant(c, value)
+}
 
+fn parse_color(literal: &str) -> Rgba {
     let hex = &literal[1..];
     let bytes: [u8; 4] = u32::from_str_radix(hex, 16)
         .ok()
@@ -264,10 +567,13 @@ fn compile_color(c: &mut Compiler, src: &Source, node_id: NodeId) -> CompileResu
         })
         .unwrap_or([0, 0, 0, 0]);
 
-    c.chunk.emit_opcode(Opcode::Rgba)?;
-    c.chunk.emit_bytes(&bytes)?;
-
-    Ok(())
+    let [r, g, b, a] = bytes;
+    Rgba {
+        r: r as f32 / 255.0,
+        g: g as f32 / 255.0,
+        b: b as f32 / 255.0,
+        a: a as f32 / 255.0,
+    }
 }
 
 fn compile_list<'a>(c: &mut Compiler<'a>, src: &Source<'a>, node_id: NodeId) -> CompileResult {
@@ -306,10 +612,7 @@ fn compile_unary<'a>(c: &mut Compiler<'a>, src: &Source<'a>, node_id: NodeId) ->
 
     compile_expr(c, src, expr)?;
     if let Some(index) = (src.system.resolve_fn)(SystemFnArity::Unary, name) {
-        let argument_count = 1;
-        c.chunk.emit_opcode(Opcode::System)?;
-        c.chunk.emit_u8(index)?;
-        c.chunk.emit_u8(argument_count)?;
+        emit_system_call(c, src.system, index, 1)?;
     } else {
         c.emit(Diagnostic::error(
             src.ast.span(op),
@@ -346,10 +649,7 @@ fn compile_binary<'a>(c: &mut Compiler<'a>, src: &Source<'a>, node_id: NodeId) -
     compile_expr(c, src, left)?;
     compile_expr(c, src, right)?;
     if let Some(index) = (src.system.resolve_fn)(SystemFnArity::Binary, name) {
-        let argument_count = 2;
-        c.chunk.emit_opcode(Opcode::System)?;
-        c.chunk.emit_u8(index)?;
-        c.chunk.emit_u8(argument_count)?;
+        emit_system_call(c, src.system, index, 2)?;
     } else {
         c.emit(Diagnostic::error(
             src.ast.span(op),
@@ -385,9 +685,7 @@ fn compile_call<'a>(c: &mut Compiler<'a>, src: &Source<'a>, node_id: NodeId) ->
         src.ast.kind(func),
         (src.system.resolve_fn)(SystemFnArity::Nary, name),
     ) {
-        c.chunk.emit_opcode(Opcode::System)?;
-        c.chunk.emit_u8(index)?;
-        c.chunk.emit_u8(argument_count)?;
+        emit_system_call(c, src.system, index, argument_count)?;
     } else {
         // This is a bit of an oddity: we only emit the function expression _after_ the arguments,
         // but since the language is effectless this doesn't matter in practice.
@@ -424,21 +722,40 @@ fn compile_if<'a>(c: &mut Compiler<'a>, src: &Source<'a>, node_id: NodeId) -> Co
         return Ok(());
     };
 
+    let condition_offset = c.chunk.offset();
     compile_expr(c, src, condition)?;
 
+    if let Some(condition) = constant_since(c, condition_offset) {
+        // With a constant condition, only the branch that's taken is kept.
+        // The other branch is still compiled to report any diagnostics, but its code is discarded.
+        truncate(c, condition_offset);
+        let (taken, dead) = if condition.is_truthy() {
+            (if_true, if_false)
+        } else {
+            (if_false, if_true)
+        };
+        compile_expr(c, src, taken)?;
+        let dead_offset = c.chunk.offset();
+        compile_expr(c, src, dead)?;
+        truncate(c, dead_offset);
+        return Ok(());
+    }
+
     c.chunk.emit_opcode(Opcode::JumpIfNot)?;
     let false_jump_offset_offset = c.chunk.emit_u16(0)?;
+    c.relocate(Relocation::Offset(false_jump_offset_offset));
 
     compile_expr(c, src, if_true)?;
     c.chunk.emit_opcode(Opcode::Jump)?;
     let true_jump_offset_offset = c.chunk.emit_u16(0)?;
+    c.relocate(Relocation::Offset(true_jump_offset_offset));
 
-    let false_jump_offset = c.chunk.offset();
+    let false_jump_offset = label(c);
     c.chunk
         .patch_offset(false_jump_offset_offset, false_jump_offset);
     compile_expr(c, src, if_false)?;
 
-    let true_jump_offset = c.chunk.offset();
+    let true_jump_offset = label(c);
     c.chunk
         .patch_offset(true_jump_offset_offset, true_jump_offset);
 
@@ -512,6 +829,8 @@ fn compile_lambda<'a>(c: &mut Compiler<'a>, src: &Source<'a>, node_id: NodeId) -
     c.chunk.emit_opcode(Opcode::Function)?;
     c.chunk.emit_u8(param_count)?;
     let after_offset = c.chunk.emit_u16(0)?;
+    c.relocate(Relocation::Offset(after_offset));
+    let body_start = c.chunk.offset();
 
     c.scopes.push(Scope {
         locals,
@@ -525,6 +844,20 @@ fn compile_lambda<'a>(c: &mut Compiler<'a>, src: &Source<'a>, node_id: NodeId) -
     c.chunk.patch_u16(after_offset, after);
 
     let scope = c.scopes.pop().unwrap();
+    let parent_scope_index = c.scopes.len() - 1;
+    let function = c.debug_info.is_some().then(|| FunctionInfo {
+        start: body_start.to_u16(),
+        span: src.ast.span(node_id),
+        locals: scope.locals.iter().map(|l| l.name.to_owned()).collect(),
+        captures: scope
+            .captures
+            .iter()
+            .map(|&v| variable_name(c, parent_scope_index, v).to_owned())
+            .collect(),
+    });
+    if let (Some(debug_info), Some(function)) = (&mut c.debug_info, function) {
+        debug_info.functions.push(function);
+    }
     let let_count = u8::try_from(scope.let_count).unwrap_or_else(|_| {
         c.emit(Diagnostic::error(
             src.ast.span(body),
@@ -559,18 +892,67 @@ fn compile_lambda<'a>(c: &mut Compiler<'a>, src: &Source<'a>, node_id: NodeId) -
     Ok(())
 }
 
+/// Returns the name of a variable as seen from the scope at `scope_index`.
+fn variable_name<'a>(c: &Compiler<'a>, scope_index: usize, variable: Variable) -> &'a str {
+    match variable {
+        Variable::Local(index) => c.scopes[scope_index].locals[index as usize].name,
+        Variable::Captured(index) => variable_name(
+            c,
+            scope_index - 1,
+            c.scopes[scope_index].captures[index as usize],
+        ),
+    }
+}
+
 fn compile_toplevel<'a>(c: &mut Compiler<'a>, src: &Source<'a>, node_id: NodeId) -> CompileResult {
-    def_prepass(c, src, node_id)?;
+    let start = c.chunk.offset();
+    let program_defs = def_prepass(c, src, node_id)?;
+    if let Some(cache) = c.def_cache.as_deref_mut() {
+        cache.begin();
+    }
+
+    // Code that varies between points of a stroke is split off from the rest, such that the rest
+    // only has to be evaluated once per stroke.
+    let split = varying::analyze(c, src, node_id, &program_defs);
+    if let Some(split) = &split {
+        for &def in &split.constant_defs {
+            incremental::compile_def(c, src, def)?;
+        }
+        c.point_start = Some(c.chunk.emit_opcode(Opcode::PointStart)?);
+    }
 
     let mut walk = src.ast.walk(node_id);
     let mut result_expr = None;
     while let Some(toplevel_expr) = walk.node() {
+        // Imports and params are declarations rather than code, so they may appear anywhere.
+        if matches!(
+            src.ast.kind(toplevel_expr),
+            NodeKind::Import | NodeKind::ParamDecl
+        ) {
+            compile_expr(c, src, toplevel_expr)?;
+            continue;
+        }
+
+        if split
+            .as_ref()
+            .is_some_and(|split| split.constant_defs.contains(&toplevel_expr))
+        {
+            continue;
+        }
+
+        // Nothing after the result would ever be evaluated, including defs, so that's an error.
+        // Code after the result is still compiled, so that errors in it are reported, too.
         if let Some(result_expr) = result_expr {
-            // TODO: This diagnostic should show you the expression after the result.
-            c.emit(Diagnostic::error(
-                src.ast.span(result_expr),
-                "the result value must be the last thing in the program",
-            ));
+            c.emit(
+                Diagnostic::error(
+                    src.ast.span(toplevel_expr),
+                    "unreachable code: the result of the program must be the last thing in it",
+                )
+                .with_label(
+                    src.ast.span(result_expr),
+                    "the result of the program is here",
+                ),
+            );
         }
 
         match compile_toplevel_expr(c, src, toplevel_expr)? {
@@ -585,34 +967,85 @@ fn compile_toplevel<'a>(c: &mut Compiler<'a>, src: &Source<'a>, node_id: NodeId)
     }
     c.chunk.emit_opcode(Opcode::Return)?;
 
+    if let Some(debug_info) = &mut c.debug_info {
+        debug_info.functions.push(FunctionInfo {
+            start: start.to_u16(),
+            span: src.ast.span(node_id),
+            locals: c.scopes[0]
+                .locals
+                .iter()
+                .map(|l| l.name.to_owned())
+                .collect(),
+            captures: Vec::new(),
+        });
+    }
+
+    if let Some(cache) = c.def_cache.as_deref_mut() {
+        cache.finish();
+    }
+
     Ok(())
 }
 
-fn def_prepass<'a>(c: &mut Compiler<'a>, src: &Source<'a>, toplevel: NodeId) -> CompileResult {
+/// Adds all defs of the program to the compiler's defs, and returns their names and IDs.
+fn def_prepass<'a>(
+    c: &mut Compiler<'a>,
+    src: &Source<'a>,
+    toplevel: NodeId,
+) -> CompileResult<Vec<(&'a str, DefId)>> {
     let mut walk = src.ast.walk(toplevel);
+    let mut program_defs = Vec::new();
+    // Where each def in this program was first defined, to point to it in case of duplicates.
+    let mut def_spans: Vec<(&str, Span)> = Vec::new();
 
     // This is a bit of a pattern matching tapeworm, but Rust unfortunately doesn't have `if let`
     // chains yet to make this more readable.
-    while let Some(binary) = walk.node_of(NodeKind::Binary) {
-        let mut binary_walk = src.ast.walk(binary);
-        if let (Some(ident), Some(op)) = (binary_walk.node(), binary_walk.get(NodeKind::Op)) {
-            if src.ast.span(op).slice(src.code) == "=" {
-                let name = src.ast.span(ident).slice(src.code);
-                match c.defs.add(name) {
-                    Ok(_) => (),
-                    Err(DefError::Exists) => c.emit(Diagnostic::error(
-                        src.ast.span(ident),
-                        "a def with this name already exists",
-                    )),
-                    Err(DefError::OutOfSpace) => {
-                        c.emit(Diagnostic::error(src.ast.span(binary), "too many defs"))
-                    }
+    while let Some(toplevel_expr) = walk.node() {
+        let ident = match src.ast.kind(toplevel_expr) {
+            NodeKind::Binary => {
+                let mut binary_walk = src.ast.walk(toplevel_expr);
+                match (binary_walk.node(), binary_walk.get(NodeKind::Op)) {
+                    (Some(ident), Some(op)) if src.ast.span(op).slice(src.code) == "=" => ident,
+                    _ => continue,
+                }
+            }
+            // Params are defs whose values are supplied by the host.
+            NodeKind::ParamDecl => match src.ast.param_ident(toplevel_expr) {
+                Some(ident) => ident,
+                None => continue,
+            },
+            _ => continue,
+        };
+
+        let span = src.ast.span(ident);
+        let name = span.slice(src.code);
+        match c.defs.add(name) {
+            Ok(def_id) => {
+                program_defs.push((name, def_id));
+                def_spans.push((name, span));
+                if let Some(debug_info) = &mut c.debug_info {
+                    debug_info.defs.push((name.to_owned(), def_id));
+                }
+            }
+            Err(DefError::Exists) => {
+                let mut diagnostic = Diagnostic::error(span, "a def with this name already exists");
+                if let Some(&(_, first_span)) =
+                    def_spans.iter().find(|&&(def_name, _)| def_name == name)
+                {
+                    diagnostic = diagnostic.with_label(first_span, "def first defined here");
                 }
+                c.emit(diagnostic.with_note(
+                    "defs are visible in the entire program, so each def must have a unique name",
+                ))
             }
+            Err(DefError::OutOfSpace) => c.emit(Diagnostic::error(
+                src.ast.span(toplevel_expr),
+                "too many defs",
+            )),
         }
     }
 
-    Ok(())
+    Ok(program_defs)
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -626,13 +1059,9 @@ fn compile_toplevel_expr<'a>(
     src: &Source<'a>,
     node_id: NodeId,
 ) -> CompileResult<ToplevelExpr> {
-    if src.ast.kind(node_id) == NodeKind::Binary {
-        if let Some(op) = src.ast.walk(node_id).get(NodeKind::Op) {
-            if src.ast.span(op).slice(src.code) == "=" {
-                compile_def(c, src, node_id)?;
-                return Ok(ToplevelExpr::Def);
-            }
-        }
+    if src.ast.is_def(src.code, node_id) {
+        incremental::compile_def(c, src, node_id)?;
+        return Ok(ToplevelExpr::Def);
     }
 
     compile_expr(c, src, node_id)?;
@@ -664,13 +1093,122 @@ fn compile_def<'a>(c: &mut Compiler<'a>, src: &Source<'a>, node_id: NodeId) -> C
     // zero def instead.
     let def_id = c.defs.get(name).unwrap_or_default();
 
+    let start = c.chunk.offset();
     compile_expr(c, src, right)?;
     c.chunk.emit_opcode(Opcode::SetDef)?;
-    c.chunk.emit_u16(def_id.to_u16())?;
+    let operand = c.chunk.emit_u16(def_id.to_u16())?;
+    c.relocate(Relocation::Def(operand, name));
+    c.depend_on(name);
+    record_span(c, start, src.ast.span(node_id));
+
+    Ok(())
+}
+
+fn compile_param_decl(c: &mut Compiler, src: &Source, node_id: NodeId) -> CompileResult {
+    // If the name is missing, the parser has already reported an error.
+    let Some(ident) = src.ast.param_ident(node_id) else {
+        return Ok(());
+    };
+    let name = src.ast.span(ident).slice(src.code);
+    let mut walk = src.ast.walk(node_id);
+    let (Some(_ident), Some(default_node)) = (walk.node(), walk.node()) else {
+        return Ok(());
+    };
+    let range_node = walk.node();
+
+    let Some(default) = param_literal(src, default_node) else {
+        c.emit(Diagnostic::error(
+            src.ast.span(default_node),
+            "the default value of a parameter must be a number, color, or boolean literal",
+        ));
+        return Ok(());
+    };
+
+    let mut range = None;
+    if let Some(range_node) = range_node {
+        let span = src.ast.span(range_node);
+        if default.kind() != ParamKind::Number {
+            c.emit(Diagnostic::error(
+                span,
+                "only number parameters can have a range",
+            ));
+            return Ok(());
+        }
+
+        let mut range_walk = src.ast.walk(range_node);
+        let bounds = (
+            range_walk.node().and_then(|node| param_literal(src, node)),
+            range_walk.node().and_then(|node| param_literal(src, node)),
+            range_walk.node(),
+        );
+        let (Some(ParamValue::Number(min)), Some(ParamValue::Number(max)), None) = bounds else {
+            c.emit(Diagnostic::error(
+                span,
+                "the range of a parameter must be a list of two numbers: `[min, max]`",
+            ));
+            return Ok(());
+        };
+        if min > max {
+            c.emit(Diagnostic::error(
+                span,
+                "the minimum of the range must not be greater than its maximum",
+            ));
+            return Ok(());
+        }
+        if let ParamValue::Number(x) = default {
+            if !(min..=max).contains(&x) {
+                c.emit(
+                    Diagnostic::error(
+                        src.ast.span(default_node),
+                        "the default value of a parameter must be within its range",
+                    )
+                    .with_label(span, "the range is declared here"),
+                );
+                return Ok(());
+            }
+        }
+        range = Some((min, max));
+    }
+
+    c.params.push(Param {
+        name: name.to_owned(),
+        def_id: c.defs.get(name).unwrap_or_default(),
+        default,
+        range,
+    });
 
     Ok(())
 }
 
+/// Returns the value of a literal usable as a parameter's default value or range.
+fn param_literal(src: &Source, node_id: NodeId) -> Option<ParamValue> {
+    let literal = src.ast.span(node_id).slice(src.code);
+    match src.ast.kind(node_id) {
+        NodeKind::Number => literal
+            .parse()
+            .ok()
+            .filter(|x: &f32| x.is_finite())
+            .map(ParamValue::Number),
+        NodeKind::Color => Some(ParamValue::Rgba(parse_color(literal))),
+        NodeKind::Tag => match literal {
+            "False" => Some(ParamValue::Boolean(false)),
+            "True" => Some(ParamValue::Boolean(true)),
+            _ => None,
+        },
+        NodeKind::Unary => {
+            let mut walk = src.ast.walk(node_id);
+            let (Some(op), Some(expr)) = (walk.node(), walk.node()) else {
+                return None;
+            };
+            match (src.ast.span(op).slice(src.code), param_literal(src, expr)?) {
+                ("-", ParamValue::Number(x)) => Some(ParamValue::Number(-x)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CompileError {
     Emit,
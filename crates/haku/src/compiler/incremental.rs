@@ -0,0 +1,302 @@
+//! Incremental compilation of toplevel defs.
+//!
+//! While a brush is being edited, most of its defs stay the same between compilations. A
+//! [`DefCache`] remembers the bytecode each def compiled to, keyed by a hash of the def's source
+//! code, so that defs which didn't change can be copied into the new chunk instead of being
+//! compiled again.
+//!
+//! Bytecode is not position-independent, since jumps and functions refer to absolute offsets in
+//! the chunk, and defs are referred to by their [`DefId`][crate::bytecode::DefId]s. Therefore each
+//! cached def remembers where these operands are, so that they can be relocated when the def is
+//! copied to a different place. Def IDs are looked up again by name, so when a def that another
+//! def depends on disappears, the dependent def is compiled again (and reports the undefined
+//! variable.)
+//!
+//! Debug information is cached along with the bytecode if it was collected, relative to the def's
+//! position in the chunk and in the source code.
+//!
+//! Defs that produce diagnostics are never cached, such that their diagnostics are reported on
+//! every compilation. Neither are defs that leave `let` variables behind in the toplevel scope,
+//! which are rare enough not to be worth the trouble.
+
+use alloc::{borrow::ToOwned, collections::BTreeMap, string::String, vec::Vec};
+
+use crate::{ast::NodeId, bytecode::DefId, debug_info::DebugInfo};
+
+use super::{CompileResult, Compiler, Relocation, Source};
+
+/// Cache of compiled defs, reused across compilations of the same program.
+///
+/// A cache must only ever be used with a single [`System`][crate::system::System], since the
+/// bytecode of cached defs refers to its system functions.
+#[derive(Debug, Clone, Default)]
+pub struct DefCache {
+    /// Defs cached by the previous compilation.
+    previous: BTreeMap<u64, CachedDef>,
+    /// Defs cached by the compilation in progress.
+    /// Defs from `previous` that are not reused are dropped once the compilation finishes.
+    current: BTreeMap<u64, CachedDef>,
+    stats: DefCacheStats,
+}
+
+/// Statistics about how many defs were reused during a compilation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DefCacheStats {
+    /// Defs whose bytecode was copied from the cache.
+    pub reused: usize,
+    /// Defs which had to be compiled.
+    pub compiled: usize,
+}
+
+#[derive(Debug, Clone)]
+struct CachedDef {
+    /// The def's source code, to rule out hash collisions.
+    source: String,
+    /// Names of the locals in the toplevel scope at the start of the def, since the def may refer
+    /// to them.
+    locals: Vec<String>,
+    /// Bytecode of the def, with offsets relative to its start, and def IDs left as they were.
+    bytecode: Vec<u8>,
+    relocations: Vec<CachedRelocation>,
+    /// Names of the defs this def refers to, and the IDs they had when it was compiled.
+    dependencies: Vec<(String, DefId)>,
+    /// Debug information about the def, relative to its start in the chunk and the source code.
+    /// Only present if the compiler was collecting debug information.
+    debug_info: Option<DebugInfo>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum CachedRelocation {
+    /// An offset relative to the start of the def.
+    Offset(u16),
+    /// The ID of the def at the given index in `dependencies`.
+    Def(u16, u16),
+}
+
+impl DefCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns statistics about the last compilation that used this cache.
+    pub fn stats(&self) -> DefCacheStats {
+        self.stats
+    }
+
+    pub(super) fn begin(&mut self) {
+        self.current.clear();
+        self.stats = DefCacheStats::default();
+    }
+
+    pub(super) fn finish(&mut self) {
+        self.previous = core::mem::take(&mut self.current);
+    }
+}
+
+/// FNV-1a, which is plenty good for telling apart snippets of source code.
+/// This hashes 8 bytes at a time rather than 1, since that's several times faster, and collisions
+/// are ruled out by comparing the source code anyways.
+fn hash(source: &str) -> u64 {
+    let chunks = source.as_bytes().chunks(8);
+    chunks.fold(0xcbf29ce484222325, |hash, chunk| {
+        let mut word = [0; 8];
+        word[..chunk.len()].copy_from_slice(chunk);
+        (hash ^ u64::from_le_bytes(word)).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn read_u16(bytecode: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytecode[at], bytecode[at + 1]])
+}
+
+fn write_u16(bytecode: &mut [u8], at: usize, x: u16) {
+    bytecode[at..at + 2].copy_from_slice(&x.to_le_bytes());
+}
+
+/// Compile a toplevel def, reusing its bytecode from the compiler's def cache if possible.
+pub(super) fn compile_def<'a>(
+    c: &mut Compiler<'a>,
+    src: &Source<'a>,
+    node_id: NodeId,
+) -> CompileResult {
+    let Some(cache) = c.def_cache.as_deref_mut() else {
+        return super::compile_def(c, src, node_id);
+    };
+
+    let span = src.ast.span(node_id);
+    let source = span.slice(src.code);
+    let hash = hash(source);
+
+    if let Some(def) = cache.previous.remove(&hash) {
+        if reuse(c, &def, source, span.start) {
+            let cache = cache_of(c);
+            cache.stats.reused += 1;
+            cache.current.insert(hash, def);
+            return Ok(());
+        }
+    }
+
+    let def = compile(c, src, node_id)?;
+    let cache = cache_of(c);
+    cache.stats.compiled += 1;
+    if let Some(def) = def {
+        cache.current.insert(hash, def);
+    }
+
+    Ok(())
+}
+
+fn cache_of<'c>(c: &'c mut Compiler) -> &'c mut DefCache {
+    c.def_cache
+        .as_deref_mut()
+        .expect("def cache must not be removed during compilation")
+}
+
+/// Copy a cached def's bytecode into the chunk. Returns `false` if the def cannot be reused in the
+/// current program, in which case nothing is emitted.
+fn reuse(c: &mut Compiler, def: &CachedDef, source: &str, position: u32) -> bool {
+    let locals = &c.scopes[0].locals;
+    if def.source != source || !def.locals.iter().eq(locals.iter().map(|local| local.name)) {
+        return false;
+    }
+    if c.debug_info.is_some() && def.debug_info.is_none() {
+        return false;
+    }
+
+    let Some(def_ids): Option<Vec<u16>> = def
+        .dependencies
+        .iter()
+        .map(|(name, def_id)| {
+            if c.defs.is_current(*def_id, name) {
+                Some(def_id.to_u16())
+            } else {
+                c.defs.get(name).map(|def_id| def_id.to_u16())
+            }
+        })
+        .collect()
+    else {
+        return false;
+    };
+
+    let Ok(start) = c.chunk.emit_bytes(&def.bytecode) else {
+        // Let the def be compiled, so that it fails the same way a fresh compilation would.
+        return false;
+    };
+    let start = start.to_u16();
+    let bytecode = &mut c.chunk.bytecode[start as usize..];
+    for &relocation in &def.relocations {
+        match relocation {
+            CachedRelocation::Offset(at) => {
+                let offset = read_u16(bytecode, at as usize);
+                write_u16(bytecode, at as usize, offset.wrapping_add(start));
+            }
+            CachedRelocation::Def(at, index) => {
+                write_u16(bytecode, at as usize, def_ids[index as usize]);
+            }
+        }
+    }
+
+    if let (Some(debug_info), Some(def_debug_info)) = (&mut c.debug_info, &def.debug_info) {
+        debug_info.extend_moved(
+            &def_debug_info.spans,
+            &def_debug_info.functions,
+            start,
+            position,
+        );
+    }
+
+    // The def ends with a SetDef, so nothing before it can take part in constant folding.
+    c.constants.clear();
+
+    true
+}
+
+/// Compile a def, and return it in a cacheable form if it can be cached.
+fn compile<'a>(
+    c: &mut Compiler<'a>,
+    src: &Source<'a>,
+    node_id: NodeId,
+) -> CompileResult<Option<CachedDef>> {
+    let start = c.chunk.offset();
+    let local_count = c.scopes[0].locals.len();
+    // If the diagnostics are already full, new ones would go unnoticed.
+    let diagnostic_count = c.diagnostics.len();
+    let cacheable = diagnostic_count < c.diagnostics.capacity();
+    let debug_info_len = c
+        .debug_info
+        .as_ref()
+        .map(|info| (info.spans.len(), info.functions.len()));
+
+    c.relocations.clear();
+    c.dependencies.clear();
+
+    super::compile_def(c, src, node_id)?;
+
+    if !cacheable
+        || c.diagnostics.len() != diagnostic_count
+        || c.scopes[0].locals.len() != local_count
+    {
+        return Ok(None);
+    }
+
+    let start = start.to_u16();
+    let mut bytecode = c.chunk.bytecode[start as usize..].to_vec();
+    let relocations = c
+        .relocations
+        .iter()
+        .map(|&relocation| {
+            let at = relocation.offset().to_u16() - start;
+            match relocation {
+                Relocation::Offset(_) => {
+                    let offset = read_u16(&bytecode, at as usize);
+                    write_u16(&mut bytecode, at as usize, offset - start);
+                    CachedRelocation::Offset(at)
+                }
+                Relocation::Def(_, name) => {
+                    let index = c
+                        .dependencies
+                        .iter()
+                        .position(|&dependency| dependency == name)
+                        .expect("def relocations must be dependencies");
+                    CachedRelocation::Def(at, index as u16)
+                }
+            }
+        })
+        .collect();
+
+    let position = src.ast.span(node_id).start;
+    let debug_info =
+        c.debug_info
+            .as_ref()
+            .zip(debug_info_len)
+            .map(|(info, (span_count, function_count))| {
+                let mut def_info = DebugInfo::new();
+                def_info.extend_moved(
+                    &info.spans[span_count..],
+                    &info.functions[function_count..],
+                    start.wrapping_neg(),
+                    position.wrapping_neg(),
+                );
+                def_info
+            });
+
+    Ok(Some(CachedDef {
+        source: src.ast.span(node_id).slice(src.code).to_owned(),
+        locals: c.scopes[0]
+            .locals
+            .iter()
+            .map(|local| local.name.to_owned())
+            .collect(),
+        bytecode,
+        relocations,
+        dependencies: c
+            .dependencies
+            .iter()
+            .map(|&name| (name.to_owned(), c.defs.get(name).unwrap_or_default()))
+            .collect(),
+        debug_info,
+    }))
+}
+
+#[cfg(test)]
+mod tests;
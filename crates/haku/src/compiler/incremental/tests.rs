@@ -0,0 +1,171 @@
+use alloc::{string::String, vec::Vec};
+
+use crate::{
+    bytecode::Defs,
+    debug_info::DebugInfo,
+    system::System,
+    testing::{self, Options},
+};
+
+use super::{DefCache, DefCacheStats};
+
+struct Compiled {
+    bytecode: Vec<u8>,
+    errors: Vec<String>,
+    debug_info: Option<DebugInfo>,
+}
+
+fn compile(code: &str, cache: Option<&mut DefCache>) -> Compiled {
+    compile_with_debug_info(code, cache, true)
+}
+
+fn compile_with_debug_info(
+    code: &str,
+    cache: Option<&mut DefCache>,
+    collect_debug_info: bool,
+) -> Compiled {
+    let mut system = System::new(1);
+    let mut defs = Defs::new(256);
+
+    let mut compiled = testing::compile_with(
+        code,
+        &mut system,
+        &mut defs,
+        Options {
+            debug_info: collect_debug_info,
+            def_cache: cache,
+            ..Default::default()
+        },
+    );
+    let errors = compiled.errors();
+    let debug_info = compiled.debug_info.take();
+    let bytecode = compiled.chunk.bytecode.clone();
+    if errors.is_empty() {
+        // Reused code has to pass verification just like freshly compiled code.
+        compiled.add_to(&mut system, &defs);
+    }
+
+    Compiled {
+        bytecode,
+        errors,
+        debug_info,
+    }
+}
+
+/// Compiles each version of a program in sequence using the same cache, checking that the result
+/// is exactly the same as compiling it from scratch. Returns cache statistics for each version.
+fn compile_versions(versions: &[&str]) -> Vec<DefCacheStats> {
+    let mut cache = DefCache::new();
+    versions
+        .iter()
+        .map(|code| {
+            let incremental = compile(code, Some(&mut cache));
+            let fresh = compile(code, None);
+            assert_eq!(incremental.errors, fresh.errors, "in {code:?}");
+            assert_eq!(incremental.bytecode, fresh.bytecode, "in {code:?}");
+            assert_eq!(incremental.debug_info, fresh.debug_info, "in {code:?}");
+            cache.stats()
+        })
+        .collect()
+}
+
+fn stats(reused: usize, compiled: usize) -> DefCacheStats {
+    DefCacheStats { reused, compiled }
+}
+
+#[test]
+fn unchanged() {
+    let code = "a = 1\nb = \\x -> if (x > 0) a else x\nc = b a\nc";
+    assert_eq!(compile_versions(&[code, code]), [stats(0, 3), stats(3, 0)]);
+}
+
+#[test]
+fn edited() {
+    assert_eq!(
+        compile_versions(&[
+            "a = 1\nb = \\x -> if (x > 0) a else x\nc = b a\nc",
+            "a = 2\nb = \\x -> if (x > 0) a else x\nc = b a\nc",
+            // Code after an edited def moves, so its jumps and functions have to be relocated.
+            "a = 2 + [1, 2, 3]\nb = \\x -> if (x > 0) a else x\nc = b a\nc",
+            "a = 2 + [1, 2, 3]\nb = \\x -> if (x > 0) a else x\nc = b a\nc + 1",
+        ]),
+        [stats(0, 3), stats(2, 1), stats(2, 1), stats(3, 0)]
+    );
+}
+
+#[test]
+fn reordered() {
+    // Def IDs are assigned in source order, so they change when defs are moved around.
+    assert_eq!(
+        compile_versions(&[
+            "x = 1\nf = \\a -> if (a) x else y\ny = 2\nf True",
+            "y = 2\nx = 1\nf = \\a -> if (a) x else y\nf True",
+            "f = \\a -> if (a) x else y\ny = 2\nz = 3\nx = 1\nf True",
+        ]),
+        [stats(0, 3), stats(3, 0), stats(3, 1)]
+    );
+}
+
+#[test]
+fn dependency_removed() {
+    assert_eq!(
+        compile_versions(&["a = 1\nb = a + 1\nb", "b = a + 1\nb", "a = 1\nb = a + 1\nb"]),
+        [stats(0, 2), stats(0, 1), stats(0, 2)]
+    );
+    assert_eq!(compile("b = a + 1\nb", None).errors, ["undefined variable"]);
+
+    // Code removed by constant folding still counts as a dependency.
+    assert_eq!(
+        compile_versions(&[
+            "a = 1\nb = if (True) 1 else a\nb",
+            "b = if (True) 1 else a\nb",
+        ]),
+        [stats(0, 2), stats(0, 1)]
+    );
+}
+
+#[test]
+fn errors_are_not_cached() {
+    let code = "a = 1\nb = c\nb";
+    assert_eq!(compile_versions(&[code, code]), [stats(0, 2), stats(1, 1)]);
+}
+
+#[test]
+fn toplevel_locals() {
+    // Lets at the top level of a def leave their variables behind in the toplevel scope, where
+    // later defs can see them.
+    assert_eq!(
+        compile_versions(&[
+            "a = (let k = 1\nk)\nb = k\nb",
+            "a = (let k = 1\nk)\nb = k\nb",
+            "a = (let j = 1\nj)\nb = k\nb",
+        ]),
+        [stats(0, 2), stats(1, 1), stats(0, 2)]
+    );
+}
+
+#[test]
+fn params() {
+    assert_eq!(
+        compile_versions(&[
+            "param size = 4\nf = \\x -> x * size\nf 2",
+            "param width = 4\nf = \\x -> x * size\nf 2",
+            "param size = 8\nf = \\x -> x * size\nf 2",
+        ]),
+        [stats(0, 1), stats(0, 1), stats(0, 1)]
+    );
+}
+
+#[test]
+fn debug_info() {
+    let code = "a = 1\nf = \\x -> x + a\nf 2";
+    let mut cache = DefCache::new();
+    compile_with_debug_info(code, Some(&mut cache), false);
+    // Defs cached without debug info must be compiled again once it's needed.
+    let incremental = compile(code, Some(&mut cache));
+    assert_eq!(cache.stats(), stats(0, 2));
+    assert_eq!(incremental.debug_info, compile(code, None).debug_info);
+    // Defs cached with debug info can still be reused without it.
+    compile_with_debug_info(code, Some(&mut cache), false);
+    assert_eq!(cache.stats(), stats(2, 0));
+}
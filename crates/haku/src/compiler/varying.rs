@@ -0,0 +1,174 @@
+//! Analysis of which toplevel code varies between points of a stroke.
+//!
+//! A toplevel def is varying if it refers to a varying def, either directly or through other defs
+//! of the same program. Stroke inputs are the defs that vary to begin with. The analysis is
+//! conservative: any identifier that names a varying def counts, even if it actually refers to a
+//! local variable of the same name, and even if it's inside a function that's never called.
+//!
+//! See the [`stroke`][crate::stroke] module for how the result is used.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use crate::{
+    ast::{NodeId, NodeKind},
+    bytecode::{DefId, Defs},
+};
+
+use super::{Compiler, Source};
+
+/// Toplevel code which is split into a stroke-constant part and a per-point part.
+pub(super) struct Split {
+    /// Def nodes which can be evaluated once per stroke, in source order.
+    pub constant_defs: Vec<NodeId>,
+}
+
+struct Def {
+    node_id: NodeId,
+    def_id: DefId,
+    dependencies: Vec<DefId>,
+    varying: bool,
+}
+
+/// Find out which of the program's defs are varying, and mark them as such.
+/// Returns `None` if nothing in the program varies, in which case it doesn't need to be split.
+/// `program_defs` are the names and IDs of the program's own defs.
+pub(super) fn analyze<'a>(
+    c: &mut Compiler,
+    src: &Source<'a>,
+    toplevel: NodeId,
+    program_defs: &[(&'a str, DefId)],
+) -> Option<Split> {
+    let mut names = Names {
+        def_ids: program_defs
+            .iter()
+            .map(|&(name, def_id)| (name, Some(def_id)))
+            .collect(),
+    };
+    let mut defs: Vec<Def> = Vec::new();
+    let mut result = Vec::new();
+    // Locals bound with `let` outside of functions are visible to all code after them, so if there
+    // are any, defs cannot be moved between parts.
+    let mut has_toplevel_locals = false;
+
+    let mut walk = src.ast.walk(toplevel);
+    while let Some(toplevel_expr) = walk.node() {
+        if matches!(
+            src.ast.kind(toplevel_expr),
+            NodeKind::Import | NodeKind::ParamDecl
+        ) {
+            continue;
+        }
+
+        if src.ast.is_def(src.code, toplevel_expr) {
+            let mut def_walk = src.ast.walk(toplevel_expr);
+            let (Some(left), Some(_op), Some(right)) =
+                (def_walk.node(), def_walk.node(), def_walk.node())
+            else {
+                continue;
+            };
+            let Some(def_id) = names.get(c.defs, src.ast.span(left).slice(src.code)) else {
+                continue;
+            };
+            let mut dependencies = Vec::new();
+            has_toplevel_locals |= dependencies_of(c, &mut names, src, right, &mut dependencies);
+            defs.push(Def {
+                node_id: toplevel_expr,
+                def_id,
+                varying: dependencies.iter().any(|&d| c.defs.is_varying(d)),
+                dependencies,
+            });
+        } else {
+            // Code after the result is a compile error, so programs with any never run, and the
+            // split only has to be right for programs without it. Defs after the result are
+            // therefore left out of the analysis: they're never marked varying, and never split
+            // off as constant, so they're compiled where they are, after the result.
+            has_toplevel_locals |= dependencies_of(c, &mut names, src, toplevel_expr, &mut result);
+            break;
+        }
+    }
+
+    // Propagate varying-ness through defs of this program, which may refer to each other in any
+    // order.
+    loop {
+        let mut changed = false;
+        for i in 0..defs.len() {
+            if !defs[i].varying
+                && defs[i]
+                    .dependencies
+                    .iter()
+                    .any(|&d| defs.iter().any(|def| def.def_id == d && def.varying))
+            {
+                defs[i].varying = true;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    let result_varying = result
+        .iter()
+        .any(|&d| c.defs.is_varying(d) || defs.iter().any(|def| def.def_id == d && def.varying));
+    if !result_varying && !defs.iter().any(|def| def.varying) {
+        return None;
+    }
+
+    let mut constant_defs = Vec::new();
+    for def in &defs {
+        if def.varying || has_toplevel_locals {
+            c.defs.set_varying(def.def_id);
+        } else {
+            constant_defs.push(def.node_id);
+        }
+    }
+    Some(Split { constant_defs })
+}
+
+/// Defs looked up by name. [`Defs::get`] has to search through all defs, and doing that for every
+/// identifier would make the analysis take longer than compiling the program itself. The program's
+/// own defs, which take priority over imported ones, are known upfront, so only the few remaining
+/// names have to be searched for, once each.
+struct Names<'a> {
+    def_ids: BTreeMap<&'a str, Option<DefId>>,
+}
+
+impl<'a> Names<'a> {
+    fn get(&mut self, defs: &mut Defs, name: &'a str) -> Option<DefId> {
+        *self.def_ids.entry(name).or_insert_with(|| defs.get(name))
+    }
+}
+
+/// Collect the defs referred to by identifiers in the expression. Returns whether the expression
+/// binds any locals outside of functions.
+fn dependencies_of<'a>(
+    c: &mut Compiler,
+    names: &mut Names<'a>,
+    src: &Source<'a>,
+    node_id: NodeId,
+    out: &mut Vec<DefId>,
+) -> bool {
+    let mut binds_locals = false;
+    let mut stack = Vec::from_iter([(node_id, false)]);
+    while let Some((node_id, in_function)) = stack.pop() {
+        match src.ast.kind(node_id) {
+            NodeKind::Ident => {
+                if let Some(def_id) = names.get(c.defs, src.ast.span(node_id).slice(src.code)) {
+                    if !out.contains(&def_id) {
+                        out.push(def_id);
+                    }
+                }
+            }
+            NodeKind::Let if !in_function => binds_locals = true,
+            _ => (),
+        }
+        let in_function = in_function || src.ast.kind(node_id) == NodeKind::Lambda;
+        stack.extend(
+            src.ast
+                .children(node_id)
+                .iter()
+                .map(|&child| (child, in_function)),
+        );
+    }
+    binds_locals
+}
@@ -0,0 +1,120 @@
+//! Debug information, which maps bytecode back to the source code it was compiled from.
+//!
+//! The compiler produces debug information for each chunk alongside its bytecode, in
+//! [`Compiler::debug_info`][crate::compiler::Compiler::debug_info]. It's up to the host to keep it
+//! around for as long as it wants to debug the chunk using the VM's debugger (see
+//! [`vm::debug`][crate::vm::debug].)
+
+use alloc::{string::String, vec::Vec};
+
+use crate::{bytecode::DefId, source::Span};
+
+/// The bytecode in `start..end` was emitted for the AST node spanning `span`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpanRange {
+    pub start: u16,
+    pub end: u16,
+    pub span: Span,
+}
+
+/// Names of the variables of a function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionInfo {
+    /// Offset of the function's first instruction.
+    pub start: u16,
+    /// Span of the lambda the function was compiled from, or the whole program for the
+    /// toplevel function.
+    pub span: Span,
+    /// Names of the function's parameters and local variables, in the order they're stored in the
+    /// function's stack window.
+    pub locals: Vec<String>,
+    /// Names of the variables captured by the function, in the order of its captures.
+    pub captures: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DebugInfo {
+    /// The bytecode emitted for each AST node that emitted any.
+    /// Nodes are listed in the order they finished compiling in, so inner nodes always come before
+    /// the outer nodes that contain them.
+    pub spans: Vec<SpanRange>,
+    pub functions: Vec<FunctionInfo>,
+    /// Names of the defs declared by the program, along with their IDs.
+    pub defs: Vec<(String, DefId)>,
+}
+
+impl DebugInfo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the span of the innermost AST node whose bytecode contains the given offset.
+    pub fn span_at(&self, offset: u16) -> Option<Span> {
+        self.spans
+            .iter()
+            .find(|range| (range.start..range.end).contains(&offset))
+            .map(|range| range.span)
+    }
+
+    /// Returns the offset of the first instruction of the innermost AST node at the given position
+    /// in the source code. This is where a breakpoint placed at that position should stop.
+    pub fn breakpoint_at(&self, position: u32) -> Option<u16> {
+        self.spans
+            .iter()
+            .filter(|range| (range.span.start..=range.span.end).contains(&position))
+            .min_by_key(|range| range.span.end - range.span.start)
+            .map(|range| range.start)
+    }
+
+    /// Returns the function whose first instruction is at the given offset.
+    pub fn function(&self, start: u16) -> Option<&FunctionInfo> {
+        self.functions
+            .iter()
+            .find(|function| function.start == start)
+    }
+
+    /// Remove all debug information about bytecode starting at the given offset.
+    pub(crate) fn truncate(&mut self, offset: u16) {
+        // Code is only ever truncated back to the start of a node that's still being compiled, so
+        // everything past the offset was recorded last.
+        while self.spans.last().is_some_and(|range| range.end > offset) {
+            self.spans.pop();
+        }
+        while self
+            .functions
+            .last()
+            .is_some_and(|function| function.start >= offset)
+        {
+            self.functions.pop();
+        }
+    }
+
+    /// Append debug information about code that was moved `offset` bytes further into the chunk,
+    /// and whose source code was moved `position` bytes further into the file.
+    /// Both may wrap around, to move code backwards.
+    pub(crate) fn extend_moved(
+        &mut self,
+        spans: &[SpanRange],
+        functions: &[FunctionInfo],
+        offset: u16,
+        position: u32,
+    ) {
+        let move_span = |span: Span| {
+            Span::new(
+                span.start.wrapping_add(position),
+                span.end.wrapping_add(position),
+            )
+        };
+        self.spans.extend(spans.iter().map(|range| SpanRange {
+            start: range.start.wrapping_add(offset),
+            end: range.end.wrapping_add(offset),
+            span: move_span(range.span),
+        }));
+        self.functions
+            .extend(functions.iter().map(|function| FunctionInfo {
+                start: function.start.wrapping_add(offset),
+                span: move_span(function.span),
+                ..function.clone()
+            }));
+    }
+}
@@ -0,0 +1,24 @@
+use super::{edit_distance, similar_name};
+
+#[test]
+fn edit_distances() {
+    assert_eq!(edit_distance("", ""), 0);
+    assert_eq!(edit_distance("circle", "circle"), 0);
+    assert_eq!(edit_distance("circl", "circle"), 1);
+    assert_eq!(edit_distance("cricle", "circle"), 2);
+    assert_eq!(edit_distance("stroke", "fill"), 6);
+    assert_eq!(edit_distance("", "vec"), 3);
+}
+
+#[test]
+fn similar_names() {
+    let candidates = ["circle", "stroke", "fill", "vec"];
+    assert_eq!(similar_name("circl", candidates), Some("circle"));
+    assert_eq!(similar_name("strok", candidates), Some("stroke"));
+    assert_eq!(similar_name("vex", candidates), Some("vec"));
+    // Short names only allow for a single typo.
+    assert_eq!(similar_name("vx", candidates), None);
+    assert_eq!(similar_name("rectangle", candidates), None);
+    // The name itself is never suggested.
+    assert_eq!(similar_name("fill", candidates), None);
+}
@@ -0,0 +1,468 @@
+//! Canonical source code formatting.
+//!
+//! Since newlines are significant in haku, the formatter does not decide where lines break.
+//! It keeps the line breaks written in the source code, and normalizes everything around them:
+//! indentation, spacing between tokens, and blank lines (at most one in a row is kept.)
+//!
+//! Lines are indented by two spaces relative to the line on which the expression containing the
+//! line break starts:
+//!
+//! ```haku
+//! airbrush = \size ->
+//!   if (size > 0)
+//!     [splat size, airbrush (size - 8)]
+//!   else
+//!     []
+//! ```
+//!
+//! The only exception to keeping line breaks are lists, which are formatted with each element on
+//! its own line if any line break appears within the list.
+
+use alloc::{string::String, vec::Vec};
+
+use crate::{
+    ast::{Ast, NodeId, NodeKind},
+    source::{SourceCode, Span},
+    token::{Lexis, TokenKind},
+};
+
+const INDENT: &str = "  ";
+
+/// Formats parsed source code.
+///
+/// `lexis` must be lexed with trivia recorded (see [`Lexis::with_trivia`]), otherwise comments
+/// would be lost. The source code must be free of syntax errors, since it cannot be formatted
+/// faithfully otherwise---check that the lexer and the parser did not emit any diagnostics before
+/// formatting.
+pub fn format(code: &SourceCode, lexis: &Lexis, ast: &Ast, root: NodeId) -> String {
+    assert!(
+        lexis.trivia.is_some(),
+        "trivia must be recorded to format code without losing comments"
+    );
+    let comments: Vec<Span> = lexis.comments().collect();
+
+    let mut f = Formatter {
+        code,
+        lexis,
+        ast,
+        comments: &comments,
+        next_comment: 0,
+        out: String::new(),
+        indent: 0,
+        last_end: 0,
+    };
+
+    let first_token = lexis
+        .kinds
+        .iter()
+        .zip(&lexis.spans)
+        .find(|(&kind, _)| kind != TokenKind::Newline)
+        .map(|(_, span)| span.start)
+        .unwrap_or(code.len() as u32);
+    if f.comments_until(first_token, 0) > 0 && first_token < code.len() as u32 {
+        f.line_break(0, first_token);
+    }
+
+    f.node(root);
+
+    f.comments_until(code.len() as u32, 0);
+    if !f.out.is_empty() {
+        f.out.push('\n');
+    }
+
+    f.out
+}
+
+struct Formatter<'a> {
+    code: &'a SourceCode,
+    lexis: &'a Lexis,
+    ast: &'a Ast,
+    comments: &'a [Span],
+    next_comment: usize,
+
+    out: String,
+    /// Indentation level of the line being written.
+    indent: usize,
+    /// End of the last token written, or comment skipped over.
+    last_end: u32,
+}
+
+impl Formatter<'_> {
+    fn token_kind(&self, span: Span) -> TokenKind {
+        let index = self
+            .lexis
+            .spans
+            .partition_point(|token| token.start < span.start);
+        self.lexis.kind(index as u32)
+    }
+
+    /// Iterates over the tokens that are direct children of the node.
+    fn tokens(&self, node_id: NodeId) -> impl Iterator<Item = (TokenKind, Span)> + '_ {
+        self.ast
+            .children(node_id)
+            .iter()
+            .filter(|&&child| self.ast.kind(child) == NodeKind::Token)
+            .map(|&child| {
+                let span = self.ast.span(child);
+                (self.token_kind(span), span)
+            })
+    }
+
+    fn token_span(&self, node_id: NodeId, kind: TokenKind) -> Option<Span> {
+        self.tokens(node_id)
+            .find(|&(k, _)| k == kind)
+            .map(|(_, span)| span)
+    }
+
+    fn has_line_break(&self, node_id: NodeId) -> bool {
+        self.tokens(node_id)
+            .any(|(kind, _)| kind == TokenKind::Newline)
+    }
+
+    /// Writes out comments located before `end`, each on its own line unless it's on the same
+    /// line as the last token. Returns the number of comments written.
+    fn comments_until(&mut self, end: u32, indent: usize) -> usize {
+        let start = self.next_comment;
+        while let Some(&comment) = self
+            .comments
+            .get(self.next_comment)
+            .filter(|comment| comment.start < end)
+        {
+            let on_same_line =
+                !self.code[self.last_end as usize..comment.start as usize].contains('\n');
+            if self.out.is_empty() {
+                // Nothing to break from at the start of the file.
+            } else if on_same_line && self.next_comment == start {
+                self.out.push(' ');
+            } else {
+                self.newline(indent, comment.start);
+            }
+            self.out.push_str(comment.slice(self.code).trim_end());
+
+            self.last_end = comment.end;
+            self.next_comment += 1;
+        }
+        self.next_comment - start
+    }
+
+    /// Starts a new line, keeping a blank line before `next` if the source code has one.
+    fn newline(&mut self, indent: usize, next: u32) {
+        let blank_line = self.code[self.last_end as usize..next as usize]
+            .matches('\n')
+            .count()
+            >= 2;
+        self.out.push('\n');
+        if blank_line {
+            self.out.push('\n');
+        }
+        for _ in 0..indent {
+            self.out.push_str(INDENT);
+        }
+        self.indent = indent;
+    }
+
+    /// Breaks the line before the token or node starting at `next`.
+    fn line_break(&mut self, indent: usize, next: u32) {
+        // Comments on their own lines go at the same indentation level as the following code,
+        // unless they're followed by closing brackets.
+        let next_is_closing = self.code[next as usize..].starts_with([']', ')']);
+        let comment_indent = if next_is_closing { indent + 1 } else { indent };
+        self.comments_until(next, comment_indent);
+        self.newline(indent, next);
+    }
+
+    fn space_or_line_break(&mut self, line_break: bool, indent: usize, next: u32) {
+        if line_break {
+            self.line_break(indent, next);
+        } else {
+            self.out.push(' ');
+        }
+    }
+
+    fn token(&mut self, span: Span) {
+        if self
+            .comments
+            .get(self.next_comment)
+            .is_some_and(|comment| comment.start < span.start)
+        {
+            // Comments always end with a line break, and we can't remove it without
+            // commenting out the token.
+            self.line_break(self.indent, span.start);
+        }
+        self.out.push_str(span.slice(self.code));
+        self.last_end = span.end;
+    }
+
+    fn node(&mut self, node_id: NodeId) {
+        let ast = self.ast;
+        // Line breaks within the node are indented relative to the line the node starts on.
+        let base = self.indent;
+        let start = |node_id| ast.span(node_id).start;
+
+        match ast.kind(node_id) {
+            NodeKind::Nil => (),
+
+            NodeKind::Token
+            | NodeKind::Ident
+            | NodeKind::Tag
+            | NodeKind::Number
+            | NodeKind::Color
+            | NodeKind::Op
+            | NodeKind::Param
+            | NodeKind::Error => self.token(ast.span(node_id)),
+
+            NodeKind::List => {
+                let (Some(lbrack), Some(rbrack)) = (
+                    self.token_span(node_id, TokenKind::LBrack),
+                    self.token_span(node_id, TokenKind::RBrack),
+                ) else {
+                    return;
+                };
+                let broken = self.has_line_break(node_id);
+
+                self.token(lbrack);
+                let mut walk = ast.walk(node_id);
+                let mut first = true;
+                while let Some(element) = walk.node() {
+                    if broken {
+                        self.line_break(base + 1, start(element));
+                    } else if !first {
+                        self.out.push_str(", ");
+                    }
+                    self.node(element);
+                    first = false;
+                }
+                if broken && !first {
+                    self.line_break(base, rbrack.start);
+                }
+                self.token(rbrack);
+            }
+
+            NodeKind::Unary => {
+                let mut walk = ast.walk(node_id);
+                let (Some(op), Some(expr)) = (walk.node(), walk.node()) else {
+                    return;
+                };
+                self.node(op);
+                // Don't turn `- -x` into a comment.
+                if self.code[ast.span(op).start as usize..].starts_with('-')
+                    && self.code[start(expr) as usize..].starts_with('-')
+                {
+                    self.out.push(' ');
+                }
+                self.node(expr);
+            }
+
+            NodeKind::Binary => {
+                let mut walk = ast.walk(node_id);
+                let (Some(left), Some(op), Some(right)) = (walk.node(), walk.node(), walk.node())
+                else {
+                    return;
+                };
+                self.node(left);
+                self.out.push(' ');
+                self.node(op);
+                self.space_or_line_break(self.has_line_break(node_id), base + 1, start(right));
+                self.node(right);
+            }
+
+            NodeKind::Call => {
+                let mut walk = ast.walk(node_id);
+                if let Some(func) = walk.node() {
+                    self.node(func);
+                }
+                while let Some(arg) = walk.node() {
+                    self.out.push(' ');
+                    self.node(arg);
+                }
+            }
+
+            NodeKind::ParenEmpty => {
+                for (_, span) in self.tokens(node_id).collect::<Vec<_>>() {
+                    self.token(span);
+                }
+            }
+
+            NodeKind::Paren => {
+                let (Some(lparen), Some(inner), Some(rparen)) = (
+                    self.token_span(node_id, TokenKind::LParen),
+                    ast.walk(node_id).node(),
+                    self.token_span(node_id, TokenKind::RParen),
+                ) else {
+                    return;
+                };
+                let broken = self.has_line_break(node_id);
+
+                self.token(lparen);
+                if broken {
+                    self.line_break(base + 1, start(inner));
+                }
+                self.node(inner);
+                if broken {
+                    self.line_break(base, rparen.start);
+                }
+                self.token(rparen);
+            }
+
+            NodeKind::Lambda => {
+                let mut walk = ast.walk(node_id);
+                let (Some(backslash), Some(params), Some(arrow), Some(body)) = (
+                    self.token_span(node_id, TokenKind::Backslash),
+                    walk.node(),
+                    self.token_span(node_id, TokenKind::RArrow),
+                    walk.node(),
+                ) else {
+                    return;
+                };
+
+                self.token(backslash);
+                self.node(params);
+                self.out.push(' ');
+                self.token(arrow);
+                self.space_or_line_break(self.has_line_break(node_id), base + 1, start(body));
+                self.node(body);
+            }
+
+            NodeKind::Params => {
+                let mut walk = ast.walk(node_id);
+                let mut first = true;
+                while let Some(param) = walk.node() {
+                    if !first {
+                        self.out.push_str(", ");
+                    }
+                    self.node(param);
+                    first = false;
+                }
+            }
+
+            NodeKind::If => {
+                // Line breaks may appear after the condition, and before and after `else`.
+                let mut breaks = [false; 3];
+                let mut nodes = 0;
+                let mut else_span = None;
+                for &child in ast.children(node_id) {
+                    match ast.kind(child) {
+                        NodeKind::Token => match self.token_kind(ast.span(child)) {
+                            TokenKind::Else => else_span = Some(ast.span(child)),
+                            TokenKind::Newline if else_span.is_some() => breaks[2] = true,
+                            TokenKind::Newline if nodes == 2 => breaks[1] = true,
+                            TokenKind::Newline => breaks[0] = true,
+                            _ => (),
+                        },
+                        NodeKind::Nil | NodeKind::Error => (),
+                        _ => nodes += 1,
+                    }
+                }
+
+                let mut walk = ast.walk(node_id);
+                let (
+                    Some(if_span),
+                    Some(lparen),
+                    Some(condition),
+                    Some(rparen),
+                    Some(if_true),
+                    Some(else_span),
+                    Some(if_false),
+                ) = (
+                    self.token_span(node_id, TokenKind::If),
+                    self.token_span(node_id, TokenKind::LParen),
+                    walk.node(),
+                    self.token_span(node_id, TokenKind::RParen),
+                    walk.node(),
+                    else_span,
+                    walk.node(),
+                )
+                else {
+                    return;
+                };
+
+                self.token(if_span);
+                self.out.push(' ');
+                self.token(lparen);
+                self.node(condition);
+                self.token(rparen);
+                self.space_or_line_break(breaks[0], base + 1, start(if_true));
+                self.node(if_true);
+                self.space_or_line_break(breaks[1], base, else_span.start);
+                self.token(else_span);
+                self.space_or_line_break(breaks[2], base + 1, start(if_false));
+                self.node(if_false);
+            }
+
+            NodeKind::Let => {
+                let mut walk = ast.walk(node_id);
+                let (Some(let_span), Some(ident), Some(equal), Some(expr), Some(then)) = (
+                    self.token_span(node_id, TokenKind::Let),
+                    walk.node(),
+                    self.token_span(node_id, TokenKind::Equal),
+                    walk.node(),
+                    walk.node(),
+                ) else {
+                    return;
+                };
+
+                self.token(let_span);
+                self.out.push(' ');
+                self.node(ident);
+                self.out.push(' ');
+                self.token(equal);
+                self.out.push(' ');
+                self.node(expr);
+                self.line_break(base, start(then));
+                self.node(then);
+            }
+
+            NodeKind::Import => {
+                let (Some(import), Some(name)) = (
+                    self.token_span(node_id, TokenKind::Import),
+                    ast.walk(node_id).node(),
+                ) else {
+                    return;
+                };
+
+                self.token(import);
+                self.out.push(' ');
+                self.node(name);
+            }
+
+            NodeKind::ParamDecl => {
+                let mut walk = ast.walk(node_id);
+                let (Some(param), Some(ident), Some(equal), Some(default)) = (
+                    self.token_span(node_id, TokenKind::Param),
+                    walk.node(),
+                    self.token_span(node_id, TokenKind::Equal),
+                    walk.node(),
+                ) else {
+                    return;
+                };
+
+                self.token(param);
+                self.out.push(' ');
+                self.node(ident);
+                self.out.push(' ');
+                self.token(equal);
+                self.out.push(' ');
+                self.node(default);
+                if let Some(range) = walk.node() {
+                    self.out.push(' ');
+                    self.node(range);
+                }
+            }
+
+            NodeKind::Toplevel => {
+                let mut walk = ast.walk(node_id);
+                let mut first = true;
+                while let Some(expr) = walk.node() {
+                    if !first {
+                        self.line_break(base, start(expr));
+                    }
+                    self.node(expr);
+                    first = false;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
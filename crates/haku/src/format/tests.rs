@@ -0,0 +1,178 @@
+use alloc::{format, string::String};
+
+use crate::{
+    ast::{Ast, NodeId, NodeKind},
+    source::SourceCode,
+    testing::{parse_with_trivia, Parsed},
+};
+
+use super::format;
+
+/// Formats the code, and also checks that the formatted code means the same thing and is already
+/// formatted.
+fn fmt(s: &str) -> String {
+    let (formatted, structure) = fmt_once(s);
+    let (reformatted, new_structure) = fmt_once(&formatted);
+    assert_eq!(
+        structure, new_structure,
+        "formatting changed the meaning of the code"
+    );
+    assert_eq!(formatted, reformatted, "formatting is not idempotent");
+    formatted
+}
+
+/// Returns the formatted code, and the structure of its AST without tokens (which includes
+/// newlines.)
+fn fmt_once(s: &str) -> (String, String) {
+    let parsed = parse_with_trivia(s);
+    parsed.assert_no_diagnostics();
+    let Parsed {
+        code,
+        lexis,
+        ast,
+        root,
+        ..
+    } = parsed;
+
+    fn structure(ast: &Ast, code: &SourceCode, node: NodeId, out: &mut String) {
+        match ast.kind(node) {
+            NodeKind::Token => (),
+            NodeKind::Ident
+            | NodeKind::Tag
+            | NodeKind::Number
+            | NodeKind::Color
+            | NodeKind::Op
+            | NodeKind::Param => out.push_str(&format!(
+                "{:?}({}) ",
+                ast.kind(node),
+                ast.span(node).slice(code)
+            )),
+            kind => {
+                out.push_str(&format!("{kind:?}( "));
+                for &child in ast.children(node) {
+                    structure(ast, code, child, out);
+                }
+                out.push_str(") ");
+            }
+        }
+    }
+    let mut s = String::new();
+    structure(&ast, code, root, &mut s);
+
+    (format(code, &lexis, &ast, root), s)
+}
+
+#[track_caller]
+fn assert_fmt(s: &str, expected: &str) {
+    let got = fmt(s);
+    if got != expected {
+        panic!("formatting mismatch. expected:\n{expected}\ngot:\n{got}");
+    }
+}
+
+#[test]
+fn empty() {
+    assert_fmt("", "");
+    assert_fmt("\n\n", "");
+}
+
+#[test]
+fn spacing() {
+    assert_fmt("stroke   8 #000 ( vec 0  0 )", "stroke 8 #000 (vec 0 0)\n");
+    assert_fmt("1+2*  3", "1 + 2 * 3\n");
+    assert_fmt("!  x", "!x\n");
+    assert_fmt("- -x", "- -x\n");
+    assert_fmt("-(-x)", "-(-x)\n");
+    assert_fmt("f ( )", "f ()\n");
+    assert_fmt("\\ x ,y->x", "\\x, y -> x\n");
+    assert_fmt("if(a)b else c", "if (a) b else c\n");
+    assert_fmt("import   easing", "import easing\n");
+    assert_fmt("param size=-4[ -8,8 ]", "param size = -4 [-8, 8]\n");
+}
+
+#[test]
+fn toplevel_blank_lines() {
+    assert_fmt(
+        "\n\nx=1\ny  =  x\n\n\n\nz = y\n\n\nz\n\n",
+        "x = 1\ny = x\n\nz = y\n\nz\n",
+    );
+}
+
+#[test]
+fn indentation() {
+    assert_fmt(
+        r#"
+splat = \radius ->
+        fill #0001 (circle 0 0 radius)
+
+airbrush = \size ->
+    if (size > 0)
+            [
+      splat size
+      airbrush (size - 8)
+  ]
+ else
+       []
+
+airbrush 64
+"#,
+        r#"splat = \radius ->
+  fill #0001 (circle 0 0 radius)
+
+airbrush = \size ->
+  if (size > 0)
+    [
+      splat size
+      airbrush (size - 8)
+    ]
+  else
+    []
+
+airbrush 64
+"#,
+    );
+
+    assert_fmt(
+        "color =\n    if (radius < 16)\n  #00F\n    else\n  #F00",
+        "color =\n  if (radius < 16)\n    #00F\n  else\n    #F00\n",
+    );
+    assert_fmt(
+        "f = \\x,y->\n      let z = x+y\n        z",
+        "f = \\x, y ->\n  let z = x + y\n  z\n",
+    );
+    assert_fmt("x = 1 +\n        2 +\n  3", "x = 1 +\n  2 +\n  3\n");
+    assert_fmt("x = (\n1 + 2\n    )", "x = (\n  1 + 2\n)\n");
+}
+
+#[test]
+fn lists() {
+    assert_fmt("[1,2 ,3]", "[1, 2, 3]\n");
+    assert_fmt("[ ]", "[]\n");
+    assert_fmt("[\n]", "[]\n");
+    assert_fmt("[1, 2\n3]", "[\n  1\n  2\n  3\n]\n");
+    assert_fmt("[[1, 2], [\n3]]", "[[1, 2], [\n  3\n]]\n");
+}
+
+#[test]
+fn comments() {
+    assert_fmt(
+        "-- This is your brush.\n-- Try playing around.\n\nstroke 8 #000 (vec 0 0)",
+        "-- This is your brush.\n-- Try playing around.\n\nstroke 8 #000 (vec 0 0)\n",
+    );
+    assert_fmt(
+        "airbrush 64 -- sounds like some Nintendo 64 game   ",
+        "airbrush 64 -- sounds like some Nintendo 64 game\n",
+    );
+    assert_fmt(
+        "x = 1   -- one\n  -- two\ny = 2\n\n   -- the end",
+        "x = 1 -- one\n-- two\ny = 2\n\n-- the end\n",
+    );
+    assert_fmt(
+        "[\n-- first\n  1 -- one\n\n    -- last\n]",
+        "[\n  -- first\n  1 -- one\n\n  -- last\n]\n",
+    );
+    assert_fmt("f = \\x -> -- comment\n x", "f = \\x -> -- comment\n  x\n");
+    assert_fmt("[ -- nothing\n]", "[ -- nothing\n]\n");
+    assert_fmt("-- only a comment\n", "-- only a comment\n");
+    assert_fmt("x ---- comment\r\n", "x ---- comment\n");
+}
@@ -3,7 +3,7 @@ use alloc::vec::Vec;
 use crate::{
     diagnostic::Diagnostic,
     source::{SourceCode, Span},
-    token::{Lexis, TokenAllocError, TokenKind},
+    token::{Lexis, TokenAllocError, TokenKind, TriviaKind},
 };
 
 pub struct Lexer<'a> {
@@ -74,6 +74,8 @@ fn ident(l: &mut Lexer<'_>) -> TokenKind {
         "if" => TokenKind::If,
         "else" => TokenKind::Else,
         "let" => TokenKind::Let,
+        "import" => TokenKind::Import,
+        "param" => TokenKind::Param,
         _ => TokenKind::Ident,
     }
 }
@@ -143,6 +145,8 @@ fn whitespace_and_comments(l: &mut Lexer<'_>) {
                     while l.current() != '\n' && l.current() != '\0' {
                         l.advance();
                     }
+                    l.lexis
+                        .push_trivia(TriviaKind::Comment, Span::new(position, l.position));
                 } else {
                     // An unfortunate little bit of backtracking here;
                     // This seems like the simplest possible solution though.
@@ -154,7 +158,12 @@ fn whitespace_and_comments(l: &mut Lexer<'_>) {
                 }
             }
 
-            ' ' | '\r' | '\t' => l.advance(),
+            ' ' | '\r' | '\t' => {
+                let position = l.position;
+                l.advance();
+                l.lexis
+                    .push_trivia(TriviaKind::Whitespace, Span::new(position, l.position));
+            }
 
             _ => break,
         }
@@ -171,7 +180,10 @@ fn newline(l: &mut Lexer<'_>) -> (TokenKind, Span) {
     loop {
         whitespace_and_comments(l);
         if l.current() == '\n' {
+            let position = l.position;
             l.advance();
+            l.lexis
+                .push_trivia(TriviaKind::Whitespace, Span::new(position, l.position));
             continue;
         } else {
             break;
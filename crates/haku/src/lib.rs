@@ -5,12 +5,23 @@ extern crate alloc;
 pub mod ast;
 pub mod bytecode;
 pub mod compiler;
+pub mod debug_info;
 pub mod diagnostic;
+pub mod format;
 pub mod lexer;
+pub mod lint;
+pub mod module;
+pub mod param;
 pub mod parser;
 pub mod render;
+pub mod semantic;
+pub mod snapshot;
 pub mod source;
+pub mod stroke;
 pub mod system;
+#[cfg(test)]
+mod testing;
 pub mod token;
+pub mod types;
 pub mod value;
 pub mod vm;
@@ -0,0 +1,244 @@
+//! Lints, which point out code that compiles, but is likely not what was intended.
+//!
+//! Lints never emit errors, so they do not prevent code from being compiled and run.
+
+use alloc::{format, vec::Vec};
+
+use crate::{
+    ast::{scope::Scopes, NodeId, NodeKind},
+    compiler::Source,
+    diagnostic::Diagnostic,
+    source::Span,
+    system::SystemFnArity,
+};
+
+/// Lints the program. Diagnostics are returned in source order.
+pub fn lint(src: &Source, root: NodeId) -> Vec<Diagnostic> {
+    let mut l = Linter {
+        src,
+        defs: Vec::new(),
+        current_def: None,
+        scopes: Scopes::new(),
+        diagnostics: Vec::new(),
+    };
+    l.node(root);
+    l.diagnostics
+        .sort_by_key(|diagnostic| diagnostic.span().start);
+    l.diagnostics
+}
+
+struct Binding<'a> {
+    name: &'a str,
+    span: Span,
+    used: bool,
+}
+
+struct Linter<'a, 'b> {
+    src: &'b Source<'a>,
+    defs: Vec<Binding<'a>>,
+    /// The def whose body is being linted. References to a def from within its own body do not
+    /// count as uses of the def.
+    current_def: Option<&'a str>,
+    scopes: Scopes<'a, Binding<'a>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Names starting with an underscore are allowed to be unused.
+fn is_intentionally_unused(name: &str) -> bool {
+    name.starts_with('_')
+}
+
+impl<'a> Linter<'a, '_> {
+    fn name(&self, node_id: NodeId) -> &'a str {
+        self.src.ast.span(node_id).slice(self.src.code)
+    }
+
+    fn bind(&mut self, node_id: NodeId) {
+        let name = self.name(node_id);
+        let span = self.src.ast.span(node_id);
+        if let Some(shadowed) = self.scopes.get(name) {
+            self.diagnostics.push(
+                Diagnostic::hint(
+                    span,
+                    format!("`{name}` shadows a variable with the same name"),
+                )
+                .with_label(shadowed.span, "shadowed variable defined here"),
+            );
+        } else if let Some(shadowed) = self.defs.iter().find(|def| def.name == name) {
+            self.diagnostics.push(
+                Diagnostic::hint(span, format!("`{name}` shadows a def with the same name"))
+                    .with_label(shadowed.span, "shadowed def defined here"),
+            );
+        }
+        self.scopes.push(
+            name,
+            Binding {
+                name,
+                span,
+                used: false,
+            },
+        );
+    }
+
+    fn node(&mut self, node_id: NodeId) {
+        let ast = self.src.ast;
+        match ast.kind(node_id) {
+            NodeKind::Ident => {
+                let name = self.name(node_id);
+                if let Some(binding) = self.scopes.get_mut(name) {
+                    binding.used = true;
+                } else if self.current_def != Some(name) {
+                    if let Some(def) = self.defs.iter_mut().find(|def| def.name == name) {
+                        def.used = true;
+                    }
+                }
+            }
+
+            NodeKind::Number => {
+                let literal = self.name(node_id);
+                if literal
+                    .parse::<f32>()
+                    .is_ok_and(|number| number.is_infinite())
+                {
+                    self.diagnostics.push(Diagnostic::warning(
+                        ast.span(node_id),
+                        "this number is too large to be represented, and will be infinity",
+                    ));
+                }
+            }
+
+            NodeKind::Color => {
+                let digits = &self.name(node_id)[1..];
+                let alpha = match digits.len() {
+                    4 => &digits[3..],
+                    8 => &digits[6..],
+                    _ => "",
+                };
+                if !alpha.is_empty() && alpha.bytes().all(|digit| digit == b'0') {
+                    self.diagnostics.push(Diagnostic::warning(
+                        ast.span(node_id),
+                        "this color has zero alpha, so it is fully transparent",
+                    ));
+                }
+            }
+
+            NodeKind::Binary => {
+                let mut walk = ast.walk(node_id);
+                let (Some(left), Some(op), Some(right)) = (walk.node(), walk.node(), walk.node())
+                else {
+                    return;
+                };
+                if self.name(op) == "/"
+                    && ast.kind(right) == NodeKind::Number
+                    && self.name(right).parse::<f32>() == Ok(0.0)
+                {
+                    self.diagnostics.push(Diagnostic::warning(
+                        ast.span(node_id),
+                        "division by zero results in infinity or NaN",
+                    ));
+                }
+                self.node(left);
+                self.node(right);
+            }
+
+            NodeKind::Call => {
+                let mut walk = ast.walk(node_id);
+                if let Some(func) = walk.node() {
+                    let is_system_fn = ast.kind(func) == NodeKind::Ident
+                        && (self.src.system.resolve_fn)(SystemFnArity::Nary, self.name(func))
+                            .is_some();
+                    if !is_system_fn {
+                        self.node(func);
+                    }
+                }
+                while let Some(arg) = walk.node() {
+                    self.node(arg);
+                }
+            }
+
+            NodeKind::Lambda => {
+                let mut walk = ast.walk(node_id);
+                let scopes_len = self.scopes.len();
+                if let Some(params) = walk.node() {
+                    let mut params_walk = ast.walk(params);
+                    while let Some(param) = params_walk.node() {
+                        self.bind(param);
+                    }
+                }
+                if let Some(body) = walk.node() {
+                    self.node(body);
+                }
+                // Unused parameters are fine, since the caller decides what gets passed in.
+                self.scopes.truncate(scopes_len);
+            }
+
+            NodeKind::Let => {
+                let mut walk = ast.walk(node_id);
+                let (Some(ident), Some(expr)) = (walk.node(), walk.node()) else {
+                    return;
+                };
+                self.node(expr);
+                self.bind(ident);
+                if let Some(then) = walk.node() {
+                    self.node(then);
+                }
+                if let Some((_, binding)) = self.scopes.pop() {
+                    if !binding.used && !is_intentionally_unused(binding.name) {
+                        self.diagnostics.push(Diagnostic::warning(
+                            binding.span,
+                            format!("unused variable `{}`", binding.name),
+                        ));
+                    }
+                }
+            }
+
+            NodeKind::Toplevel => {
+                let mut walk = ast.walk(node_id);
+                while let Some(toplevel_expr) = walk.node() {
+                    if let Some(ident) = ast.def_ident(self.src.code, toplevel_expr) {
+                        self.defs.push(Binding {
+                            name: self.name(ident),
+                            span: ast.span(ident),
+                            used: false,
+                        });
+                    }
+                }
+
+                let mut walk = ast.walk(node_id);
+                while let Some(toplevel_expr) = walk.node() {
+                    if let Some(ident) = ast.def_ident(self.src.code, toplevel_expr) {
+                        self.current_def = Some(self.name(ident));
+                        let mut walk = ast.walk(toplevel_expr);
+                        if let (Some(_ident), Some(_op), Some(expr)) =
+                            (walk.node(), walk.node(), walk.node())
+                        {
+                            self.node(expr);
+                        }
+                        self.current_def = None;
+                    } else {
+                        self.node(toplevel_expr);
+                    }
+                }
+
+                for def in &self.defs {
+                    if !def.used && !is_intentionally_unused(def.name) {
+                        self.diagnostics.push(Diagnostic::warning(
+                            def.span,
+                            format!("unused def `{}`", def.name),
+                        ));
+                    }
+                }
+            }
+
+            _ => {
+                let mut walk = ast.walk(node_id);
+                while let Some(child) = walk.node() {
+                    self.node(child);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,103 @@
+use alloc::{format, string::String, vec::Vec};
+
+use crate::{system::System, testing::parse};
+
+use super::lint;
+
+/// Returns `source: message` for every diagnostic, with the severity in front and labels after.
+fn lints(s: &str) -> Vec<String> {
+    let parsed = parse(s);
+    parsed.assert_no_diagnostics();
+    let code = parsed.code;
+
+    let system = System::new(1);
+    lint(&parsed.source(&system), parsed.root)
+        .into_iter()
+        .map(|d| {
+            let mut line = format!(
+                "{:?} {}: {}",
+                d.severity(),
+                d.span().slice(code),
+                d.message()
+            );
+            for label in d.labels() {
+                line += &format!(
+                    " ({}..{}: {})",
+                    label.span.start, label.span.end, label.message
+                );
+            }
+            line
+        })
+        .collect()
+}
+
+#[test]
+fn clean() {
+    assert!(lints("stroke 8 #000 (vec 0 0)").is_empty());
+    assert!(lints("f = \\x -> x\nlet y = 2\nf y").is_empty());
+    // Unused parameters are fine.
+    assert!(lints("(\\x, y -> x) 1 2").is_empty());
+}
+
+#[test]
+fn unused() {
+    assert_eq!(lints("x = 1\n2"), ["Warning x: unused def `x`"]);
+    assert_eq!(lints("let x = 1\n2"), ["Warning x: unused variable `x`"]);
+    assert!(lints("_x = 1\nlet _y = 2\n3").is_empty());
+
+    // Recursion doesn't count as a use.
+    assert_eq!(
+        lints("f = \\x -> if (x > 0) f (x - 1) else 0\n1"),
+        ["Warning f: unused def `f`"]
+    );
+    assert!(lints("f = \\x -> if (x > 0) f (x - 1) else 0\nf 1").is_empty());
+
+    // Defs can be used before they're defined.
+    assert!(lints("f = \\x -> g x\ng = \\x -> x\nf 1").is_empty());
+}
+
+#[test]
+fn shadowing() {
+    assert_eq!(
+        lints("let x = 1\nlet x = x + 1\nx"),
+        ["Hint x: `x` shadows a variable with the same name (4..5: shadowed variable defined here)"]
+    );
+    assert_eq!(
+        lints("x = 1\n(\\x -> x) x"),
+        ["Hint x: `x` shadows a def with the same name (0..1: shadowed def defined here)"]
+    );
+}
+
+#[test]
+fn suspicious_numbers() {
+    assert_eq!(
+        lints("1 / 0"),
+        ["Warning 1 / 0: division by zero results in infinity or NaN"]
+    );
+    assert_eq!(
+        lints("1 / 0.0"),
+        ["Warning 1 / 0.0: division by zero results in infinity or NaN"]
+    );
+    assert!(lints("0 / 1").is_empty());
+
+    let huge = "1".repeat(40);
+    assert_eq!(
+        lints(&huge),
+        [format!(
+            "Warning {huge}: this number is too large to be represented, and will be infinity"
+        )]
+    );
+}
+
+#[test]
+fn transparent_colors() {
+    assert_eq!(
+        lints("[#FFF0, #00000000, #0000]"),
+        [
+            "Warning #FFF0: this color has zero alpha, so it is fully transparent",
+            "Warning #00000000: this color has zero alpha, so it is fully transparent",
+            "Warning #0000: this color has zero alpha, so it is fully transparent",
+        ]
+    );
+    assert!(lints("[#000, #000000, #0001, #00000001]").is_empty());
+}
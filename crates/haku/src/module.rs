@@ -0,0 +1,341 @@
+//! Modules: haku code compiled into its own chunk, whose defs can be imported by other code with
+//! `import name`.
+//!
+//! Imports are not resolved by the compiler itself; instead, the host looks up the source code of
+//! each imported module using a [`Resolver`], and compiles it before the importing code, using a
+//! [`Loader`]. Every module gets its own [`Namespace`] in [`Defs`], so modules may define defs
+//! with the same names as the code importing them.
+//!
+//! Before code which imports modules can be run, the modules' chunks have to be run, to set
+//! their defs. See [`init`].
+
+use alloc::{collections::BTreeMap, format, string::String, vec::Vec};
+
+use crate::{
+    ast::{Ast, NodeId, NodeKind},
+    bytecode::{Chunk, Defs, Namespace},
+    compiler::{compile_expr, ClosureSpec, CompileError, Compiler, Source},
+    diagnostic::Diagnostic,
+    lexer::{lex, Lexer},
+    parser::{self, IntoAstError, Parser, ParserLimits},
+    source::{SourceCode, Span},
+    stroke,
+    system::{ChunkError, ChunkId, System},
+    token::Lexis,
+    value::Ref,
+    vm::{Exception, Vm},
+};
+
+/// The name of the module implicitly imported into all code.
+pub const PRELUDE: &str = "prelude";
+
+/// Looks up the source code of modules.
+pub trait Resolver {
+    fn resolve(&self, name: &str) -> Option<&str>;
+}
+
+/// Resolves modules by name from a list of `(name, code)` pairs.
+impl Resolver for [(&str, &str)] {
+    fn resolve(&self, name: &str) -> Option<&str> {
+        self.iter()
+            .find(|(module_name, _)| *module_name == name)
+            .map(|&(_, code)| code)
+    }
+}
+
+/// Resolves modules by name from a map of names to code, such as modules supplied by the host.
+impl Resolver for BTreeMap<String, String> {
+    fn resolve(&self, name: &str) -> Option<&str> {
+        self.get(name).map(|code| code.as_str())
+    }
+}
+
+/// Resolves modules using the first resolver, falling back to the second one for modules the first
+/// one doesn't know about.
+impl<A, B> Resolver for (&A, &B)
+where
+    A: Resolver + ?Sized,
+    B: Resolver + ?Sized,
+{
+    fn resolve(&self, name: &str) -> Option<&str> {
+        self.0.resolve(name).or_else(|| self.1.resolve(name))
+    }
+}
+
+/// Modules bundled with haku, including the prelude.
+pub const BUNDLED: &[(&str, &str)] = &[
+    (PRELUDE, include_str!("module/prelude.haku")),
+    ("easing", include_str!("module/easing.haku")),
+];
+
+/// Limits for compiling a single module. These should match the limits of the code importing the
+/// module.
+#[derive(Debug, Clone, Copy)]
+pub struct ModuleLimits {
+    pub max_source_code_len: u32,
+    pub max_tokens: usize,
+    pub max_parser_events: usize,
+    pub ast_capacity: usize,
+    pub chunk_capacity: usize,
+}
+
+/// A module compiled into a chunk.
+#[derive(Debug, Clone, Copy)]
+pub struct Module {
+    pub namespace: Namespace,
+    pub chunk_id: ChunkId,
+    pub closure_spec: ClosureSpec,
+}
+
+/// A module which failed to load, along with its namespace if it got far enough to have one.
+struct LoadFailure {
+    diagnostic: Diagnostic,
+    namespace: Option<Namespace>,
+}
+
+impl From<Diagnostic> for LoadFailure {
+    fn from(diagnostic: Diagnostic) -> Self {
+        Self {
+            diagnostic,
+            namespace: None,
+        }
+    }
+}
+
+/// Loads modules and their imports, compiling each module only once.
+pub struct Loader<'a, R: ?Sized> {
+    resolver: &'a R,
+    limits: ModuleLimits,
+    /// Implicitly imported by all code, if it's been loaded.
+    prelude: Option<Namespace>,
+    /// Loaded modules, in the order they must be initialized in.
+    loaded: Vec<(String, Module)>,
+    /// Modules whose imports are currently being loaded, used for detecting import cycles.
+    loading: Vec<String>,
+}
+
+impl<'a, R: Resolver + ?Sized> Loader<'a, R> {
+    pub fn new(resolver: &'a R, limits: ModuleLimits, prelude: Option<Namespace>) -> Self {
+        Self {
+            resolver,
+            limits,
+            prelude,
+            loaded: Vec::new(),
+            loading: Vec::new(),
+        }
+    }
+
+    /// Returns the loaded modules, in the order they must be initialized in.
+    pub fn modules(&self) -> impl Iterator<Item = Module> + '_ {
+        self.loaded.iter().map(|&(_, module)| module)
+    }
+
+    /// Loads all modules imported by the program, and then begins a new namespace for the program
+    /// itself, with the prelude and imported modules visible from it.
+    ///
+    /// Diagnostics are reported at the `import` that caused them.
+    pub fn import_all(
+        &mut self,
+        system: &mut System,
+        defs: &mut Defs,
+        code: &SourceCode,
+        ast: &Ast,
+        root: NodeId,
+    ) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        let mut namespaces = Vec::new();
+        let mut walk = ast.walk(root);
+        while let Some(import) = walk.node_of(NodeKind::Import) {
+            let Some(name) = ast.walk(import).node() else {
+                continue;
+            };
+            let span = ast.span(name);
+            match self.load_module(system, defs, span.slice(code), span) {
+                Ok(module) => namespaces.push(module.namespace),
+                Err(failure) => {
+                    // The module's defs are still imported, to avoid cascading errors about them
+                    // being undefined.
+                    namespaces.extend(failure.namespace);
+                    diagnostics.push(failure.diagnostic);
+                }
+            }
+        }
+
+        defs.begin_namespace();
+        if let Some(prelude) = self.prelude {
+            defs.import(prelude);
+        }
+        for namespace in namespaces {
+            defs.import(namespace);
+        }
+
+        diagnostics
+    }
+
+    /// Loads the module with the given name, along with everything it imports.
+    /// If loading fails, the returned diagnostic is reported at `span`.
+    pub fn load(
+        &mut self,
+        system: &mut System,
+        defs: &mut Defs,
+        name: &str,
+        span: Span,
+    ) -> Result<Module, Diagnostic> {
+        self.load_module(system, defs, name, span)
+            .map_err(|failure| failure.diagnostic)
+    }
+
+    fn load_module(
+        &mut self,
+        system: &mut System,
+        defs: &mut Defs,
+        name: &str,
+        span: Span,
+    ) -> Result<Module, LoadFailure> {
+        if let Some(&(_, module)) = self.loaded.iter().find(|(n, _)| n == name) {
+            return Ok(module);
+        }
+
+        if let Some(start) = self.loading.iter().position(|n| n == name) {
+            let mut cycle = String::new();
+            for module_name in &self.loading[start..] {
+                cycle += &format!("`{module_name}` -> ");
+            }
+            cycle += &format!("`{name}`");
+            return Err(
+                Diagnostic::error(span, "modules cannot import each other in a cycle")
+                    .with_note(format!("import cycle: {cycle}"))
+                    .into(),
+            );
+        }
+
+        let Some(code) = self.resolver.resolve(name) else {
+            return Err(Diagnostic::error(span, format!("no module named `{name}` exists")).into());
+        };
+
+        self.loading.push(name.into());
+        let result = self.compile(system, defs, name, code, span);
+        self.loading.pop();
+
+        let module = result?;
+        self.loaded.push((name.into(), module));
+        Ok(module)
+    }
+
+    fn compile(
+        &mut self,
+        system: &mut System,
+        defs: &mut Defs,
+        name: &str,
+        code: &str,
+        span: Span,
+    ) -> Result<Module, LoadFailure> {
+        let too_large =
+            |what: &str| Diagnostic::error(span, format!("module `{name}` is too large: {what}"));
+
+        let code = SourceCode::limited_len(code, self.limits.max_source_code_len)
+            .ok_or_else(|| too_large("source code is too long"))?;
+
+        let mut lexer = Lexer::new(Lexis::new(self.limits.max_tokens), code);
+        lex(&mut lexer).map_err(|_| too_large("too many tokens"))?;
+
+        let mut parser = Parser::new(
+            &lexer.lexis,
+            &ParserLimits {
+                max_events: self.limits.max_parser_events,
+            },
+        );
+        parser::toplevel(&mut parser);
+        let mut ast = Ast::new(self.limits.ast_capacity);
+        let (root, mut parser_diagnostics) =
+            parser.into_ast(&mut ast).map_err(|error| match error {
+                IntoAstError::NodeAlloc(_) => too_large("too many AST nodes"),
+                IntoAstError::TooManyEvents => too_large("too many parser events"),
+                IntoAstError::UnbalancedEvents => too_large("parser produced unbalanced events"),
+            })?;
+
+        let mut import_diagnostics = self.import_all(system, defs, code, &ast, root);
+        let namespace = defs.current_namespace();
+
+        let src = Source {
+            code,
+            ast: &ast,
+            system,
+        };
+        let mut chunk = Chunk::new(self.limits.chunk_capacity)
+            .expect("chunk capacity must be representable as a 16-bit number");
+        let mut compiler = Compiler::new(defs, &mut chunk);
+        compile_expr(&mut compiler, &src, root).map_err(|error| match error {
+            CompileError::Emit => too_large("bytecode does not fit in a chunk"),
+        })?;
+        let closure_spec = compiler.closure_spec();
+
+        let mut diagnostics = lexer.diagnostics;
+        diagnostics.append(&mut parser_diagnostics);
+        diagnostics.append(&mut import_diagnostics);
+        diagnostics.append(&mut compiler.diagnostics);
+        if diagnostics.iter().any(|diagnostic| diagnostic.is_error()) {
+            let mut diagnostic =
+                Diagnostic::error(span, format!("module `{name}` could not be compiled"));
+            for error in diagnostics.iter().filter(|d| d.is_error()) {
+                let (line, column) = line_column(code, error.span().start);
+                diagnostic =
+                    diagnostic.with_note(format!("{name}:{line}:{column}: {}", error.message()));
+                // Errors from modules imported by this one come with notes of their own.
+                for note in error.notes() {
+                    diagnostic = diagnostic.with_note(note.clone());
+                }
+            }
+            return Err(LoadFailure {
+                diagnostic,
+                namespace: Some(namespace),
+            });
+        }
+
+        let chunk_id =
+            system
+                .add_chunk(chunk, defs, closure_spec)
+                .map_err(|error| match error {
+                    ChunkError::TooManyChunks => {
+                        Diagnostic::error(span, "too many modules are imported")
+                    }
+                    ChunkError::Verify(error) => Diagnostic::error(
+                        span,
+                        format!("module `{name}` compiled to invalid bytecode: {error}"),
+                    ),
+                })?;
+
+        Ok(Module {
+            namespace,
+            chunk_id,
+            closure_spec,
+        })
+    }
+}
+
+/// Returns the 1-based line and column of a byte offset.
+fn line_column(code: &str, offset: u32) -> (usize, usize) {
+    let before = &code[..offset as usize];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map(|i| i + 1).unwrap_or(0) + 1;
+    (line, column)
+}
+
+/// Runs the chunks of modules, setting their defs in the VM.
+/// This has to be done after `Vm::apply_defs` and before running any code importing the modules.
+///
+/// Only the part of the modules' code selected by the VM's [`EvalMode`][stroke::EvalMode] is run.
+pub fn init(vm: &mut Vm, system: &System, modules: &[Module]) -> Result<(), Exception> {
+    for module in modules {
+        let Some(closure) = stroke::toplevel(module.chunk_id, module.closure_spec, vm.eval_mode())
+        else {
+            continue;
+        };
+        let closure_id = vm.create_ref(Ref::Closure(closure))?;
+        vm.run(system, closure_id)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,177 @@
+use alloc::{string::String, vec, vec::Vec};
+
+use crate::{
+    bytecode::Defs,
+    system::System,
+    testing::{compile_with, host_modules, load_prelude, Imports, Options, VM_LIMITS},
+    value::{Ref, Value},
+    vm::Vm,
+};
+
+use super::{init, BUNDLED};
+
+/// Evaluates the code with the given modules (and the bundled ones) available for import, the same
+/// way hosts supply their own modules.
+/// Returns the messages and notes of error diagnostics if the code doesn't compile.
+fn eval_with_modules(modules: &[(&str, &str)], code: &str) -> Result<Value, Vec<String>> {
+    let host = host_modules(modules);
+    let mut system = System::new(16);
+    let mut defs = Defs::new(256);
+
+    let prelude = load_prelude(&mut system, &mut defs);
+    let compiled = compile_with(
+        code,
+        &mut system,
+        &mut defs,
+        Options {
+            imports: Some(Imports {
+                resolver: &(BUNDLED, &host),
+                prelude: prelude.namespace,
+            }),
+            ..Default::default()
+        },
+    );
+
+    let errors: Vec<String> = compiled
+        .diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.is_error())
+        .flat_map(|diagnostic| {
+            [String::from(diagnostic.message())]
+                .into_iter()
+                .chain(diagnostic.notes().iter().cloned())
+        })
+        .collect();
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let modules: Vec<_> = [prelude]
+        .into_iter()
+        .chain(compiled.modules.iter().copied())
+        .collect();
+    let closure = compiled.add_to(&mut system, &defs);
+    let mut vm = Vm::new(&defs, &VM_LIMITS);
+    init(&mut vm, &system, &modules).unwrap();
+    let closure_id = vm.create_ref(Ref::Closure(closure)).unwrap();
+    Ok(vm.run(&system, closure_id).unwrap())
+}
+
+fn eval(code: &str) -> Result<Value, Vec<String>> {
+    eval_with_modules(&[], code)
+}
+
+#[test]
+fn prelude() {
+    assert_eq!(eval("clamp 5 0 1"), Ok(Value::Number(1.0)));
+    assert_eq!(eval("lerp 0 10 0.5"), Ok(Value::Number(5.0)));
+    assert_eq!(eval("max (min 3 2) 1"), Ok(Value::Number(2.0)));
+    assert_eq!(
+        eval("vecY (subv (addv (vec 1 2) (vec 3 4)) (vec 0 1))"),
+        Ok(Value::Number(5.0))
+    );
+}
+
+#[test]
+fn defs_shadow_prelude() {
+    assert_eq!(eval("min = \\a, b -> 42\nmin 1 2"), Ok(Value::Number(42.0)));
+}
+
+#[test]
+fn bundled() {
+    assert_eq!(
+        eval("import easing\nsmoothstep 0 1 0.5"),
+        Ok(Value::Number(0.5))
+    );
+}
+
+#[test]
+fn easing_with_lerp() {
+    // The usage suggested by the easing module's documentation.
+    assert_eq!(
+        eval("import easing\nlerp 0 10 (easeInQuad 0.5)"),
+        Ok(Value::Number(2.5))
+    );
+    assert_eq!(
+        eval("import easing\nrgbaR (lerpRgba #000 #FFF (easeInQuad 0.5))"),
+        Ok(Value::Number(0.25))
+    );
+}
+
+#[test]
+fn bundled_modules_cannot_be_replaced() {
+    let modules = [("easing", "smoothstep = \\a, b, t -> 42")];
+    assert_eq!(
+        eval_with_modules(&modules, "import easing\nsmoothstep 0 1 0.5"),
+        Ok(Value::Number(0.5))
+    );
+}
+
+#[test]
+fn namespaces() {
+    let modules = [("a", "x = 1\ngetX = \\_ -> x")];
+    assert_eq!(
+        eval_with_modules(&modules, "import a\nx = 2\nx + getX ()"),
+        Ok(Value::Number(3.0))
+    );
+}
+
+#[test]
+fn imports_are_not_transitive() {
+    let modules = [("a", "x = 1"), ("b", "import a\ny = x + 1")];
+    assert_eq!(
+        eval_with_modules(&modules, "import b\ny"),
+        Ok(Value::Number(2.0))
+    );
+    assert_eq!(
+        eval_with_modules(&modules, "import b\nx"),
+        Err(vec!["undefined variable".into()])
+    );
+}
+
+#[test]
+fn shared_imports_load_once() {
+    let modules = [
+        ("a", "import c\nfromA = c"),
+        ("b", "import c\nfromB = c"),
+        ("c", "c = 1"),
+    ];
+    assert_eq!(
+        eval_with_modules(&modules, "import a\nimport b\nfromA + fromB"),
+        Ok(Value::Number(2.0))
+    );
+}
+
+#[test]
+fn missing_module() {
+    assert_eq!(
+        eval("import nope\n1"),
+        Err(vec!["no module named `nope` exists".into()])
+    );
+}
+
+#[test]
+fn cycles() {
+    let modules = [("a", "import b\nx = 1"), ("b", "import a\ny = 1")];
+    assert_eq!(
+        eval_with_modules(&modules, "import a\nx"),
+        Err(vec![
+            "module `a` could not be compiled".into(),
+            "a:1:8: module `b` could not be compiled".into(),
+            "b:1:8: modules cannot import each other in a cycle".into(),
+            "import cycle: `a` -> `b` -> `a`".into(),
+        ])
+    );
+}
+
+#[test]
+fn module_errors() {
+    let modules = [("broken", "x = 1\ny = z")];
+    assert_eq!(
+        eval_with_modules(&modules, "import broken\nx"),
+        Err(vec![
+            "module `broken` could not be compiled".into(),
+            "broken:2:5: undefined variable".into(),
+        ])
+    );
+}
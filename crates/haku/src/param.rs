@@ -0,0 +1,101 @@
+//! Brush parameters: values declared in code with `param name = default`, which the artist can
+//! tweak without editing the code.
+//!
+//! The compiler records each parameter's metadata in [`Compiler::params`][crate::compiler::Compiler::params],
+//! and does not emit any code setting their defs. Instead, the host has to set them using [`apply`]
+//! before running the brush.
+
+use alloc::string::String;
+
+use crate::{
+    bytecode::DefId,
+    value::{Rgba, Value},
+    vm::{Exception, Vm},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParamValue {
+    Number(f32),
+    Rgba(Rgba),
+    Boolean(bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ParamKind {
+    Number,
+    Rgba,
+    Boolean,
+}
+
+impl ParamValue {
+    pub fn kind(&self) -> ParamKind {
+        match self {
+            ParamValue::Number(_) => ParamKind::Number,
+            ParamValue::Rgba(_) => ParamKind::Rgba,
+            ParamValue::Boolean(_) => ParamKind::Boolean,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub def_id: DefId,
+    pub default: ParamValue,
+    /// The inclusive `(min, max)` range of a number parameter, if it has one.
+    pub range: Option<(f32, f32)>,
+}
+
+impl Param {
+    pub fn kind(&self) -> ParamKind {
+        self.default.kind()
+    }
+
+    /// Returns the value the parameter takes on when `value` is supplied for it.
+    /// Values of the wrong kind (and NaNs) are replaced with the default, and numbers are clamped to the
+    /// parameter's range.
+    pub fn sanitize(&self, value: Option<ParamValue>) -> ParamValue {
+        match value {
+            Some(ParamValue::Number(x)) if self.kind() == ParamKind::Number && !x.is_nan() => {
+                ParamValue::Number(match self.range {
+                    Some((min, max)) => x.clamp(min, max),
+                    None => x,
+                })
+            }
+            Some(ParamValue::Rgba(rgba)) if self.kind() == ParamKind::Rgba => {
+                ParamValue::Rgba(Rgba {
+                    r: rgba.r.clamp(0.0, 1.0),
+                    g: rgba.g.clamp(0.0, 1.0),
+                    b: rgba.b.clamp(0.0, 1.0),
+                    a: rgba.a.clamp(0.0, 1.0),
+                })
+            }
+            Some(value @ ParamValue::Boolean(_)) if self.kind() == ParamKind::Boolean => value,
+            _ => self.default,
+        }
+    }
+}
+
+/// Sets the defs of parameters to the values returned by `value_of`, or their defaults.
+/// `value_of` is called once for each parameter, in order.
+/// This has to be done after `Vm::apply_defs` and before running the brush.
+pub fn apply(
+    vm: &mut Vm,
+    params: &[Param],
+    mut value_of: impl FnMut(&Param) -> Option<ParamValue>,
+) -> Result<(), Exception> {
+    for param in params {
+        let value = match param.sanitize(value_of(param)) {
+            ParamValue::Number(x) => Value::Number(x),
+            ParamValue::Rgba(rgba) => vm.create_rgba(rgba)?,
+            ParamValue::Boolean(false) => Value::False,
+            ParamValue::Boolean(true) => Value::True,
+        };
+        vm.set_def(param.def_id, value);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests;
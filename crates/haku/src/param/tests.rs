@@ -0,0 +1,127 @@
+use alloc::{string::String, vec::Vec};
+
+use crate::{
+    bytecode::Defs,
+    system::System,
+    testing::{self, load, Compiled, Loaded},
+    value::{Rgba, Value},
+};
+
+use super::{apply, ParamKind, ParamValue};
+
+fn compile(code: &str) -> Compiled {
+    testing::compile(code, &mut System::new(1), &mut Defs::new(256))
+}
+
+fn errors(code: &str) -> Vec<String> {
+    compile(code).errors()
+}
+
+/// Runs the code with the given parameter values, returning the number it evaluates to.
+fn eval(code: &str, values: &[(&str, ParamValue)]) -> f32 {
+    let Loaded {
+        system,
+        mut vm,
+        closure_id,
+        params,
+        ..
+    } = load(code);
+    apply(&mut vm, &params, |param| {
+        values
+            .iter()
+            .find(|(name, _)| *name == param.name)
+            .map(|&(_, value)| value)
+    })
+    .unwrap();
+    match vm.run(&system, closure_id).unwrap() {
+        Value::Number(x) => x,
+        value => panic!("expected a number, got {value:?}"),
+    }
+}
+
+#[test]
+fn metadata() {
+    let params = compile(
+        "param size = 4 [1, 64]\nparam offset = -2\nparam color = #F00\nparam hollow = True\n0",
+    )
+    .params;
+    let summary: Vec<_> = params
+        .iter()
+        .map(|param| (param.name.as_str(), param.default, param.range))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            ("size", ParamValue::Number(4.0), Some((1.0, 64.0))),
+            ("offset", ParamValue::Number(-2.0), None),
+            (
+                "color",
+                ParamValue::Rgba(Rgba {
+                    r: 1.0,
+                    g: 0.0,
+                    b: 0.0,
+                    a: 1.0
+                }),
+                None
+            ),
+            ("hollow", ParamValue::Boolean(true), None),
+        ]
+    );
+    assert_eq!(params[3].kind(), ParamKind::Boolean);
+}
+
+#[test]
+fn values() {
+    let code = "param size = 4 [1, 64]\nparam double = False\nif (double) size * 2 else size";
+    assert_eq!(eval(code, &[]), 4.0);
+    assert_eq!(eval(code, &[("size", ParamValue::Number(10.0))]), 10.0);
+    assert_eq!(eval(code, &[("double", ParamValue::Boolean(true))]), 8.0);
+    // Numbers are clamped to the range, and values of the wrong kind are ignored.
+    assert_eq!(eval(code, &[("size", ParamValue::Number(100.0))]), 64.0);
+    assert_eq!(eval(code, &[("size", ParamValue::Boolean(true))]), 4.0);
+    assert_eq!(eval(code, &[("size", ParamValue::Number(f32::NAN))]), 4.0);
+}
+
+#[test]
+fn params_are_defs() {
+    assert_eq!(
+        eval("twice = \\x -> x * size\nparam size = 2\ntwice 3", &[]),
+        6.0
+    );
+    assert_eq!(
+        errors("param size = 1\nsize = 2\nsize"),
+        ["a def with this name already exists"]
+    );
+}
+
+#[test]
+fn errors_in_declarations() {
+    assert_eq!(
+        errors("param size = 1 + 1\nsize"),
+        ["the default value of a parameter must be a single literal, optionally followed by a `[min, max]` range"]
+    );
+    assert_eq!(
+        errors("x = 1\nparam size = x\nsize"),
+        ["the default value of a parameter must be a number, color, or boolean literal"]
+    );
+    assert_eq!(
+        errors("param color = #000 [0, 1]\ncolor"),
+        ["only number parameters can have a range"]
+    );
+    assert_eq!(
+        errors("param size = 1 [0]\nsize"),
+        ["the range of a parameter must be a list of two numbers: `[min, max]`"]
+    );
+    assert_eq!(
+        errors("param size = 1 [4, 2]\nsize"),
+        ["the minimum of the range must not be greater than its maximum"]
+    );
+    assert_eq!(
+        errors("param size = 10 [1, 8]\nsize"),
+        ["the default value of a parameter must be within its range"]
+    );
+    assert_eq!(
+        errors("f = \\x -> (param)\nf"),
+        ["`param` may only appear at the top level of the program"]
+    );
+}
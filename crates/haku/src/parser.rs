@@ -1,7 +1,7 @@
 use core::{cell::Cell, error::Error, fmt};
 
 use alloc::vec::Vec;
-use log::{error, info};
+use log::error;
 
 use crate::{
     ast::{Ast, NodeAllocError, NodeId, NodeKind},
@@ -136,6 +136,27 @@ impl<'a> Parser<'a> {
         self.tokens.span(self.position)
     }
 
+    /// Returns whether the current token is a line break, after which a def `name = ...` starts.
+    ///
+    /// Defs may only appear at the top level, so a def starting on the next line means that the
+    /// current expression is incomplete. Stopping there lets the parser recover, and report errors
+    /// in the following defs independently.
+    fn newline_before_def(&self) -> bool {
+        let kind = |offset: usize| {
+            self.tokens
+                .kinds
+                .get(self.position as usize + offset)
+                .copied()
+                .unwrap_or(TokenKind::Eof)
+        };
+        kind(0) == TokenKind::Newline && kind(1) == TokenKind::Ident && kind(2) == TokenKind::Equal
+    }
+
+    /// Returns whether the token after the current one is of the given kind.
+    fn next_is(&self, kind: TokenKind) -> bool {
+        self.tokens.kinds.get(self.position as usize + 1) == Some(&kind)
+    }
+
     fn emit(&mut self, diagnostic: Diagnostic) {
         if self.diagnostics.len() < self.diagnostics.capacity() {
             self.diagnostics.push(diagnostic);
@@ -158,6 +179,25 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Skips tokens until the end of the line or one of the `until` tokens, wrapping them all in a
+    /// single error node. Bracketed tokens are skipped over as a whole.
+    #[track_caller]
+    fn skip_until(&mut self, until: TokenKindSet) -> Closed {
+        let opened = self.open();
+        let mut depth = 0_usize;
+        loop {
+            match self.peek() {
+                TokenKind::Eof | TokenKind::Newline => break,
+                kind if depth == 0 && until.contains(kind) => break,
+                TokenKind::LParen | TokenKind::LBrack => depth += 1,
+                TokenKind::RParen | TokenKind::RBrack => depth = depth.saturating_sub(1),
+                _ => (),
+            }
+            self.advance();
+        }
+        self.close(opened, NodeKind::Error)
+    }
+
     pub fn into_ast(self, ast: &mut Ast) -> Result<(NodeId, Vec<Diagnostic>), IntoAstError> {
         // If events are at capacity, that means the pool was exhausted and we return an error.
         if self.events.len() == self.events.capacity() {
@@ -166,6 +206,7 @@ impl<'a> Parser<'a> {
         }
 
         let mut token = 0;
+        let mut next_trivia = 0;
         let mut events = self.events;
         let mut stack = Vec::new();
 
@@ -205,6 +246,13 @@ impl<'a> Parser<'a> {
                 EventKind::Advance => {
                     let span = self.tokens.span(token);
                     let node_id = ast.alloc(NodeKind::Token, span)?;
+                    if let Some(trivia) = &self.tokens.trivia {
+                        let end = next_trivia
+                            + trivia[next_trivia..]
+                                .partition_point(|trivia| trivia.span.start < span.start);
+                        ast.attach_trivia(node_id, &trivia[next_trivia..end]);
+                        next_trivia = end;
+                    }
                     stack
                         .last_mut()
                         .expect("advance() may only be used in an open node")
@@ -226,6 +274,9 @@ impl<'a> Parser<'a> {
         let stack_entry = stack.pop().unwrap();
         ast.alloc_children(stack_entry.node_id, &stack_entry.children);
         ast.extend_span(stack_entry.node_id, end_span.end);
+        if let Some(trivia) = &self.tokens.trivia {
+            ast.attach_trivia(stack_entry.node_id, &trivia[next_trivia..]);
+        }
 
         Ok((stack_entry.node_id, self.diagnostics))
     }
@@ -381,6 +432,11 @@ fn list(p: &mut Parser) -> Closed {
         expr(p);
 
         match p.peek() {
+            TokenKind::Newline if p.newline_before_def() => {
+                p.emit(Diagnostic::error(lspan, "missing `]` to close this list"));
+                break;
+            }
+
             TokenKind::Comma | TokenKind::Newline => {
                 p.advance();
                 continue;
@@ -397,7 +453,10 @@ fn list(p: &mut Parser) -> Closed {
                     span,
                     "comma `,` or new line expected after list element",
                 ));
-                p.advance_with_error();
+                p.skip_until(TokenKindSet::new(&[TokenKind::Comma, TokenKind::RBrack]));
+                if p.peek() == TokenKind::Comma {
+                    p.advance();
+                }
             }
         }
     }
@@ -427,13 +486,25 @@ fn paren(p: &mut Parser) -> Closed {
     } else {
         p.optional_newline();
         expr(p);
-        p.optional_newline();
-        if p.peek() != TokenKind::RParen {
-            p.emit(Diagnostic::error(lspan, "missing closing parenthesis `)`"));
-            p.advance_with_error();
-        } else {
+        // Only allow the line to break before the closing parenthesis, so that a missing `)`
+        // does not swallow the next line.
+        if p.peek() == TokenKind::Newline && p.next_is(TokenKind::RParen) {
             p.advance();
         }
+        match p.peek() {
+            TokenKind::RParen => p.advance(),
+            TokenKind::Newline | TokenKind::Eof => {
+                p.emit(Diagnostic::error(lspan, "missing closing parenthesis `)`"));
+            }
+            _ => {
+                let span = p.span();
+                p.emit(Diagnostic::error(span, "closing parenthesis `)` expected"));
+                p.skip_until(TokenKindSet::new(&[TokenKind::RParen]));
+                if p.peek() == TokenKind::RParen {
+                    p.advance();
+                }
+            }
+        }
         p.close(o, NodeKind::Paren)
     }
 }
@@ -441,15 +512,21 @@ fn paren(p: &mut Parser) -> Closed {
 fn param(p: &mut Parser) {
     let o = p.open();
 
-    if let TokenKind::Ident | TokenKind::Underscore = p.peek() {
-        p.advance();
-    } else {
-        let span = p.span();
-        p.emit(Diagnostic::error(
-            span,
-            "parameter names must be identifiers or `_`",
-        ));
-        p.advance_with_error();
+    match p.peek() {
+        TokenKind::Ident | TokenKind::Underscore => p.advance(),
+        // The parameter is missing entirely, so leave the rest of the lambda to be parsed.
+        TokenKind::RArrow | TokenKind::Newline | TokenKind::Eof => {
+            let span = p.span();
+            p.emit(Diagnostic::error(span, "parameter name expected"));
+        }
+        _ => {
+            let span = p.span();
+            p.emit(Diagnostic::error(
+                span,
+                "parameter names must be identifiers or `_`",
+            ));
+            p.advance_with_error();
+        }
     }
 
     p.close(o, NodeKind::Param);
@@ -470,6 +547,15 @@ fn lambda(p: &mut Parser) -> Closed {
 
             TokenKind::RArrow => break,
 
+            // A missing comma is more likely than a stray identifier, so keep parsing parameters.
+            TokenKind::Ident | TokenKind::Underscore => {
+                let span = p.span();
+                p.emit(Diagnostic::error(
+                    span,
+                    "`,` or `->` expected after function parameter",
+                ));
+            }
+
             _ => {
                 let span = p.span();
                 p.emit(Diagnostic::error(
@@ -486,9 +572,17 @@ fn lambda(p: &mut Parser) -> Closed {
     // NOTE: Can be false if there are some stray tokens.
     // We prefer to bail early and let the rest of the program parse.
     if p.peek() == TokenKind::RArrow {
+        let arrow_span = p.span();
         p.advance();
-        p.optional_newline();
-        expr(p);
+        if p.newline_before_def() {
+            p.emit(Diagnostic::error(
+                arrow_span,
+                "function body expected after `->`",
+            ));
+        } else {
+            p.optional_newline();
+            expr(p);
+        }
     }
 
     p.close(o, NodeKind::Lambda)
@@ -499,7 +593,10 @@ fn if_expr(p: &mut Parser) -> Closed {
 
     let if_span = p.span();
     p.advance(); // if
-    if p.peek() != TokenKind::LParen {
+    let has_lparen = p.peek() == TokenKind::LParen;
+    if has_lparen {
+        p.advance();
+    } else {
         let span = p.span();
         p.emit(Diagnostic::error(
             span,
@@ -507,29 +604,41 @@ fn if_expr(p: &mut Parser) -> Closed {
         ));
         // NOTE: Don't advance, it's more likely the programmer expected no parentheses to be needed.
     }
-    p.advance();
     expr(p); // Condition
-    if p.peek() != TokenKind::RParen {
+    let has_rparen = p.peek() == TokenKind::RParen;
+    if has_rparen {
+        p.advance();
+    } else if has_lparen {
         let span = p.span();
         p.emit(Diagnostic::error(
             span,
             "missing closing parenthesis after `if` condition",
         ));
     }
-    p.advance();
-    p.optional_newline();
+    if !p.newline_before_def() {
+        p.optional_newline();
+    }
 
-    expr(p); // True branch
-    p.optional_newline();
+    // Without the parentheses, the condition may have swallowed the true branch; that was
+    // already reported.
+    if has_lparen && has_rparen || p.peek() != TokenKind::Else {
+        expr(p); // True branch
+    }
+    if p.peek() == TokenKind::Newline && p.next_is(TokenKind::Else) {
+        p.advance();
+    }
 
     if p.peek() != TokenKind::Else {
         p.emit(Diagnostic::error(
             if_span,
             "`if` expression is missing an `else` clause",
         ));
+        return p.close(o, NodeKind::If);
     }
     p.advance();
-    p.optional_newline();
+    if !p.newline_before_def() {
+        p.optional_newline();
+    }
 
     expr(p); // False branch
 
@@ -548,7 +657,10 @@ fn let_expr(p: &mut Parser) -> Closed {
     } else {
         let span = p.span();
         p.emit(Diagnostic::error(span, "`let` variable name expected"));
-        p.advance_with_error();
+        // If it's the `=`, the name is simply missing.
+        if p.peek() != TokenKind::Equal {
+            p.advance_with_error();
+        }
     }
 
     if p.peek() == TokenKind::Equal {
@@ -556,21 +668,31 @@ fn let_expr(p: &mut Parser) -> Closed {
     } else {
         let span = p.span();
         p.emit(Diagnostic::error(span, "`=` expected after variable name"));
-        p.advance_with_error();
+        // If the value is already there, the `=` is simply missing.
+        if !PREFIX_TOKENS.contains(p.peek()) {
+            p.advance_with_error();
+        }
     }
 
     expr(p);
 
-    if p.peek() == TokenKind::Newline {
-        p.advance();
-    } else {
+    if p.peek() != TokenKind::Newline {
         let span = p.span();
         p.emit(Diagnostic::error(
             span,
             "new line expected after `let` expression",
         ));
-        p.advance_with_error();
+        p.skip_until(TokenKindSet::new(&[]));
+    }
+    if p.peek() == TokenKind::Eof || p.newline_before_def() {
+        let span = p.span();
+        p.emit(Diagnostic::error(
+            span,
+            "`let` expression must be followed by an expression which uses the variable",
+        ));
+        return p.close(o, NodeKind::Let);
     }
+    p.advance();
 
     expr(p);
 
@@ -608,6 +730,23 @@ fn prefix(p: &mut Parser) -> Closed {
         TokenKind::If => if_expr(p),
         TokenKind::Let => let_expr(p),
 
+        TokenKind::Import => {
+            let span = p.span();
+            p.emit(Diagnostic::error(
+                span,
+                "`import` may only appear at the top level of the program",
+            ));
+            p.advance_with_error()
+        }
+        TokenKind::Param => {
+            let span = p.span();
+            p.emit(Diagnostic::error(
+                span,
+                "`param` may only appear at the top level of the program",
+            ));
+            p.advance_with_error()
+        }
+
         _ => {
             assert!(
                 !PREFIX_TOKENS.contains(p.peek()),
@@ -620,7 +759,17 @@ fn prefix(p: &mut Parser) -> Closed {
                 span,
                 "an expression was expected, but this token does not start one",
             ));
-            p.advance_with_error()
+            // Tokens which end lines and brackets are left for the surrounding code to recover
+            // from; consuming them would only produce more errors down the line.
+            match p.peek() {
+                TokenKind::Newline
+                | TokenKind::Eof
+                | TokenKind::RParen
+                | TokenKind::RBrack
+                | TokenKind::Comma
+                | TokenKind::Else => Closed { index: None },
+                _ => p.advance_with_error(),
+            }
         }
     }
 }
@@ -647,10 +796,18 @@ fn infix(p: &mut Parser, op: TokenKind) -> NodeKind {
 
 fn infix_binary(p: &mut Parser, op: TokenKind) -> NodeKind {
     let o = p.open();
+    let op_span = p.span();
     p.advance();
     p.close(o, NodeKind::Op);
 
     if p.peek() == TokenKind::Newline {
+        if p.newline_before_def() {
+            p.emit(Diagnostic::error(
+                op_span,
+                "an expression was expected after this operator",
+            ));
+            return NodeKind::Binary;
+        }
         p.advance();
     }
 
@@ -670,11 +827,95 @@ pub fn expr(p: &mut Parser) {
     precedence_parse(p, TokenKind::Eof)
 }
 
+fn import(p: &mut Parser) -> Closed {
+    let o = p.open();
+    p.advance(); // import
+
+    if p.peek() == TokenKind::Ident {
+        let ident = p.open();
+        p.advance();
+        p.close(ident, NodeKind::Ident);
+    } else {
+        let span = p.span();
+        p.emit(Diagnostic::error(
+            span,
+            "module name expected after `import`",
+        ));
+    }
+
+    p.close(o, NodeKind::Import)
+}
+
+fn param_decl(p: &mut Parser) -> Closed {
+    let o = p.open();
+    p.advance(); // param
+
+    if p.peek() == TokenKind::Ident {
+        let ident = p.open();
+        p.advance();
+        p.close(ident, NodeKind::Ident);
+    } else {
+        let span = p.span();
+        p.emit(Diagnostic::error(
+            span,
+            "parameter name expected after `param`",
+        ));
+        // If it's the `=`, the name is simply missing.
+        if !matches!(
+            p.peek(),
+            TokenKind::Equal | TokenKind::Newline | TokenKind::Eof
+        ) {
+            p.advance_with_error();
+        }
+    }
+
+    if p.peek() == TokenKind::Equal {
+        p.advance();
+    } else {
+        let span = p.span();
+        p.emit(Diagnostic::error(
+            span,
+            "`=` and a default value expected after parameter name",
+        ));
+        // If the value is already there, the `=` is simply missing.
+        if !PREFIX_TOKENS.contains(p.peek()) {
+            return p.close(o, NodeKind::ParamDecl);
+        }
+    }
+
+    // The default value, which must be a literal; this is checked by the compiler.
+    prefix(p);
+
+    // The optional `[min, max]` range.
+    if p.peek() == TokenKind::LBrack {
+        list(p);
+    }
+
+    if !matches!(p.peek(), TokenKind::Newline | TokenKind::Eof) {
+        let span = p.span();
+        p.emit(Diagnostic::error(
+            span,
+            "the default value of a parameter must be a single literal, optionally followed by a `[min, max]` range",
+        ));
+        p.skip_until(TokenKindSet::new(&[]));
+    }
+
+    p.close(o, NodeKind::ParamDecl)
+}
+
 pub fn toplevel(p: &mut Parser) {
     let o = p.open();
     p.optional_newline();
     while p.peek() != TokenKind::Eof {
-        expr(p);
+        match p.peek() {
+            TokenKind::Import => {
+                import(p);
+            }
+            TokenKind::Param => {
+                param_decl(p);
+            }
+            _ => expr(p),
+        }
 
         match p.peek() {
             TokenKind::Newline => {
@@ -689,7 +930,10 @@ pub fn toplevel(p: &mut Parser) {
                 p.emit(Diagnostic::error(
                     span,
                     "newline expected after toplevel expression",
-                ))
+                ));
+                // Skip the rest of the line, so that the next line can be parsed independently.
+                p.skip_until(TokenKindSet::new(&[]));
+                p.optional_newline();
             }
         }
     }
@@ -1,17 +1,20 @@
-use alloc::{format, string::String};
+use alloc::{format, string::String, vec, vec::Vec};
 
 use crate::{
-    ast::{dump::dump, Ast, NodeId},
+    ast::{dump::dump, print::print, Ast, NodeId, NodeKind},
     lexer::{lex, Lexer},
     parser::expr,
     source::SourceCode,
-    token::Lexis,
+    token::{Lexis, TriviaKind},
 };
 
 use super::{toplevel, Parser, ParserLimits};
 
+/// Parses the code, and also checks that the AST with trivia attached prints back to the exact
+/// same code.
 fn parse(s: &str, f: fn(&mut Parser)) -> (Ast, NodeId) {
-    let mut lexer = Lexer::new(Lexis::new(1024), SourceCode::unlimited_len(s));
+    let code = SourceCode::unlimited_len(s);
+    let mut lexer = Lexer::new(Lexis::with_trivia(1024), code);
     lex(&mut lexer).expect("too many tokens");
 
     let mut parser = Parser::new(&lexer.lexis, &ParserLimits { max_events: 1024 });
@@ -26,6 +29,12 @@ fn parse(s: &str, f: fn(&mut Parser)) -> (Ast, NodeId) {
 
     let mut ast = Ast::new(1024);
     let (root, _) = parser.into_ast(&mut ast).unwrap();
+
+    let printed = print(&ast, root, code);
+    if printed != s {
+        panic!("round trip mismatch. expected:\n{s:?}\ngot:\n{printed:?}");
+    }
+
     (ast, root)
 }
 
@@ -913,3 +922,81 @@ Toplevel @ 1..50
                     Token @ 49..50",
     )
 }
+
+#[test]
+fn lossless() {
+    fn round_trip(s: &str) {
+        let code = SourceCode::unlimited_len(s);
+        let mut lexer = Lexer::new(Lexis::with_trivia(1024), code);
+        lex(&mut lexer).expect("too many tokens");
+        let mut parser = Parser::new(&lexer.lexis, &ParserLimits { max_events: 1024 });
+        toplevel(&mut parser);
+        let mut ast = Ast::new(1024);
+        let (root, _) = parser.into_ast(&mut ast).unwrap();
+        assert_eq!(print(&ast, root, code), s);
+    }
+
+    round_trip("");
+    round_trip("   \n\n  -- nothing here\n");
+    round_trip("-- This is your brush.\n\nstroke 8 #000 (vec 0 0) -- trailing\n");
+    round_trip("x = 1\r\n\r\n\t-- a\n  -- b\r\ny = [1,  2\n   3]\n\nx + y  ");
+    round_trip("f = \\x ->\n  if (x)\n    1 -- one\n  else\n    2\n");
+
+    // Code with syntax errors is preserved too.
+    round_trip("stroke 8 #000 (vec 0 0");
+    round_trip("x = \n]) -- oops\n");
+    round_trip("let x = 1");
+}
+
+#[test]
+fn trivia_attachment() {
+    let s = "-- a\nx -- b\n\n";
+    let code = SourceCode::unlimited_len(s);
+    let mut lexer = Lexer::new(Lexis::with_trivia(1024), code);
+    lex(&mut lexer).expect("too many tokens");
+    let mut parser = Parser::new(&lexer.lexis, &ParserLimits { max_events: 1024 });
+    toplevel(&mut parser);
+    let mut ast = Ast::new(1024);
+    let (root, _) = parser.into_ast(&mut ast).unwrap();
+
+    fn tokens<'a>(
+        ast: &Ast,
+        node: NodeId,
+        code: &'a SourceCode,
+        out: &mut Vec<(&'a str, Vec<(TriviaKind, &'a str)>)>,
+    ) {
+        if ast.kind(node) == NodeKind::Token {
+            let trivia = ast
+                .trivia(node)
+                .iter()
+                .map(|trivia| (trivia.kind, trivia.span.slice(code)))
+                .collect();
+            out.push((ast.span(node).slice(code), trivia));
+        }
+        for &child in ast.children(node) {
+            tokens(ast, child, code, out);
+        }
+    }
+    let mut got = Vec::new();
+    tokens(&ast, root, code, &mut got);
+
+    // The first newline in a row is a token, and the rest is whitespace.
+    assert_eq!(
+        got,
+        [
+            ("\n", vec![(TriviaKind::Comment, "-- a")]),
+            ("x", vec![]),
+            (
+                "\n",
+                vec![(TriviaKind::Whitespace, " "), (TriviaKind::Comment, "-- b")]
+            ),
+        ]
+    );
+    assert_eq!(
+        ast.trivia(root)
+            .iter()
+            .map(|trivia| (trivia.kind, trivia.span.slice(code)))
+            .collect::<Vec<_>>(),
+        [(TriviaKind::Whitespace, "\n")]
+    );
+}
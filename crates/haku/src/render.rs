@@ -1,19 +1,49 @@
+use core::ops::AddAssign;
+
 use alloc::vec::Vec;
+use rkgk_image_ops::{
+    blur::gaussian_blur,
+    color::{color_matrix, ColorMatrix},
+    composite::copy_masked,
+    resample::{displace, pixelate},
+};
 use tiny_skia::{
-    BlendMode, Color, FillRule, LineCap, Paint, Path, PathBuilder, Pixmap, Shader,
-    Stroke as SStroke, Transform,
+    BlendMode, Color, FillRule, IntRect, LineCap, Mask, Paint, Path, PathBuilder, Pixmap, Point,
+    Shader, Stroke as SStroke, Transform,
 };
 
 use crate::{
-    value::{Fill, Ref, Rgba, Scribble, Shape, Stroke, Value},
+    value::{Effect, Fill, Filter, Ref, Rgba, Scribble, Shape, Stroke, Value},
     vm::{Exception, Vm},
 };
 
 pub use tiny_skia;
 
+pub mod accumulator;
+
 pub struct RendererLimits {
     pub pixmap_stack_capacity: usize,
     pub transform_stack_capacity: usize,
+    /// Maximum number of pixels a single render may touch. See [`RenderCost::pixels`].
+    pub max_pixels: usize,
+    /// Maximum number of path segments a single render may rasterise.
+    pub max_path_segments: usize,
+}
+
+/// Work done by a renderer while rasterising scribbles.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RenderCost {
+    /// Number of pixels covered by the bounding boxes of all rasterised paths, clipped to the
+    /// pixmap. This is an upper bound on how many pixels were actually painted.
+    pub pixels: usize,
+    pub path_segments: usize,
+}
+
+impl AddAssign for RenderCost {
+    fn add_assign(&mut self, rhs: Self) {
+        self.pixels = self.pixels.saturating_add(rhs.pixels);
+        self.path_segments = self.path_segments.saturating_add(rhs.path_segments);
+    }
 }
 
 pub enum RenderTarget<'a> {
@@ -24,6 +54,9 @@ pub enum RenderTarget<'a> {
 pub struct Renderer<'a> {
     pixmap_stack: Vec<RenderTarget<'a>>,
     transform_stack: Vec<Transform>,
+    max_pixels: usize,
+    max_path_segments: usize,
+    cost: RenderCost,
 }
 
 impl<'a> Renderer<'a> {
@@ -40,9 +73,17 @@ impl<'a> Renderer<'a> {
         Self {
             pixmap_stack: blend_stack,
             transform_stack,
+            max_pixels: limits.max_pixels,
+            max_path_segments: limits.max_path_segments,
+            cost: RenderCost::default(),
         }
     }
 
+    /// Returns the work done by the renderer so far.
+    pub fn cost(&self) -> RenderCost {
+        self.cost
+    }
+
     fn create_exception(vm: &Vm, _at: Value, message: &'static str) -> Exception {
         vm.create_exception(message)
     }
@@ -82,6 +123,7 @@ impl<'a> Renderer<'a> {
             Ref::Scribble(scribble) => match scribble {
                 Scribble::Stroke(stroke) => self.render_stroke(vm, value, stroke)?,
                 Scribble::Fill(fill) => self.render_fill(vm, value, fill)?,
+                Scribble::Filter(filter) => self.render_filter(vm, value, filter)?,
             },
             _ => return Err(Self::create_exception(vm, value, NOT_A_SCRIBBLE))?,
         }
@@ -89,6 +131,75 @@ impl<'a> Renderer<'a> {
         Ok(())
     }
 
+    /// Account for rasterising a path, whose bounds are extended by `outset` on each side
+    /// (such as by the stroke's thickness.)
+    /// Fails if this would exceed the render budget, in which case nothing should be rasterised.
+    fn charge(&mut self, vm: &Vm, value: Value, path: &Path, outset: f32) -> Result<(), Exception> {
+        let pixels = self
+            .pixel_bounds(path, outset)
+            .map_or(0, |rect| rect.width() as usize * rect.height() as usize);
+
+        let cost = RenderCost {
+            pixels: self.cost.pixels.saturating_add(pixels),
+            path_segments: self.cost.path_segments.saturating_add(path.len()),
+        };
+        if cost.pixels > self.max_pixels {
+            return Err(Self::create_exception(
+                vm,
+                value,
+                "too many pixels drawn (scribbles are too large or too many)",
+            ));
+        }
+        if cost.path_segments > self.max_path_segments {
+            return Err(Self::create_exception(
+                vm,
+                value,
+                "too many shapes drawn (scribbles are too complex or too many)",
+            ));
+        }
+        self.cost = cost;
+        Ok(())
+    }
+
+    /// Returns the pixels covered by the bounding box of a path extended by `outset` on each side,
+    /// clipped to the pixmap. Returns `None` if no pixels are covered.
+    fn pixel_bounds(&mut self, path: &Path, outset: f32) -> Option<IntRect> {
+        let transform = self.transform();
+        let pixmap = self.pixmap_mut();
+        let (width, height) = (pixmap.width() as f32, pixmap.height() as f32);
+
+        let bounds = path.bounds();
+        let mut corners = [
+            Point::from_xy(bounds.left() - outset, bounds.top() - outset),
+            Point::from_xy(bounds.right() + outset, bounds.top() - outset),
+            Point::from_xy(bounds.left() - outset, bounds.bottom() + outset),
+            Point::from_xy(bounds.right() + outset, bounds.bottom() + outset),
+        ];
+        transform.map_points(&mut corners);
+        let (mut left, mut top, mut right, mut bottom) = (width, height, 0.0_f32, 0.0_f32);
+        for corner in corners {
+            left = left.min(corner.x);
+            top = top.min(corner.y);
+            right = right.max(corner.x);
+            bottom = bottom.max(corner.y);
+        }
+        let left = left.max(0.0);
+        let top = top.max(0.0);
+        let right = right.min(width);
+        let bottom = bottom.min(height);
+        // NaN bounds produce no pixels, as do paths outside the pixmap.
+        if left < right && top < bottom {
+            IntRect::from_ltrb(
+                libm::floorf(left) as i32,
+                libm::floorf(top) as i32,
+                libm::ceilf(right) as i32,
+                libm::ceilf(bottom) as i32,
+            )
+        } else {
+            None
+        }
+    }
+
     fn shape_to_path(shape: &Shape) -> Path {
         let mut pb = PathBuilder::new();
         match shape {
@@ -114,13 +225,16 @@ impl<'a> Renderer<'a> {
         pb.finish().unwrap()
     }
 
-    fn render_stroke(&mut self, _vm: &Vm, _value: Value, stroke: &Stroke) -> Result<(), Exception> {
+    fn render_stroke(&mut self, vm: &Vm, value: Value, stroke: &Stroke) -> Result<(), Exception> {
         let paint = Paint {
             shader: Shader::SolidColor(tiny_skia_color(stroke.color)),
             ..default_paint()
         };
         let transform = self.transform();
         let path = Self::shape_to_path(&stroke.shape);
+        // Square caps extend past the ends of the path by half the thickness, and their corners
+        // even further at an angle.
+        self.charge(vm, value, &path, stroke.thickness.abs())?;
 
         self.pixmap_mut().stroke_path(
             &path,
@@ -137,19 +251,153 @@ impl<'a> Renderer<'a> {
         Ok(())
     }
 
-    fn render_fill(&mut self, _vm: &Vm, _value: Value, fill: &Fill) -> Result<(), Exception> {
+    fn render_fill(&mut self, vm: &Vm, value: Value, fill: &Fill) -> Result<(), Exception> {
         let paint = Paint {
             shader: Shader::SolidColor(tiny_skia_color(fill.color)),
             ..default_paint()
         };
         let transform = self.transform();
         let path = Self::shape_to_path(&fill.shape);
+        self.charge(vm, value, &path, 0.0)?;
 
         self.pixmap_mut()
             .fill_path(&path, &paint, FillRule::EvenOdd, transform, None);
 
         Ok(())
     }
+
+    fn render_filter(&mut self, vm: &Vm, value: Value, filter: &Filter) -> Result<(), Exception> {
+        let transform = self.transform();
+        let path = Self::shape_to_path(&filter.shape);
+        // Effects such as blurs read pixels from outside the shape, so the area they work on has
+        // to be extended by how far they reach.
+        let reach = effect_reach(filter.effect);
+        self.charge(vm, value, &path, reach)?;
+
+        let Some(area) = self.pixel_bounds(&path, reach) else {
+            return Ok(());
+        };
+        let pixmap = self.pixmap_mut();
+        let (Some(mut filtered), Some(mut mask)) = (
+            pixmap.clone_rect(area),
+            Mask::new(pixmap.width(), pixmap.height()),
+        ) else {
+            return Ok(());
+        };
+
+        let mut filtered_mut = filtered.as_mut();
+        match filter.effect {
+            // Radii larger than the area are limited to its size, so that the budget charged for
+            // the area covers huge radii too.
+            Effect::Blur(radius) => gaussian_blur(&mut filtered_mut, radius),
+            Effect::HueShift(angle) => {
+                color_matrix(&mut filtered_mut, &ColorMatrix::hue_rotate(angle))
+            }
+            Effect::BrightnessContrast {
+                brightness,
+                contrast,
+            } => color_matrix(
+                &mut filtered_mut,
+                &ColorMatrix::brightness_contrast(brightness, contrast),
+            ),
+            Effect::Invert => color_matrix(&mut filtered_mut, &ColorMatrix::INVERT),
+            Effect::Pixelate(size) => pixelate(&mut filtered_mut, size as u32),
+            Effect::Displace { distance, scale } => {
+                let distance = if distance.is_finite() { distance } else { 0.0 };
+                let scale = scale.max(1.0);
+                // The noise is sampled in the scribble's coordinate space, so that it doesn't
+                // depend on where the scribble ends up on the pixmap.
+                let to_scribble = transform.invert().unwrap_or_default();
+                displace(&mut filtered_mut, |x, y| {
+                    let mut point = Point::from_xy(
+                        (area.x() + x as i32) as f32 + 0.5,
+                        (area.y() + y as i32) as f32 + 0.5,
+                    );
+                    to_scribble.map_points(core::slice::from_mut(&mut point));
+                    let (x, y) = (point.x / scale, point.y / scale);
+                    (
+                        distance * value_noise(x, y, 0),
+                        distance * value_noise(x, y, 1),
+                    )
+                });
+            }
+        }
+
+        // Only the pixels within the shape are replaced. The rest of the area is only there for
+        // the effect to read from.
+        mask.fill_path(&path, FillRule::EvenOdd, false, transform);
+        copy_masked(
+            &mut pixmap.as_mut(),
+            filtered.as_ref(),
+            area.x(),
+            area.y(),
+            &mask,
+        );
+
+        Ok(())
+    }
+}
+
+/// Returns whether the value has any filter scribbles in it.
+///
+/// Filters change what's already drawn, so hosts which draw scribbles onto something other than
+/// the wall itself (such as a separate layer that's blended onto the wall later) have to draw
+/// values with filters onto the wall directly.
+pub fn has_filters(vm: &Vm, value: Value) -> bool {
+    match vm.get_ref_value(value) {
+        Some((_, Ref::List(list))) => list.elements.iter().any(|&e| has_filters(vm, e)),
+        Some((_, Ref::Scribble(Scribble::Filter(_)))) => true,
+        _ => false,
+    }
+}
+
+/// Returns how far outside its shape an effect reads pixels.
+fn effect_reach(effect: Effect) -> f32 {
+    let reach = match effect {
+        // Three standard deviations cover nearly all of a Gaussian.
+        Effect::Blur(radius) => 3.0 * radius,
+        Effect::Displace { distance, .. } => distance,
+        Effect::HueShift(_)
+        | Effect::BrightnessContrast { .. }
+        | Effect::Invert
+        | Effect::Pixelate(_) => 0.0,
+    };
+    // Huge radii and distances overflow into an infinite reach, which is clipped to the pixmap
+    // like any other.
+    if reach.is_nan() {
+        0.0
+    } else {
+        reach.abs()
+    }
+}
+
+/// Smoothly interpolated random values in the `-1` to `1` range, one for each point of the integer
+/// grid. Different seeds give unrelated noise.
+fn value_noise(x: f32, y: f32, seed: u32) -> f32 {
+    let (floor_x, floor_y) = (libm::floorf(x), libm::floorf(y));
+    let smoothstep = |t: f32| t * t * (3.0 - 2.0 * t);
+    let (tx, ty) = (smoothstep(x - floor_x), smoothstep(y - floor_y));
+
+    let (x, y) = (floor_x as i32, floor_y as i32);
+    let at = |dx: i32, dy: i32| noise_hash(x.wrapping_add(dx), y.wrapping_add(dy), seed);
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    lerp(
+        lerp(at(0, 0), at(1, 0), tx),
+        lerp(at(0, 1), at(1, 1), tx),
+        ty,
+    )
+}
+
+fn noise_hash(x: i32, y: i32, seed: u32) -> f32 {
+    let mut h = (x as u32).wrapping_mul(0x27d4eb2d)
+        ^ (y as u32).wrapping_mul(0x165667b1)
+        ^ seed.wrapping_mul(0x9e3779b9);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x85ebca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2ae35);
+    h ^= h >> 16;
+    (h >> 8) as f32 / (1 << 23) as f32 - 1.0
 }
 
 fn default_paint() -> Paint<'static> {
@@ -170,3 +418,6 @@ fn tiny_skia_color(color: Rgba) -> Color {
     )
     .unwrap()
 }
+
+#[cfg(test)]
+mod tests;
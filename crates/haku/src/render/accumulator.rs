@@ -0,0 +1,139 @@
+//! High-precision accumulation of scribbles.
+//!
+//! Pixmaps store colors with 8 bits per channel, so blending many translucent scribbles on top of
+//! each other directly in a pixmap rounds the colors after every single one of them. With low
+//! enough alpha, blending a scribble rounds right back to the color that was already there, and
+//! the color gets stuck before it ever reaches the scribbles' color.
+//!
+//! An [`Accumulator`] avoids this by blending scribbles with floating-point precision, and only
+//! rounding once, when the accumulated scribbles are composited onto a pixmap. Since all scribbles
+//! are blended with [source-over][tiny_skia::BlendMode::SourceOver], blending them into an
+//! initially transparent accumulator and compositing that onto a pixmap gives the same colors as
+//! blending them onto the pixmap one by one, minus the rounding errors.
+
+use alloc::{vec, vec::Vec};
+use tiny_skia::{Pixmap, PremultipliedColorU8};
+
+/// A buffer of premultiplied colors with floating-point channels, in the `0` to `1` range.
+#[derive(Debug, Clone)]
+pub struct Accumulator {
+    width: u32,
+    height: u32,
+    pixels: Vec<[f32; 4]>,
+}
+
+/// How colors are rounded to 8 bits when compositing an [`Accumulator`] onto a pixmap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dither {
+    /// Round each channel to the nearest 8-bit value.
+    #[default]
+    None,
+    /// Offset rounding by a 4×4 Bayer matrix, such that an area with a color between two 8-bit
+    /// values comes out as a mix of both. The pattern is aligned to the pixmap's top-left corner,
+    /// so it's seamless across pixmaps whose sizes are multiples of 4.
+    Ordered,
+}
+
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+impl Dither {
+    /// Returns the offset added to a channel before rounding it, in 8-bit units.
+    fn offset(self, x: usize, y: usize) -> f32 {
+        match self {
+            Dither::None => 0.0,
+            Dither::Ordered => (BAYER[y % 4][x % 4] as f32 + 0.5) / 16.0 - 0.5,
+        }
+    }
+}
+
+impl Accumulator {
+    /// Create a transparent accumulator of the given size.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![[0.0; 4]; width as usize * height as usize],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Make all pixels transparent again.
+    pub fn clear(&mut self) {
+        self.pixels.fill([0.0; 4]);
+    }
+
+    /// Blend a pixmap over the accumulated colors, with its top-left corner at `(x, y)`.
+    /// Parts of the pixmap that lie outside the accumulator are ignored.
+    pub fn blend(&mut self, pixmap: &Pixmap, x: i32, y: i32) {
+        let left = x.max(0);
+        let top = y.max(0);
+        let right = x
+            .saturating_add_unsigned(pixmap.width())
+            .min(self.width as i32);
+        let bottom = y
+            .saturating_add_unsigned(pixmap.height())
+            .min(self.height as i32);
+
+        let src_pixels = pixmap.pixels();
+        for dst_y in top..bottom {
+            let src_row = (dst_y - y) as usize * pixmap.width() as usize;
+            let dst_row = dst_y as usize * self.width as usize;
+            for dst_x in left..right {
+                let src = src_pixels[src_row + (dst_x - x) as usize];
+                if src.alpha() == 0 {
+                    continue;
+                }
+
+                let src =
+                    [src.red(), src.green(), src.blue(), src.alpha()].map(|c| c as f32 / 255.0);
+                let dst = &mut self.pixels[dst_row + dst_x as usize];
+                let inverse_alpha = 1.0 - src[3];
+                for (d, s) in dst.iter_mut().zip(src) {
+                    *d = s + *d * inverse_alpha;
+                }
+            }
+        }
+    }
+
+    /// Composite the accumulated colors over a pixmap of the same size, rounding them to 8 bits.
+    pub fn composite(&self, pixmap: &mut Pixmap, dither: Dither) {
+        assert_eq!(
+            (pixmap.width(), pixmap.height()),
+            (self.width, self.height),
+            "pixmap must be the same size as the accumulator"
+        );
+
+        let width = self.width as usize;
+        for (i, (dst, src)) in pixmap.pixels_mut().iter_mut().zip(&self.pixels).enumerate() {
+            // Premultiplied colors are all zeros when they're transparent, so there's nothing
+            // to blend.
+            if src[3] == 0.0 {
+                continue;
+            }
+
+            let offset = dither.offset(i % width, i / width);
+            let inverse_alpha = 1.0 - src[3];
+            let channel = |s: f32, d: u8| {
+                let c = (s + d as f32 / 255.0 * inverse_alpha) * 255.0 + offset;
+                libm::roundf(c).clamp(0.0, 255.0) as u8
+            };
+            let a = channel(src[3], dst.alpha());
+            // Rounding the channels separately may leave a color channel greater than alpha,
+            // which is not a valid premultiplied color.
+            let r = channel(src[0], dst.red()).min(a);
+            let g = channel(src[1], dst.green()).min(a);
+            let b = channel(src[2], dst.blue()).min(a);
+            *dst = PremultipliedColorU8::from_rgba(r, g, b, a).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
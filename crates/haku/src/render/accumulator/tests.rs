@@ -0,0 +1,93 @@
+use alloc::vec::Vec;
+
+use tiny_skia::{Color, Pixmap, PremultipliedColorU8};
+
+use super::{Accumulator, Dither};
+
+fn pixmap(width: u32, height: u32, color: Color) -> Pixmap {
+    let mut pixmap = Pixmap::new(width, height).unwrap();
+    pixmap.fill(color);
+    pixmap
+}
+
+fn rgba(pixel: PremultipliedColorU8) -> [u8; 4] {
+    [pixel.red(), pixel.green(), pixel.blue(), pixel.alpha()]
+}
+
+#[test]
+fn low_alpha_does_not_get_stuck() {
+    // Blending this directly in 8 bits gets stuck at around 156, as described in the docs.
+    let splat = pixmap(1, 1, Color::from_rgba8(255, 255, 255, 3));
+
+    let mut accumulator = Accumulator::new(1, 1);
+    for _ in 0..2000 {
+        accumulator.blend(&splat, 0, 0);
+    }
+    let mut wall = pixmap(1, 1, Color::BLACK);
+    accumulator.composite(&mut wall, Dither::None);
+    assert_eq!(rgba(wall.pixels()[0]), [255, 255, 255, 255]);
+}
+
+#[test]
+fn composite_is_source_over() {
+    let splat = pixmap(1, 1, Color::from_rgba8(255, 0, 0, 128));
+
+    let mut accumulator = Accumulator::new(1, 1);
+    accumulator.blend(&splat, 0, 0);
+    let mut wall = pixmap(1, 1, Color::from_rgba8(0, 0, 255, 255));
+    accumulator.composite(&mut wall, Dither::None);
+    assert_eq!(rgba(wall.pixels()[0]), [128, 0, 127, 255]);
+
+    // Translucent walls stay translucent.
+    let mut wall = pixmap(1, 1, Color::TRANSPARENT);
+    accumulator.composite(&mut wall, Dither::None);
+    assert_eq!(rgba(wall.pixels()[0]), [128, 0, 0, 128]);
+}
+
+#[test]
+fn blend_is_clipped() {
+    let splat = pixmap(2, 2, Color::WHITE);
+
+    let mut accumulator = Accumulator::new(3, 3);
+    accumulator.blend(&splat, -1, -1);
+    accumulator.blend(&splat, 2, 2);
+    accumulator.blend(&splat, 3, 0);
+    let mut wall = pixmap(3, 3, Color::BLACK);
+    accumulator.composite(&mut wall, Dither::None);
+
+    let white: Vec<_> = wall
+        .pixels()
+        .iter()
+        .map(|&pixel| rgba(pixel) == [255; 4])
+        .collect();
+    #[rustfmt::skip]
+    assert_eq!(white, [
+        true, false, false,
+        false, false, false,
+        false, false, true,
+    ]);
+}
+
+#[test]
+fn untouched_pixels_are_kept() {
+    let mut wall = pixmap(4, 4, Color::from_rgba8(10, 20, 30, 40));
+    let before = wall.clone();
+    Accumulator::new(4, 4).composite(&mut wall, Dither::Ordered);
+    assert_eq!(wall, before);
+}
+
+#[test]
+fn ordered_dither() {
+    // Halfway between 100 and 101.
+    let value = 100.5 / 255.0;
+    let mut accumulator = Accumulator::new(4, 4);
+    accumulator.pixels.fill([value, value, value, 1.0]);
+
+    let mut wall = pixmap(4, 4, Color::TRANSPARENT);
+    accumulator.composite(&mut wall, Dither::None);
+    assert!(wall.pixels().iter().all(|&pixel| pixel.red() == 101));
+
+    accumulator.composite(&mut wall, Dither::Ordered);
+    let sum: u32 = wall.pixels().iter().map(|pixel| pixel.red() as u32).sum();
+    assert_eq!(sum, 100 * 8 + 101 * 8);
+}
@@ -0,0 +1,281 @@
+use alloc::vec::Vec;
+
+use tiny_skia::{Pixmap, PremultipliedColorU8};
+
+use crate::{
+    bytecode::Defs,
+    value::{Effect, Fill, Filter, List, Ref, Rgba, Scribble, Shape, Stroke, Value, Vec2},
+    vm::{Vm, VmLimits},
+};
+
+use super::{has_filters, RenderCost, Renderer, RendererLimits};
+
+fn vm() -> Vm {
+    Vm::new(
+        &Defs::new(1),
+        &VmLimits {
+            stack_capacity: 16,
+            call_stack_capacity: 16,
+            ref_capacity: 256,
+            fuel: 1,
+            memory: 65536,
+            strict_math: false,
+        },
+    )
+}
+
+fn limits(max_pixels: usize, max_path_segments: usize) -> RendererLimits {
+    RendererLimits {
+        pixmap_stack_capacity: 1,
+        transform_stack_capacity: 1,
+        max_pixels,
+        max_path_segments,
+    }
+}
+
+fn fill(vm: &mut Vm, shape: Shape) -> Value {
+    let id = vm
+        .create_ref(Ref::Scribble(Scribble::Fill(Fill {
+            color: Rgba::default(),
+            shape,
+        })))
+        .unwrap();
+    Value::Ref(id)
+}
+
+fn filter(vm: &mut Vm, effect: Effect, shape: Shape) -> Value {
+    let id = vm
+        .create_ref(Ref::Scribble(Scribble::Filter(Filter { effect, shape })))
+        .unwrap();
+    Value::Ref(id)
+}
+
+fn list(vm: &mut Vm, elements: Vec<Value>) -> Value {
+    Value::Ref(vm.create_ref(Ref::List(List { elements })).unwrap())
+}
+
+fn rect(x: f32, y: f32, width: f32, height: f32) -> Shape {
+    Shape::Rect(
+        Vec2 { x, y },
+        Vec2 {
+            x: width,
+            y: height,
+        },
+    )
+}
+
+#[test]
+fn cost() {
+    let mut vm = vm();
+    let scribble = fill(&mut vm, rect(2.0, 2.0, 4.0, 3.0));
+    let scribbles = list(&mut vm, Vec::from([scribble, scribble]));
+
+    let mut pixmap = Pixmap::new(32, 32).unwrap();
+    let mut renderer = Renderer::new(&mut pixmap, &limits(usize::MAX, usize::MAX));
+    renderer.render(&vm, scribbles).unwrap();
+    // A rectangle is a move, three lines and a close.
+    assert_eq!(
+        renderer.cost(),
+        RenderCost {
+            pixels: 2 * 4 * 3,
+            path_segments: 2 * 5,
+        }
+    );
+}
+
+#[test]
+fn cost_is_clipped_to_pixmap() {
+    let mut vm = vm();
+    let huge = fill(&mut vm, rect(-1e6, -1e6, 2e6, 2e6));
+    let offscreen = fill(&mut vm, rect(100.0, 100.0, 4.0, 4.0));
+    let scribbles = list(&mut vm, Vec::from([huge, offscreen]));
+
+    let mut pixmap = Pixmap::new(32, 32).unwrap();
+    let mut renderer = Renderer::new(&mut pixmap, &limits(usize::MAX, usize::MAX));
+    renderer.translate(4.0, 0.0);
+    renderer.render(&vm, scribbles).unwrap();
+    assert_eq!(renderer.cost().pixels, 32 * 32);
+}
+
+#[test]
+fn stroke_thickness() {
+    let mut vm = vm();
+    let stroke = vm
+        .create_ref(Ref::Scribble(Scribble::Stroke(Stroke {
+            thickness: 2.0,
+            color: Rgba::default(),
+            shape: Shape::Line(Vec2 { x: 10.0, y: 10.0 }, Vec2 { x: 20.0, y: 10.0 }),
+        })))
+        .unwrap();
+
+    let mut pixmap = Pixmap::new(32, 32).unwrap();
+    let mut renderer = Renderer::new(&mut pixmap, &limits(usize::MAX, usize::MAX));
+    renderer.render(&vm, Value::Ref(stroke)).unwrap();
+    assert_eq!(renderer.cost().pixels, 14 * 4);
+}
+
+#[test]
+fn budget() {
+    let mut vm = vm();
+    let scribble = fill(&mut vm, rect(0.0, 0.0, 10.0, 10.0));
+    let scribbles = list(&mut vm, Vec::from([scribble; 3]));
+
+    let mut pixmap = Pixmap::new(32, 32).unwrap();
+    let mut renderer = Renderer::new(&mut pixmap, &limits(250, usize::MAX));
+    let exception = renderer.render(&vm, scribbles).unwrap_err();
+    assert!(exception.message.contains("too many pixels"));
+    // The scribble that would exceed the budget is not drawn, nor counted.
+    assert_eq!(renderer.cost().pixels, 200);
+
+    let mut renderer = Renderer::new(&mut pixmap, &limits(usize::MAX, 12));
+    let exception = renderer.render(&vm, scribbles).unwrap_err();
+    assert!(exception.message.contains("too many shapes"));
+    assert_eq!(renderer.cost().path_segments, 10);
+}
+
+fn gray(value: u8) -> PremultipliedColorU8 {
+    PremultipliedColorU8::from_rgba(value, value, value, 255).unwrap()
+}
+
+/// The gray level of each pixel in a row of the pixmap.
+fn row(pixmap: &Pixmap, y: u32) -> Vec<u8> {
+    (0..pixmap.width())
+        .map(|x| pixmap.pixel(x, y).unwrap().red())
+        .collect()
+}
+
+#[test]
+fn filter_only_changes_its_shape() {
+    let mut vm = vm();
+    let invert = filter(&mut vm, Effect::Invert, rect(1.0, 1.0, 2.0, 1.0));
+
+    let mut pixmap = Pixmap::new(5, 3).unwrap();
+    pixmap.pixels_mut().fill(gray(0));
+    let mut renderer = Renderer::new(&mut pixmap, &limits(usize::MAX, usize::MAX));
+    renderer.translate(1.0, 0.0);
+    renderer.render(&vm, invert).unwrap();
+    assert_eq!(row(&pixmap, 0), [0, 0, 0, 0, 0]);
+    assert_eq!(row(&pixmap, 1), [0, 0, 255, 255, 0]);
+    assert_eq!(row(&pixmap, 2), [0, 0, 0, 0, 0]);
+}
+
+#[test]
+fn filter_reads_outside_its_shape() {
+    let mut vm = vm();
+    let blur = filter(&mut vm, Effect::Blur(1.0), rect(3.0, 0.0, 1.0, 1.0));
+
+    let mut pixmap = Pixmap::new(8, 1).unwrap();
+    pixmap.pixels_mut()[..4].fill(gray(255));
+    pixmap.pixels_mut()[4..].fill(gray(0));
+    let mut renderer = Renderer::new(&mut pixmap, &limits(usize::MAX, usize::MAX));
+    renderer.render(&vm, blur).unwrap();
+    // The blurred area reaches 3 pixels to each side of the shape, but only the pixel within the
+    // shape is changed.
+    assert_eq!(renderer.cost().pixels, 7);
+    let row = row(&pixmap, 0);
+    assert_eq!(row[..3], [255, 255, 255], "{row:?}");
+    assert!(0 < row[3] && row[3] < 255, "{row:?}");
+    assert_eq!(row[4..], [0, 0, 0, 0]);
+}
+
+#[test]
+fn huge_filters_stay_within_budget() {
+    let mut vm = vm();
+    // Effects which read outside their shape can reach across the whole pixmap, but no further.
+    let effects = [
+        (Effect::Blur(1e10), 64),
+        (Effect::Blur(f32::MAX), 64),
+        (Effect::Blur(f32::INFINITY), 64),
+        (Effect::Pixelate(1e10), 4),
+        (
+            Effect::Displace {
+                distance: 1e10,
+                scale: 1e10,
+            },
+            64,
+        ),
+    ];
+    for (effect, pixels) in effects {
+        let huge = filter(&mut vm, effect, rect(3.0, 3.0, 2.0, 2.0));
+
+        let mut pixmap = Pixmap::new(8, 8).unwrap();
+        pixmap.pixels_mut()[..32].fill(gray(255));
+        let mut renderer = Renderer::new(&mut pixmap, &limits(64, usize::MAX));
+        renderer.render(&vm, huge).unwrap();
+        assert_eq!(renderer.cost().pixels, pixels, "{effect:?}");
+        assert!(row(&pixmap, 0).iter().all(|&c| c == 255), "{effect:?}");
+    }
+}
+
+#[test]
+fn filters_on_points_and_lines_do_nothing() {
+    let mut vm = vm();
+    let point = filter(
+        &mut vm,
+        Effect::Invert,
+        Shape::Point(Vec2 { x: 1.0, y: 1.0 }),
+    );
+    let line = filter(
+        &mut vm,
+        Effect::Invert,
+        Shape::Line(Vec2 { x: 0.0, y: 1.0 }, Vec2 { x: 3.0, y: 1.0 }),
+    );
+    let scribbles = list(&mut vm, Vec::from([point, line]));
+
+    let mut pixmap = Pixmap::new(4, 4).unwrap();
+    pixmap.pixels_mut().fill(gray(0));
+    let mut renderer = Renderer::new(&mut pixmap, &limits(usize::MAX, usize::MAX));
+    renderer.render(&vm, scribbles).unwrap();
+    assert!(pixmap.pixels().iter().all(|&p| p == gray(0)));
+}
+
+#[test]
+fn displacement_does_not_depend_on_translation() {
+    let mut vm = vm();
+    let displace = filter(
+        &mut vm,
+        Effect::Displace {
+            distance: 4.0,
+            scale: 3.0,
+        },
+        rect(0.0, 0.0, 16.0, 16.0),
+    );
+
+    let render = |translation: f32| {
+        let mut pixmap = Pixmap::new(48, 48).unwrap();
+        for (i, pixel) in pixmap.pixels_mut().iter_mut().enumerate() {
+            let x = (i % 48) as f32 - translation;
+            let y = (i / 48) as f32 - translation;
+            *pixel = gray(((x * 7.0 + y * 13.0).rem_euclid(256.0)) as u8);
+        }
+        let mut renderer = Renderer::new(&mut pixmap, &limits(usize::MAX, usize::MAX));
+        renderer.translate(translation, translation);
+        renderer.render(&vm, displace).unwrap();
+        pixmap
+    };
+
+    let a = render(16.0);
+    let b = render(20.0);
+    for y in 0..16 {
+        for x in 0..16 {
+            assert_eq!(a.pixel(16 + x, 16 + y), b.pixel(20 + x, 20 + y));
+        }
+    }
+    assert_ne!(a.pixel(16, 16), render(16.0).pixel(17, 16));
+}
+
+#[test]
+fn finding_filters() {
+    let mut vm = vm();
+    let fill = fill(&mut vm, rect(0.0, 0.0, 1.0, 1.0));
+    let invert = filter(&mut vm, Effect::Invert, rect(0.0, 0.0, 1.0, 1.0));
+    let without = list(&mut vm, Vec::from([fill, fill]));
+    let nested = list(&mut vm, Vec::from([fill, invert]));
+    let with = list(&mut vm, Vec::from([without, nested]));
+
+    assert!(!has_filters(&vm, Value::Nil));
+    assert!(!has_filters(&vm, fill));
+    assert!(!has_filters(&vm, without));
+    assert!(has_filters(&vm, invert));
+    assert!(has_filters(&vm, with));
+}
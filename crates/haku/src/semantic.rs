@@ -0,0 +1,195 @@
+//! Semantic classification of source code, for syntax highlighting.
+//!
+//! The lexer alone cannot tell whether an identifier refers to a def, a function parameter, a
+//! `let` variable, or a system function; this module resolves names the same way the compiler
+//! does, so that editors can colour code the way the compiler sees it.
+
+use alloc::vec::Vec;
+
+use crate::{
+    ast::{scope::Scopes, NodeId, NodeKind},
+    compiler::Source,
+    source::Span,
+    system::SystemFnArity,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SemanticClass {
+    Def,
+    Param,
+    Local,
+    SystemFn,
+    Tag,
+    Color,
+}
+
+impl SemanticClass {
+    pub fn name(self) -> &'static str {
+        match self {
+            SemanticClass::Def => "def",
+            SemanticClass::Param => "param",
+            SemanticClass::Local => "local",
+            SemanticClass::SystemFn => "systemFn",
+            SemanticClass::Tag => "tag",
+            SemanticClass::Color => "color",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SemanticToken {
+    pub span: Span,
+    pub class: SemanticClass,
+    /// Whether this is where the name is introduced, as opposed to a use of the name.
+    /// Def names with this set form an outline of the program.
+    pub is_definition: bool,
+}
+
+/// Classifies identifiers and literals in the program. Tokens are returned in source order.
+/// Identifiers which do not resolve to anything are not included.
+pub fn classify(src: &Source, root: NodeId) -> Vec<SemanticToken> {
+    let mut c = Classifier {
+        src,
+        defs: Vec::new(),
+        scopes: Scopes::new(),
+        tokens: Vec::new(),
+    };
+    c.node(root);
+    c.tokens
+}
+
+struct Classifier<'a, 'b> {
+    src: &'b Source<'a>,
+    defs: Vec<&'a str>,
+    scopes: Scopes<'a, SemanticClass>,
+    tokens: Vec<SemanticToken>,
+}
+
+impl<'a> Classifier<'a, '_> {
+    fn name(&self, node_id: NodeId) -> &'a str {
+        self.src.ast.span(node_id).slice(self.src.code)
+    }
+
+    fn push(&mut self, node_id: NodeId, class: SemanticClass, is_definition: bool) {
+        self.tokens.push(SemanticToken {
+            span: self.src.ast.span(node_id),
+            class,
+            is_definition,
+        });
+    }
+
+    fn bind(&mut self, node_id: NodeId, class: SemanticClass) {
+        self.push(node_id, class, true);
+        self.scopes.push(self.name(node_id), class);
+    }
+
+    fn node(&mut self, node_id: NodeId) {
+        let ast = self.src.ast;
+        match ast.kind(node_id) {
+            NodeKind::Tag => self.push(node_id, SemanticClass::Tag, false),
+            NodeKind::Color => self.push(node_id, SemanticClass::Color, false),
+
+            NodeKind::Ident => {
+                let name = self.name(node_id);
+                if let Some(&class) = self.scopes.get(name) {
+                    self.push(node_id, class, false);
+                } else if self.defs.contains(&name) {
+                    self.push(node_id, SemanticClass::Def, false);
+                }
+            }
+
+            NodeKind::Call => {
+                let mut walk = ast.walk(node_id);
+                if let Some(func) = walk.node() {
+                    if ast.kind(func) == NodeKind::Ident
+                        && (self.src.system.resolve_fn)(SystemFnArity::Nary, self.name(func))
+                            .is_some()
+                    {
+                        self.push(func, SemanticClass::SystemFn, false);
+                    } else {
+                        self.node(func);
+                    }
+                }
+                while let Some(arg) = walk.node() {
+                    self.node(arg);
+                }
+            }
+
+            NodeKind::Lambda => {
+                let mut walk = ast.walk(node_id);
+                let scopes_len = self.scopes.len();
+                if let Some(params) = walk.node() {
+                    let mut params_walk = ast.walk(params);
+                    while let Some(param) = params_walk.node() {
+                        self.bind(param, SemanticClass::Param);
+                    }
+                }
+                if let Some(body) = walk.node() {
+                    self.node(body);
+                }
+                self.scopes.truncate(scopes_len);
+            }
+
+            NodeKind::Let => {
+                let mut walk = ast.walk(node_id);
+                let (Some(ident), Some(expr)) = (walk.node(), walk.node()) else {
+                    return;
+                };
+                // The variable is not in scope within its own initializer.
+                self.push(ident, SemanticClass::Local, true);
+                self.node(expr);
+                self.scopes.push(self.name(ident), SemanticClass::Local);
+                if let Some(then) = walk.node() {
+                    self.node(then);
+                }
+                self.scopes.pop();
+            }
+
+            NodeKind::Toplevel => {
+                // Defs are visible in the entire program, regardless of order.
+                let mut walk = ast.walk(node_id);
+                while let Some(toplevel_expr) = walk.node() {
+                    if let Some(ident) = ast
+                        .def_ident(self.src.code, toplevel_expr)
+                        .or_else(|| ast.param_ident(toplevel_expr))
+                    {
+                        self.defs.push(self.name(ident));
+                    }
+                }
+
+                let mut walk = ast.walk(node_id);
+                while let Some(toplevel_expr) = walk.node() {
+                    if let Some(ident) = ast.param_ident(toplevel_expr) {
+                        self.push(ident, SemanticClass::Def, true);
+                        let mut walk = ast.walk(toplevel_expr);
+                        walk.node();
+                        while let Some(child) = walk.node() {
+                            self.node(child);
+                        }
+                    } else if let Some(ident) = ast.def_ident(self.src.code, toplevel_expr) {
+                        self.push(ident, SemanticClass::Def, true);
+                        let mut walk = ast.walk(toplevel_expr);
+                        if let (Some(_ident), Some(_op), Some(expr)) =
+                            (walk.node(), walk.node(), walk.node())
+                        {
+                            self.node(expr);
+                        }
+                    } else {
+                        self.node(toplevel_expr);
+                    }
+                }
+            }
+
+            _ => {
+                let mut walk = ast.walk(node_id);
+                while let Some(child) = walk.node() {
+                    self.node(child);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,73 @@
+use alloc::{format, string::String, vec::Vec};
+
+use crate::{system::System, testing::parse};
+
+use super::classify;
+
+/// Returns `text:class` for every classified token, with `!` marking definitions.
+fn classes(s: &str) -> Vec<String> {
+    let parsed = parse(s);
+    let code = parsed.code;
+
+    let system = System::new(1);
+    classify(&parsed.source(&system), parsed.root)
+        .into_iter()
+        .map(|token| {
+            format!(
+                "{}:{}{}",
+                token.span.slice(code),
+                token.class.name(),
+                if token.is_definition { "!" } else { "" }
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn literals() {
+    assert_eq!(classes("[Hello, #FFF, 1]"), ["Hello:tag", "#FFF:color"]);
+}
+
+#[test]
+fn system_fns() {
+    assert_eq!(
+        classes("stroke 8 #000 (vec 0 0)"),
+        ["stroke:systemFn", "#000:color", "vec:systemFn"]
+    );
+    // System functions are only recognized when called.
+    assert!(classes("stroke").is_empty());
+    // And they take priority over variables.
+    assert_eq!(
+        classes("let vec = 1\nvec 1"),
+        ["vec:local!", "vec:systemFn"]
+    );
+}
+
+#[test]
+fn variables() {
+    assert_eq!(
+        classes("f = \\x -> x + g\ng = 1\nf 2"),
+        ["f:def!", "x:param!", "x:param", "g:def", "g:def!", "f:def"]
+    );
+    assert_eq!(
+        classes("x = 1\nlet x = x\n(\\x -> x) x"),
+        ["x:def!", "x:local!", "x:def", "x:param!", "x:param", "x:local"]
+    );
+    assert!(classes("undefined").is_empty());
+}
+
+#[test]
+fn params() {
+    assert_eq!(
+        classes("param color = #000\nparam size = 4 [1, 8]\nstroke size color (vec 0 0)"),
+        [
+            "color:def!",
+            "#000:color",
+            "size:def!",
+            "stroke:systemFn",
+            "size:def",
+            "color:def",
+            "vec:systemFn"
+        ]
+    );
+}
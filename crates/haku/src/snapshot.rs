@@ -0,0 +1,268 @@
+//! Binary snapshots of compiled chunks, defs, and VM state, for saving them and loading them back
+//! later without recompiling or rerunning any code.
+//!
+//! Every snapshot starts with a header made up of the magic bytes `haku`, the format [`VERSION`]
+//! (u16), and a byte saying what kind of data follows. All numbers are little-endian.
+//! Snapshots can be concatenated; each `read_*` function consumes a single snapshot from the
+//! beginning of its input, and leaves the rest for the next one.
+//!
+//! Snapshots are checked while reading, so that malformed input results in an error rather than
+//! a panic later on. Chunks read from snapshots still have to be added to a [`System`], which
+//! verifies their bytecode as usual.
+//! VM snapshots are read into an existing system and defs. The closures inside them are checked
+//! against the entries of the chunks they point into, and the snapshot must have a value for each
+//! def. This only ensures that the closures are safe to run,
+//! though; for them to do anything sensible, the VM has to be read along with the same chunks
+//! (added in the same order) that it was taken with.
+
+use core::{
+    error::Error,
+    fmt::{self, Display},
+};
+
+use alloc::{string::String, vec::Vec};
+
+use crate::{
+    bytecode::{Chunk, Defs},
+    compiler::ClosureSpec,
+    system::System,
+    vm::{Vm, VmLimits},
+};
+
+pub const MAGIC: [u8; 4] = *b"haku";
+
+/// Version of the snapshot format. Must be bumped whenever the format, or the bytecode format
+/// stored inside of it, changes in any way.
+pub const VERSION: u16 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum Kind {
+    Chunk = 1,
+    Defs = 2,
+    Vm = 3,
+}
+
+pub fn write_chunk(out: &mut Vec<u8>, chunk: &Chunk, spec: ClosureSpec) {
+    let mut w = Writer::new(out, Kind::Chunk);
+    w.u8(spec.local_count);
+    match spec.point_start {
+        Some(offset) => {
+            w.u8(1);
+            w.u16(offset);
+        }
+        None => w.u8(0),
+    }
+    w.bytes(&chunk.bytecode);
+}
+
+/// Read a chunk and the spec of the closure to run it with.
+/// Fails if the chunk would not fit in `capacity` bytes.
+pub fn read_chunk(
+    input: &mut &[u8],
+    capacity: usize,
+) -> Result<(Chunk, ClosureSpec), SnapshotError> {
+    let mut r = Reader::new(input, Kind::Chunk)?;
+    let local_count = r.u8()?;
+    let point_start = match r.u8()? {
+        0 => None,
+        1 => Some(r.u16()?),
+        _ => return Err(SnapshotError::Invalid),
+    };
+    let bytecode = r.bytes()?;
+
+    let mut chunk = Chunk::new(capacity).map_err(|_| SnapshotError::TooLarge)?;
+    chunk
+        .emit_bytes(bytecode)
+        .map_err(|_| SnapshotError::TooLarge)?;
+    Ok((
+        chunk,
+        ClosureSpec {
+            local_count,
+            point_start,
+        },
+    ))
+}
+
+pub fn write_defs(out: &mut Vec<u8>, defs: &Defs) {
+    let mut w = Writer::new(out, Kind::Defs);
+    defs.write_snapshot(&mut w);
+}
+
+/// Read defs, which may hold at most `capacity` defs.
+pub fn read_defs(input: &mut &[u8], capacity: usize) -> Result<Defs, SnapshotError> {
+    let mut r = Reader::new(input, Kind::Defs)?;
+    Defs::read_snapshot(&mut r, capacity)
+}
+
+/// Write the state of a VM: its value stack, refs, vectors, and def values.
+///
+/// # Panics
+///
+/// If the VM is running code, which includes being paused by the debugger.
+pub fn write_vm(out: &mut Vec<u8>, vm: &Vm) {
+    let mut w = Writer::new(out, Kind::Vm);
+    vm.write_snapshot(&mut w);
+}
+
+/// Read the state of a VM into a new VM with the given limits.
+/// The VM gets a full tank of fuel, and the heap memory not used up by the snapshot's data.
+///
+/// All chunks that closures in the snapshot point into must already be added to `system`, and the
+/// snapshot must have a value for each def in `defs`.
+pub fn read_vm(
+    input: &mut &[u8],
+    system: &System,
+    defs: &Defs,
+    limits: &VmLimits,
+) -> Result<Vm, SnapshotError> {
+    let mut r = Reader::new(input, Kind::Vm)?;
+    Vm::read_snapshot(&mut r, system, defs, limits)
+}
+
+pub(crate) struct Writer<'a> {
+    out: &'a mut Vec<u8>,
+}
+
+impl<'a> Writer<'a> {
+    fn new(out: &'a mut Vec<u8>, kind: Kind) -> Self {
+        out.extend_from_slice(&MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.push(kind as u8);
+        Self { out }
+    }
+
+    pub fn u8(&mut self, x: u8) {
+        self.out.push(x);
+    }
+
+    pub fn u16(&mut self, x: u16) {
+        self.out.extend_from_slice(&x.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, x: u32) {
+        self.out.extend_from_slice(&x.to_le_bytes());
+    }
+
+    pub fn f32(&mut self, x: f32) {
+        self.out.extend_from_slice(&x.to_le_bytes());
+    }
+
+    /// Write a collection length. Lengths of everything stored in snapshots fit in a u32, because
+    /// the VM refers to its contents with u32 IDs.
+    pub fn len(&mut self, len: usize) {
+        self.u32(len as u32);
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.len(bytes.len());
+        self.out.extend_from_slice(bytes);
+    }
+
+    pub fn str(&mut self, s: &str) {
+        self.bytes(s.as_bytes());
+    }
+}
+
+pub(crate) struct Reader<'a, 'b> {
+    input: &'a mut &'b [u8],
+}
+
+impl<'a, 'b> Reader<'a, 'b> {
+    fn new(input: &'a mut &'b [u8], kind: Kind) -> Result<Self, SnapshotError> {
+        let mut r = Self { input };
+        if r.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err(SnapshotError::NotASnapshot);
+        }
+        let version = r.u16()?;
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        if r.u8()? != kind as u8 {
+            return Err(SnapshotError::WrongKind);
+        }
+        Ok(r)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'b [u8], SnapshotError> {
+        if self.input.len() < len {
+            return Err(SnapshotError::Truncated);
+        }
+        let (taken, rest) = self.input.split_at(len);
+        *self.input = rest;
+        Ok(taken)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, SnapshotError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, SnapshotError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn f32(&mut self) -> Result<f32, SnapshotError> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// Read a collection length, which must not exceed `max`.
+    pub fn len(&mut self, max: usize) -> Result<usize, SnapshotError> {
+        let len = self.u32()? as usize;
+        if len > max {
+            return Err(SnapshotError::TooLarge);
+        }
+        Ok(len)
+    }
+
+    pub fn bytes(&mut self) -> Result<&'b [u8], SnapshotError> {
+        let len = self.len(usize::MAX)?;
+        self.take(len)
+    }
+
+    pub fn string(&mut self) -> Result<String, SnapshotError> {
+        let bytes = self.bytes()?;
+        core::str::from_utf8(bytes)
+            .map(String::from)
+            .map_err(|_| SnapshotError::Invalid)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// The input does not start with the magic bytes.
+    NotASnapshot,
+    /// The snapshot was written by a different version of haku.
+    UnsupportedVersion(u16),
+    /// The snapshot contains a different kind of data than was requested.
+    WrongKind,
+    Truncated,
+    /// The snapshot contains data that doesn't make sense, such as IDs pointing nowhere.
+    Invalid,
+    /// The snapshot contains more data than the given limits allow.
+    TooLarge,
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::NotASnapshot => f.write_str("not a haku snapshot"),
+            SnapshotError::UnsupportedVersion(version) => write!(
+                f,
+                "snapshot format version {version} is not supported (expected {VERSION})"
+            ),
+            SnapshotError::WrongKind => f.write_str("snapshot contains a different kind of data"),
+            SnapshotError::Truncated => f.write_str("snapshot is truncated"),
+            SnapshotError::Invalid => f.write_str("snapshot contains invalid data"),
+            SnapshotError::TooLarge => f.write_str("snapshot does not fit within limits"),
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,282 @@
+use alloc::{string::String, vec::Vec};
+
+use crate::{
+    bytecode::Defs,
+    system::System,
+    testing::{compile, VM_LIMITS},
+    value::{Closure, Ref, Value},
+    vm::{Vm, VmLimits},
+};
+
+use super::{
+    read_chunk, read_defs, read_vm, write_chunk, write_defs, write_vm, SnapshotError, MAGIC,
+    VERSION,
+};
+
+/// Compile and run code on top of the given system, defs, and VM.
+fn run(code: &str, system: &mut System, defs: &mut Defs, vm: &mut Vm) -> String {
+    let compiled = compile(code, system, defs);
+    compiled.assert_no_diagnostics();
+    let closure = compiled.add_to(system, defs);
+    vm.apply_defs(defs);
+    let closure_id = vm.create_ref(Ref::Closure(closure)).unwrap();
+    let value = vm.run(system, closure_id).unwrap();
+    vm.format_value(value)
+}
+
+const DEFS_CODE: &str = r#"
+add = \x -> \y -> x + y
+add1 = add 1
+things = [1, vec 1 2, #F80, stroke 2 #000 (vec 3 4), blur 2 (circle 0 0 4)]
+True
+"#;
+const USE_CODE: &str = "[add1 2, things]";
+
+#[test]
+fn round_trip() {
+    let mut system = System::new(4);
+    let mut defs = Defs::new(256);
+    let mut vm = Vm::new(&defs, &VM_LIMITS);
+    let compiled = compile(DEFS_CODE, &mut system, &mut defs);
+    compiled.assert_no_diagnostics();
+    let (chunk, spec) = (compiled.chunk, compiled.spec);
+    let chunk_id = system.add_chunk(chunk.clone(), &defs, spec).unwrap();
+    vm.apply_defs(&defs);
+    let closure_id = vm
+        .create_ref(Ref::Closure(Closure::chunk(chunk_id, spec)))
+        .unwrap();
+    vm.run(&system, closure_id).unwrap();
+
+    // Everything is written into a single buffer, and read back in order.
+    let mut snapshot = Vec::new();
+    write_chunk(&mut snapshot, &chunk, spec);
+    write_defs(&mut snapshot, &defs);
+    write_vm(&mut snapshot, &vm);
+
+    let mut input = &snapshot[..];
+    let (read_chunk, read_spec) = read_chunk(&mut input, 65536).unwrap();
+    let mut read_defs = read_defs(&mut input, 256).unwrap();
+    let mut read_system = System::new(4);
+    assert_eq!(read_chunk.bytecode, chunk.bytecode);
+    read_system
+        .add_chunk(read_chunk, &read_defs, read_spec)
+        .unwrap();
+    let mut read_vm = read_vm(&mut input, &read_system, &read_defs, &VM_LIMITS).unwrap();
+    assert!(input.is_empty());
+
+    assert_eq!(read_spec.local_count, spec.local_count);
+    assert!(read_defs.names().eq(defs.names()));
+    assert_eq!(read_vm.remaining_memory(), vm.remaining_memory());
+    assert_eq!(read_vm.stack(), vm.stack());
+    for (&read, &original) in read_vm.def_values().iter().zip(vm.def_values()) {
+        assert_eq!(read_vm.format_value(read), vm.format_value(original));
+    }
+
+    // Code compiled against the read defs can use the values left in the read VM, including
+    // calling closures pointing into the read chunk.
+    let expected = run(USE_CODE, &mut system, &mut defs, &mut vm);
+    let result = run(USE_CODE, &mut read_system, &mut read_defs, &mut read_vm);
+    assert_eq!(result, expected);
+    assert_eq!(
+        result,
+        "[3, [1, vec 1 2 0 0, rgba 1 0.53333336 0 1, <stroke point>, <blur circle>]]"
+    );
+}
+
+#[test]
+fn defs_namespaces() {
+    let mut defs = Defs::new(16);
+    defs.add("a").unwrap();
+    let first = defs.current_namespace();
+    defs.begin_namespace();
+    defs.add("a").unwrap();
+    defs.add("b").unwrap();
+    defs.import(first);
+
+    let mut snapshot = Vec::new();
+    write_defs(&mut snapshot, &defs);
+    let mut read = read_defs(&mut &snapshot[..], 16).unwrap();
+
+    assert_eq!(read.current_namespace(), defs.current_namespace());
+    assert_eq!(read.get("a"), defs.get("a"));
+    assert!(read.names().eq(defs.names()));
+    // Adding defs continues in the same namespace, with the next ID.
+    assert_eq!(read.add("c"), defs.add("c"));
+
+    assert_eq!(
+        read_defs(&mut &snapshot[..], 2).unwrap_err(),
+        SnapshotError::TooLarge
+    );
+}
+
+#[test]
+fn header() {
+    let mut snapshot = Vec::new();
+    write_defs(&mut snapshot, &Defs::new(1));
+
+    assert_eq!(
+        read_defs(&mut &b"not haku"[..], 1).unwrap_err(),
+        SnapshotError::NotASnapshot
+    );
+    assert_eq!(
+        read_vm(
+            &mut &snapshot[..],
+            &System::new(1),
+            &Defs::new(0),
+            &VM_LIMITS
+        )
+        .unwrap_err(),
+        SnapshotError::WrongKind
+    );
+
+    let mut future = snapshot.clone();
+    future[MAGIC.len()..MAGIC.len() + 2].copy_from_slice(&(VERSION + 1).to_le_bytes());
+    assert_eq!(
+        read_defs(&mut &future[..], 1).unwrap_err(),
+        SnapshotError::UnsupportedVersion(VERSION + 1)
+    );
+}
+
+#[test]
+fn truncated() {
+    let mut system = System::new(1);
+    let mut defs = Defs::new(256);
+    let mut vm = Vm::new(&defs, &VM_LIMITS);
+    run(DEFS_CODE, &mut system, &mut defs, &mut vm);
+
+    let mut snapshot = Vec::new();
+    write_vm(&mut snapshot, &vm);
+    for len in 0..snapshot.len() {
+        assert!(read_vm(&mut &snapshot[..len], &system, &defs, &VM_LIMITS).is_err());
+    }
+}
+
+#[test]
+fn invalid() {
+    let mut vm = Vm::new(&Defs::new(1), &VM_LIMITS);
+    let mut defs = Defs::new(1);
+    defs.add("a").unwrap();
+    vm.apply_defs(&defs);
+    let id = vm.create_ref(Ref::Shape(crate::value::Shape::Circle(
+        Default::default(),
+        1.0,
+    )));
+    vm.set_def(Default::default(), Value::Ref(id.unwrap()));
+
+    let mut snapshot = Vec::new();
+    write_vm(&mut snapshot, &vm);
+    let system = System::new(1);
+    assert!(read_vm(&mut &snapshot[..], &system, &defs, &VM_LIMITS).is_ok());
+
+    // The def is the last thing in the snapshot, so the last 4 bytes are its ref ID.
+    let len = snapshot.len();
+    snapshot[len - 4..].copy_from_slice(&1_u32.to_le_bytes());
+    assert_eq!(
+        read_vm(&mut &snapshot[..], &system, &defs, &VM_LIMITS).unwrap_err(),
+        SnapshotError::Invalid
+    );
+
+    let few_refs = VmLimits {
+        ref_capacity: 0,
+        ..VM_LIMITS
+    };
+    assert_eq!(
+        read_vm(&mut &snapshot[..], &system, &defs, &few_refs).unwrap_err(),
+        SnapshotError::TooLarge
+    );
+}
+
+#[test]
+fn closures_must_match_their_chunks() {
+    let mut system = System::new(1);
+    let mut defs = Defs::new(256);
+    let mut vm = Vm::new(&defs, &VM_LIMITS);
+    run(DEFS_CODE, &mut system, &mut defs, &mut vm);
+    let mut snapshot = Vec::new();
+    write_vm(&mut snapshot, &vm);
+    assert!(read_vm(&mut &snapshot[..], &system, &defs, &VM_LIMITS).is_ok());
+
+    // The closures point into a chunk that doesn't exist.
+    assert_eq!(
+        read_vm(&mut &snapshot[..], &System::new(1), &defs, &VM_LIMITS).unwrap_err(),
+        SnapshotError::Invalid
+    );
+
+    // The closures point into a chunk, but not the one they were taken with, whose functions start
+    // elsewhere.
+    let mut other_system = System::new(1);
+    let mut other_defs = Defs::new(256);
+    let mut other_vm = Vm::new(&other_defs, &VM_LIMITS);
+    run(
+        "f = \\x, y -> x\nTrue",
+        &mut other_system,
+        &mut other_defs,
+        &mut other_vm,
+    );
+    assert_eq!(
+        read_vm(&mut &snapshot[..], &other_system, &defs, &VM_LIMITS).unwrap_err(),
+        SnapshotError::Invalid
+    );
+}
+
+/// Builds a VM snapshot by hand, with one free vector slot, one free ref slot, an empty stack, and
+/// the given def values, already encoded.
+fn vm_snapshot_with_free_slots(defs: &[&[u8]]) -> Vec<u8> {
+    let mut snapshot = Vec::new();
+    write_vm(&mut snapshot, &Vm::new(&Defs::new(0), &VM_LIMITS));
+    // Keep only the header: the magic bytes, version, and kind.
+    snapshot.truncate(MAGIC.len() + 3);
+
+    let free_slots = [1_u32.to_le_bytes().as_slice(), &[0]].concat();
+    snapshot.extend_from_slice(&free_slots);
+    snapshot.extend_from_slice(&free_slots);
+    snapshot.extend_from_slice(&0_u32.to_le_bytes());
+    snapshot.extend_from_slice(&(defs.len() as u32).to_le_bytes());
+    for def in defs {
+        snapshot.extend_from_slice(def);
+    }
+    snapshot
+}
+
+#[test]
+fn values_must_not_point_at_free_slots() {
+    let system = System::new(1);
+    let mut defs = Defs::new(1);
+    defs.add("a").unwrap();
+
+    let nil = vm_snapshot_with_free_slots(&[&[0]]);
+    assert!(read_vm(&mut &nil[..], &system, &defs, &VM_LIMITS).is_ok());
+
+    // Vec4, Rgba, and Ref values pointing at slot 0, which is free.
+    for tag in [4, 5, 6] {
+        let value = [tag, 0, 0, 0, 0];
+        let snapshot = vm_snapshot_with_free_slots(&[&value]);
+        assert_eq!(
+            read_vm(&mut &snapshot[..], &system, &defs, &VM_LIMITS).unwrap_err(),
+            SnapshotError::Invalid
+        );
+    }
+}
+
+#[test]
+fn def_count_must_match() {
+    let system = System::new(1);
+    let mut defs = Defs::new(2);
+    defs.add("a").unwrap();
+
+    let snapshot = vm_snapshot_with_free_slots(&[&[0]]);
+    assert!(read_vm(&mut &snapshot[..], &system, &defs, &VM_LIMITS).is_ok());
+
+    // Bytecode verified against these defs may only use def 0, but the VM must still have exactly
+    // one value per def, so that def IDs can be trusted either way.
+    let more = vm_snapshot_with_free_slots(&[&[0], &[0]]);
+    assert_eq!(
+        read_vm(&mut &more[..], &system, &defs, &VM_LIMITS).unwrap_err(),
+        SnapshotError::Invalid
+    );
+    defs.add("b").unwrap();
+    assert_eq!(
+        read_vm(&mut &snapshot[..], &system, &defs, &VM_LIMITS).unwrap_err(),
+        SnapshotError::Invalid
+    );
+}
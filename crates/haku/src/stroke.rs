@@ -0,0 +1,89 @@
+//! Evaluating brushes along strokes.
+//!
+//! A brush is drawn at every point of a stroke. Most of its defs evaluate to the same value at
+//! every point, but some depend on *stroke inputs* such as `wallPosition`, whose values are
+//! supplied by the host separately for each point. Such defs, along with all defs depending on
+//! them, are called *varying*; the rest are *stroke-constant*. Which are which is figured out by
+//! the compiler.
+//!
+//! Toplevel code that has anything varying in it is split into two parts, separated by an
+//! [`Opcode::PointStart`][crate::bytecode::Opcode::PointStart]: first all the stroke-constant
+//! defs, and then the varying defs followed by the result. Evaluating the whole thing at once
+//! ([`EvalMode::Full`]) works exactly like it does for code that isn't split. To draw a stroke,
+//! the host instead evaluates the stroke-constant part once ([`EvalMode::Stroke`]), takes an image
+//! of the VM, and then for each point restores the image, sets the inputs, and evaluates only the
+//! per-point part ([`EvalMode::Point`]).
+//!
+//! Code that isn't split doesn't depend on the inputs at all, so its result from
+//! [`EvalMode::Stroke`] can be drawn at every point as is.
+
+use crate::{
+    bytecode::{DefId, Defs, Namespace},
+    compiler::ClosureSpec,
+    system::ChunkId,
+    value::{BytecodeLoc, Closure, FunctionName, Vec2, Vec4},
+    vm::{Exception, Vm},
+};
+
+/// Which part of toplevel code to evaluate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvalMode {
+    /// Evaluate all of it.
+    #[default]
+    Full,
+    /// Evaluate only the stroke-constant part. Split code returns nil.
+    Stroke,
+    /// Evaluate only the per-point part.
+    Point,
+}
+
+/// The defs of stroke inputs, which are declared in the prelude.
+#[derive(Debug, Clone, Copy)]
+pub struct Inputs {
+    /// Position of the point on the wall.
+    pub wall_position: DefId,
+}
+
+impl Inputs {
+    /// Find the inputs in the prelude's namespace, and mark them as varying.
+    /// This has to be done before compiling any code that uses them.
+    pub fn declare(defs: &mut Defs, prelude: Namespace) -> Option<Self> {
+        let inputs = Self {
+            wall_position: defs.get_in(prelude, "wallPosition")?,
+        };
+        defs.set_varying(inputs.wall_position);
+        Some(inputs)
+    }
+
+    /// Set the inputs for a point of a stroke.
+    /// This has to be done after `Vm::apply_defs` and before running the per-point part of code.
+    pub fn apply(&self, vm: &mut Vm, wall_position: Vec2) -> Result<(), Exception> {
+        let wall_position = vm.create_vec4(Vec4 {
+            x: wall_position.x,
+            y: wall_position.y,
+            z: 0.0,
+            w: 0.0,
+        })?;
+        vm.set_def(self.wall_position, wall_position);
+        Ok(())
+    }
+}
+
+/// Returns a closure evaluating the given part of a chunk's toplevel code, or `None` if that part
+/// is empty, which is the case for the per-point part of code that isn't split.
+pub fn toplevel(chunk_id: ChunkId, spec: ClosureSpec, mode: EvalMode) -> Option<Closure> {
+    let offset = match mode {
+        EvalMode::Full | EvalMode::Stroke => 0,
+        EvalMode::Point => spec.point_start?,
+    };
+    Some(Closure {
+        start: BytecodeLoc { chunk_id, offset },
+        name: FunctionName::Anonymous,
+        param_count: 0,
+        local_count: spec.local_count,
+        captures: Default::default(),
+    })
+}
+
+#[cfg(test)]
+mod tests;
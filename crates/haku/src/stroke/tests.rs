@@ -0,0 +1,234 @@
+use alloc::{string::String, vec::Vec};
+
+use crate::{
+    bytecode::Defs,
+    compiler::ClosureSpec,
+    module::{self, Module, BUNDLED},
+    system::{ChunkId, System},
+    testing::{compile_with, host_modules, load_prelude, Imports, Options, VM_LIMITS},
+    value::{Ref, Vec2},
+    vm::Vm,
+};
+
+use super::{toplevel as toplevel_closure, EvalMode, Inputs};
+
+struct Brush {
+    system: System,
+    defs: Defs,
+    vm: Vm,
+    inputs: Inputs,
+    modules: Vec<Module>,
+    chunk_id: ChunkId,
+    spec: ClosureSpec,
+}
+
+/// Compile a brush with the prelude and the given modules available, the same way hosts do.
+fn compile(modules: &[(&str, &str)], code: &str) -> Brush {
+    let host = host_modules(modules);
+    let mut system = System::new(16);
+    let mut defs = Defs::new(256);
+
+    let prelude = load_prelude(&mut system, &mut defs);
+    let inputs = Inputs::declare(&mut defs, prelude.namespace).unwrap();
+    let mut vm = Vm::new(&defs, &VM_LIMITS);
+    module::init(&mut vm, &system, &[prelude]).unwrap();
+
+    let compiled = compile_with(
+        code,
+        &mut system,
+        &mut defs,
+        Options {
+            imports: Some(Imports {
+                resolver: &(BUNDLED, &host),
+                prelude: prelude.namespace,
+            }),
+            ..Default::default()
+        },
+    );
+    compiled.assert_no_diagnostics();
+    let modules = compiled.modules.clone();
+    let spec = compiled.spec;
+    let chunk_id = compiled.add_to(&mut system, &defs).start.chunk_id;
+
+    Brush {
+        system,
+        defs,
+        vm,
+        inputs,
+        modules,
+        chunk_id,
+        spec,
+    }
+}
+
+impl Brush {
+    fn is_varying(&mut self, name: &str) -> bool {
+        let def_id = self.defs.get(name).unwrap();
+        self.defs.is_varying(def_id)
+    }
+
+    /// Run the given part of the brush and its modules, and format the result.
+    /// Returns `None` if the brush has no code to run in that part.
+    fn run(&mut self, mode: EvalMode) -> Option<String> {
+        self.vm.set_eval_mode(mode);
+        self.vm.apply_defs(&self.defs);
+        module::init(&mut self.vm, &self.system, &self.modules).unwrap();
+        let closure = toplevel_closure(self.chunk_id, self.spec, mode)?;
+        let closure_id = self.vm.create_ref(Ref::Closure(closure)).unwrap();
+        let value = self.vm.run(&self.system, closure_id).unwrap();
+        Some(self.vm.format_value(value))
+    }
+
+    fn set_position(&mut self, x: f32, y: f32) {
+        self.vm.apply_defs(&self.defs);
+        self.inputs.apply(&mut self.vm, Vec2 { x, y }).unwrap();
+    }
+
+    fn def(&mut self, name: &str) -> String {
+        let def_id = self.defs.get(name).unwrap();
+        let value = self.vm.def_values()[def_id.to_u16() as usize];
+        self.vm.format_value(value)
+    }
+}
+
+#[test]
+fn constant_brush() {
+    let mut brush = compile(&[], "size = 4\nstroke size #000 (vec 0 0)");
+    assert!(!brush.spec.varies_per_point());
+    assert!(!brush.is_varying("size"));
+    assert_eq!(brush.run(EvalMode::Stroke).unwrap(), "<stroke point>");
+    assert_eq!(brush.run(EvalMode::Point), None);
+}
+
+#[test]
+fn varying_defs() {
+    let mut brush = compile(
+        &[],
+        r#"
+            x = offset + half
+            half = size / 2
+            size = 4
+            f = \p -> vecX p + vecX wallPosition
+            offset = f (vec 1)
+            g = \n -> n + 1
+            [x, g size]
+        "#,
+    );
+    assert!(brush.spec.varies_per_point());
+    // Defs depending on inputs vary, even if they appear earlier in the code.
+    assert!(brush.is_varying("f"));
+    assert!(brush.is_varying("offset"));
+    assert!(brush.is_varying("x"));
+    assert!(!brush.is_varying("half"));
+    assert!(!brush.is_varying("size"));
+    assert!(!brush.is_varying("g"));
+}
+
+#[test]
+fn split_evaluation() {
+    let code = r#"
+        size = 2 + 3
+        x = vecX wallPosition * size
+        [size, x]
+    "#;
+
+    let mut brush = compile(&[], code);
+    brush.set_position(2.0, 0.0);
+    assert_eq!(brush.run(EvalMode::Full).unwrap(), "[5, 10]");
+
+    let mut brush = compile(&[], code);
+    assert_eq!(brush.run(EvalMode::Stroke).unwrap(), "()");
+    assert_eq!(brush.def("size"), "5");
+    let image = brush.vm.image();
+    for (x, expected) in [(1.0, "[5, 5]"), (3.0, "[5, 15]")] {
+        brush.vm.restore_image(&image);
+        brush.set_position(x, 0.0);
+        assert_eq!(brush.run(EvalMode::Point).unwrap(), expected);
+    }
+}
+
+#[test]
+fn point_part_does_not_rerun_constant_defs() {
+    let mut brush = compile(
+        &[],
+        "list = [1, 2, 3]\npoint = [vecX wallPosition]\n[list, point]",
+    );
+    brush.run(EvalMode::Stroke);
+    let fuel = brush.vm.remaining_fuel();
+    brush.set_position(1.0, 0.0);
+    brush.run(EvalMode::Point);
+    let fuel_per_point = fuel - brush.vm.remaining_fuel();
+
+    let mut full = compile(
+        &[],
+        "list = [1, 2, 3]\npoint = [vecX wallPosition]\n[list, point]",
+    );
+    full.set_position(1.0, 0.0);
+    let fuel = full.vm.remaining_fuel();
+    full.run(EvalMode::Full);
+    assert!(fuel_per_point < fuel - full.vm.remaining_fuel());
+}
+
+#[test]
+fn toplevel_locals() {
+    // The let leaves `k` behind in the toplevel scope, where `b` can see it, so `a` cannot be
+    // evaluated in a different part than `b`.
+    let mut brush = compile(&[], "a = (let k = 1\nk)\nb = k + vecX wallPosition\nb");
+    assert!(brush.is_varying("a"));
+    assert!(brush.is_varying("b"));
+    assert_eq!(brush.run(EvalMode::Stroke).unwrap(), "()");
+    brush.set_position(2.0, 0.0);
+    assert_eq!(brush.run(EvalMode::Point).unwrap(), "3");
+}
+
+#[test]
+fn varying_modules() {
+    let mut brush = compile(
+        &[(
+            "position",
+            "scale = 10\nscaledX = vecX wallPosition * scale\nTrue",
+        )],
+        "import position\ny = 1\n[scaledX, y, scale]",
+    );
+    assert!(brush.is_varying("scaledX"));
+    assert!(!brush.is_varying("scale"));
+    assert!(brush.spec.varies_per_point());
+
+    brush.run(EvalMode::Stroke);
+    let image = brush.vm.image();
+    for (x, expected) in [(1.0, "[10, 1, 10]"), (2.0, "[20, 1, 10]")] {
+        brush.vm.restore_image(&image);
+        brush.set_position(x, 0.0);
+        assert_eq!(brush.run(EvalMode::Point).unwrap(), expected);
+    }
+}
+
+#[test]
+fn points_do_not_see_values_from_previous_points() {
+    // Depending on the point, the defs take turns allocating lots of lists and a few vectors.
+    // Values left behind in defs by one point must not outlive the refs and vectors they point
+    // to, or the next point's garbage collection trips over them.
+    let mut brush = compile(
+        &[],
+        r#"
+            garbage = \n -> (let l = [[n], [n], [n], [n], [n], [n], [n]]
+                0)
+            lists = \n -> if (n > 0) (let g = garbage n
+                lists (n - 1)) else 0
+            lastv = \n, v -> if (n > 0) lastv (n - 1) (vec n n) else v
+            e = if (vecX wallPosition > 0) 0 else lists 40
+            d = if (vecX wallPosition > 0) lastv 60 (vec 0 0) else 0
+            stroke 1 #000 (vec 0 0)
+        "#,
+    );
+    assert!(brush.is_varying("e"));
+    assert!(brush.is_varying("d"));
+
+    brush.run(EvalMode::Stroke).unwrap();
+    let image = brush.vm.image();
+    for x in [1.0, -1.0, 1.0, -1.0] {
+        brush.vm.restore_image(&image);
+        brush.set_position(x, 0.0);
+        assert_eq!(brush.run(EvalMode::Point).unwrap(), "<stroke point>");
+    }
+}
@@ -6,15 +6,19 @@ use core::{
 use alloc::vec::Vec;
 
 use crate::{
-    bytecode::Chunk,
+    bytecode::{
+        verify::{verify, Entry, VerifyError},
+        Chunk, Defs,
+    },
+    compiler::ClosureSpec,
     value::Value,
     vm::{Exception, FnArgs, Vm},
 };
 
 pub type SystemFn = fn(&mut Vm, FnArgs) -> Result<Value, Exception>;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct ChunkId(u32);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ChunkId(pub(crate) u32);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SystemFnArity {
@@ -27,8 +31,17 @@ pub enum SystemFnArity {
 pub struct System {
     /// Resolves a system function name to an index into `fn`s.
     pub resolve_fn: fn(SystemFnArity, &str) -> Option<u8>,
+    /// Returns the type signatures of a system function, used for type checking.
+    pub signatures_fn: fn(u8) -> &'static [&'static str],
+    /// Returns the documentation of a system function, used for editor hover.
+    pub docs_fn: fn(u8) -> &'static str,
+    /// The index, arity, and name of every system function.
+    pub names: &'static [(u8, SystemFnArity, &'static str)],
     pub fns: [Option<SystemFn>; 256],
-    pub chunks: Vec<Chunk>,
+    chunks: Vec<Chunk>,
+    /// Entries of each chunk, found while verifying it.
+    entries: Vec<Vec<Entry>>,
+    max_chunks: usize,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -37,7 +50,10 @@ pub struct SystemImage {
 }
 
 macro_rules! def_fns {
-    ($($index:tt $arity:tt $name:tt => $fnref:expr),* $(,)?) => {
+    ($(
+        $(#[doc = $doc:literal])*
+        $index:literal $arity:ident $name:literal => $fnref:expr, [$($signature:literal),* $(,)?]
+    ),* $(,)?) => {
         pub(crate) fn init_fns(system: &mut System) {
             $(
                 debug_assert!(system.fns[$index].is_none());
@@ -51,6 +67,29 @@ macro_rules! def_fns {
                 _ => None,
             }
         }
+
+        /// Returns the signatures of the system function with the given index, in the same
+        /// format as the system library documentation (`name : type, ... -> type`.)
+        /// There's one signature per accepted number of arguments.
+        pub(crate) fn signatures(index: u8) -> &'static [&'static str] {
+            match index {
+                $($index => &[$($signature),*],)*
+                _ => &[],
+            }
+        }
+
+        /// Returns the documentation of the system function with the given index.
+        /// Every line is prefixed with a space, as is the case with Rust doc comments.
+        pub(crate) fn docs(index: u8) -> &'static str {
+            match index {
+                $($index => concat!($($doc, "\n"),*),)*
+                _ => "",
+            }
+        }
+
+        pub(crate) const NAMES: &'static [(u8, SystemFnArity, &'static str)] = &[
+            $(($index, SystemFnArity::$arity, $name),)*
+        ];
     };
 }
 
@@ -60,20 +99,37 @@ impl System {
 
         let mut system = Self {
             resolve_fn: Self::resolve,
+            signatures_fn: Self::signatures,
+            docs_fn: Self::docs,
+            names: Self::NAMES,
             fns: [None; 256],
             chunks: Vec::with_capacity(max_chunks),
+            entries: Vec::with_capacity(max_chunks),
+            max_chunks,
         };
         Self::init_fns(&mut system);
         system
     }
 
-    pub fn add_chunk(&mut self, chunk: Chunk) -> Result<ChunkId, ChunkError> {
-        if self.chunks.len() >= self.chunks.capacity() {
-            return Err(ChunkError);
+    /// Verify and add a chunk to the system.
+    ///
+    /// `defs` must contain all defs that the chunk refers to, and `spec` must be the spec of the
+    /// chunk's toplevel code, as returned by the compiler.
+    pub fn add_chunk(
+        &mut self,
+        chunk: Chunk,
+        defs: &Defs,
+        spec: ClosureSpec,
+    ) -> Result<ChunkId, ChunkError> {
+        if self.chunks.len() >= self.max_chunks {
+            return Err(ChunkError::TooManyChunks);
         }
 
+        let entries = verify(self, defs, &chunk, spec).map_err(ChunkError::Verify)?;
+
         let id = ChunkId(self.chunks.len() as u32);
         self.chunks.push(chunk);
+        self.entries.push(entries);
         Ok(id)
     }
 
@@ -81,6 +137,16 @@ impl System {
         &self.chunks[id.0 as usize]
     }
 
+    /// Returns the entry at the given offset of a chunk, or `None` if there's no such chunk, or
+    /// closures cannot start running code at that offset.
+    pub fn entry(&self, id: ChunkId, offset: u16) -> Option<Entry> {
+        let entries = self.entries.get(id.0 as usize)?;
+        let index = entries
+            .binary_search_by_key(&offset, |entry| entry.offset)
+            .ok()?;
+        Some(entries[index])
+    }
+
     pub fn image(&self) -> SystemImage {
         SystemImage {
             chunks: self.chunks.len(),
@@ -91,25 +157,36 @@ impl System {
         self.chunks.resize_with(image.chunks, || {
             panic!("image must be a subset of the current system")
         });
+        self.entries.truncate(image.chunks);
     }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct ChunkError;
+pub enum ChunkError {
+    TooManyChunks,
+    Verify(VerifyError),
+}
 
 impl Display for ChunkError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.write_str("too many chunks")
+        match self {
+            ChunkError::TooManyChunks => f.write_str("too many chunks"),
+            ChunkError::Verify(e) => Display::fmt(e, f),
+        }
     }
 }
 
 impl Error for ChunkError {}
 
 pub mod fns {
-    use alloc::{format, vec::Vec};
+    use core::{cmp::Ordering, fmt::Write};
+
+    use alloc::{format, string::String, vec::Vec};
 
     use crate::{
-        value::{Fill, List, Ref, Rgba, Scribble, Shape, Stroke, Value, Vec2, Vec4},
+        value::{
+            Effect, Fill, Filter, List, Ref, Rgba, Scribble, Shape, Stroke, Value, Vec2, Vec4,
+        },
         vm::{Exception, FnArgs, Vm},
     };
 
@@ -117,102 +194,243 @@ pub mod fns {
 
     impl System {
         def_fns! {
-            0x00 Binary "+" => add,
-            0x01 Binary "-" => sub,
-            0x02 Binary "*" => mul,
-            0x03 Binary "/" => div,
-            0x04 Unary "-" => neg,
-
-            0x10 Nary "floor" => floorf,
-            0x11 Nary "ceil" => ceilf,
-            0x12 Nary "round" => roundf,
-            0x13 Nary "abs" => fabsf,
-            0x14 Nary "mod" => fmodf,
-            0x15 Nary "pow" => powf,
-            0x16 Nary "sqrt" => sqrtf,
-            0x17 Nary "cbrt" => cbrtf,
-            0x18 Nary "exp" => expf,
-            0x19 Nary "exp2" => exp2f,
-            0x1A Nary "ln" => logf,
-            0x1B Nary "log2" => log2f,
-            0x1C Nary "log10" => log10f,
-            0x1D Nary "hypot" => hypotf,
-            0x1E Nary "sin" => sinf,
-            0x1F Nary "cos" => cosf,
-            0x20 Nary "tan" => tanf,
-            0x21 Nary "asin" => asinf,
-            0x22 Nary "acos" => acosf,
-            0x23 Nary "atan" => atanf,
-            0x24 Nary "atan2" => atan2f,
-            0x25 Nary "expMinus1" => expm1f,
-            0x26 Nary "ln1Plus" => log1pf,
-            0x27 Nary "sinh" => sinhf,
-            0x28 Nary "cosh" => coshf,
-            0x29 Nary "tanh" => tanhf,
-            0x2A Nary "asinh" => asinhf,
-            0x2B Nary "acosh" => acoshf,
-            0x2C Nary "atanh" => atanhf,
-
-            0x40 Unary "!" => not,
-            0x41 Binary "==" => eq,
-            0x42 Binary "!=" => neq,
-            0x43 Binary "<" => lt,
-            0x44 Binary "<=" => leq,
-            0x45 Binary ">" => gt,
-            0x46 Binary ">=" => geq,
-
-            0x80 Nary "vec" => vec,
-            0x81 Nary "vecX" => vec_x,
-            0x82 Nary "vecY" => vec_y,
-            0x83 Nary "vecZ" => vec_z,
-            0x84 Nary "vecW" => vec_w,
-
-            0x85 Nary "rgba" => rgba,
-            0x86 Nary "rgbaR" => rgba_r,
-            0x87 Nary "rgbaG" => rgba_g,
-            0x88 Nary "rgbaB" => rgba_b,
-            0x89 Nary "rgbaA" => rgba_a,
+            /// Adds two numbers together.
+            0x00 Binary "+" => add, ["a : number, b : number -> number"],
+            /// Subtracts `b` from `a`.
+            0x01 Binary "-" => sub, ["a : number, b : number -> number"],
+            /// Multiplies two numbers together.
+            0x02 Binary "*" => mul, ["a : number, b : number -> number"],
+            /// Divides `a` by `b`.
+            0x03 Binary "/" => div, ["a : number, b : number -> number"],
+            /// Returns `a` with the opposite sign.
+            0x04 Unary "-" => neg, ["a : number -> number"],
+
+            /// Rounds `x` towards -∞.
+            0x10 Nary "floor" => floorf, ["x : number -> number"],
+            /// Rounds `x` towards +∞.
+            0x11 Nary "ceil" => ceilf, ["x : number -> number"],
+            /// Rounds `x` to the nearest integer, with halves rounded towards +∞.
+            0x12 Nary "round" => roundf, ["x : number -> number"],
+            /// Returns the absolute value of `x`.
+            0x13 Nary "abs" => fabsf, ["x : number -> number"],
+            /// Returns the remainder of dividing `x` by `y`.
+            /// The remainder is always non-negative.
+            0x14 Nary "mod" => fmodf, ["x : number, y : number -> number"],
+            /// Raises `base` to the given `exponent`.
+            0x15 Nary "pow" => powf, ["base : number, exponent : number -> number"],
+            /// Returns the square root of `x`.
+            0x16 Nary "sqrt" => sqrtf, ["x : number -> number"],
+            /// Returns the cubic root of `x`.
+            0x17 Nary "cbrt" => cbrtf, ["x : number -> number"],
+            /// The exponential function `pow e x`.
+            0x18 Nary "exp" => expf, ["x : number -> number"],
+            /// The exponential function `pow 2 x`.
+            0x19 Nary "exp2" => exp2f, ["x : number -> number"],
+            /// The natural logarithm of `x`.
+            0x1A Nary "ln" => logf, ["x : number -> number"],
+            /// The logarithm base `2` of `x`.
+            0x1B Nary "log2" => log2f, ["x : number -> number"],
+            /// The logarithm base `10` of `x`.
+            0x1C Nary "log10" => log10f, ["x : number -> number"],
+            /// The hypotenuse of the right triangle with sides `x` and `y`.
+            0x1D Nary "hypot" => hypotf, ["x : number, y : number -> number"],
+            /// The sine of `x`, in radians.
+            0x1E Nary "sin" => sinf, ["x : number -> number"],
+            /// The cosine of `x`, in radians.
+            0x1F Nary "cos" => cosf, ["x : number -> number"],
+            /// The tangent of `x`, in radians.
+            0x20 Nary "tan" => tanf, ["x : number -> number"],
+            /// The arc sine of `x`, in radians.
+            0x21 Nary "asin" => asinf, ["x : number -> number"],
+            /// The arc cosine of `x`, in radians.
+            0x22 Nary "acos" => acosf, ["x : number -> number"],
+            /// The arc tangent of `x`, in radians.
+            0x23 Nary "atan" => atanf, ["x : number -> number"],
+            /// The angle between the positive X axis and the line passing through `(0, 0)` and `(x, y)`.
+            /// Note the reverse argument order: `y` comes first.
+            0x24 Nary "atan2" => atan2f, ["y : number, x : number -> number"],
+            /// `pow e x - 1`, but accurate even when `x` is close to zero.
+            0x25 Nary "expMinus1" => expm1f, ["x : number -> number"],
+            /// `ln (1 + x)`, but more accurate than performing the operations separately.
+            0x26 Nary "ln1Plus" => log1pf, ["x : number -> number"],
+            /// The hyperbolic sine of `x`.
+            0x27 Nary "sinh" => sinhf, ["x : number -> number"],
+            /// The hyperbolic cosine of `x`.
+            0x28 Nary "cosh" => coshf, ["x : number -> number"],
+            /// The hyperbolic tangent of `x`.
+            0x29 Nary "tanh" => tanhf, ["x : number -> number"],
+            /// The inverse hyperbolic sine of `x`.
+            0x2A Nary "asinh" => asinhf, ["x : number -> number"],
+            /// The inverse hyperbolic cosine of `x`.
+            0x2B Nary "acosh" => acoshf, ["x : number -> number"],
+            /// The inverse hyperbolic tangent of `x`.
+            0x2C Nary "atanh" => atanhf, ["x : number -> number"],
+
+            /// Returns `True` if `a` is `()` or `False`, and `False` otherwise.
+            0x40 Unary "!" => not, ["a : _ -> boolean"],
+            /// Returns `True` if `a` and `b` are equal.
+            /// Numbers, vectors, and colors are compared by value; everything else by reference.
+            0x41 Binary "==" => eq, ["a : _, b : _ -> boolean"],
+            /// Returns `True` if `a` and `b` are not equal.
+            0x42 Binary "!=" => neq, ["a : _, b : _ -> boolean"],
+            /// Returns `True` if `a` is less than `b`.
+            0x43 Binary "<" => lt, ["a : _, b : _ -> boolean"],
+            /// Returns `True` if `a` is less than or equal to `b`.
+            0x44 Binary "<=" => leq, ["a : _, b : _ -> boolean"],
+            /// Returns `True` if `a` is greater than `b`.
+            0x45 Binary ">" => gt, ["a : _, b : _ -> boolean"],
+            /// Returns `True` if `a` is greater than or equal to `b`.
+            0x46 Binary ">=" => geq, ["a : _, b : _ -> boolean"],
+
+            /// Creates a new `vec` from one to four numbers.
+            /// Omitted dimensions are initialized to zero.
+            0x80 Nary "vec" => vec, [
+                "x : number -> vec",
+                "x : number, y : number -> vec",
+                "x : number, y : number, z : number -> vec",
+                "x : number, y : number, z : number, w : number -> vec",
+            ],
+            /// Returns the X component of `v`.
+            0x81 Nary "vecX" => vec_x, ["v : vec -> number"],
+            /// Returns the Y component of `v`.
+            0x82 Nary "vecY" => vec_y, ["v : vec -> number"],
+            /// Returns the Z component of `v`.
+            0x83 Nary "vecZ" => vec_z, ["v : vec -> number"],
+            /// Returns the W component of `v`.
+            0x84 Nary "vecW" => vec_w, ["v : vec -> number"],
+
+            /// Creates a new `rgba` with the given color channels, in the `0` to `1` range.
+            0x85 Nary "rgba" => rgba, ["r : number, g : number, b : number, a : number -> rgba"],
+            /// Returns the red channel of `color`.
+            0x86 Nary "rgbaR" => rgba_r, ["color : rgba -> number"],
+            /// Returns the green channel of `color`.
+            0x87 Nary "rgbaG" => rgba_g, ["color : rgba -> number"],
+            /// Returns the blue channel of `color`.
+            0x88 Nary "rgbaB" => rgba_b, ["color : rgba -> number"],
+            /// Returns the alpha channel of `color`.
+            0x89 Nary "rgbaA" => rgba_a, ["color : rgba -> number"],
 
             // NOTE: Not used right now, has been replaced with Opcode::List.
             // Keeping it around to reserve a slot for data structure operations.
-            0x90 Nary "list (unused)" => list,
-
-            0xc0 Nary "toShape" => to_shape_f,
-            0xc1 Nary "line" => line,
-            0xc2 Nary "rect" => rect,
-            0xc3 Nary "circle" => circle,
-            0xe0 Nary "stroke" => stroke,
-            0xe1 Nary "fill" => fill,
+            0x90 Nary "list (unused)" => list, [],
+
+            /// Converts `value` to a shape.
+            /// Shapes are cloned, vectors become points, and anything else becomes `()`.
+            0xc0 Nary "toShape" => to_shape_f, ["value : _ -> () | shape"],
+            /// Creates a line segment shape from `start` to `end`.
+            0xc1 Nary "line" => line, ["start : vec, end : vec -> shape"],
+            /// Creates a rectangle shape with its top-left corner at `position` and the given `size`.
+            0xc2 Nary "rect" => rect, [
+                "position : vec, size : vec -> shape",
+                "x : number, y : number, width : number, height : number -> shape",
+            ],
+            /// Creates a circle shape with its center at `center` and the given `radius`.
+            0xc3 Nary "circle" => circle, [
+                "center : vec, radius : number -> shape",
+                "x : number, y : number, radius : number -> shape",
+            ],
+            /// Creates a stroke scribble, which outlines `shape` with the given `thickness` and `color`.
+            0xe0 Nary "stroke" => stroke, ["thickness : number, color : rgba, shape : shapeLike -> scribble"],
+            /// Creates a fill scribble, which fills the area of `shape` with `color`.
+            0xe1 Nary "fill" => fill, ["color : rgba, shape : shapeLike -> scribble"],
+            /// Creates a filter scribble, which blurs what's drawn in the area of `shape`.
+            /// `radius` is the standard deviation of the blur, in pixels.
+            0xe2 Nary "blur" => blur, ["radius : number, shape : shapeLike -> scribble"],
+            /// Creates a filter scribble, which rotates the hues of what's drawn in the area of `shape` by `angle`, in radians.
+            0xe3 Nary "hueShift" => hue_shift, ["angle : number, shape : shapeLike -> scribble"],
+            /// Creates a filter scribble, which adjusts the brightness and contrast of what's drawn in the area of `shape`.
+            /// `brightness` is added to the color channels, after their distance from middle gray is multiplied by `contrast`.
+            0xe4 Nary "brightnessContrast" => brightness_contrast, ["brightness : number, contrast : number, shape : shapeLike -> scribble"],
+            /// Creates a filter scribble, which inverts the colors of what's drawn in the area of `shape`.
+            0xe5 Nary "invert" => invert, ["shape : shapeLike -> scribble"],
+            /// Creates a filter scribble, which pixelates what's drawn in the area of `shape` into square blocks with sides `size` pixels long.
+            0xe6 Nary "pixelate" => pixelate, ["size : number, shape : shapeLike -> scribble"],
+            /// Creates a filter scribble, which moves what's drawn in the area of `shape` around by up to `distance` pixels, in directions given by noise.
+            /// `scale` is roughly the size of the noise's features, in pixels.
+            0xe7 Nary "displace" => displace, ["distance : number, scale : number, shape : shapeLike -> scribble"],
         }
     }
 
     pub fn add(vm: &mut Vm, args: FnArgs) -> Result<Value, Exception> {
         let a = args.get_number(vm, 0, "arguments to `+` must be numbers")?;
         let b = args.get_number(vm, 1, "arguments to `+` must be numbers")?;
-        Ok(Value::Number(a + b))
+        number(vm, Call::Binary("+", a, b), a + b)
     }
 
     pub fn sub(vm: &mut Vm, args: FnArgs) -> Result<Value, Exception> {
         let a = args.get_number(vm, 0, "arguments to `-` must be numbers")?;
         let b = args.get_number(vm, 1, "arguments to `-` must be numbers")?;
-        Ok(Value::Number(a - b))
+        number(vm, Call::Binary("-", a, b), a - b)
     }
 
     pub fn mul(vm: &mut Vm, args: FnArgs) -> Result<Value, Exception> {
         let a = args.get_number(vm, 0, "arguments to `*` must be numbers")?;
         let b = args.get_number(vm, 1, "arguments to `*` must be numbers")?;
-        Ok(Value::Number(a * b))
+        number(vm, Call::Binary("*", a, b), a * b)
     }
 
     pub fn div(vm: &mut Vm, args: FnArgs) -> Result<Value, Exception> {
         let a = args.get_number(vm, 0, "arguments to `/` must be numbers")?;
         let b = args.get_number(vm, 1, "arguments to `/` must be numbers")?;
-        Ok(Value::Number(a / b))
+        number(vm, Call::Binary("/", a, b), a / b)
     }
 
     pub fn neg(vm: &mut Vm, args: FnArgs) -> Result<Value, Exception> {
         let x = args.get_number(vm, 0, "`-` can only work with numbers")?;
-        Ok(Value::Number(-x))
+        number(vm, Call::Unary("-", x), -x)
+    }
+
+    /// A call to a system function, for describing it in exceptions.
+    enum Call<'a> {
+        Unary(&'a str, f32),
+        Binary(&'a str, f32, f32),
+        Function(&'a str, &'a [f32]),
+    }
+
+    /// Returns a number computed by a system function.
+    /// In strict math mode, NaN and infinities raise an exception naming the function call instead.
+    #[inline]
+    fn number(vm: &Vm, call: Call, x: f32) -> Result<Value, Exception> {
+        if vm.strict_math() && !x.is_finite() {
+            return Err(non_finite(vm, call, x));
+        }
+        Ok(Value::Number(x))
+    }
+
+    #[cold]
+    #[inline(never)]
+    fn non_finite(vm: &Vm, call: Call, x: f32) -> Exception {
+        // Negative arguments are parenthesized, so that the call reads like haku code.
+        fn arg(s: &mut String, x: f32) {
+            // Writing to a String never fails.
+            _ = if x.is_sign_negative() && !x.is_nan() {
+                write!(s, "({x})")
+            } else {
+                write!(s, "{x}")
+            };
+        }
+
+        let mut s = String::new();
+        match call {
+            Call::Unary(op, a) => {
+                s.push_str(op);
+                arg(&mut s, a);
+            }
+            Call::Binary(op, a, b) => {
+                _ = write!(s, "{a} {op} ");
+                arg(&mut s, b);
+            }
+            Call::Function(name, args) => {
+                s.push_str(name);
+                for &a in args {
+                    s.push(' ');
+                    arg(&mut s, a);
+                }
+            }
+        }
+        vm.create_exception(format!(
+            "`{s}` results in {x}, but strict math does not allow NaN or infinity"
+        ))
     }
 
     #[inline(never)]
@@ -227,7 +445,7 @@ pub mod fns {
             .get(vm, 0)
             .to_number()
             .ok_or_else(|| vm.create_exception(format!("`{name}` argument must be a number")))?;
-        Ok(Value::Number(f(x)))
+        number(vm, Call::Function(name, &[x]), f(x))
     }
 
     #[inline(never)]
@@ -249,7 +467,7 @@ pub mod fns {
             .get(vm, 1)
             .to_number()
             .ok_or_else(|| vm.create_exception(format!("`{name}` arguments must be numbers")))?;
-        Ok(Value::Number(f(x, y)))
+        number(vm, Call::Function(name, &[x, y]), f(x, y))
     }
 
     macro_rules! math_fns {
@@ -302,37 +520,49 @@ pub mod fns {
     pub fn eq(vm: &mut Vm, args: FnArgs) -> Result<Value, Exception> {
         let a = args.get(vm, 0);
         let b = args.get(vm, 1);
-        Ok(Value::from(a == b))
+        Ok(Value::from(
+            vm.compare_values(a, b) == Some(Ordering::Equal),
+        ))
     }
 
     pub fn neq(vm: &mut Vm, args: FnArgs) -> Result<Value, Exception> {
         let a = args.get(vm, 0);
         let b = args.get(vm, 1);
-        Ok(Value::from(a != b))
+        Ok(Value::from(
+            vm.compare_values(a, b) != Some(Ordering::Equal),
+        ))
     }
 
     pub fn lt(vm: &mut Vm, args: FnArgs) -> Result<Value, Exception> {
         let a = args.get(vm, 0);
         let b = args.get(vm, 1);
-        Ok(Value::from(a < b))
+        Ok(Value::from(vm.compare_values(a, b) == Some(Ordering::Less)))
     }
 
     pub fn leq(vm: &mut Vm, args: FnArgs) -> Result<Value, Exception> {
         let a = args.get(vm, 0);
         let b = args.get(vm, 1);
-        Ok(Value::from(a <= b))
+        Ok(Value::from(matches!(
+            vm.compare_values(a, b),
+            Some(Ordering::Less | Ordering::Equal)
+        )))
     }
 
     pub fn gt(vm: &mut Vm, args: FnArgs) -> Result<Value, Exception> {
         let a = args.get(vm, 0);
         let b = args.get(vm, 1);
-        Ok(Value::from(a > b))
+        Ok(Value::from(
+            vm.compare_values(a, b) == Some(Ordering::Greater),
+        ))
     }
 
     pub fn geq(vm: &mut Vm, args: FnArgs) -> Result<Value, Exception> {
         let a = args.get(vm, 0);
         let b = args.get(vm, 1);
-        Ok(Value::from(a >= b))
+        Ok(Value::from(matches!(
+            vm.compare_values(a, b),
+            Some(Ordering::Greater | Ordering::Equal)
+        )))
     }
 
     pub fn vec(vm: &mut Vm, args: FnArgs) -> Result<Value, Exception> {
@@ -340,35 +570,35 @@ pub mod fns {
         match args.num() {
             1 => {
                 let x = args.get_number(vm, 0, ERROR)?;
-                Ok(Value::Vec4(Vec4 {
+                vm.create_vec4(Vec4 {
                     x,
                     y: 0.0,
                     z: 0.0,
                     w: 0.0,
-                }))
+                })
             }
             2 => {
                 let x = args.get_number(vm, 0, ERROR)?;
                 let y = args.get_number(vm, 1, ERROR)?;
-                Ok(Value::Vec4(Vec4 {
+                vm.create_vec4(Vec4 {
                     x,
                     y,
                     z: 0.0,
                     w: 0.0,
-                }))
+                })
             }
             3 => {
                 let x = args.get_number(vm, 0, ERROR)?;
                 let y = args.get_number(vm, 1, ERROR)?;
                 let z = args.get_number(vm, 2, ERROR)?;
-                Ok(Value::Vec4(Vec4 { x, y, z, w: 0.0 }))
+                vm.create_vec4(Vec4 { x, y, z, w: 0.0 })
             }
             4 => {
                 let x = args.get_number(vm, 0, ERROR)?;
                 let y = args.get_number(vm, 1, ERROR)?;
                 let z = args.get_number(vm, 2, ERROR)?;
                 let w = args.get_number(vm, 3, ERROR)?;
-                Ok(Value::Vec4(Vec4 { x, y, z, w }))
+                vm.create_vec4(Vec4 { x, y, z, w })
             }
             _ => Err(vm.create_exception("`vec` expects 1-4 arguments (vec x y z w)")),
         }
@@ -421,7 +651,7 @@ pub mod fns {
         let b = args.get_number(vm, 2, ERROR)?;
         let a = args.get_number(vm, 3, ERROR)?;
 
-        Ok(Value::Rgba(Rgba { r, g, b, a }))
+        vm.create_rgba(Rgba { r, g, b, a })
     }
 
     pub fn rgba_r(vm: &mut Vm, args: FnArgs) -> Result<Value, Exception> {
@@ -477,7 +707,7 @@ pub mod fns {
                     None
                 }
             }
-            Value::Vec4(vec) => Some(Shape::Point(vec.into())),
+            Value::Vec4(_) => vm.get_vec4(value).map(|vec| Shape::Point(vec.into())),
         }
     }
 
@@ -588,4 +818,109 @@ pub mod fns {
             Ok(Value::Nil)
         }
     }
+
+    fn filter(vm: &mut Vm, effect: Effect, shape: Value) -> Result<Value, Exception> {
+        if let Some(shape) = to_shape(shape, vm) {
+            let id = vm.create_ref(Ref::Scribble(Scribble::Filter(Filter { effect, shape })))?;
+            Ok(Value::Ref(id))
+        } else {
+            Ok(Value::Nil)
+        }
+    }
+
+    pub fn blur(vm: &mut Vm, args: FnArgs) -> Result<Value, Exception> {
+        if args.num() != 2 {
+            return Err(vm.create_exception("`blur` expects 2 arguments (blur radius shape)"));
+        }
+
+        let radius = args.get_number(
+            vm,
+            0,
+            "1st argument to `blur` must be a radius in pixels (number)",
+        )?;
+        filter(vm, Effect::Blur(radius), args.get(vm, 1))
+    }
+
+    pub fn hue_shift(vm: &mut Vm, args: FnArgs) -> Result<Value, Exception> {
+        if args.num() != 2 {
+            return Err(
+                vm.create_exception("`hueShift` expects 2 arguments (hueShift angle shape)")
+            );
+        }
+
+        let angle = args.get_number(
+            vm,
+            0,
+            "1st argument to `hueShift` must be an angle in radians (number)",
+        )?;
+        filter(vm, Effect::HueShift(angle), args.get(vm, 1))
+    }
+
+    pub fn brightness_contrast(vm: &mut Vm, args: FnArgs) -> Result<Value, Exception> {
+        if args.num() != 3 {
+            return Err(vm.create_exception(
+                "`brightnessContrast` expects 3 arguments (brightnessContrast brightness contrast shape)",
+            ));
+        }
+
+        let brightness = args.get_number(
+            vm,
+            0,
+            "1st argument to `brightnessContrast` must be the brightness (number)",
+        )?;
+        let contrast = args.get_number(
+            vm,
+            1,
+            "2nd argument to `brightnessContrast` must be the contrast (number)",
+        )?;
+        filter(
+            vm,
+            Effect::BrightnessContrast {
+                brightness,
+                contrast,
+            },
+            args.get(vm, 2),
+        )
+    }
+
+    pub fn invert(vm: &mut Vm, args: FnArgs) -> Result<Value, Exception> {
+        if args.num() != 1 {
+            return Err(vm.create_exception("`invert` expects 1 argument (invert shape)"));
+        }
+
+        filter(vm, Effect::Invert, args.get(vm, 0))
+    }
+
+    pub fn pixelate(vm: &mut Vm, args: FnArgs) -> Result<Value, Exception> {
+        if args.num() != 2 {
+            return Err(vm.create_exception("`pixelate` expects 2 arguments (pixelate size shape)"));
+        }
+
+        let size = args.get_number(
+            vm,
+            0,
+            "1st argument to `pixelate` must be a block size in pixels (number)",
+        )?;
+        filter(vm, Effect::Pixelate(size), args.get(vm, 1))
+    }
+
+    pub fn displace(vm: &mut Vm, args: FnArgs) -> Result<Value, Exception> {
+        if args.num() != 3 {
+            return Err(vm.create_exception(
+                "`displace` expects 3 arguments (displace distance scale shape)",
+            ));
+        }
+
+        let distance = args.get_number(
+            vm,
+            0,
+            "1st argument to `displace` must be a distance in pixels (number)",
+        )?;
+        let scale = args.get_number(
+            vm,
+            1,
+            "2nd argument to `displace` must be the scale of the noise in pixels (number)",
+        )?;
+        filter(vm, Effect::Displace { distance, scale }, args.get(vm, 2))
+    }
 }
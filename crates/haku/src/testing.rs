@@ -0,0 +1,237 @@
+//! Helpers shared between unit tests, for taking code through the lexer, parser, compiler, and
+//! VM without repeating the same setup in every test module.
+
+use alloc::{borrow::ToOwned, collections::BTreeMap, string::String, vec::Vec};
+
+use crate::{
+    ast::{Ast, NodeId},
+    bytecode::{Chunk, Defs, Namespace},
+    compiler::{compile_expr, incremental::DefCache, ClosureSpec, Compiler, Source},
+    debug_info::DebugInfo,
+    diagnostic::Diagnostic,
+    lexer::{lex, Lexer},
+    module::{Loader, Module, ModuleLimits, Resolver, BUNDLED, PRELUDE},
+    param::Param,
+    parser::{toplevel, Parser, ParserLimits},
+    source::{SourceCode, Span},
+    system::System,
+    token::Lexis,
+    value::{Closure, Ref, RefId},
+    vm::{Vm, VmLimits},
+};
+
+pub const VM_LIMITS: VmLimits = VmLimits {
+    stack_capacity: 256,
+    call_stack_capacity: 256,
+    ref_capacity: 256,
+    fuel: 65536,
+    memory: 65536,
+    strict_math: false,
+};
+
+pub const MODULE_LIMITS: ModuleLimits = ModuleLimits {
+    max_source_code_len: 65536,
+    max_tokens: 1024,
+    max_parser_events: 1024,
+    ast_capacity: 1024,
+    chunk_capacity: 65536,
+};
+
+/// Code lexed and parsed into an AST.
+pub struct Parsed<'a> {
+    pub code: &'a SourceCode,
+    pub lexis: Lexis,
+    pub ast: Ast,
+    pub root: NodeId,
+    /// Diagnostics emitted by the lexer and parser.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+pub fn parse(code: &str) -> Parsed<'_> {
+    parse_into(code, Lexis::new(1024))
+}
+
+/// Parses the code, keeping trivia around so that the AST can be printed back.
+pub fn parse_with_trivia(code: &str) -> Parsed<'_> {
+    parse_into(code, Lexis::with_trivia(1024))
+}
+
+fn parse_into(code: &str, lexis: Lexis) -> Parsed<'_> {
+    let code = SourceCode::unlimited_len(code);
+    let mut lexer = Lexer::new(lexis, code);
+    lex(&mut lexer).expect("too many tokens");
+    let mut parser = Parser::new(&lexer.lexis, &ParserLimits { max_events: 1024 });
+    toplevel(&mut parser);
+    let mut ast = Ast::new(1024);
+    let (root, mut parser_diagnostics) = parser.into_ast(&mut ast).unwrap();
+
+    let mut diagnostics = lexer.diagnostics;
+    diagnostics.append(&mut parser_diagnostics);
+    Parsed {
+        code,
+        lexis: lexer.lexis,
+        ast,
+        root,
+        diagnostics,
+    }
+}
+
+impl<'a> Parsed<'a> {
+    pub fn source<'b>(&'b self, system: &'b System) -> Source<'b> {
+        Source {
+            code: self.code,
+            ast: &self.ast,
+            system,
+        }
+    }
+
+    #[track_caller]
+    pub fn assert_no_diagnostics(&self) {
+        assert!(self.diagnostics.is_empty(), "{:#?}", self.diagnostics);
+    }
+}
+
+/// Modules importable by the code being compiled.
+pub struct Imports<'a> {
+    pub resolver: &'a dyn Resolver,
+    pub prelude: Namespace,
+}
+
+#[derive(Default)]
+pub struct Options<'a> {
+    pub debug_info: bool,
+    pub def_cache: Option<&'a mut DefCache>,
+    /// If set, the code's imports are loaded before compiling it, the same way hosts do.
+    pub imports: Option<Imports<'a>>,
+}
+
+/// Code compiled into a chunk, which hasn't been added to a system yet.
+pub struct Compiled {
+    pub chunk: Chunk,
+    pub spec: ClosureSpec,
+    pub params: Vec<Param>,
+    pub debug_info: Option<DebugInfo>,
+    /// Modules imported by the code, in the order they must be initialized in.
+    pub modules: Vec<Module>,
+    /// Diagnostics emitted by all stages of compilation, including lexing and parsing.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+pub fn compile(code: &str, system: &mut System, defs: &mut Defs) -> Compiled {
+    compile_with(code, system, defs, Options::default())
+}
+
+pub fn compile_with(
+    code: &str,
+    system: &mut System,
+    defs: &mut Defs,
+    options: Options,
+) -> Compiled {
+    let parsed = parse(code);
+    let mut diagnostics = parsed.diagnostics.clone();
+
+    let mut modules = Vec::new();
+    if let Some(imports) = options.imports {
+        let mut loader = Loader::new(imports.resolver, MODULE_LIMITS, Some(imports.prelude));
+        diagnostics.append(&mut loader.import_all(
+            system,
+            defs,
+            parsed.code,
+            &parsed.ast,
+            parsed.root,
+        ));
+        modules = loader.modules().collect();
+    }
+
+    let src = parsed.source(system);
+    let mut chunk = Chunk::new(65536).unwrap();
+    let mut compiler = Compiler::new(defs, &mut chunk);
+    compiler.def_cache = options.def_cache;
+    compiler.debug_info = options.debug_info.then(DebugInfo::new);
+    compile_expr(&mut compiler, &src, parsed.root).unwrap();
+    let spec = compiler.closure_spec();
+    let params = compiler.params;
+    let debug_info = compiler.debug_info;
+    diagnostics.append(&mut compiler.diagnostics);
+
+    Compiled {
+        chunk,
+        spec,
+        params,
+        debug_info,
+        modules,
+        diagnostics,
+    }
+}
+
+impl Compiled {
+    /// Messages of the error diagnostics.
+    pub fn errors(&self) -> Vec<String> {
+        self.diagnostics
+            .iter()
+            .filter(|d| d.is_error())
+            .map(|d| d.message().to_owned())
+            .collect()
+    }
+
+    #[track_caller]
+    pub fn assert_no_diagnostics(&self) {
+        assert!(self.diagnostics.is_empty(), "{:#?}", self.diagnostics);
+    }
+
+    /// Adds the chunk to the system, and returns a closure running it.
+    pub fn add_to(self, system: &mut System, defs: &Defs) -> Closure {
+        let chunk_id = system.add_chunk(self.chunk, defs, self.spec).unwrap();
+        Closure::chunk(chunk_id, self.spec)
+    }
+}
+
+/// Code added to a system of its own, along with a VM ready to run it.
+pub struct Loaded {
+    pub system: System,
+    pub vm: Vm,
+    pub closure: Closure,
+    pub closure_id: RefId,
+    pub params: Vec<Param>,
+    pub debug_info: Option<DebugInfo>,
+}
+
+/// Compiles code that must not emit any diagnostics, and loads it into a fresh system and VM.
+pub fn load(code: &str) -> Loaded {
+    load_with(code, Options::default())
+}
+
+pub fn load_with(code: &str, options: Options) -> Loaded {
+    let mut system = System::new(1);
+    let mut defs = Defs::new(256);
+    let mut compiled = compile_with(code, &mut system, &mut defs, options);
+    compiled.assert_no_diagnostics();
+    let debug_info = compiled.debug_info.take();
+    let params = core::mem::take(&mut compiled.params);
+    let closure = compiled.add_to(&mut system, &defs);
+    let mut vm = Vm::new(&defs, &VM_LIMITS);
+    let closure_id = vm.create_ref(Ref::Closure(closure.clone())).unwrap();
+    Loaded {
+        system,
+        vm,
+        closure,
+        closure_id,
+        params,
+        debug_info,
+    }
+}
+
+/// Modules supplied by a host, in the form hosts keep them in.
+pub fn host_modules(modules: &[(&str, &str)]) -> BTreeMap<String, String> {
+    modules
+        .iter()
+        .map(|&(name, code)| (name.into(), code.into()))
+        .collect()
+}
+
+/// Loads the prelude from the bundled modules.
+pub fn load_prelude(system: &mut System, defs: &mut Defs) -> Module {
+    Loader::new(BUNDLED, MODULE_LIMITS, None)
+        .load(system, defs, PRELUDE, Span::new(0, 0))
+        .expect("the prelude must compile")
+}
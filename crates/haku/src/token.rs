@@ -44,15 +44,34 @@ pub enum TokenKind {
     If,
     Else,
     Let,
+    Import,
+    Param,
 
     // NOTE: This must be kept last for TokenSet to work correctly.
     Error,
 }
 
+/// Source code between tokens, which has no meaning to the parser.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriviaKind {
+    /// Spaces, tabs, and newlines other than the first one in a row (which is a token.)
+    Whitespace,
+    /// A `--` comment, not including the newline at its end.
+    Comment,
+}
+
 #[derive(Debug, Clone)]
 pub struct Lexis {
     pub kinds: Vec<TokenKind>,
     pub spans: Vec<Span>,
+    /// Trivia in source order, if it was requested using [`Lexis::with_trivia`].
+    pub trivia: Option<Vec<Trivia>>,
 }
 
 impl Lexis {
@@ -62,7 +81,43 @@ impl Lexis {
         Self {
             kinds: Vec::with_capacity(capacity),
             spans: Vec::with_capacity(capacity),
+            trivia: None,
+        }
+    }
+
+    /// Like [`Lexis::new`], but also makes the lexer record whitespace and comments, for tools
+    /// which need to preserve them. Trivia does not count towards the capacity.
+    pub fn with_trivia(capacity: usize) -> Self {
+        Self {
+            trivia: Some(Vec::new()),
+            ..Self::new(capacity)
+        }
+    }
+
+    /// Records trivia, if requested. Adjacent whitespace is merged into a single piece of trivia.
+    pub fn push_trivia(&mut self, kind: TriviaKind, span: Span) {
+        let Some(trivia) = &mut self.trivia else {
+            return;
+        };
+        if let Some(last) = trivia.last_mut() {
+            if kind == TriviaKind::Whitespace
+                && last.kind == TriviaKind::Whitespace
+                && last.span.end == span.start
+            {
+                last.span.end = span.end;
+                return;
+            }
         }
+        trivia.push(Trivia { kind, span });
+    }
+
+    /// Iterates over the spans of recorded comments.
+    pub fn comments(&self) -> impl Iterator<Item = Span> + '_ {
+        self.trivia
+            .iter()
+            .flatten()
+            .filter(|trivia| trivia.kind == TriviaKind::Comment)
+            .map(|trivia| trivia.span)
     }
 
     pub fn len(&self) -> u32 {
@@ -110,7 +165,7 @@ pub struct TokenKindSet {
 }
 
 impl TokenKindSet {
-    const WORDS: usize = ((TokenKind::Error as u32 + u32::BITS - 1) / (u32::BITS)) as usize;
+    const WORDS: usize = (TokenKind::Error as u32).div_ceil(u32::BITS) as usize;
 
     const fn word(kind: TokenKind) -> usize {
         (kind as u32 / u32::BITS) as usize
@@ -0,0 +1,884 @@
+//! Static type inference.
+//!
+//! This is an optional pass over the AST, which infers the types of expressions and reports type
+//! errors as diagnostics before the program is ever run. It does not affect compilation in any
+//! way---the VM still checks all types at runtime.
+//!
+//! The type system is Hindley-Milner with a couple of extensions, to fit the dynamically typed
+//! nature of haku:
+//!
+//! - `_` is a type that's compatible with any other type.
+//! - `a | b` is a union type. Unions are never unified; instead, only their compatibility with
+//!   other types is checked, where two types are compatible if they could possibly describe the
+//!   same value.
+//!   Unions arise from system functions (such as `toShape`), and `if` expressions whose branches
+//!   have incompatible types.
+//! - List elements of incompatible types widen the list's element type to `_`.
+//!
+//! All of this means the checker only reports errors for code that is definitely wrong, and will
+//! let some type errors slip through to runtime.
+
+use core::fmt::{self, Display};
+
+use alloc::{format, string::String, vec::Vec};
+
+use crate::{
+    ast::{scope::Scopes, NodeId, NodeKind},
+    compiler::Source,
+    diagnostic::Diagnostic,
+    source::Span,
+    system::SystemFnArity,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TypeId(u32);
+
+const ANY: TypeId = TypeId(0);
+const NIL: TypeId = TypeId(1);
+const BOOLEAN: TypeId = TypeId(2);
+const NUMBER: TypeId = TypeId(3);
+const VEC: TypeId = TypeId(4);
+const RGBA: TypeId = TypeId(5);
+const SHAPE: TypeId = TypeId(6);
+const SCRIBBLE: TypeId = TypeId(7);
+
+#[derive(Debug, Clone, PartialEq)]
+enum Type {
+    /// Type that is not known yet.
+    /// The level is the number of enclosing `let`s and defs, used for deciding which variables
+    /// can be generalized.
+    Var {
+        level: u32,
+    },
+    /// Type variable that was unified with another type.
+    Link(TypeId),
+    /// Type variable of a polymorphic type, which is replaced with a new variable each time
+    /// the type is used.
+    Generic,
+
+    Any,
+    Nil,
+    Boolean,
+    Number,
+    Vec,
+    Rgba,
+    Shape,
+    Scribble,
+    List(TypeId),
+    Function(Vec<TypeId>, TypeId),
+    Union(Vec<TypeId>),
+}
+
+/// Types inferred by [`check`].
+#[derive(Debug, Clone)]
+pub struct Types {
+    types: Vec<Type>,
+    node_types: Vec<Option<TypeId>>,
+}
+
+impl Types {
+    fn new(node_count: usize) -> Self {
+        Self {
+            types: Vec::from_iter([
+                Type::Any,
+                Type::Nil,
+                Type::Boolean,
+                Type::Number,
+                Type::Vec,
+                Type::Rgba,
+                Type::Shape,
+                Type::Scribble,
+            ]),
+            node_types: Vec::from_iter(core::iter::repeat_n(None, node_count)),
+        }
+    }
+
+    /// Returns the type of an identifier, for displaying in editors.
+    /// Types are recorded for identifier uses, as well as the names bound by defs, `let`s, and
+    /// function parameters.
+    pub fn type_of(&self, node_id: NodeId) -> Option<TypeId> {
+        self.node_types.get(node_id.0 as usize).copied().flatten()
+    }
+
+    pub fn display(&self, id: TypeId) -> DisplayType<'_> {
+        DisplayType { types: self, id }
+    }
+
+    fn alloc(&mut self, ty: Type) -> TypeId {
+        let id = TypeId(self.types.len() as u32);
+        self.types.push(ty);
+        id
+    }
+
+    fn resolve(&self, mut id: TypeId) -> TypeId {
+        while let Type::Link(next) = self.types[id.0 as usize] {
+            id = next;
+        }
+        id
+    }
+
+    fn get(&self, id: TypeId) -> &Type {
+        &self.types[self.resolve(id).0 as usize]
+    }
+}
+
+pub struct DisplayType<'a> {
+    types: &'a Types,
+    id: TypeId,
+}
+
+impl DisplayType<'_> {
+    fn write(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        id: TypeId,
+        vars: &mut Vec<TypeId>,
+        parenthesize: bool,
+    ) -> fmt::Result {
+        let id = self.types.resolve(id);
+        let ty = self.types.get(id);
+
+        let needs_parens = parenthesize && matches!(ty, Type::Function(..) | Type::Union(_));
+        if needs_parens {
+            f.write_str("(")?;
+        }
+
+        match ty {
+            Type::Var { .. } | Type::Generic => {
+                let index = vars.iter().position(|&v| v == id).unwrap_or_else(|| {
+                    vars.push(id);
+                    vars.len() - 1
+                });
+                if index < 26 {
+                    write!(f, "{}", (b'a' + index as u8) as char)?;
+                } else {
+                    write!(f, "t{index}")?;
+                }
+            }
+            Type::Link(_) => unreachable!("links are resolved"),
+            Type::Any => f.write_str("_")?,
+            Type::Nil => f.write_str("()")?,
+            Type::Boolean => f.write_str("boolean")?,
+            Type::Number => f.write_str("number")?,
+            Type::Vec => f.write_str("vec")?,
+            Type::Rgba => f.write_str("rgba")?,
+            Type::Shape => f.write_str("shape")?,
+            Type::Scribble => f.write_str("scribble")?,
+            Type::List(element) => {
+                f.write_str("list ")?;
+                let nested = matches!(self.types.get(*element), Type::List(_));
+                if nested {
+                    f.write_str("(")?;
+                }
+                self.write(f, *element, vars, true)?;
+                if nested {
+                    f.write_str(")")?;
+                }
+            }
+            Type::Function(params, result) => {
+                f.write_str("\\")?;
+                for (i, &param) in params.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(", ")?;
+                    }
+                    self.write(f, param, vars, true)?;
+                }
+                f.write_str(" -> ")?;
+                self.write(f, *result, vars, false)?;
+            }
+            Type::Union(members) => {
+                for (i, &member) in members.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(" | ")?;
+                    }
+                    self.write(f, member, vars, true)?;
+                }
+            }
+        }
+
+        if needs_parens {
+            f.write_str(")")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for DisplayType<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, self.id, &mut Vec::new(), false)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Mismatch;
+
+#[derive(Debug, Clone, Copy)]
+struct Def<'a> {
+    name: &'a str,
+    ty: TypeId,
+    inferred: bool,
+}
+
+struct Checker<'a, 'b> {
+    src: &'b Source<'a>,
+    types: Types,
+    diagnostics: Vec<Diagnostic>,
+    level: u32,
+    locals: Scopes<'a, TypeId>,
+    defs: Vec<Def<'a>>,
+}
+
+/// Infer the types of the program rooted at the given node.
+pub fn check(src: &Source, root: NodeId) -> (Types, Vec<Diagnostic>) {
+    let mut c = Checker {
+        src,
+        types: Types::new(src.ast.len()),
+        diagnostics: Vec::with_capacity(16),
+        level: 0,
+        locals: Scopes::new(),
+        defs: Vec::new(),
+    };
+    c.infer(root);
+    (c.types, c.diagnostics)
+}
+
+impl<'a> Checker<'a, '_> {
+    fn emit(&mut self, diagnostic: Diagnostic) {
+        if self.diagnostics.len() < self.diagnostics.capacity() {
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    fn emit_mismatch(&mut self, span: Span, expected: TypeId, found: TypeId) {
+        let message = format!(
+            "type mismatch: expected `{}`, found `{}`",
+            self.types.display(expected),
+            self.types.display(found)
+        );
+        self.emit(Diagnostic::error(span, message));
+    }
+
+    fn name(&self, node_id: NodeId) -> &'a str {
+        self.src.ast.span(node_id).slice(self.src.code)
+    }
+
+    fn record(&mut self, node_id: NodeId, ty: TypeId) {
+        self.types.node_types[node_id.0 as usize] = Some(ty);
+    }
+
+    fn var(&mut self) -> TypeId {
+        self.types.alloc(Type::Var { level: self.level })
+    }
+
+    fn union(&mut self, a: TypeId, b: TypeId) -> TypeId {
+        let mut members: Vec<TypeId> = Vec::new();
+        for id in [a, b] {
+            let id = self.types.resolve(id);
+            match self.types.get(id) {
+                Type::Union(ms) => members.extend(ms.iter().map(|&m| self.types.resolve(m))),
+                _ => members.push(id),
+            }
+        }
+        let mut unique: Vec<TypeId> = Vec::with_capacity(members.len());
+        for member in members {
+            if !unique.contains(&member) {
+                unique.push(member);
+            }
+        }
+        if unique.len() == 1 {
+            unique[0]
+        } else {
+            self.types.alloc(Type::Union(unique))
+        }
+    }
+
+    /// Replace all generic variables in the type with new variables.
+    fn instantiate(&mut self, id: TypeId) -> TypeId {
+        self.instantiate_with(id, &mut Vec::new())
+    }
+
+    fn instantiate_with(&mut self, id: TypeId, generics: &mut Vec<(TypeId, TypeId)>) -> TypeId {
+        let id = self.types.resolve(id);
+        match self.types.get(id).clone() {
+            Type::Generic => {
+                if let Some(&(_, var)) = generics.iter().find(|(generic, _)| *generic == id) {
+                    var
+                } else {
+                    let var = self.var();
+                    generics.push((id, var));
+                    var
+                }
+            }
+            Type::List(element) => {
+                let new_element = self.instantiate_with(element, generics);
+                if new_element == element {
+                    id
+                } else {
+                    self.types.alloc(Type::List(new_element))
+                }
+            }
+            Type::Function(params, result) => {
+                let new_params: Vec<_> = params
+                    .iter()
+                    .map(|&param| self.instantiate_with(param, generics))
+                    .collect();
+                let new_result = self.instantiate_with(result, generics);
+                if new_params == params && new_result == result {
+                    id
+                } else {
+                    self.types.alloc(Type::Function(new_params, new_result))
+                }
+            }
+            Type::Union(members) => {
+                let new_members: Vec<_> = members
+                    .iter()
+                    .map(|&member| self.instantiate_with(member, generics))
+                    .collect();
+                if new_members == members {
+                    id
+                } else {
+                    self.types.alloc(Type::Union(new_members))
+                }
+            }
+            _ => id,
+        }
+    }
+
+    /// Turn all variables introduced at a deeper level than the current one into generic
+    /// variables.
+    fn generalize(&mut self, id: TypeId) {
+        let id = self.types.resolve(id);
+        match self.types.get(id).clone() {
+            Type::Var { level } if level > self.level => {
+                self.types.types[id.0 as usize] = Type::Generic;
+            }
+            Type::List(element) => self.generalize(element),
+            Type::Function(params, result) => {
+                for param in params {
+                    self.generalize(param);
+                }
+                self.generalize(result);
+            }
+            Type::Union(members) => {
+                for member in members {
+                    self.generalize(member);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Returns whether the variable occurs in the type, and lowers the levels of all variables in
+    /// the type to at most `level`.
+    fn occurs(&mut self, var: TypeId, level: u32, id: TypeId) -> bool {
+        let id = self.types.resolve(id);
+        if id == var {
+            return true;
+        }
+        match self.types.get(id).clone() {
+            Type::Var { level: other } => {
+                self.types.types[id.0 as usize] = Type::Var {
+                    level: other.min(level),
+                };
+                false
+            }
+            Type::List(element) => self.occurs(var, level, element),
+            Type::Function(params, result) => {
+                params.iter().any(|&param| self.occurs(var, level, param))
+                    || self.occurs(var, level, result)
+            }
+            Type::Union(members) => members
+                .iter()
+                .any(|&member| self.occurs(var, level, member)),
+            _ => false,
+        }
+    }
+
+    fn bind(&mut self, var: TypeId, id: TypeId) -> Result<(), Mismatch> {
+        let Type::Var { level } = *self.types.get(var) else {
+            unreachable!("only variables can be bound");
+        };
+        if self.occurs(var, level, id) {
+            return Err(Mismatch);
+        }
+        self.types.types[var.0 as usize] = Type::Link(id);
+        Ok(())
+    }
+
+    /// Returns whether the two types could possibly describe the same value.
+    fn compatible(&self, a: TypeId, b: TypeId) -> bool {
+        let (a, b) = (self.types.resolve(a), self.types.resolve(b));
+        if a == b {
+            return true;
+        }
+        match (self.types.get(a), self.types.get(b)) {
+            (Type::Var { .. } | Type::Generic | Type::Any, _)
+            | (_, Type::Var { .. } | Type::Generic | Type::Any) => true,
+            (Type::Union(members), _) => members.iter().any(|&m| self.compatible(m, b)),
+            (_, Type::Union(members)) => members.iter().any(|&m| self.compatible(a, m)),
+            (Type::List(x), Type::List(y)) => self.compatible(*x, *y),
+            (Type::Function(ps, r), Type::Function(qs, s)) => {
+                ps.len() == qs.len()
+                    && ps.iter().zip(qs).all(|(&p, &q)| self.compatible(p, q))
+                    && self.compatible(*r, *s)
+            }
+            (x, y) => x == y,
+        }
+    }
+
+    fn unify(&mut self, a: TypeId, b: TypeId) -> Result<(), Mismatch> {
+        let (a, b) = (self.types.resolve(a), self.types.resolve(b));
+        if a == b {
+            return Ok(());
+        }
+        match (self.types.get(a).clone(), self.types.get(b).clone()) {
+            // `_` accepts anything without constraining it, so variables stay free.
+            (Type::Any, _) | (_, Type::Any) => Ok(()),
+            (Type::Var { .. }, _) => self.bind(a, b),
+            (_, Type::Var { .. }) => self.bind(b, a),
+            (Type::Union(_), _) | (_, Type::Union(_)) => {
+                if self.compatible(a, b) {
+                    Ok(())
+                } else {
+                    Err(Mismatch)
+                }
+            }
+            (Type::List(x), Type::List(y)) => self.unify(x, y),
+            (Type::Function(ps, r), Type::Function(qs, s)) => {
+                if ps.len() != qs.len() {
+                    return Err(Mismatch);
+                }
+                for (p, q) in ps.into_iter().zip(qs) {
+                    self.unify(p, q)?;
+                }
+                self.unify(r, s)
+            }
+            (x, y) if x == y => Ok(()),
+            _ => Err(Mismatch),
+        }
+    }
+
+    fn infer(&mut self, node_id: NodeId) -> TypeId {
+        match self.src.ast.kind(node_id) {
+            NodeKind::Nil
+            | NodeKind::Token
+            | NodeKind::Op
+            | NodeKind::Params
+            | NodeKind::Param
+            | NodeKind::Import
+            | NodeKind::ParamDecl
+            | NodeKind::Error => ANY,
+
+            NodeKind::Ident => self.infer_ident(node_id),
+            NodeKind::Tag => match self.name(node_id) {
+                "False" | "True" => BOOLEAN,
+                _ => ANY,
+            },
+            NodeKind::Number => NUMBER,
+            NodeKind::Color => RGBA,
+            NodeKind::List => self.infer_list(node_id),
+
+            NodeKind::Unary => self.infer_unary(node_id),
+            NodeKind::Binary => self.infer_binary(node_id),
+            NodeKind::Call => self.infer_call(node_id),
+            NodeKind::Paren => match self.src.ast.walk(node_id).node() {
+                Some(inner) => self.infer(inner),
+                None => ANY,
+            },
+            NodeKind::ParenEmpty => NIL,
+            NodeKind::Lambda => self.infer_lambda(node_id),
+            NodeKind::If => self.infer_if(node_id),
+            NodeKind::Let => self.infer_let(node_id),
+
+            NodeKind::Toplevel => self.infer_toplevel(node_id),
+        }
+    }
+
+    fn infer_ident(&mut self, node_id: NodeId) -> TypeId {
+        let name = self.name(node_id);
+        let ty = if let Some(&ty) = self.locals.get(name) {
+            self.instantiate(ty)
+        } else if let Some(def) = self.defs.iter().find(|def| def.name == name).copied() {
+            // Defs that are still being inferred are monomorphic.
+            if def.inferred {
+                self.instantiate(def.ty)
+            } else {
+                def.ty
+            }
+        } else {
+            // The compiler reports undefined variables.
+            ANY
+        };
+        self.record(node_id, ty);
+        ty
+    }
+
+    fn infer_list(&mut self, node_id: NodeId) -> TypeId {
+        let mut element = self.var();
+        let mut walk = self.src.ast.walk(node_id);
+        while let Some(expr) = walk.node() {
+            let ty = self.infer(expr);
+            if self.compatible(element, ty) {
+                // Compatible types can still fail to unify if one of them is a union.
+                if self.unify(element, ty).is_err() {
+                    element = ANY;
+                }
+            } else {
+                element = ANY;
+            }
+        }
+        self.types.alloc(Type::List(element))
+    }
+
+    fn infer_unary(&mut self, node_id: NodeId) -> TypeId {
+        let mut walk = self.src.ast.walk(node_id);
+        let (Some(op), Some(expr)) = (walk.node(), walk.node()) else {
+            return ANY;
+        };
+        let ty = self.infer(expr);
+
+        let name = self.name(op);
+        match (self.src.system.resolve_fn)(SystemFnArity::Unary, name) {
+            Some(index) => {
+                let span = self.src.ast.span(op);
+                self.check_system_call(name, index, span, &[(self.src.ast.span(expr), ty)])
+            }
+            None => ANY,
+        }
+    }
+
+    fn infer_binary(&mut self, node_id: NodeId) -> TypeId {
+        let mut walk = self.src.ast.walk(node_id);
+        let (Some(left), Some(op), Some(right)) = (walk.node(), walk.node(), walk.node()) else {
+            return ANY;
+        };
+        let name = self.name(op);
+        if name == "=" {
+            // Defs are only valid at the top level, and are handled by infer_toplevel.
+            return ANY;
+        }
+
+        let left_ty = self.infer(left);
+        let right_ty = self.infer(right);
+        match (self.src.system.resolve_fn)(SystemFnArity::Binary, name) {
+            Some(index) => {
+                let args = [
+                    (self.src.ast.span(left), left_ty),
+                    (self.src.ast.span(right), right_ty),
+                ];
+                self.check_system_call(name, index, self.src.ast.span(op), &args)
+            }
+            None => ANY,
+        }
+    }
+
+    fn infer_call(&mut self, node_id: NodeId) -> TypeId {
+        let mut walk = self.src.ast.walk(node_id);
+        let Some(func) = walk.node() else {
+            return ANY;
+        };
+
+        let mut args = Vec::new();
+        while let Some(arg) = walk.node() {
+            let ty = self.infer(arg);
+            args.push((self.src.ast.span(arg), ty));
+        }
+
+        // NOTE: This must match how the compiler decides whether a call is a system call.
+        let name = self.name(func);
+        if let (NodeKind::Ident, Some(index)) = (
+            self.src.ast.kind(func),
+            (self.src.system.resolve_fn)(SystemFnArity::Nary, name),
+        ) {
+            return self.check_system_call(name, index, self.src.ast.span(func), &args);
+        }
+
+        let func_ty = self.infer(func);
+        let func_ty = self.types.resolve(func_ty);
+        if let Type::Function(params, result) = self.types.get(func_ty).clone() {
+            if params.len() == args.len() {
+                // Report mismatches on individual arguments, which makes for nicer errors than
+                // comparing the whole function type.
+                for (param, (span, arg)) in params.into_iter().zip(args) {
+                    if self.unify(param, arg).is_err() {
+                        self.emit_mismatch(span, param, arg);
+                    }
+                }
+                return result;
+            }
+        }
+
+        let result = self.var();
+        let expected = self.types.alloc(Type::Function(
+            args.iter().map(|&(_, ty)| ty).collect(),
+            result,
+        ));
+        if self.unify(expected, func_ty).is_err() {
+            self.emit_mismatch(self.src.ast.span(func), expected, func_ty);
+            return ANY;
+        }
+        result
+    }
+
+    fn check_system_call(
+        &mut self,
+        name: &str,
+        index: u8,
+        span: Span,
+        args: &[(Span, TypeId)],
+    ) -> TypeId {
+        let signatures = (self.src.system.signatures_fn)(index);
+        if signatures.is_empty() {
+            return ANY;
+        }
+
+        let Some((params, result)) = signatures
+            .iter()
+            .map(|signature| self.parse_signature(signature))
+            .find(|(params, _)| params.len() == args.len())
+        else {
+            let counts: Vec<_> = signatures
+                .iter()
+                .map(|signature| self.parse_signature(signature).0.len())
+                .collect();
+            let message = format!(
+                "`{name}` expects {} argument{}, but got {}",
+                list_counts(&counts),
+                if counts == [1] { "" } else { "s" },
+                args.len()
+            );
+            self.emit(Diagnostic::error(span, message));
+            return ANY;
+        };
+
+        for (param, &(span, arg)) in params.into_iter().zip(args) {
+            if self.unify(param, arg).is_err() {
+                self.emit_mismatch(span, param, arg);
+            }
+        }
+
+        result
+    }
+
+    /// Parse a system function signature, in the format used by the documentation.
+    fn parse_signature(&mut self, signature: &str) -> (Vec<TypeId>, TypeId) {
+        let (params, result) = signature
+            .split_once(" -> ")
+            .expect("signature must specify a result type");
+        let params = params
+            .split(", ")
+            .map(|param| {
+                let (_name, ty) = param
+                    .split_once(" : ")
+                    .expect("parameters must be written as `name : type`");
+                self.parse_type(ty)
+            })
+            .collect();
+        (params, self.parse_type(result))
+    }
+
+    fn parse_type(&mut self, ty: &str) -> TypeId {
+        let mut members = ty.split(" | ");
+        let first = members
+            .next()
+            .expect("split always yields at least one element");
+        let first = self.parse_type_atom(first);
+        members.fold(first, |ty, member| {
+            let member = self.parse_type_atom(member);
+            self.union(ty, member)
+        })
+    }
+
+    fn parse_type_atom(&mut self, atom: &str) -> TypeId {
+        match atom {
+            "_" => ANY,
+            "()" => NIL,
+            "boolean" => BOOLEAN,
+            "number" => NUMBER,
+            "vec" => VEC,
+            "rgba" => RGBA,
+            "shape" => SHAPE,
+            "shapeLike" => self.union(VEC, SHAPE),
+            "scribble" => SCRIBBLE,
+            _ => {
+                if let Some(element) = atom.strip_prefix("list ") {
+                    let element = self.parse_type_atom(element);
+                    self.types.alloc(Type::List(element))
+                } else {
+                    panic!("unknown type in system function signature: {atom}")
+                }
+            }
+        }
+    }
+
+    fn infer_lambda(&mut self, node_id: NodeId) -> TypeId {
+        let mut walk = self.src.ast.walk(node_id);
+        let (Some(params), Some(body)) = (walk.node(), walk.node()) else {
+            return ANY;
+        };
+
+        let locals_len = self.locals.len();
+        let mut param_types = Vec::new();
+        let mut params_walk = self.src.ast.walk(params);
+        while let Some(param) = params_walk.node() {
+            let ty = self.var();
+            self.record(param, ty);
+            self.locals.push(self.name(param), ty);
+            param_types.push(ty);
+        }
+
+        let result = self.infer(body);
+        self.locals.truncate(locals_len);
+
+        self.types.alloc(Type::Function(param_types, result))
+    }
+
+    fn infer_if(&mut self, node_id: NodeId) -> TypeId {
+        let mut walk = self.src.ast.walk(node_id);
+        let (Some(condition), Some(if_true), Some(if_false)) =
+            (walk.node(), walk.node(), walk.node())
+        else {
+            return ANY;
+        };
+
+        // Any value can be used as a condition.
+        self.infer(condition);
+        let true_ty = self.infer(if_true);
+        let false_ty = self.infer(if_false);
+
+        if self.compatible(true_ty, false_ty) && self.unify(true_ty, false_ty).is_ok() {
+            true_ty
+        } else {
+            self.union(true_ty, false_ty)
+        }
+    }
+
+    fn infer_let(&mut self, node_id: NodeId) -> TypeId {
+        let mut walk = self.src.ast.walk(node_id);
+        let (Some(ident), Some(expr), Some(then)) = (walk.node(), walk.node(), walk.node()) else {
+            return ANY;
+        };
+
+        self.level += 1;
+        let ty = self.infer(expr);
+        self.level -= 1;
+        self.generalize(ty);
+        self.record(ident, ty);
+
+        self.locals.push(self.name(ident), ty);
+        let result = self.infer(then);
+        self.locals.pop();
+
+        result
+    }
+
+    fn infer_toplevel(&mut self, node_id: NodeId) -> TypeId {
+        // Like in the compiler, all defs are collected beforehand so that they can refer to each
+        // other regardless of order.
+        let mut walk = self.src.ast.walk(node_id);
+        while let Some(binary) = walk.node_of(NodeKind::Binary) {
+            if let Some(ident) = self.src.ast.def_ident(self.src.code, binary) {
+                let name = self.name(ident);
+                if !self.defs.iter().any(|def| def.name == name) {
+                    let ty = self.var();
+                    self.defs.push(Def {
+                        name,
+                        ty,
+                        inferred: false,
+                    });
+                }
+            }
+        }
+
+        // Params take on the type of their default value, which the compiler checks to be a
+        // literal.
+        let mut walk = self.src.ast.walk(node_id);
+        while let Some(param_decl) = walk.node_of(NodeKind::ParamDecl) {
+            let mut param_walk = self.src.ast.walk(param_decl);
+            let (Some(ident), Some(default)) = (param_walk.node(), param_walk.node()) else {
+                continue;
+            };
+            let name = self.name(ident);
+            if self.src.ast.kind(ident) != NodeKind::Ident
+                || self.defs.iter().any(|def| def.name == name)
+            {
+                continue;
+            }
+            let ty = self.infer(default);
+            self.defs.push(Def {
+                name,
+                ty,
+                inferred: true,
+            });
+            self.record(ident, ty);
+        }
+
+        let mut result = NIL;
+        let mut walk = self.src.ast.walk(node_id);
+        while let Some(toplevel_expr) = walk.node() {
+            if let Some(ident) = self.src.ast.def_ident(self.src.code, toplevel_expr) {
+                self.infer_def(toplevel_expr, ident);
+            } else if !matches!(
+                self.src.ast.kind(toplevel_expr),
+                NodeKind::Import | NodeKind::ParamDecl
+            ) {
+                result = self.infer(toplevel_expr);
+            }
+        }
+        result
+    }
+
+    fn infer_def(&mut self, node_id: NodeId, ident: NodeId) {
+        let mut walk = self.src.ast.walk(node_id);
+        let (Some(_ident), Some(_op), Some(expr)) = (walk.node(), walk.node(), walk.node()) else {
+            return;
+        };
+
+        self.level += 1;
+        let ty = self.infer(expr);
+        self.level -= 1;
+
+        let name = self.name(ident);
+        let Some(index) = self.defs.iter().position(|def| def.name == name) else {
+            return;
+        };
+        let def = self.defs[index];
+        if def.inferred {
+            // The def is a duplicate, which is reported by the compiler.
+            return;
+        }
+
+        if self.types.resolve(def.ty) == def.ty
+            && matches!(self.types.get(def.ty), Type::Var { .. })
+        {
+            // The def wasn't used before being inferred, so it can be polymorphic.
+            self.generalize(ty);
+            self.types.types[def.ty.0 as usize] = Type::Link(ty);
+        } else {
+            // Otherwise, its uses already constrained its type. This lowers the levels of all
+            // variables involved, so they won't be generalized.
+            if self.unify(def.ty, ty).is_err() {
+                self.emit_mismatch(self.src.ast.span(expr), def.ty, ty);
+            }
+            self.generalize(ty);
+        }
+
+        self.defs[index].inferred = true;
+        self.record(ident, def.ty);
+    }
+}
+
+fn list_counts(counts: &[usize]) -> String {
+    let mut s = String::new();
+    for (i, count) in counts.iter().enumerate() {
+        if i != 0 {
+            s.push_str(if i == counts.len() - 1 { " or " } else { ", " });
+        }
+        s.push_str(&format!("{count}"));
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,273 @@
+use alloc::{format, string::String, vec::Vec};
+
+use crate::{
+    ast::{scope::Scopes, Ast, NodeId, NodeKind},
+    compiler::Source,
+    source::SourceCode,
+    system::System,
+    testing::{parse, Parsed},
+};
+
+use super::check;
+
+struct Checked {
+    /// `name : type` for every identifier that has a type, in source order.
+    idents: Vec<String>,
+    /// `source: message` for every diagnostic.
+    diagnostics: Vec<String>,
+}
+
+fn check_code(s: &str) -> Checked {
+    let parsed = parse(s);
+    parsed.assert_no_diagnostics();
+    let Parsed { code, ref ast, .. } = parsed;
+
+    let system = System::new(1);
+    let (types, diagnostics) = check(&parsed.source(&system), parsed.root);
+
+    let idents = (0..ast.len() as u32)
+        .map(NodeId)
+        .filter(|&id| matches!(ast.kind(id), NodeKind::Ident | NodeKind::Param))
+        .filter_map(|id| {
+            let ty = types.type_of(id)?;
+            Some(format!(
+                "{} : {}",
+                ast.span(id).slice(code),
+                types.display(ty)
+            ))
+        })
+        .collect();
+    let diagnostics = diagnostics
+        .iter()
+        .map(|d| format!("{}: {}", d.span().slice(code), d.message()))
+        .collect();
+
+    Checked {
+        idents,
+        diagnostics,
+    }
+}
+
+#[track_caller]
+fn assert_ok(s: &str) {
+    let checked = check_code(s);
+    assert!(
+        checked.diagnostics.is_empty(),
+        "unexpected diagnostics: {:#?}",
+        checked.diagnostics
+    );
+}
+
+#[track_caller]
+fn assert_errors(s: &str, expected: &[&str]) {
+    assert_eq!(check_code(s).diagnostics, expected);
+}
+
+#[track_caller]
+fn assert_type(s: &str, ident: &str, ty: &str) {
+    let checked = check_code(s);
+    let prefix = format!("{ident} : ");
+    let found = checked
+        .idents
+        .iter()
+        .find(|i| i.starts_with(&prefix))
+        .unwrap_or_else(|| panic!("no type for {ident}: {:#?}", checked.idents));
+    assert_eq!(&found[prefix.len()..], ty);
+}
+
+#[test]
+fn signatures_parse() {
+    // Type checking a call parses the signature, and panics if it's malformed.
+    let system = System::new(1);
+    let code = SourceCode::unlimited_len("");
+    let ast = Ast::new(1);
+    let src = Source {
+        code,
+        ast: &ast,
+        system: &system,
+    };
+    let mut c = super::Checker {
+        src: &src,
+        types: super::Types::new(0),
+        diagnostics: Vec::new(),
+        level: 0,
+        locals: Scopes::new(),
+        defs: Vec::new(),
+    };
+    for index in 0..=255 {
+        for signature in (system.signatures_fn)(index) {
+            c.parse_signature(signature);
+        }
+    }
+}
+
+#[test]
+fn literals() {
+    assert_type("let x = 1\nx", "x", "number");
+    assert_type("let x = #FFF\nx", "x", "rgba");
+    assert_type("let x = True\nx", "x", "boolean");
+    assert_type("let x = ()\nx", "x", "()");
+    assert_type("let x = [1, 2]\nx", "x", "list number");
+    assert_type("let x = [[1], [2]]\nx", "x", "list (list number)");
+}
+
+#[test]
+fn system_calls() {
+    assert_ok("stroke 8 #000 (vec 0 0)");
+    assert_type("x = vecX (vec 1 2)", "x", "number");
+    assert_type("x = rect 0 0 10 10", "x", "shape");
+    assert_type("x = toShape 1", "x", "() | shape");
+}
+
+#[test]
+fn system_call_mismatch() {
+    assert_errors(
+        "stroke 1 1 1",
+        &[
+            "1: type mismatch: expected `rgba`, found `number`",
+            "1: type mismatch: expected `vec | shape`, found `number`",
+        ],
+    );
+    assert_errors(
+        "-(vec 1)",
+        &["(vec 1): type mismatch: expected `number`, found `vec`"],
+    );
+    assert_errors(
+        "1 + #000",
+        &["#000: type mismatch: expected `number`, found `rgba`"],
+    );
+}
+
+#[test]
+fn system_call_argument_count() {
+    assert_errors(
+        "vec 1 2 3 4 5",
+        &["vec: `vec` expects 1, 2, 3 or 4 arguments, but got 5"],
+    );
+    assert_errors(
+        "rect 1 2 3",
+        &["rect: `rect` expects 2 or 4 arguments, but got 3"],
+    );
+    assert_errors("sqrt 1 2", &["sqrt: `sqrt` expects 1 argument, but got 2"]);
+}
+
+#[test]
+fn lambdas() {
+    assert_type("f = \\x -> x + 1", "f", "\\number -> number");
+    assert_type("f = \\x, y -> x", "f", "\\a, b -> a");
+    assert_type("f = \\g -> g 1", "f", "\\(\\number -> a) -> a");
+    assert_errors(
+        "f = \\v -> vecX v\nf 1",
+        &["1: type mismatch: expected `vec`, found `number`"],
+    );
+    assert_errors(
+        "(\\x -> x) 1 2",
+        &["(\\x -> x): type mismatch: expected `\\number, number -> a`, found `\\a -> a`"],
+    );
+    assert_errors(
+        "1 2",
+        &["1: type mismatch: expected `\\number -> a`, found `number`"],
+    );
+}
+
+#[test]
+fn infinite_type() {
+    assert_errors(
+        "f = \\x -> x x",
+        &["x: type mismatch: expected `\\a -> b`, found `a`"],
+    );
+}
+
+#[test]
+fn polymorphism() {
+    let code = r#"
+        id = \x -> x
+        stroke (id 1) (id #000) (id (vec 0 0))
+    "#;
+    assert_ok(code);
+    assert_type(code, "id", "\\a -> a");
+
+    let code = r#"
+        let id = \x -> x
+        stroke (id 1) (id #000) (id (vec 0 0))
+    "#;
+    assert_ok(code);
+
+    // Function parameters are not polymorphic.
+    assert_errors(
+        "f = \\id -> stroke (id 1) (id #000) (id (vec 0 0))",
+        &[
+            "#000: type mismatch: expected `number`, found `rgba`",
+            "(vec 0 0): type mismatch: expected `number`, found `vec`",
+            "(id #000): type mismatch: expected `rgba`, found `number`",
+            "(id (vec 0 0)): type mismatch: expected `vec | shape`, found `number`",
+        ],
+    );
+}
+
+#[test]
+fn recursion() {
+    let code = r#"
+        fib = \n ->
+            if (n < 2)
+                n
+            else
+                fib (n - 1) + fib (n - 2)
+
+        fib 10
+    "#;
+    assert_ok(code);
+    assert_type(code, "fib", "\\number -> number");
+
+    let code = r#"
+        f = \x ->
+            if (x < 10)
+                g (x + 1)
+            else
+                x
+
+        g = \x ->
+            if (x < 10)
+                f (x * 2)
+            else
+                x
+
+        f 0
+    "#;
+    assert_ok(code);
+    assert_type(code, "g", "\\number -> number");
+
+    assert_errors(
+        "f = \\x -> if (x < 1) 0 else f (x - 1)\nf (vec 0)",
+        &["(vec 0): type mismatch: expected `number`, found `vec`"],
+    );
+}
+
+#[test]
+fn unions() {
+    // Branches of incompatible types make a union.
+    assert_type("x = if (True) 1 else #000", "x", "number | rgba");
+    // Unions are accepted where any of their members is.
+    assert_ok("(if (True) 1 else #000) + 1");
+    assert_ok("stroke 1 #000 (toShape (vec 0 0))");
+    assert_errors(
+        "(if (True) 1 else #000) + vec 1",
+        &["vec 1: type mismatch: expected `number`, found `vec`"],
+    );
+    assert_errors(
+        "fill #000 (if (True) 1 else #000)",
+        &["(if (True) 1 else #000): type mismatch: expected `vec | shape`, found `number | rgba`"],
+    );
+}
+
+#[test]
+fn heterogeneous_lists() {
+    assert_type("x = [stroke 1 #000 (vec 0 0), 1]", "x", "list _");
+    assert_type("x = [1, \\y -> y]", "x", "list _");
+}
+
+#[test]
+fn undefined_variables() {
+    // Undefined variables are reported by the compiler, not the type checker.
+    assert_ok("stroke x y z");
+}
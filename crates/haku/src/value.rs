@@ -2,19 +2,28 @@ use alloc::vec::Vec;
 
 use crate::{compiler::ClosureSpec, system::ChunkId};
 
-// TODO: Probably needs some pretty hardcore space optimization.
-// Maybe when we have static typing.
+// NOTE: Values are kept at 8 bytes, because they make up the entirety of the value stack, as well
+// as list backing arrays. Anything that doesn't fit in 4 bytes is stored out of line in the VM,
+// and referred to by ID.
+//
+// Vectors and colors get their own arena separate from refs (see VecId), because they're created
+// very often, and are tiny compared to refs.
+//
+// Note that this means comparing two vectors or colors with == only compares their IDs.
+// Use Vm::compare_values to compare their contents.
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
 pub enum Value {
     Nil,
     False,
     True,
     Number(f32),
-    Vec4(Vec4),
-    Rgba(Rgba),
+    Vec4(VecId),
+    Rgba(VecId),
     Ref(RefId),
 }
 
+const _: () = assert!(core::mem::size_of::<Value>() == 8);
+
 impl Value {
     pub fn is_falsy(&self) -> bool {
         matches!(self, Self::Nil | Self::False)
@@ -30,20 +39,6 @@ impl Value {
             _ => None,
         }
     }
-
-    pub fn to_vec4(&self) -> Option<Vec4> {
-        match self {
-            Self::Vec4(v) => Some(*v),
-            _ => None,
-        }
-    }
-
-    pub fn to_rgba(&self) -> Option<Rgba> {
-        match self {
-            Self::Rgba(v) => Some(*v),
-            _ => None,
-        }
-    }
 }
 
 impl From<()> for Value {
@@ -99,6 +94,33 @@ pub struct Rgba {
     pub a: f32,
 }
 
+impl From<Vec4> for Rgba {
+    fn from(value: Vec4) -> Self {
+        Self {
+            r: value.x,
+            g: value.y,
+            b: value.z,
+            a: value.w,
+        }
+    }
+}
+
+impl From<Rgba> for Vec4 {
+    fn from(value: Rgba) -> Self {
+        Self {
+            x: value.r,
+            y: value.g,
+            z: value.b,
+            w: value.a,
+        }
+    }
+}
+
+/// ID of a vector or color stored in the VM.
+/// Like [`RefId`], this only ever refers to vectors inside the current VM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VecId(pub(crate) u32);
+
 // NOTE: This is not a pointer, because IDs are safer and easier to clone.
 //
 // Since this only ever refers to refs inside the current VM, there is no need to walk through all
@@ -134,7 +156,7 @@ impl Ref {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct BytecodeLoc {
     pub chunk_id: ChunkId,
     pub offset: u16,
@@ -201,8 +223,37 @@ pub struct Fill {
     pub shape: Shape,
 }
 
+/// A filter changes what's already drawn in the area of its shape, rather than adding to it.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    pub effect: Effect,
+    pub shape: Shape,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Effect {
+    /// Gaussian blur with the given standard deviation, in pixels.
+    Blur(f32),
+    /// Rotation of hues by the given angle, in radians.
+    HueShift(f32),
+    BrightnessContrast {
+        brightness: f32,
+        contrast: f32,
+    },
+    Invert,
+    /// Averaging of square blocks of pixels with the given size.
+    Pixelate(f32),
+    /// Displacement of pixels by up to `distance` pixels in a direction given by value noise,
+    /// whose features are roughly `scale` pixels large.
+    Displace {
+        distance: f32,
+        scale: f32,
+    },
+}
+
 #[derive(Debug, Clone)]
 pub enum Scribble {
     Stroke(Stroke),
     Fill(Fill),
+    Filter(Filter),
 }
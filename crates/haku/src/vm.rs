@@ -1,17 +1,25 @@
 use core::{
+    cmp::Ordering,
     error::Error,
     fmt::{self, Display},
     iter,
 };
 
-use alloc::{string::String, vec::Vec};
+use alloc::{string::String, vec, vec::Vec};
 
 use crate::{
-    bytecode::{self, Defs, Opcode, CAPTURE_CAPTURE, CAPTURE_LOCAL},
-    system::{ChunkId, System},
-    value::{BytecodeLoc, Closure, FunctionName, List, Ref, RefId, Rgba, Value, Vec4},
+    bytecode::{self, DefId, Defs, Opcode, CAPTURE_CAPTURE, CAPTURE_LOCAL},
+    stroke::EvalMode,
+    system::{ChunkId, System, SystemFn},
+    value::{BytecodeLoc, Closure, FunctionName, List, Ref, RefId, Rgba, Value, Vec4, VecId},
 };
 
+use self::{debug::Step, profile::Profiler};
+
+pub mod debug;
+pub mod profile;
+mod snapshot;
+
 macro_rules! vmtrace {
     ($($args:expr),* $(,)?) => {
         #[cfg(feature = "vm-trace")]
@@ -27,24 +35,54 @@ pub struct VmLimits {
     pub ref_capacity: usize,
     pub fuel: usize,
     pub memory: usize,
+    /// Whether system functions raise an exception instead of returning NaN or an infinity.
+    pub strict_math: bool,
 }
 
 #[derive(Debug, Clone)]
 pub struct Vm {
     stack: Vec<Value>,
     call_stack: Vec<CallFrame>,
-    refs: Vec<Ref>,
+    // Freed slots are None. See collect_garbage.
+    refs: Vec<Option<Ref>>,
+    free_refs: Vec<RefId>,
+    vecs: Vec<Vec4>,
+    free_vecs: Vec<VecId>,
     defs: Vec<Value>,
     fuel: usize,
     memory: usize,
+    /// Running totals of heap memory and refs allocated over the VM's lifetime, which the
+    /// profiler attributes to functions. They are kept even while not profiling, because checking
+    /// for the profiler on every allocation is slower than counting.
+    allocated_memory: usize,
+    allocated_refs: usize,
+
+    /// The closure whose code is currently running. This is not part of the call stack, which
+    /// only stores closures we will return to.
+    running_closure: Option<RefId>,
+    /// Refs and vectors below these indices were created before the current `run`, and are never
+    /// garbage collected.
+    ref_floor: usize,
+    vec_floor: usize,
+
+    /// Registers of the code being debugged, while it's paused. See [`debug`].
+    paused: Option<CallFrame>,
+    /// Present while profiling. See [`profile`].
+    profiler: Option<Profiler>,
+    /// Which part of toplevel code is evaluated. See [`crate::stroke`].
+    eval_mode: EvalMode,
+    strict_math: bool,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct VmImage {
     stack: usize,
     call_stack: usize,
     refs: usize,
-    defs: usize,
+    vecs: usize,
+    /// Values of defs are restored along with everything else, because defs evaluated after the
+    /// image was taken may point to refs and vectors that don't exist once it's restored.
+    defs: Vec<Value>,
     fuel: usize,
     memory: usize,
 }
@@ -61,15 +99,36 @@ struct Context {
     fuel: usize,
 }
 
+/// Why [`Vm::execute`] stopped executing code.
+enum Halt {
+    Return(Value),
+    /// Execution was paused by the debugger. Resume it by passing the returned registers back to
+    /// `execute`.
+    Pause(CallFrame),
+}
+
 impl Vm {
     pub fn new(defs: &Defs, limits: &VmLimits) -> Self {
         Self {
             stack: Vec::with_capacity(limits.stack_capacity),
             call_stack: Vec::with_capacity(limits.call_stack_capacity),
             refs: Vec::with_capacity(limits.ref_capacity),
-            defs: Vec::from_iter(iter::repeat(Value::Nil).take(defs.len() as usize)),
+            free_refs: Vec::new(),
+            // Vectors are limited by heap memory rather than capacity, so they aren't preallocated.
+            vecs: Vec::new(),
+            free_vecs: Vec::new(),
+            defs: Vec::from_iter(iter::repeat_n(Value::Nil, defs.len() as usize)),
             fuel: limits.fuel,
             memory: limits.memory,
+            allocated_memory: 0,
+            allocated_refs: 0,
+            running_closure: None,
+            ref_floor: 0,
+            vec_floor: 0,
+            paused: None,
+            profiler: None,
+            eval_mode: EvalMode::Full,
+            strict_math: limits.strict_math,
         }
     }
 
@@ -81,6 +140,26 @@ impl Vm {
         self.fuel = fuel;
     }
 
+    pub fn eval_mode(&self) -> EvalMode {
+        self.eval_mode
+    }
+
+    /// Set which part of toplevel code is evaluated by code run from now on.
+    /// Note that this does not affect which part a closure starts at; see
+    /// [`stroke::toplevel`][crate::stroke::toplevel] for that.
+    pub fn set_eval_mode(&mut self, mode: EvalMode) {
+        self.eval_mode = mode;
+    }
+
+    /// Returns whether NaN and infinities are exceptions. See [`VmLimits::strict_math`].
+    pub fn strict_math(&self) -> bool {
+        self.strict_math
+    }
+
+    pub fn remaining_memory(&self) -> usize {
+        self.memory
+    }
+
     pub fn image(&self) -> VmImage {
         assert!(
             self.stack.is_empty() && self.call_stack.is_empty(),
@@ -90,7 +169,8 @@ impl Vm {
             stack: self.stack.len(),
             call_stack: self.call_stack.len(),
             refs: self.refs.len(),
-            defs: self.defs.len(),
+            vecs: self.vecs.len(),
+            defs: self.defs.clone(),
             fuel: self.fuel,
             memory: self.memory,
         }
@@ -115,11 +195,23 @@ impl Vm {
         self.refs.resize_with(image.refs, || {
             panic!("image must be a subset of the current VM")
         });
-        self.defs.resize_with(image.defs, || {
+        self.vecs.resize_with(image.vecs, || {
+            panic!("image must be a subset of the current VM")
+        });
+        self.defs.resize_with(image.defs.len(), || {
             panic!("image must be a subset of the current VM")
         });
+        self.defs.copy_from_slice(&image.defs);
         self.fuel = image.fuel;
         self.memory = image.memory;
+
+        // Anything that was freed since the image was taken lies beyond the image, so it's gone
+        // after truncation anyways.
+        self.free_refs.clear();
+        self.free_vecs.clear();
+        self.ref_floor = self.ref_floor.min(image.refs);
+        self.vec_floor = self.vec_floor.min(image.vecs);
+        self.paused = None;
     }
 
     pub fn apply_defs(&mut self, defs: &Defs) {
@@ -130,6 +222,11 @@ impl Vm {
         self.defs.resize(defs.len() as usize, Value::Nil);
     }
 
+    /// Sets the value of a def from outside of bytecode. Used for supplying brush parameters.
+    pub fn set_def(&mut self, def_id: DefId, value: Value) {
+        self.defs[def_id.to_u16() as usize] = value;
+    }
+
     fn push(&mut self, value: Value) -> Result<(), Exception> {
         if self.stack.len() >= self.stack.capacity() {
             return Err(self.create_exception(
@@ -141,27 +238,24 @@ impl Vm {
         Ok(())
     }
 
-    fn get(&mut self, index: usize) -> Result<Value, Exception> {
-        self.stack.get(index).copied().ok_or_else(|| {
-            self.create_exception("corrupted bytecode (local variable out of bounds)")
-        })
+    // NOTE: The following functions do not check for corrupted bytecode, because all chunks are
+    // verified when they're added to the System. See bytecode::verify.
+
+    fn get(&self, index: usize) -> Value {
+        self.stack[index]
     }
 
-    fn get_mut(&mut self, index: usize) -> Result<&mut Value, Exception> {
-        if self.stack.get(index).is_some() {
-            Ok(&mut self.stack[index])
-        } else {
-            Err(self.create_exception("corrupted bytecode (set local variable out of bounds)"))
-        }
+    fn get_mut(&mut self, index: usize) -> &mut Value {
+        &mut self.stack[index]
     }
 
-    fn pop(&mut self) -> Result<Value, Exception> {
+    fn pop(&mut self) -> Value {
         let value = self
             .stack
             .pop()
-            .ok_or_else(|| self.create_exception("corrupted bytecode (value stack underflow)"))?;
+            .expect("verified bytecode must not underflow the value stack");
         vmtrace!("pop  {:?} -> {:?}", self.stack, value);
-        Ok(value)
+        value
     }
 
     fn push_call(&mut self, frame: CallFrame) -> Result<(), Exception> {
@@ -172,25 +266,39 @@ impl Vm {
         Ok(())
     }
 
-    fn pop_call(&mut self) -> Result<CallFrame, Exception> {
+    fn pop_call(&mut self) -> CallFrame {
         self.call_stack
             .pop()
-            .ok_or_else(|| self.create_exception("corrupted bytecode (call stack underflow)"))
+            .expect("verified bytecode must not underflow the call stack")
     }
 
-    pub fn run(&mut self, system: &System, mut closure_id: RefId) -> Result<Value, Exception> {
+    pub fn run(&mut self, system: &System, closure_id: RefId) -> Result<Value, Exception> {
+        let frame = self.enter(closure_id)?;
+        let halt = if self.profiler.is_some() {
+            self.execute::<false, true>(system, frame, &[], Step::Continue)
+        } else {
+            self.execute::<false, false>(system, frame, &[], Step::Continue)
+        };
+        if let Some(profiler) = &mut self.profiler {
+            profiler.halt();
+        }
+        match halt? {
+            Halt::Return(value) => Ok(value),
+            Halt::Pause(_) => unreachable!("code must not be paused outside of the debugger"),
+        }
+    }
+
+    /// Prepare to run a closure, and return the registers to start executing it with.
+    fn enter(&mut self, closure_id: RefId) -> Result<CallFrame, Exception> {
         let closure = self
             .get_ref(closure_id)
             .as_closure()
             .expect("a Closure-type Ref must be passed to `run`");
 
-        let mut chunk_id = closure.start.chunk_id;
-        let mut chunk = system.chunk(chunk_id);
-        let mut pc = closure.start.offset as usize;
-        let mut bottom = self.stack.len();
-        let mut fuel = self.fuel;
+        let chunk_id = closure.start.chunk_id;
+        let pc = closure.start.offset as usize;
+        let bottom = self.stack.len();
 
-        let init_bottom = bottom;
         for _ in 0..closure.local_count {
             self.push(Value::Nil)?;
         }
@@ -198,17 +306,93 @@ impl Vm {
         #[allow(unused)]
         let closure = (); // Do not use `closure` after this! Use `get_ref` on `closure_id` instead.
 
-        self.push_call(CallFrame {
+        if self.call_stack.is_empty() {
+            // Only refs and vectors created during this run can be garbage collected.
+            // Anything freed by previous runs is left alone until the next restore_image, so that
+            // restoring an image taken in between runs truncates away all changes made since.
+            self.ref_floor = self.refs.len();
+            self.vec_floor = self.vecs.len();
+            self.free_refs.clear();
+            self.free_vecs.clear();
+        }
+
+        let frame = CallFrame {
             closure_id,
             chunk_id,
             pc,
             bottom,
-        })?;
+        };
+        self.push_call(frame.clone())?;
+        self.running_closure = Some(closure_id);
+
+        Ok(frame)
+    }
+
+    /// Execute code starting with the given registers, until the initial closure returns.
+    ///
+    /// In `DEBUG` mode, execution pauses before every instruction if `step` is
+    /// [`Step::Instruction`], or else before the instructions at `breakpoints`, except for the
+    /// very first instruction, so that execution can be resumed from a breakpoint.
+    ///
+    /// In `PROFILE` mode, resource usage is recorded into the profiler, which must be present.
+    fn execute<const DEBUG: bool, const PROFILE: bool>(
+        &mut self,
+        system: &System,
+        frame: CallFrame,
+        breakpoints: &[BytecodeLoc],
+        step: Step,
+    ) -> Result<Halt, Exception> {
+        let CallFrame {
+            mut closure_id,
+            mut chunk_id,
+            mut pc,
+            mut bottom,
+        } = frame;
+        let mut chunk = system.chunk(chunk_id);
+        let mut fuel = self.fuel;
+        let mut first = true;
+
+        if PROFILE {
+            let start = self.get_ref(closure_id).as_closure().unwrap().start;
+            let allocated = (self.allocated_memory, self.allocated_refs);
+            let profiler = self.profiler.as_mut().unwrap();
+            // Anything allocated before the code started running is not attributed to it.
+            profiler.skip_allocations(allocated);
+            profiler.call(start);
+        }
 
         loop {
+            if DEBUG {
+                let loc = BytecodeLoc {
+                    chunk_id,
+                    offset: pc as u16,
+                };
+                if !first && (step == Step::Instruction || breakpoints.contains(&loc)) {
+                    self.store_context(Context { fuel });
+                    return Ok(Halt::Pause(CallFrame {
+                        closure_id,
+                        chunk_id,
+                        pc,
+                        bottom,
+                    }));
+                }
+                first = false;
+            }
+
             fuel = fuel
                 .checked_sub(1)
                 .ok_or_else(|| self.create_exception("code ran for too long"))?;
+            if PROFILE {
+                let (stack_depth, call_depth) = (self.stack.len(), self.call_stack.len());
+                let allocated = (self.allocated_memory, self.allocated_refs);
+                let remaining_memory = self.memory;
+                self.profiler.as_mut().unwrap().instruction(
+                    stack_depth,
+                    call_depth,
+                    allocated,
+                    remaining_memory,
+                );
+            }
 
             #[allow(unused)]
             let pc2 = pc;
@@ -229,63 +413,66 @@ impl Vm {
                     let g = chunk.read_u8(&mut pc)?;
                     let b = chunk.read_u8(&mut pc)?;
                     let a = chunk.read_u8(&mut pc)?;
-                    self.push(Value::Rgba(Rgba {
+                    let value = self.create_rgba(Rgba {
                         r: r as f32 / 255.0,
                         g: g as f32 / 255.0,
                         b: b as f32 / 255.0,
                         a: a as f32 / 255.0,
-                    }))?;
+                    })?;
+                    self.push(value)?;
+                }
+
+                Opcode::Vec4 => {
+                    let mask = chunk.read_u8(&mut pc)?;
+                    let mut components = [0.0; 4];
+                    for (i, component) in components.iter_mut().enumerate() {
+                        if mask & (1 << i) != 0 {
+                            *component = chunk.read_f32(&mut pc)?;
+                        }
+                    }
+                    let [x, y, z, w] = components;
+                    let value = self.create_vec4(Vec4 { x, y, z, w })?;
+                    self.push(value)?;
                 }
 
                 Opcode::Local => {
                     let index = chunk.read_u8(&mut pc)? as usize;
-                    let value = self.get(bottom + index)?;
+                    let value = self.get(bottom + index);
                     self.push(value)?;
                 }
 
                 Opcode::SetLocal => {
                     let index = chunk.read_u8(&mut pc)? as usize;
-                    let new_value = self.pop()?;
-                    *self.get_mut(bottom + index)? = new_value;
+                    let new_value = self.pop();
+                    *self.get_mut(bottom + index) = new_value;
                 }
 
                 Opcode::Capture => {
                     let index = chunk.read_u8(&mut pc)? as usize;
                     let closure = self.get_ref(closure_id).as_closure().unwrap();
-                    self.push(closure.captures.get(index).copied().ok_or_else(|| {
-                        self.create_exception("corrupted bytecode (capture index out of bounds)")
-                    })?)?;
+                    self.push(closure.captures[index])?;
                 }
 
                 Opcode::Def => {
                     let index = chunk.read_u16(&mut pc)? as usize;
-                    self.push(self.defs.get(index).copied().ok_or_else(|| {
-                        self.create_exception("corrupted bytecode (def index out of bounds)")
-                    })?)?
+                    self.push(self.defs[index])?
                 }
 
                 Opcode::SetDef => {
                     let index = chunk.read_u16(&mut pc)? as usize;
-                    let value = self.pop()?;
-                    if let Some(def) = self.defs.get_mut(index) {
-                        *def = value;
-                    } else {
-                        return Err(self
-                            .create_exception("corrupted bytecode (set def index out of bounds)"));
-                    }
+                    let value = self.pop();
+                    self.defs[index] = value;
                 }
 
                 Opcode::List => {
                     let len = chunk.read_u16(&mut pc)? as usize;
-                    let bottom = self.stack.len().checked_sub(len).ok_or_else(|| {
-                        self.create_exception(
-                            "corrupted bytecode (list has more elements than stack)",
-                        )
-                    })?;
+                    let bottom = self.stack.len() - len;
                     let elements = self.stack[bottom..].to_vec();
-                    self.stack.resize_with(bottom, || unreachable!());
+                    // NOTE: Elements must stay on the stack while allocating, so that they
+                    // aren't garbage collected.
                     self.track_array(&elements)?;
                     let id = self.create_ref(Ref::List(List { elements }))?;
+                    self.stack.resize_with(bottom, || unreachable!());
                     self.push(Value::Ref(id))?;
                 }
 
@@ -303,16 +490,12 @@ impl Vm {
                         let capture_kind = chunk.read_u8(&mut pc)?;
                         let index = chunk.read_u8(&mut pc)? as usize;
                         captures.push(match capture_kind {
-                            CAPTURE_LOCAL => self.get(bottom + index)?,
+                            CAPTURE_LOCAL => self.get(bottom + index),
                             CAPTURE_CAPTURE => {
                                 let closure = self.get_ref(closure_id).as_closure().unwrap();
-                                closure.captures.get(index).copied().ok_or_else(|| {
-                                    self.create_exception(
-                                        "corrupted bytecode (captured capture index out of bounds)",
-                                    )
-                                })?
+                                closure.captures[index]
                             }
-                            _ => Value::Nil,
+                            _ => unreachable!("capture kinds are checked by the verifier"),
                         })
                     }
 
@@ -338,7 +521,7 @@ impl Vm {
 
                 Opcode::JumpIfNot => {
                     let offset = chunk.read_u16(&mut pc)? as usize;
-                    let value = self.pop()?;
+                    let value = self.pop();
                     if !value.is_truthy() {
                         pc = offset;
                     }
@@ -347,7 +530,7 @@ impl Vm {
                 Opcode::Call => {
                     let argument_count = chunk.read_u8(&mut pc)? as usize;
 
-                    let function_value = self.pop()?;
+                    let function_value = self.pop();
                     let Some((called_closure_id, Ref::Closure(closure))) =
                         self.get_ref_value(function_value)
                     else {
@@ -371,15 +554,7 @@ impl Vm {
                     chunk_id = closure.start.chunk_id;
                     chunk = system.chunk(chunk_id);
                     pc = closure.start.offset as usize;
-                    bottom = self
-                        .stack
-                        .len()
-                        .checked_sub(argument_count)
-                        .ok_or_else(|| {
-                            self.create_exception(
-                                "corrupted bytecode (not enough values on the stack for arguments)",
-                            )
-                        })?;
+                    bottom = self.stack.len() - argument_count;
 
                     // NOTE: Locals are only pushed _after_ we do any stack calculations.
                     for _ in 0..closure.local_count {
@@ -387,24 +562,23 @@ impl Vm {
                     }
 
                     self.push_call(frame)?;
+                    self.running_closure = Some(closure_id);
+                    if PROFILE {
+                        let start = self.get_ref(closure_id).as_closure().unwrap().start;
+                        self.profiler.as_mut().unwrap().call(start);
+                    }
                 }
 
                 Opcode::System => {
                     let index = chunk.read_u8(&mut pc)? as usize;
                     let argument_count = chunk.read_u8(&mut pc)? as usize;
-                    let system_fn = system.fns.get(index).copied().flatten().ok_or_else(|| {
-                        self.create_exception("corrupted bytecode (invalid system function index)")
-                    })?;
+                    let system_fn = system.fns[index].expect("verified system function must exist");
 
                     self.store_context(Context { fuel });
                     let result = system_fn(
                         self,
                         FnArgs {
-                            base: self
-                                .stack
-                                .len()
-                                .checked_sub(argument_count)
-                                .ok_or_else(|| self.create_exception("corrupted bytecode (not enough values on the stack for arguments)"))?,
+                            base: self.stack.len() - argument_count,
                             len: argument_count,
                         },
                     )?;
@@ -415,9 +589,19 @@ impl Vm {
                     self.push(result)?;
                 }
 
-                Opcode::Return => {
-                    let value = self.pop()?;
-                    let frame = self.pop_call()?;
+                Opcode::PointStart | Opcode::Return => {
+                    if opcode == Opcode::PointStart {
+                        if self.eval_mode != EvalMode::Stroke {
+                            continue;
+                        }
+                        // The stroke-constant part of toplevel code is over. The verifier ensures
+                        // the value stack is empty here, so we can return nil as if the code
+                        // said `Nil` and `Return`.
+                        self.push(Value::Nil)?;
+                    }
+
+                    let value = self.pop();
+                    let frame = self.pop_call();
 
                     debug_assert!(bottom <= self.stack.len());
                     self.stack.resize_with(bottom, || unreachable!());
@@ -426,7 +610,9 @@ impl Vm {
                     // Once the initial frame is popped, halt the VM.
                     if self.call_stack.is_empty() {
                         self.store_context(Context { fuel });
-                        break;
+                        let result = self.pop();
+                        self.stack.resize_with(frame.bottom, || unreachable!());
+                        return Ok(Halt::Return(result));
                     }
 
                     CallFrame {
@@ -435,18 +621,34 @@ impl Vm {
                         pc,
                         bottom,
                     } = frame;
+                    self.running_closure = Some(closure_id);
                     chunk = system.chunk(chunk_id);
+                    if PROFILE {
+                        let start = self.get_ref(closure_id).as_closure().unwrap().start;
+                        self.profiler.as_mut().unwrap().resume(start);
+                    }
                 }
             }
         }
+    }
 
-        let result = self
-            .stack
-            .pop()
-            .expect("there should be a result at the top of the stack");
-        self.stack.resize_with(init_bottom, || unreachable!());
+    /// Call a system function with the given arguments, outside of any running code.
+    pub fn call_system_fn(&mut self, f: SystemFn, args: &[Value]) -> Result<Value, Exception> {
+        if self.stack.len() + args.len() > self.stack.capacity() {
+            return Err(self.create_exception("too many arguments"));
+        }
 
-        Ok(result)
+        let base = self.stack.len();
+        self.stack.extend_from_slice(args);
+        let result = f(
+            self,
+            FnArgs {
+                base,
+                len: args.len(),
+            },
+        );
+        self.stack.truncate(base);
+        result
     }
 
     fn store_context(&mut self, context: Context) {
@@ -457,18 +659,35 @@ impl Vm {
         Context { fuel: self.fuel }
     }
 
+    /// Allocate a new ref.
+    ///
+    /// NOTE: This may garbage collect refs and vectors which are not reachable from the stack,
+    /// defs, or running closures. Values held elsewhere (such as in local variables of system
+    /// functions) must not be used after allocating.
     pub fn create_ref(&mut self, r: Ref) -> Result<RefId, Exception> {
+        self.allocated_refs = self.allocated_refs.wrapping_add(1);
+        if self.free_refs.is_empty() && self.refs.len() >= self.refs.capacity() {
+            self.collect_garbage();
+        }
+
+        if let Some(id) = self.free_refs.pop() {
+            self.refs[id.0 as usize] = Some(r);
+            return Ok(id);
+        }
+
         if self.refs.len() >= self.refs.capacity() {
             return Err(self.create_exception("too many value allocations"));
         }
 
         let id = RefId(self.refs.len() as u32);
-        self.refs.push(r);
+        self.refs.push(Some(r));
         Ok(id)
     }
 
     pub fn get_ref(&self, id: RefId) -> &Ref {
-        &self.refs[id.0 as usize]
+        self.refs[id.0 as usize]
+            .as_ref()
+            .expect("reachable refs must never be freed")
     }
 
     pub fn get_ref_value(&self, value: Value) -> Option<(RefId, &Ref)> {
@@ -478,19 +697,146 @@ impl Vm {
         }
     }
 
+    fn create_vec(&mut self, vec: Vec4) -> Result<VecId, Exception> {
+        self.track_array(&[vec])?;
+
+        if let Some(id) = self.free_vecs.pop() {
+            self.vecs[id.0 as usize] = vec;
+            return Ok(id);
+        }
+
+        let id = VecId(self.vecs.len() as u32);
+        self.vecs.push(vec);
+        Ok(id)
+    }
+
+    pub fn create_vec4(&mut self, vec: Vec4) -> Result<Value, Exception> {
+        Ok(Value::Vec4(self.create_vec(vec)?))
+    }
+
+    pub fn create_rgba(&mut self, rgba: Rgba) -> Result<Value, Exception> {
+        Ok(Value::Rgba(self.create_vec(rgba.into())?))
+    }
+
+    pub fn get_vec4(&self, value: Value) -> Option<Vec4> {
+        match value {
+            Value::Vec4(id) => Some(self.vecs[id.0 as usize]),
+            _ => None,
+        }
+    }
+
+    pub fn get_rgba(&self, value: Value) -> Option<Rgba> {
+        match value {
+            Value::Rgba(id) => Some(self.vecs[id.0 as usize].into()),
+            _ => None,
+        }
+    }
+
+    /// Compare two values by their contents.
+    /// Unlike comparing values directly, this looks inside vectors and colors.
+    pub fn compare_values(&self, a: Value, b: Value) -> Option<Ordering> {
+        match (a, b) {
+            (Value::Vec4(a), Value::Vec4(b)) | (Value::Rgba(a), Value::Rgba(b)) => {
+                self.vecs[a.0 as usize].partial_cmp(&self.vecs[b.0 as usize])
+            }
+            _ => a.partial_cmp(&b),
+        }
+    }
+
     pub fn create_exception(&self, message: impl Into<String>) -> Exception {
         Exception {
             message: message.into(),
         }
     }
 
+    /// Reserve heap memory for an array.
+    ///
+    /// NOTE: Like [`Vm::create_ref`], this may garbage collect values.
     pub fn track_array<T>(&mut self, array: &[T]) -> Result<(), Exception> {
+        let size = core::mem::size_of_val(array);
+        if self.memory < size {
+            self.collect_garbage();
+        }
+
         self.memory = self
             .memory
-            .checked_sub(core::mem::size_of_val(array))
+            .checked_sub(size)
             .ok_or_else(|| self.create_exception("out of heap memory"))?;
+        self.allocated_memory = self.allocated_memory.wrapping_add(size);
         Ok(())
     }
+
+    /// Free all refs and vectors created during the current `run` that are no longer reachable.
+    ///
+    /// Nothing is moved around, so that IDs stay valid and VM images can still be restored by
+    /// truncation. Instead, freed slots are reused by subsequent allocations.
+    fn collect_garbage(&mut self) {
+        // Outside of `run`, the caller may be holding onto values we can't see, so it's not safe
+        // to collect anything.
+        let Some(running_closure) = self.running_closure else {
+            return;
+        };
+        if self.call_stack.is_empty() {
+            return;
+        }
+
+        let mut reachable_refs = vec![false; self.refs.len()];
+        let mut reachable_vecs = vec![false; self.vecs.len()];
+        // Already freed vectors are treated as reachable, so that they aren't freed twice.
+        for id in &self.free_vecs {
+            reachable_vecs[id.0 as usize] = true;
+        }
+
+        let mut worklist: Vec<Value> = Vec::new();
+        worklist.extend_from_slice(&self.stack);
+        worklist.extend_from_slice(&self.defs);
+        worklist.extend(
+            self.call_stack
+                .iter()
+                .map(|frame| Value::Ref(frame.closure_id)),
+        );
+        worklist.push(Value::Ref(running_closure));
+
+        // Values reachable by the VM always point at live refs and vectors. If that's ever not the
+        // case, a bug elsewhere left a dangling value behind, and indexing panics rather than
+        // leaking whatever it pointed to.
+        while let Some(value) = worklist.pop() {
+            match value {
+                Value::Nil | Value::False | Value::True | Value::Number(_) => (),
+                Value::Vec4(id) | Value::Rgba(id) => reachable_vecs[id.0 as usize] = true,
+                Value::Ref(id) => {
+                    if reachable_refs[id.0 as usize] {
+                        continue;
+                    }
+                    reachable_refs[id.0 as usize] = true;
+                    match self.get_ref(id) {
+                        Ref::Closure(closure) => worklist.extend_from_slice(&closure.captures),
+                        Ref::List(list) => worklist.extend_from_slice(&list.elements),
+                        Ref::Shape(_) | Ref::Scribble(_) => (),
+                    }
+                }
+            }
+        }
+
+        for (index, slot) in self.refs.iter_mut().enumerate().skip(self.ref_floor) {
+            if reachable_refs[index] {
+                continue;
+            }
+            if let Some(r) = slot.take() {
+                if let Ref::List(list) = &r {
+                    self.memory += core::mem::size_of_val(&list.elements[..]);
+                }
+                self.free_refs.push(RefId(index as u32));
+            }
+        }
+
+        for (index, &reachable) in reachable_vecs.iter().enumerate().skip(self.vec_floor) {
+            if !reachable {
+                self.memory += core::mem::size_of::<Vec4>();
+                self.free_vecs.push(VecId(index as u32));
+            }
+        }
+    }
 }
 
 pub struct FnArgs {
@@ -538,8 +884,7 @@ impl FnArgs {
         index: usize,
         message: &'static str,
     ) -> Result<Vec4, Exception> {
-        self.get(vm, index)
-            .to_vec4()
+        vm.get_vec4(self.get(vm, index))
             .ok_or_else(|| vm.create_exception(message))
     }
 
@@ -550,8 +895,7 @@ impl FnArgs {
         index: usize,
         message: &'static str,
     ) -> Result<Rgba, Exception> {
-        self.get(vm, index)
-            .to_rgba()
+        vm.get_rgba(self.get(vm, index))
             .ok_or_else(|| vm.create_exception(message))
     }
 }
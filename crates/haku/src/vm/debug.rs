@@ -0,0 +1,232 @@
+//! Debugger for stepping through code one instruction at a time.
+//!
+//! Debugging starts with [`Vm::debug_start`], which pauses before the first instruction of a
+//! closure. While the VM is paused, its call stack can be inspected with [`Vm::debug_frames`], and
+//! execution can be resumed with [`Vm::debug_resume`], either for a single instruction or until
+//! the next breakpoint.
+//!
+//! The VM only knows about bytecode offsets. Mapping them back to source code is up to the host,
+//! using the [`DebugInfo`][crate::debug_info::DebugInfo] produced by the compiler.
+
+use core::{fmt::Write, ops::Range};
+
+use alloc::{string::String, vec::Vec};
+
+use crate::{
+    system::System,
+    value::{BytecodeLoc, Effect, Ref, RefId, Scribble, Shape, Value},
+};
+
+use super::{Exception, Halt, Vm};
+
+/// How far [`Vm::debug_resume`] should run the code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// Execute a single instruction.
+    Instruction,
+    /// Execute until a breakpoint is reached, or the code finishes running.
+    Continue,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Debugged {
+    Paused,
+    Finished(Value),
+}
+
+/// A function call on the call stack of a paused VM.
+#[derive(Debug, Clone)]
+pub struct DebugFrame {
+    pub closure_id: RefId,
+    /// The instruction that will be executed next in this frame.
+    /// For all frames except the innermost one, this is the instruction right after the call that
+    /// is still in progress.
+    pub location: BytecodeLoc,
+    /// Indices of the function's parameters and local variables on the value stack.
+    pub locals: Range<usize>,
+    /// Indices of the temporary values (operands of expressions that haven't been evaluated yet)
+    /// on the value stack.
+    pub temporaries: Range<usize>,
+}
+
+/// Lists longer than this are cut short by [`Vm::format_value`].
+const MAX_FORMATTED_ELEMENTS: usize = 8;
+/// Lists nested deeper than this are left out by [`Vm::format_value`].
+const MAX_FORMATTED_DEPTH: usize = 3;
+
+impl Vm {
+    /// Prepare to run a closure, pausing before its first instruction.
+    pub fn debug_start(&mut self, closure_id: RefId) -> Result<(), Exception> {
+        assert!(
+            self.call_stack.is_empty(),
+            "cannot start debugging while running code"
+        );
+        let frame = self.enter(closure_id)?;
+        self.paused = Some(frame);
+        Ok(())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.is_some()
+    }
+
+    /// Resume running paused code.
+    ///
+    /// Execution pauses again before any instruction located at one of the `breakpoints`, except
+    /// for the instruction it was paused at.
+    /// If the code throws an exception, debugging ends.
+    pub fn debug_resume(
+        &mut self,
+        system: &System,
+        breakpoints: &[BytecodeLoc],
+        step: Step,
+    ) -> Result<Debugged, Exception> {
+        let frame = self
+            .paused
+            .take()
+            .expect("code must be paused to resume debugging it");
+        match self.execute::<true, false>(system, frame, breakpoints, step)? {
+            Halt::Return(value) => Ok(Debugged::Finished(value)),
+            Halt::Pause(frame) => {
+                self.paused = Some(frame);
+                Ok(Debugged::Paused)
+            }
+        }
+    }
+
+    /// Stop debugging, abandoning the paused code.
+    pub fn debug_stop(&mut self) {
+        if let Some(entry) = self.call_stack.first() {
+            self.stack.truncate(entry.bottom);
+        }
+        self.call_stack.clear();
+        self.paused = None;
+    }
+
+    /// Returns the frames on the call stack of the paused code, outermost first.
+    /// Returns nothing if the VM is not paused.
+    pub fn debug_frames(&self) -> Vec<DebugFrame> {
+        let Some(paused) = &self.paused else {
+            return Vec::new();
+        };
+
+        // The first frame on the call stack only records where the code started running, while
+        // the registers of the innermost frame are only stored in `paused`.
+        let registers: Vec<_> = self.call_stack[1..].iter().chain(Some(paused)).collect();
+        registers
+            .iter()
+            .enumerate()
+            .map(|(i, frame)| {
+                let closure = self.get_ref(frame.closure_id).as_closure().unwrap();
+                let locals_end =
+                    frame.bottom + closure.param_count as usize + closure.local_count as usize;
+                let temporaries_end = registers
+                    .get(i + 1)
+                    .map(|callee| callee.bottom)
+                    .unwrap_or(self.stack.len());
+                DebugFrame {
+                    closure_id: frame.closure_id,
+                    location: BytecodeLoc {
+                        chunk_id: frame.chunk_id,
+                        offset: frame.pc as u16,
+                    },
+                    locals: frame.bottom..locals_end,
+                    temporaries: locals_end..temporaries_end,
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the value stack, which [`DebugFrame`]s index into.
+    pub fn stack(&self) -> &[Value] {
+        &self.stack
+    }
+
+    /// Returns the values of all defs, indexed by their [`DefId`][crate::bytecode::DefId]s.
+    pub fn def_values(&self) -> &[Value] {
+        &self.defs
+    }
+
+    /// Format a value the way it would be written in haku code, for displaying it in a debugger.
+    /// Values that cannot be written down, such as functions, are formatted as `<function>` and
+    /// the like.
+    pub fn format_value(&self, value: Value) -> String {
+        let mut s = String::new();
+        self.write_value(&mut s, value, 0);
+        s
+    }
+
+    fn write_value(&self, s: &mut String, value: Value, depth: usize) {
+        // Writing to a String never fails.
+        _ = match value {
+            Value::Nil => write!(s, "()"),
+            Value::False => write!(s, "False"),
+            Value::True => write!(s, "True"),
+            Value::Number(x) => write!(s, "{x}"),
+            Value::Vec4(_) => {
+                let v = self.get_vec4(value).unwrap();
+                write!(s, "vec {} {} {} {}", v.x, v.y, v.z, v.w)
+            }
+            Value::Rgba(_) => {
+                let c = self.get_rgba(value).unwrap();
+                write!(s, "rgba {} {} {} {}", c.r, c.g, c.b, c.a)
+            }
+            Value::Ref(id) => match self.get_ref(id) {
+                Ref::Closure(_) => write!(s, "<function>"),
+                Ref::List(_) if depth >= MAX_FORMATTED_DEPTH => write!(s, "[...]"),
+                Ref::List(list) => {
+                    s.push('[');
+                    for (i, &element) in list.elements.iter().enumerate() {
+                        if i > 0 {
+                            s.push_str(", ");
+                        }
+                        if i >= MAX_FORMATTED_ELEMENTS {
+                            s.push_str("...");
+                            break;
+                        }
+                        self.write_value(s, element, depth + 1);
+                    }
+                    write!(s, "]")
+                }
+                Ref::Shape(shape) => write!(s, "<{}>", shape_name(shape)),
+                Ref::Scribble(Scribble::Stroke(stroke)) => {
+                    write!(s, "<stroke {}>", shape_name(&stroke.shape))
+                }
+                Ref::Scribble(Scribble::Fill(fill)) => {
+                    write!(s, "<fill {}>", shape_name(&fill.shape))
+                }
+                Ref::Scribble(Scribble::Filter(filter)) => {
+                    write!(
+                        s,
+                        "<{} {}>",
+                        effect_name(filter.effect),
+                        shape_name(&filter.shape)
+                    )
+                }
+            },
+        };
+    }
+}
+
+fn effect_name(effect: Effect) -> &'static str {
+    match effect {
+        Effect::Blur(_) => "blur",
+        Effect::HueShift(_) => "hueShift",
+        Effect::BrightnessContrast { .. } => "brightnessContrast",
+        Effect::Invert => "invert",
+        Effect::Pixelate(_) => "pixelate",
+        Effect::Displace { .. } => "displace",
+    }
+}
+
+fn shape_name(shape: &Shape) -> &'static str {
+    match shape {
+        Shape::Point(_) => "point",
+        Shape::Line(_, _) => "line",
+        Shape::Rect(_, _) => "rect",
+        Shape::Circle(_, _) => "circle",
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,205 @@
+use alloc::{borrow::ToOwned, string::String, vec, vec::Vec};
+
+use crate::{
+    debug_info::DebugInfo,
+    system::System,
+    testing::{load_with, Options},
+    value::{BytecodeLoc, Closure, RefId, Value},
+    vm::Vm,
+};
+
+use super::{Debugged, Step};
+
+struct Debuggee {
+    code: String,
+    system: System,
+    debug_info: DebugInfo,
+    closure: Closure,
+    vm: Vm,
+    closure_id: RefId,
+}
+
+fn compile(code: &str) -> Debuggee {
+    let loaded = load_with(
+        code,
+        Options {
+            debug_info: true,
+            ..Default::default()
+        },
+    );
+    Debuggee {
+        code: code.to_owned(),
+        system: loaded.system,
+        debug_info: loaded.debug_info.unwrap(),
+        closure: loaded.closure,
+        vm: loaded.vm,
+        closure_id: loaded.closure_id,
+    }
+}
+
+impl Debuggee {
+    fn loc(&self, offset: u16) -> BytecodeLoc {
+        BytecodeLoc {
+            chunk_id: self.closure.start.chunk_id,
+            offset,
+        }
+    }
+
+    /// Returns the source code of the innermost node being executed.
+    fn current_source(&self) -> &str {
+        let frames = self.vm.debug_frames();
+        let offset = frames.last().unwrap().location.offset;
+        let span = self.debug_info.span_at(offset).unwrap();
+        &self.code[span.start as usize..span.end as usize]
+    }
+
+    fn resume(&mut self, breakpoints: &[BytecodeLoc], step: Step) -> Debugged {
+        self.vm
+            .debug_resume(&self.system, breakpoints, step)
+            .unwrap()
+    }
+
+    fn locals(&self) -> Vec<Vec<String>> {
+        self.vm
+            .debug_frames()
+            .iter()
+            .map(|frame| {
+                self.vm.stack()[frame.locals.clone()]
+                    .iter()
+                    .map(|&value| self.vm.format_value(value))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn stepping_matches_running() {
+    let code = "f = \\x -> x * 2\ny = f 3\n[y, f y]";
+
+    let mut running = compile(code);
+    let expected = running.vm.run(&running.system, running.closure_id).unwrap();
+    let expected = running.vm.format_value(expected);
+
+    let mut debuggee = compile(code);
+    debuggee.vm.debug_start(debuggee.closure_id).unwrap();
+    let mut steps = 0;
+    let result = loop {
+        match debuggee.resume(&[], Step::Instruction) {
+            Debugged::Paused => steps += 1,
+            Debugged::Finished(value) => break value,
+        }
+    };
+    assert_eq!(debuggee.vm.format_value(result), expected);
+    assert_eq!(expected, "[6, 12]");
+    // Every instruction costs one unit of fuel, regardless of how many times execution pauses.
+    assert_eq!(debuggee.vm.remaining_fuel(), running.vm.remaining_fuel());
+    assert_eq!(65536 - debuggee.vm.remaining_fuel(), steps + 1);
+    assert!(!debuggee.vm.is_paused());
+    assert!(debuggee.vm.stack().is_empty());
+}
+
+#[test]
+fn breakpoints() {
+    let code = "f = \\x -> x * 2\nf 3 + f 4";
+    let mut debuggee = compile(code);
+    let position = code.find("x * 2").unwrap() as u32;
+    let offset = debuggee.debug_info.breakpoint_at(position).unwrap();
+    let breakpoints = [debuggee.loc(offset)];
+
+    debuggee.vm.debug_start(debuggee.closure_id).unwrap();
+    assert_eq!(debuggee.current_source(), "\\x -> x * 2");
+
+    assert_eq!(
+        debuggee.resume(&breakpoints, Step::Continue),
+        Debugged::Paused
+    );
+    assert_eq!(debuggee.current_source(), "x");
+    assert_eq!(debuggee.locals(), [vec![], vec!["3".to_owned()]]);
+
+    // Resuming from a breakpoint must not stop at the same breakpoint again.
+    assert_eq!(
+        debuggee.resume(&breakpoints, Step::Continue),
+        Debugged::Paused
+    );
+    assert_eq!(debuggee.locals(), [vec![], vec!["4".to_owned()]]);
+    let frames = debuggee.vm.debug_frames();
+    assert_eq!(
+        debuggee.vm.stack()[frames[0].temporaries.clone()],
+        [Value::Number(6.0)]
+    );
+
+    assert_eq!(
+        debuggee.resume(&breakpoints, Step::Continue),
+        Debugged::Finished(Value::Number(14.0))
+    );
+}
+
+#[test]
+fn function_info() {
+    let debuggee = compile("f = \\x -> \\y ->\n  let z = x + y\n  z\nf 1 2");
+    let functions: Vec<_> = debuggee
+        .debug_info
+        .functions
+        .iter()
+        .map(|f| (f.locals.clone(), f.captures.clone()))
+        .collect();
+    assert_eq!(
+        functions,
+        [
+            (vec!["y".to_owned(), "z".to_owned()], vec!["x".to_owned()]),
+            (vec!["x".to_owned()], vec![]),
+            (vec![], vec![]),
+        ]
+    );
+
+    let defs: Vec<_> = debuggee
+        .debug_info
+        .defs
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect();
+    assert_eq!(defs, ["f"]);
+}
+
+#[test]
+fn stop() {
+    let code = "f = \\x -> x * 2\nf 3";
+    let mut debuggee = compile(code);
+    let image = debuggee.vm.image();
+    debuggee.vm.debug_start(debuggee.closure_id).unwrap();
+    debuggee.resume(&[], Step::Instruction);
+    debuggee.vm.debug_stop();
+    assert!(!debuggee.vm.is_paused());
+    assert!(debuggee.vm.debug_frames().is_empty());
+    debuggee.vm.restore_image(&image);
+
+    let result = debuggee
+        .vm
+        .run(&debuggee.system, debuggee.closure_id)
+        .unwrap();
+    assert_eq!(result, Value::Number(6.0));
+}
+
+#[test]
+fn format_value() {
+    let mut debuggee = compile("[1, 0.5, True, (), vec 1 2, #F00, \\x -> x, [[[[1]]]]]");
+    let result = debuggee
+        .vm
+        .run(&debuggee.system, debuggee.closure_id)
+        .unwrap();
+    assert_eq!(
+        debuggee.vm.format_value(result),
+        "[1, 0.5, True, (), vec 1 2 0 0, rgba 1 0 0 1, <function>, [[[...]]]]"
+    );
+
+    let mut debuggee = compile("[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]");
+    let result = debuggee
+        .vm
+        .run(&debuggee.system, debuggee.closure_id)
+        .unwrap();
+    assert_eq!(
+        debuggee.vm.format_value(result),
+        "[1, 2, 3, 4, 5, 6, 7, 8, ...]"
+    );
+}
@@ -0,0 +1,164 @@
+//! Profiler for finding out which parts of the code use up the VM's limits.
+//!
+//! Profiling starts with [`Vm::start_profiling`], after which every [`Vm::run`] records what the
+//! code it runs consumes, until the summary is taken with [`Vm::finish_profiling`].
+//!
+//! Usage is attributed to functions by their starting location in bytecode. Mapping them back to
+//! source code is up to the host, using [`FunctionInfo`][crate::debug_info::FunctionInfo]s
+//! produced by the compiler.
+
+use alloc::vec::Vec;
+
+use crate::value::BytecodeLoc;
+
+use super::Vm;
+
+/// Resources used by all calls to a function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionProfile {
+    /// Location of the function's first instruction.
+    pub start: BytecodeLoc,
+    pub calls: usize,
+    /// Fuel consumed by the function's own instructions, not including the functions it calls.
+    pub fuel: usize,
+    /// Heap memory allocated by the function, in bytes.
+    /// Memory that was later garbage collected is included.
+    pub memory: usize,
+    /// Number of refs created by the function.
+    pub refs: usize,
+}
+
+/// Summary of the resources used while profiling.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    /// Functions in the order they were first called in.
+    pub functions: Vec<FunctionProfile>,
+
+    pub fuel_used: usize,
+    /// Fuel that was available when profiling started.
+    pub fuel_limit: usize,
+    /// The most heap memory in use at any point, in bytes.
+    pub peak_memory: usize,
+    /// Heap memory that was available when profiling started, in bytes.
+    pub memory_limit: usize,
+    /// The most values on the value stack at any point.
+    pub peak_stack_depth: usize,
+    pub stack_capacity: usize,
+    /// The most nested function calls in progress at any point.
+    pub peak_call_depth: usize,
+    pub call_stack_capacity: usize,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct Profiler {
+    profile: Profile,
+    /// Lowest amount of free memory seen so far.
+    lowest_memory: usize,
+    /// The VM's allocation totals, as of the last instruction.
+    allocated: (usize, usize),
+    /// Index of the running function in `profile.functions`, if any code is running.
+    running: Option<usize>,
+}
+
+impl Profiler {
+    fn function_index(&mut self, start: BytecodeLoc) -> usize {
+        match self.profile.functions.iter().position(|f| f.start == start) {
+            Some(index) => index,
+            None => {
+                self.profile.functions.push(FunctionProfile {
+                    start,
+                    calls: 0,
+                    fuel: 0,
+                    memory: 0,
+                    refs: 0,
+                });
+                self.profile.functions.len() - 1
+            }
+        }
+    }
+
+    /// Called when a function is called.
+    pub(super) fn call(&mut self, start: BytecodeLoc) {
+        let index = self.function_index(start);
+        self.profile.functions[index].calls += 1;
+        self.running = Some(index);
+    }
+
+    /// Called when execution returns to a function.
+    pub(super) fn resume(&mut self, start: BytecodeLoc) {
+        self.running = Some(self.function_index(start));
+    }
+
+    /// Called when the code stops running.
+    pub(super) fn halt(&mut self) {
+        self.running = None;
+    }
+
+    /// Called before each instruction, with the VM's allocation totals.
+    /// Anything allocated since the last instruction was allocated by the running function.
+    pub(super) fn instruction(
+        &mut self,
+        stack_depth: usize,
+        call_depth: usize,
+        allocated: (usize, usize),
+        remaining_memory: usize,
+    ) {
+        if let Some(index) = self.running {
+            let function = &mut self.profile.functions[index];
+            function.fuel += 1;
+            function.memory += allocated.0.wrapping_sub(self.allocated.0);
+            function.refs += allocated.1.wrapping_sub(self.allocated.1);
+        }
+        self.allocated = allocated;
+        self.lowest_memory = self.lowest_memory.min(remaining_memory);
+        self.profile.peak_stack_depth = self.profile.peak_stack_depth.max(stack_depth);
+        self.profile.peak_call_depth = self.profile.peak_call_depth.max(call_depth);
+    }
+
+    pub(super) fn skip_allocations(&mut self, allocated: (usize, usize)) {
+        self.allocated = allocated;
+    }
+}
+
+impl Vm {
+    /// Start recording the resources used by code run from now on.
+    /// Any profile recorded so far is discarded.
+    pub fn start_profiling(&mut self) {
+        self.profiler = Some(Profiler {
+            profile: Profile {
+                functions: Vec::new(),
+                fuel_used: 0,
+                fuel_limit: self.fuel,
+                peak_memory: 0,
+                memory_limit: self.memory,
+                peak_stack_depth: self.stack.len(),
+                stack_capacity: self.stack.capacity(),
+                peak_call_depth: 0,
+                call_stack_capacity: self.call_stack.capacity(),
+            },
+            lowest_memory: self.memory,
+            allocated: (self.allocated_memory, self.allocated_refs),
+            running: None,
+        });
+    }
+
+    pub fn is_profiling(&self) -> bool {
+        self.profiler.is_some()
+    }
+
+    /// Stop profiling, and return the summary of the resources used since
+    /// [`Vm::start_profiling`]. Returns `None` if the VM was not profiling.
+    pub fn finish_profiling(&mut self) -> Option<Profile> {
+        let Profiler {
+            mut profile,
+            lowest_memory,
+            ..
+        } = self.profiler.take()?;
+        profile.fuel_used = profile.functions.iter().map(|f| f.fuel).sum();
+        profile.peak_memory = profile.memory_limit - lowest_memory;
+        Some(profile)
+    }
+}
+
+#[cfg(test)]
+mod tests;
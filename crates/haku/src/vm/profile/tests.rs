@@ -0,0 +1,118 @@
+use alloc::{string::String, vec::Vec};
+
+use crate::{
+    bytecode::Defs,
+    testing::{load_with, Loaded, Options},
+    value::Value,
+    vm::{Vm, VmLimits},
+};
+
+use super::Profile;
+
+struct Profiled {
+    profile: Profile,
+    /// Source code of each profiled function, in the same order as `profile.functions`.
+    functions: Vec<String>,
+    remaining_fuel: usize,
+}
+
+fn profile(code: &str) -> Profiled {
+    let Loaded {
+        system,
+        mut vm,
+        closure_id,
+        debug_info,
+        ..
+    } = load_with(
+        code,
+        Options {
+            debug_info: true,
+            ..Default::default()
+        },
+    );
+    let debug_info = debug_info.unwrap();
+
+    vm.start_profiling();
+    vm.run(&system, closure_id).unwrap();
+    let profile = vm.finish_profiling().unwrap();
+
+    let functions = profile
+        .functions
+        .iter()
+        .map(|function| {
+            let span = debug_info.function(function.start.offset).unwrap().span;
+            String::from(&code[span.start as usize..span.end as usize])
+        })
+        .collect();
+
+    Profiled {
+        profile,
+        functions,
+        remaining_fuel: vm.remaining_fuel(),
+    }
+}
+
+#[test]
+fn fuel() {
+    let code = "f = \\x -> x * 2\n[f 1, f 2, f 3]";
+    let Profiled {
+        profile,
+        functions,
+        remaining_fuel,
+    } = profile(code);
+
+    assert_eq!(functions, [code, "\\x -> x * 2"]);
+    assert_eq!(profile.functions[0].calls, 1);
+    assert_eq!(profile.functions[1].calls, 3);
+    // x, 2, *, Return.
+    assert_eq!(profile.functions[1].fuel, 3 * 4);
+    assert_eq!(profile.fuel_used, 65536 - remaining_fuel);
+    assert_eq!(profile.fuel_limit, 65536);
+}
+
+#[test]
+fn memory_and_refs() {
+    let code = "f = \\x -> [x, x]\n[f 1, f 2]";
+    let Profiled { profile, .. } = profile(code);
+
+    let element_size = core::mem::size_of::<Value>();
+    // The closure f, and the outer list.
+    assert_eq!(profile.functions[0].refs, 2);
+    assert_eq!(profile.functions[0].memory, 2 * element_size);
+    assert_eq!(profile.functions[1].refs, 2);
+    assert_eq!(profile.functions[1].memory, 2 * 2 * element_size);
+    assert_eq!(profile.peak_memory, 3 * 2 * element_size);
+    assert_eq!(profile.memory_limit, 65536);
+}
+
+#[test]
+fn peak_depths() {
+    let shallow = profile("countdown = \\n -> if (n == 0) 0 else countdown (n - 1)\ncountdown 5");
+    let deep = profile("countdown = \\n -> if (n == 0) 0 else countdown (n - 1)\ncountdown 10");
+
+    assert_eq!(shallow.profile.functions[1].calls, 6);
+    assert_eq!(
+        deep.profile.peak_call_depth - shallow.profile.peak_call_depth,
+        5
+    );
+    assert!(deep.profile.peak_stack_depth > shallow.profile.peak_stack_depth);
+    assert_eq!(deep.profile.stack_capacity, 256);
+    assert_eq!(deep.profile.call_stack_capacity, 256);
+}
+
+#[test]
+fn not_profiling() {
+    let mut vm = Vm::new(
+        &Defs::new(1),
+        &VmLimits {
+            stack_capacity: 1,
+            call_stack_capacity: 1,
+            ref_capacity: 1,
+            fuel: 1,
+            memory: 1,
+            strict_math: false,
+        },
+    );
+    assert!(!vm.is_profiling());
+    assert_eq!(vm.finish_profiling(), None);
+}
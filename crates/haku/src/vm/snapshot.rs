@@ -0,0 +1,451 @@
+//! Reading and writing VM state. See [`crate::snapshot`].
+
+use alloc::vec::Vec;
+
+use crate::{
+    bytecode::Defs,
+    snapshot::{Reader, SnapshotError, Writer},
+    system::{ChunkId, System},
+    value::{
+        BytecodeLoc, Closure, Effect, Fill, Filter, FunctionName, List, Ref, RefId, Rgba, Scribble,
+        Shape, Stroke, Value, Vec2, Vec4, VecId,
+    },
+};
+
+use super::{Vm, VmLimits};
+
+// Tags identifying the variants of enums.
+// New tags may be added, but existing ones must never change without bumping the snapshot version.
+
+const VALUE_NIL: u8 = 0;
+const VALUE_FALSE: u8 = 1;
+const VALUE_TRUE: u8 = 2;
+const VALUE_NUMBER: u8 = 3;
+const VALUE_VEC4: u8 = 4;
+const VALUE_RGBA: u8 = 5;
+const VALUE_REF: u8 = 6;
+
+const SLOT_FREE: u8 = 0;
+const REF_CLOSURE: u8 = 1;
+const REF_LIST: u8 = 2;
+const REF_SHAPE: u8 = 3;
+const REF_STROKE: u8 = 4;
+const REF_FILL: u8 = 5;
+const REF_FILTER: u8 = 6;
+const VEC_USED: u8 = 1;
+
+const SHAPE_POINT: u8 = 0;
+const SHAPE_LINE: u8 = 1;
+const SHAPE_RECT: u8 = 2;
+const SHAPE_CIRCLE: u8 = 3;
+
+const EFFECT_BLUR: u8 = 0;
+const EFFECT_HUE_SHIFT: u8 = 1;
+const EFFECT_BRIGHTNESS_CONTRAST: u8 = 2;
+const EFFECT_INVERT: u8 = 3;
+const EFFECT_PIXELATE: u8 = 4;
+const EFFECT_DISPLACE: u8 = 5;
+
+/// DefIds are u16s.
+const MAX_DEFS: usize = u16::MAX as usize + 1;
+/// Closures cannot capture more variables than this, because capture counts are stored in bytecode
+/// as a u8.
+const MAX_CAPTURES: usize = u8::MAX as usize;
+
+impl Vm {
+    pub(crate) fn write_snapshot(&self, w: &mut Writer) {
+        assert!(
+            self.call_stack.is_empty(),
+            "cannot take a snapshot of a VM while it's running code"
+        );
+
+        // Vectors that were garbage collected are marked as free, so that their memory isn't
+        // counted when the snapshot is read back.
+        let mut free_vecs = alloc::vec![false; self.vecs.len()];
+        for id in &self.free_vecs {
+            free_vecs[id.0 as usize] = true;
+        }
+        w.len(self.vecs.len());
+        for (vec, &free) in self.vecs.iter().zip(&free_vecs) {
+            if free {
+                w.u8(SLOT_FREE);
+            } else {
+                w.u8(VEC_USED);
+                write_vec4(w, *vec);
+            }
+        }
+
+        w.len(self.refs.len());
+        for slot in &self.refs {
+            match slot {
+                None => w.u8(SLOT_FREE),
+                Some(r) => write_ref(w, r),
+            }
+        }
+
+        write_values(w, &self.stack);
+        write_values(w, &self.defs);
+    }
+
+    pub(crate) fn read_snapshot(
+        r: &mut Reader,
+        system: &System,
+        defs: &Defs,
+        limits: &VmLimits,
+    ) -> Result<Self, SnapshotError> {
+        let mut vm = Vm::new(defs, limits);
+        let mut used_memory = 0;
+
+        let max_vecs = limits.memory / core::mem::size_of::<Vec4>();
+        let vec_count = r.len(max_vecs)?;
+        let mut used_vecs = Vec::with_capacity(vec_count);
+        for _ in 0..vec_count {
+            match r.u8()? {
+                // Freed slots are only reused by the run that freed them, so their contents don't
+                // matter anymore.
+                SLOT_FREE => {
+                    vm.vecs.push(Vec4::default());
+                    used_vecs.push(false);
+                }
+                VEC_USED => {
+                    vm.vecs.push(read_vec4(r)?);
+                    used_vecs.push(true);
+                    used_memory += core::mem::size_of::<Vec4>();
+                }
+                _ => return Err(SnapshotError::Invalid),
+            }
+        }
+
+        let ids = Ids {
+            vecs: vec_count,
+            refs: r.len(limits.ref_capacity)?,
+            max_elements: limits.memory / core::mem::size_of::<Value>(),
+            system,
+        };
+        for _ in 0..ids.refs {
+            let slot = read_ref(r, &ids)?;
+            if let Some(Ref::List(list)) = &slot {
+                used_memory += core::mem::size_of_val(&list.elements[..]);
+            }
+            vm.refs.push(slot);
+        }
+
+        vm.stack
+            .extend(read_values(r, &ids, limits.stack_capacity)?);
+        // Bytecode only gets verified against the defs it was compiled with, so the VM must have
+        // a value for each of them, and no more.
+        let def_values = read_values(r, &ids, MAX_DEFS)?;
+        if def_values.len() != vm.defs.len() {
+            return Err(SnapshotError::Invalid);
+        }
+        vm.defs = def_values;
+
+        // Values may point at slots that come after them, so they can only be checked once
+        // everything has been read. The VM assumes that no value it can reach points at a free
+        // slot.
+        let points_at_used_slot = |&value: &Value| match value {
+            Value::Vec4(id) | Value::Rgba(id) => used_vecs[id.0 as usize],
+            Value::Ref(id) => vm.refs[id.0 as usize].is_some(),
+            _ => true,
+        };
+        let values_in_refs = vm.refs.iter().flat_map(|slot| match slot {
+            Some(Ref::Closure(closure)) => &closure.captures[..],
+            Some(Ref::List(list)) => &list.elements[..],
+            _ => &[],
+        });
+        if !values_in_refs
+            .chain(&vm.stack)
+            .chain(&vm.defs)
+            .all(points_at_used_slot)
+        {
+            return Err(SnapshotError::Invalid);
+        }
+
+        vm.memory = limits
+            .memory
+            .checked_sub(used_memory)
+            .ok_or(SnapshotError::TooLarge)?;
+        Ok(vm)
+    }
+}
+
+/// Number of refs and vectors in a VM snapshot, which values must not point past, and the system
+/// whose chunks closures must point into.
+struct Ids<'a> {
+    vecs: usize,
+    refs: usize,
+    max_elements: usize,
+    system: &'a System,
+}
+
+fn write_value(w: &mut Writer, value: Value) {
+    match value {
+        Value::Nil => w.u8(VALUE_NIL),
+        Value::False => w.u8(VALUE_FALSE),
+        Value::True => w.u8(VALUE_TRUE),
+        Value::Number(x) => {
+            w.u8(VALUE_NUMBER);
+            w.f32(x);
+        }
+        Value::Vec4(id) => {
+            w.u8(VALUE_VEC4);
+            w.u32(id.0);
+        }
+        Value::Rgba(id) => {
+            w.u8(VALUE_RGBA);
+            w.u32(id.0);
+        }
+        Value::Ref(id) => {
+            w.u8(VALUE_REF);
+            w.u32(id.0);
+        }
+    }
+}
+
+fn read_value(r: &mut Reader, ids: &Ids) -> Result<Value, SnapshotError> {
+    let vec_id = |r: &mut Reader| {
+        let id = r.u32()?;
+        match (id as usize) < ids.vecs {
+            true => Ok(VecId(id)),
+            false => Err(SnapshotError::Invalid),
+        }
+    };
+    Ok(match r.u8()? {
+        VALUE_NIL => Value::Nil,
+        VALUE_FALSE => Value::False,
+        VALUE_TRUE => Value::True,
+        VALUE_NUMBER => Value::Number(r.f32()?),
+        VALUE_VEC4 => Value::Vec4(vec_id(r)?),
+        VALUE_RGBA => Value::Rgba(vec_id(r)?),
+        VALUE_REF => {
+            let id = r.u32()?;
+            if id as usize >= ids.refs {
+                return Err(SnapshotError::Invalid);
+            }
+            Value::Ref(RefId(id))
+        }
+        _ => return Err(SnapshotError::Invalid),
+    })
+}
+
+fn write_values(w: &mut Writer, values: &[Value]) {
+    w.len(values.len());
+    for &value in values {
+        write_value(w, value);
+    }
+}
+
+fn read_values(r: &mut Reader, ids: &Ids, max: usize) -> Result<Vec<Value>, SnapshotError> {
+    let len = r.len(max)?;
+    (0..len).map(|_| read_value(r, ids)).collect()
+}
+
+fn write_vec4(w: &mut Writer, vec: Vec4) {
+    w.f32(vec.x);
+    w.f32(vec.y);
+    w.f32(vec.z);
+    w.f32(vec.w);
+}
+
+fn read_vec4(r: &mut Reader) -> Result<Vec4, SnapshotError> {
+    Ok(Vec4 {
+        x: r.f32()?,
+        y: r.f32()?,
+        z: r.f32()?,
+        w: r.f32()?,
+    })
+}
+
+fn write_vec2(w: &mut Writer, vec: Vec2) {
+    w.f32(vec.x);
+    w.f32(vec.y);
+}
+
+fn read_vec2(r: &mut Reader) -> Result<Vec2, SnapshotError> {
+    Ok(Vec2 {
+        x: r.f32()?,
+        y: r.f32()?,
+    })
+}
+
+fn write_rgba(w: &mut Writer, rgba: Rgba) {
+    write_vec4(w, rgba.into());
+}
+
+fn read_rgba(r: &mut Reader) -> Result<Rgba, SnapshotError> {
+    read_vec4(r).map(Rgba::from)
+}
+
+fn write_shape(w: &mut Writer, shape: &Shape) {
+    match shape {
+        Shape::Point(position) => {
+            w.u8(SHAPE_POINT);
+            write_vec2(w, *position);
+        }
+        Shape::Line(start, end) => {
+            w.u8(SHAPE_LINE);
+            write_vec2(w, *start);
+            write_vec2(w, *end);
+        }
+        Shape::Rect(position, size) => {
+            w.u8(SHAPE_RECT);
+            write_vec2(w, *position);
+            write_vec2(w, *size);
+        }
+        Shape::Circle(position, radius) => {
+            w.u8(SHAPE_CIRCLE);
+            write_vec2(w, *position);
+            w.f32(*radius);
+        }
+    }
+}
+
+fn read_shape(r: &mut Reader) -> Result<Shape, SnapshotError> {
+    Ok(match r.u8()? {
+        SHAPE_POINT => Shape::Point(read_vec2(r)?),
+        SHAPE_LINE => Shape::Line(read_vec2(r)?, read_vec2(r)?),
+        SHAPE_RECT => Shape::Rect(read_vec2(r)?, read_vec2(r)?),
+        SHAPE_CIRCLE => Shape::Circle(read_vec2(r)?, r.f32()?),
+        _ => return Err(SnapshotError::Invalid),
+    })
+}
+
+fn write_effect(w: &mut Writer, effect: Effect) {
+    match effect {
+        Effect::Blur(radius) => {
+            w.u8(EFFECT_BLUR);
+            w.f32(radius);
+        }
+        Effect::HueShift(angle) => {
+            w.u8(EFFECT_HUE_SHIFT);
+            w.f32(angle);
+        }
+        Effect::BrightnessContrast {
+            brightness,
+            contrast,
+        } => {
+            w.u8(EFFECT_BRIGHTNESS_CONTRAST);
+            w.f32(brightness);
+            w.f32(contrast);
+        }
+        Effect::Invert => w.u8(EFFECT_INVERT),
+        Effect::Pixelate(size) => {
+            w.u8(EFFECT_PIXELATE);
+            w.f32(size);
+        }
+        Effect::Displace { distance, scale } => {
+            w.u8(EFFECT_DISPLACE);
+            w.f32(distance);
+            w.f32(scale);
+        }
+    }
+}
+
+fn read_effect(r: &mut Reader) -> Result<Effect, SnapshotError> {
+    Ok(match r.u8()? {
+        EFFECT_BLUR => Effect::Blur(r.f32()?),
+        EFFECT_HUE_SHIFT => Effect::HueShift(r.f32()?),
+        EFFECT_BRIGHTNESS_CONTRAST => Effect::BrightnessContrast {
+            brightness: r.f32()?,
+            contrast: r.f32()?,
+        },
+        EFFECT_INVERT => Effect::Invert,
+        EFFECT_PIXELATE => Effect::Pixelate(r.f32()?),
+        EFFECT_DISPLACE => Effect::Displace {
+            distance: r.f32()?,
+            scale: r.f32()?,
+        },
+        _ => return Err(SnapshotError::Invalid),
+    })
+}
+
+fn write_ref(w: &mut Writer, r: &Ref) {
+    match r {
+        Ref::Closure(closure) => {
+            w.u8(REF_CLOSURE);
+            w.u32(closure.start.chunk_id.0);
+            w.u16(closure.start.offset);
+            w.u8(closure.param_count);
+            w.u8(closure.local_count);
+            write_values(w, &closure.captures);
+        }
+        Ref::List(list) => {
+            w.u8(REF_LIST);
+            write_values(w, &list.elements);
+        }
+        Ref::Shape(shape) => {
+            w.u8(REF_SHAPE);
+            write_shape(w, shape);
+        }
+        Ref::Scribble(Scribble::Stroke(stroke)) => {
+            w.u8(REF_STROKE);
+            w.f32(stroke.thickness);
+            write_rgba(w, stroke.color);
+            write_shape(w, &stroke.shape);
+        }
+        Ref::Scribble(Scribble::Fill(fill)) => {
+            w.u8(REF_FILL);
+            write_rgba(w, fill.color);
+            write_shape(w, &fill.shape);
+        }
+        Ref::Scribble(Scribble::Filter(filter)) => {
+            w.u8(REF_FILTER);
+            write_effect(w, filter.effect);
+            write_shape(w, &filter.shape);
+        }
+    }
+}
+
+/// Read a closure, checking that it starts at an entry of its chunk, and matches the entry's
+/// shape. The VM trusts closures to match the verified code they run, so a closure which doesn't
+/// would make it read out of bounds.
+fn read_closure(r: &mut Reader, ids: &Ids) -> Result<Closure, SnapshotError> {
+    let closure = Closure {
+        start: BytecodeLoc {
+            chunk_id: ChunkId(r.u32()?),
+            offset: r.u16()?,
+        },
+        name: FunctionName::Anonymous,
+        param_count: r.u8()?,
+        local_count: r.u8()?,
+        captures: read_values(r, ids, MAX_CAPTURES)?,
+    };
+
+    let entry = ids
+        .system
+        .entry(closure.start.chunk_id, closure.start.offset)
+        .ok_or(SnapshotError::Invalid)?;
+    if closure.param_count != entry.param_count
+        || closure.local_count != entry.local_count
+        || closure.captures.len() != usize::from(entry.capture_count)
+    {
+        return Err(SnapshotError::Invalid);
+    }
+
+    Ok(closure)
+}
+
+fn read_ref(r: &mut Reader, ids: &Ids) -> Result<Option<Ref>, SnapshotError> {
+    Ok(Some(match r.u8()? {
+        SLOT_FREE => return Ok(None),
+        REF_CLOSURE => Ref::Closure(read_closure(r, ids)?),
+        REF_LIST => Ref::List(List {
+            elements: read_values(r, ids, ids.max_elements)?,
+        }),
+        REF_SHAPE => Ref::Shape(read_shape(r)?),
+        REF_STROKE => Ref::Scribble(Scribble::Stroke(Stroke {
+            thickness: r.f32()?,
+            color: read_rgba(r)?,
+            shape: read_shape(r)?,
+        })),
+        REF_FILL => Ref::Scribble(Scribble::Fill(Fill {
+            color: read_rgba(r)?,
+            shape: read_shape(r)?,
+        })),
+        REF_FILTER => Ref::Scribble(Scribble::Filter(Filter {
+            effect: read_effect(r)?,
+            shape: read_shape(r)?,
+        })),
+        _ => return Err(SnapshotError::Invalid),
+    }))
+}
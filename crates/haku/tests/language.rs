@@ -9,11 +9,30 @@ use haku::{
     source::SourceCode,
     system::System,
     token::Lexis,
-    value::{Closure, Ref, RefId, Value},
+    value::{Closure, Ref, RefId, Rgba, Value, Vec4},
     vm::{Vm, VmLimits},
 };
 
+struct Eval {
+    value: Value,
+    vm: Vm,
+    bytecode_len: usize,
+    fuel_used: usize,
+}
+
 fn eval(code: &str) -> Result<Value, Box<dyn Error>> {
+    eval_with_stats(code).map(|eval| eval.value)
+}
+
+fn eval_strict(code: &str) -> Result<Value, Box<dyn Error>> {
+    eval_with_options(code, true).map(|eval| eval.value)
+}
+
+fn eval_with_stats(code: &str) -> Result<Eval, Box<dyn Error>> {
+    eval_with_options(code, false)
+}
+
+fn eval_with_options(code: &str, strict_math: bool) -> Result<Eval, Box<dyn Error>> {
     let mut system = System::new(1);
 
     let code = SourceCode::unlimited_len(code);
@@ -37,7 +56,6 @@ fn eval(code: &str) -> Result<Value, Box<dyn Error>> {
     let mut compiler = Compiler::new(&mut defs, &mut chunk);
     compile_expr(&mut compiler, &src, root)?;
     let closure_spec = compiler.closure_spec();
-    let defs = compiler.defs;
 
     let mut diagnostics = lexer.diagnostics;
     diagnostics.append(&mut parser_diagnostics);
@@ -45,7 +63,8 @@ fn eval(code: &str) -> Result<Value, Box<dyn Error>> {
 
     for diagnostic in &diagnostics {
         println!(
-            "{}..{} {:?}: {}",
+            "{:?} {}..{} {:?}: {}",
+            diagnostic.severity(),
             diagnostic.span().start,
             diagnostic.span().end,
             diagnostic.span().slice(code),
@@ -53,7 +72,7 @@ fn eval(code: &str) -> Result<Value, Box<dyn Error>> {
         );
     }
 
-    if !diagnostics.is_empty() {
+    if diagnostics.iter().any(|diagnostic| diagnostic.is_error()) {
         panic!("diagnostics were emitted")
     }
 
@@ -63,18 +82,26 @@ fn eval(code: &str) -> Result<Value, Box<dyn Error>> {
         ref_capacity: 256,
         fuel: 32768,
         memory: 1024,
+        strict_math,
     };
-    let mut vm = Vm::new(defs, &limits);
-    let chunk_id = system.add_chunk(chunk)?;
+    let mut vm = Vm::new(&defs, &limits);
+    let chunk_id = system.add_chunk(chunk, &defs, closure_spec)?;
     println!("bytecode: {:?}", system.chunk(chunk_id));
+    let bytecode_len = system.chunk(chunk_id).bytecode.len();
     println!("closure spec: {closure_spec:?}");
 
     let closure = vm.create_ref(Ref::Closure(Closure::chunk(chunk_id, closure_spec)))?;
     let result = vm.run(&system, closure)?;
 
-    println!("used fuel: {}", limits.fuel - vm.remaining_fuel());
+    let fuel_used = limits.fuel - vm.remaining_fuel();
+    println!("used fuel: {fuel_used}");
 
-    Ok(result)
+    Ok(Eval {
+        value: result,
+        vm,
+        bytecode_len,
+        fuel_used,
+    })
 }
 
 #[track_caller]
@@ -181,6 +208,26 @@ fn def_mutually_recursive() {
     expect_number(code, 14.0, 0.0001);
 }
 
+#[test]
+#[should_panic(expected = "diagnostics were emitted")]
+fn unreachable_after_result() {
+    let code = r#"
+        1
+        2
+    "#;
+    _ = eval(code);
+}
+
+#[test]
+#[should_panic(expected = "diagnostics were emitted")]
+fn def_after_result() {
+    let code = r#"
+        x + 1
+        x = 1
+    "#;
+    _ = eval(code);
+}
+
 #[test]
 fn def_botsbuildbots() {
     let code = r#"
@@ -281,3 +328,244 @@ fn issue_78() {
     "#;
     assert_eq!(eval(code).unwrap(), Value::Ref(RefId::from_u32(2)))
 }
+
+#[track_caller]
+fn expect_folded(code: &str, value: Value, bytecode_len: usize) {
+    let eval = eval_with_stats(code).unwrap();
+    assert_eq!(eval.value, value);
+    assert_eq!(eval.bytecode_len, bytecode_len, "bytecode length mismatch");
+}
+
+#[test]
+fn fold_arithmetic() {
+    // Number 7, Return
+    expect_folded("1 + 2 * 3", Value::Number(7.0), 6);
+    expect_folded("-(2 - 4)", Value::Number(2.0), 6);
+    expect_folded("sqrt 16", Value::Number(4.0), 6);
+    expect_folded("1 < 2", Value::True, 2);
+}
+
+#[test]
+fn fold_vec_rgba() {
+    // Vec4 0b0001 4, Return
+    let eval = eval_with_stats("vec (2 * 2) 0").unwrap();
+    assert_eq!(
+        eval.vm.get_vec4(eval.value),
+        Some(Vec4 {
+            x: 4.0,
+            y: 0.0,
+            z: 0.0,
+            w: 0.0,
+        })
+    );
+    assert_eq!(eval.bytecode_len, 7);
+    expect_folded("vecY (vec 1 2)", Value::Number(2.0), 6);
+    // Rgba 255 0 0 255, Return
+    let eval = eval_with_stats("rgba 1 0 0 1").unwrap();
+    assert_eq!(
+        eval.vm.get_rgba(eval.value),
+        Some(Rgba {
+            r: 1.0,
+            g: 0.0,
+            b: 0.0,
+            a: 1.0,
+        })
+    );
+    assert_eq!(eval.bytecode_len, 6);
+
+    // Colors that cannot be represented exactly with 8-bit channels are not folded.
+    let eval = eval_with_stats("rgba 0.3 0 0 1").unwrap();
+    assert_eq!(
+        eval.vm.get_rgba(eval.value),
+        Some(Rgba {
+            r: 0.3,
+            g: 0.0,
+            b: 0.0,
+            a: 1.0
+        })
+    );
+    assert!(eval.bytecode_len > 6);
+}
+
+#[test]
+fn fold_brush() {
+    let eval = eval_with_stats("stroke (4 * 2) #F00 (vec (-4) 0)").unwrap();
+    // Number 8, Rgba #F00, Vec4 0b0001 -4, System stroke 3, Return
+    assert_eq!(eval.bytecode_len, 5 + 5 + 6 + 3 + 1);
+    assert_eq!(eval.fuel_used, 5);
+}
+
+#[test]
+fn fold_if() {
+    expect_folded("if (1 < 2) 3 else 4", Value::Number(3.0), 6);
+    expect_folded("if (()) 3 else 4", Value::Number(4.0), 6);
+    expect_folded("(if (True) 1 else 2) + 3", Value::Number(4.0), 6);
+}
+
+#[test]
+#[should_panic(expected = "diagnostics were emitted")]
+fn fold_if_dead_branch_diagnostics() {
+    _ = eval("if (True) 1 else undefinedVariable");
+}
+
+#[test]
+fn fold_not_constant() {
+    let code = r#"
+        let x = 1
+        x + 2 * 3
+    "#;
+    expect_number(code, 7.0, 0.0001);
+    expect_number(r#"(\x -> if (x) 1 else 2) False"#, 2.0, 0.0001);
+    expect_number(r#"(if (False) 1 else 2) + (\x -> x) 3"#, 5.0, 0.0001);
+}
+
+#[test]
+fn fold_exception() {
+    // Exceptions are not folded, and are raised at runtime instead.
+    let error = eval("vecX 1").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Exception {\n    message: \"argument to (vecX vec) must be a `vec`\",\n}"
+    );
+}
+
+#[test]
+fn vec_compare_contents() {
+    let code = r#"
+        let a = vec 1 2
+        let b = vec 1 2
+        a == b
+    "#;
+    assert_eq!(eval(code).unwrap(), Value::True);
+
+    let code = r#"
+        let a = vec 1 2
+        a < vec 1 3
+    "#;
+    assert_eq!(eval(code).unwrap(), Value::True);
+
+    let code = r#"
+        let c = #F00
+        c != #0F0
+    "#;
+    assert_eq!(eval(code).unwrap(), Value::True);
+}
+
+#[test]
+fn vec_out_of_memory() {
+    // Each level takes up 16 bytes for the vector, and 16 bytes for the list's two elements.
+    // Tests run with 1024 bytes of heap memory.
+    let code = r#"
+        f = \n -> if (n > 0) [vec n, f (n - 1)] else []
+        f 20
+    "#;
+    assert!(eval(code).is_ok());
+
+    let code = r#"
+        f = \n -> if (n > 0) [vec n, f (n - 1)] else []
+        f 100
+    "#;
+    let error = eval(code).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Exception {\n    message: \"out of heap memory\",\n}"
+    );
+}
+
+#[test]
+fn gc_refs() {
+    // Allocates a closure per call, which is way more than the 256 refs tests run with.
+    let code = r#"
+        f = \n -> if (n > 0) f ((\x -> x - 1) n) + f ((\x -> x - 1) n) + 1 else 0
+        f 8
+    "#;
+    expect_number(code, 255.0, 0.0001);
+}
+
+#[test]
+fn gc_vecs() {
+    // Allocates a vector per call, which is way more than fits in 1024 bytes of heap memory.
+    let code = r#"
+        f = \n -> if (n > 0) f (vecX (vec (n - 1))) + f (vecX (vec (n - 1))) + 1 else 0
+        f 10
+    "#;
+    expect_number(code, 1023.0, 0.0001);
+}
+
+#[test]
+fn gc_keeps_reachable() {
+    // Garbage is created in between building up a list, which must survive.
+    let code = r#"
+        garbage = \n -> if (n > 0) garbage (n - 1) + garbage (n - 1) else (\_ -> 0) [n]
+        keep = \n -> if (n > 0) [keep (n - 1), garbage 5, vec n n, \_ -> n] else []
+        keep 6
+    "#;
+    let eval = eval_with_stats(code).unwrap();
+    let vm = &eval.vm;
+
+    let mut value = eval.value;
+    for n in (1..=6).rev() {
+        let Some((_, Ref::List(list))) = vm.get_ref_value(value) else {
+            panic!("expected list at level {n}, got {value:?}");
+        };
+        assert_eq!(list.elements.len(), 4);
+        let n = n as f32;
+        assert_eq!(
+            vm.get_vec4(list.elements[2]),
+            Some(Vec4 {
+                x: n,
+                y: n,
+                z: 0.0,
+                w: 0.0
+            })
+        );
+        let Some((_, Ref::Closure(closure))) = vm.get_ref_value(list.elements[3]) else {
+            panic!("expected closure at level {n}");
+        };
+        assert_eq!(closure.captures, [Value::Number(n)]);
+        value = list.elements[0];
+    }
+}
+
+#[test]
+fn non_strict_math() {
+    assert_eq!(eval("1 / 0").unwrap(), Value::Number(f32::INFINITY));
+    assert!(matches!(eval("sqrt (-1)").unwrap(), Value::Number(x) if x.is_nan()));
+}
+
+#[track_caller]
+fn expect_strict_math_exception(code: &str, message: &str) {
+    let error = eval_strict(code).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        format!("Exception {{\n    message: {message:?},\n}}")
+    );
+}
+
+#[test]
+fn strict_math() {
+    // These would otherwise be folded into NaN and infinity at compile time.
+    expect_strict_math_exception(
+        "1 / 0",
+        "`1 / 0` results in inf, but strict math does not allow NaN or infinity",
+    );
+    expect_strict_math_exception(
+        "sqrt (-1)",
+        "`sqrt (-1)` results in NaN, but strict math does not allow NaN or infinity",
+    );
+    expect_strict_math_exception(
+        "ln 0",
+        "`ln 0` results in -inf, but strict math does not allow NaN or infinity",
+    );
+    expect_strict_math_exception(
+        "pow 10 100",
+        "`pow 10 100` results in inf, but strict math does not allow NaN or infinity",
+    );
+    expect_number("1 / 4 + sqrt 4", 2.25, 0.0001);
+}
+
+#[test]
+#[should_panic(expected = "diagnostics were emitted")]
+fn literal_number_too_large() {
+    _ = eval("1000000000000000000000000000000000000000");
+}
@@ -0,0 +1,130 @@
+//! Snapshot tests for error recovery and reporting.
+//!
+//! Each `.haku` file in the `recovery` directory is a broken program. The diagnostics it produces
+//! are compared against the `.snap` file next to it. Run the tests with `HAKU_UPDATE_SNAPSHOTS=1`
+//! to write the snapshots out instead, and review the changes before committing them.
+
+use std::{fmt::Write, fs, path::Path};
+
+use haku::{
+    ast::Ast,
+    bytecode::{Chunk, Defs},
+    compiler::{compile_expr, Compiler, Source},
+    lexer::{lex, Lexer},
+    parser::{self, Parser, ParserLimits},
+    source::SourceCode,
+    system::System,
+    token::Lexis,
+};
+
+fn line_column(code: &str, offset: u32) -> (usize, usize) {
+    let before = &code[..offset as usize];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map(|i| i + 1).unwrap_or(0) + 1;
+    (line, column)
+}
+
+fn diagnostics(code: &str) -> String {
+    let system = System::new(1);
+    let src_code = SourceCode::unlimited_len(code);
+
+    let mut lexer = Lexer::new(Lexis::new(1024), src_code);
+    lex(&mut lexer).unwrap();
+
+    let mut parser = Parser::new(&lexer.lexis, &ParserLimits { max_events: 1024 });
+    parser::toplevel(&mut parser);
+    let mut ast = Ast::new(1024);
+    let (root, mut parser_diagnostics) = parser.into_ast(&mut ast).unwrap();
+
+    let src = Source {
+        code: src_code,
+        ast: &ast,
+        system: &system,
+    };
+    let mut defs = Defs::new(256);
+    let mut chunk = Chunk::new(65536).unwrap();
+    let mut compiler = Compiler::new(&mut defs, &mut chunk);
+    compile_expr(&mut compiler, &src, root).unwrap();
+
+    let mut diagnostics = lexer.diagnostics;
+    diagnostics.append(&mut parser_diagnostics);
+    diagnostics.append(&mut compiler.diagnostics);
+
+    let mut snapshot = String::new();
+    for diagnostic in &diagnostics {
+        let span = diagnostic.span();
+        let (line, column) = line_column(code, span.start);
+        writeln!(
+            snapshot,
+            "{line}:{column} {:?}: {}\n    {:?}",
+            diagnostic.severity(),
+            diagnostic.message(),
+            span.slice(src_code),
+        )
+        .unwrap();
+        for label in diagnostic.labels() {
+            let (line, column) = line_column(code, label.span.start);
+            writeln!(
+                snapshot,
+                "    {line}:{column} label: {}\n        {:?}",
+                label.message,
+                label.span.slice(src_code),
+            )
+            .unwrap();
+        }
+        for note in diagnostic.notes() {
+            writeln!(snapshot, "    note: {note}").unwrap();
+        }
+        for suggestion in diagnostic.suggestions() {
+            writeln!(
+                snapshot,
+                "    suggestion: {} ({:?} -> {:?})",
+                suggestion.message,
+                suggestion.span.slice(src_code),
+                suggestion.replacement,
+            )
+            .unwrap();
+        }
+    }
+    snapshot
+}
+
+#[test]
+fn recovery() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/recovery");
+    let update = std::env::var_os("HAKU_UPDATE_SNAPSHOTS").is_some();
+
+    let mut paths: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "haku"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+
+    let mut mismatches = Vec::new();
+    for path in &paths {
+        let code = fs::read_to_string(path).unwrap();
+        let got = diagnostics(&code);
+
+        let snap_path = path.with_extension("snap");
+        if update {
+            fs::write(&snap_path, &got).unwrap();
+            continue;
+        }
+
+        let expected = fs::read_to_string(&snap_path).unwrap_or_default();
+        if got != expected {
+            println!(
+                "snapshot mismatch for {}. expected:\n{expected}\ngot:\n{got}",
+                path.display()
+            );
+            mismatches.push(path.file_name().unwrap().to_owned());
+        }
+    }
+
+    assert!(
+        mismatches.is_empty(),
+        "snapshots do not match: {mismatches:?}"
+    );
+}
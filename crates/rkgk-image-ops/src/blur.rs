@@ -0,0 +1,155 @@
+//! Blurs.
+//!
+//! Blurring is done on premultiplied colors, so that the colors of transparent pixels don't bleed
+//! into their surroundings. Pixels past the edges of the pixmap are treated as copies of the
+//! nearest edge pixel, so that blurring a part of a larger image doesn't darken its borders.
+
+use alloc::vec;
+use tiny_skia::PixmapMut;
+
+/// Blur the pixmap by averaging each pixel with the pixels up to `radius` pixels away from it
+/// horizontally and vertically.
+pub fn box_blur(pixmap: &mut PixmapMut, radius: u32) {
+    box_blur_passes(pixmap, &[radius]);
+}
+
+/// Blur the pixmap with a Gaussian of the given standard deviation, in pixels.
+///
+/// The Gaussian is approximated by three box blurs, which is a lot faster than a true Gaussian,
+/// and looks close enough to it.
+///
+/// Blurring by more than the size of the pixmap doesn't make much of a difference, so `sigma` is
+/// limited to the pixmap's larger dimension.
+pub fn gaussian_blur(pixmap: &mut PixmapMut, sigma: f32) {
+    let max_sigma = pixmap.width().max(pixmap.height()) as f32;
+    box_blur_passes(pixmap, &gaussian_box_radii(sigma.min(max_sigma)));
+}
+
+/// Radii of three box blurs which, applied one after another, approximate a Gaussian.
+///
+/// See Peter Kovesi, _Fast Almost-Gaussian Filtering_ (2010).
+fn gaussian_box_radii(sigma: f32) -> [u32; 3] {
+    if !sigma.is_finite() || sigma <= 0.0 {
+        return [0; 3];
+    }
+
+    const N: f32 = 3.0;
+    let variance = 12.0 * sigma * sigma;
+    let ideal_width = libm::sqrtf(variance / N + 1.0);
+    let mut lower_width = ideal_width as u32;
+    if lower_width.is_multiple_of(2) {
+        lower_width = lower_width.saturating_sub(1);
+    }
+    let upper_width = lower_width.saturating_add(2);
+    let lower = lower_width as f32;
+    let lower_count = libm::roundf(
+        (variance - N * lower * lower - 4.0 * N * lower - 3.0 * N) / (-4.0 * lower - 4.0),
+    );
+
+    let mut radii = [0; 3];
+    for (i, radius) in radii.iter_mut().enumerate() {
+        let width = if (i as f32) < lower_count {
+            lower_width
+        } else {
+            upper_width
+        };
+        *radius = width.saturating_sub(1) / 2;
+    }
+    radii
+}
+
+/// Apply box blurs with the given radii one after another.
+fn box_blur_passes(pixmap: &mut PixmapMut, radii: &[u32]) {
+    let width = pixmap.width() as usize;
+    let height = pixmap.height() as usize;
+    let data = pixmap.data_mut();
+
+    // Box blurs are separable, and blurring in one direction commutes with blurring in the other,
+    // so all horizontal passes can be done before all vertical ones, without going back and forth
+    // between rows and columns.
+    let mut line = vec![[0; 4]; width.max(height)];
+    let mut blurred = line.clone();
+
+    for y in 0..height {
+        let line = &mut line[..width];
+        let blurred = &mut blurred[..width];
+        load(data, line, |x| y * width + x);
+        blur_line_passes(line, blurred, radii);
+        store(data, line, |x| y * width + x);
+    }
+
+    for x in 0..width {
+        let line = &mut line[..height];
+        let blurred = &mut blurred[..height];
+        load(data, line, |y| y * width + x);
+        blur_line_passes(line, blurred, radii);
+        store(data, line, |y| y * width + x);
+    }
+}
+
+fn load(data: &[u8], line: &mut [[u8; 4]], index: impl Fn(usize) -> usize) {
+    for (i, pixel) in line.iter_mut().enumerate() {
+        let at = index(i) * 4;
+        pixel.copy_from_slice(&data[at..at + 4]);
+    }
+}
+
+fn store(data: &mut [u8], line: &[[u8; 4]], index: impl Fn(usize) -> usize) {
+    for (i, pixel) in line.iter().enumerate() {
+        let at = index(i) * 4;
+        data[at..at + 4].copy_from_slice(pixel);
+    }
+}
+
+/// Blur the line with each of the radii, leaving the result in `line`.
+fn blur_line_passes(line: &mut [[u8; 4]], blurred: &mut [[u8; 4]], radii: &[u32]) {
+    for &radius in radii {
+        if radius != 0 {
+            blur_line(line, radius as usize, blurred);
+            line.copy_from_slice(blurred);
+        }
+    }
+}
+
+/// Box-blur a single line of pixels, with a running sum of the pixels in the window.
+fn blur_line(line: &[[u8; 4]], radius: usize, out: &mut [[u8; 4]]) {
+    let len = line.len();
+    let first = line[0];
+    let last = line[len - 1];
+    let window = 2 * radius as u64 + 1;
+
+    // The window around the first pixel extends `radius` pixels past the left edge, and possibly
+    // past the right edge too, if the radius is larger than the line.
+    let mut sum = [0_u64; 4];
+    add(&mut sum, first, radius as u64);
+    for &pixel in &line[..=radius.min(len - 1)] {
+        add(&mut sum, pixel, 1);
+    }
+    if radius > len - 1 {
+        add(&mut sum, last, (radius - (len - 1)) as u64);
+    }
+
+    for (x, out) in out.iter_mut().enumerate() {
+        // Rounding is monotonic, so channels can't end up larger than alpha.
+        *out = sum.map(|s| ((s + window / 2) / window) as u8);
+
+        let entering = line
+            .get(x.saturating_add(radius).saturating_add(1))
+            .copied()
+            .unwrap_or(last);
+        let leaving = x.checked_sub(radius).map_or(first, |i| line[i]);
+        add(&mut sum, entering, 1);
+        for (s, c) in sum.iter_mut().zip(leaving) {
+            *s -= u64::from(c);
+        }
+    }
+}
+
+fn add(sum: &mut [u64; 4], pixel: [u8; 4], times: u64) {
+    for (s, c) in sum.iter_mut().zip(pixel) {
+        *s += u64::from(c) * times;
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,107 @@
+use proptest::prelude::*;
+use tiny_skia::{Pixmap, PremultipliedColorU8};
+
+use crate::strategy::{color, is_premultiplied, pixmap};
+
+use super::{box_blur, gaussian_blur, gaussian_box_radii};
+
+#[test]
+fn blurs_a_dot() {
+    let mut pixmap = Pixmap::new(5, 1).unwrap();
+    pixmap.pixels_mut()[2] = PremultipliedColorU8::from_rgba(255, 0, 0, 255).unwrap();
+    box_blur(&mut pixmap.as_mut(), 1);
+    let alpha: Vec<_> = pixmap.pixels().iter().map(|p| p.alpha()).collect();
+    assert_eq!(alpha, [0, 85, 85, 85, 0]);
+}
+
+#[test]
+fn edges_are_extended() {
+    let mut pixmap = Pixmap::new(3, 1).unwrap();
+    pixmap.pixels_mut()[0] = PremultipliedColorU8::from_rgba(0, 0, 0, 255).unwrap();
+    box_blur(&mut pixmap.as_mut(), 1);
+    let alpha: Vec<_> = pixmap.pixels().iter().map(|p| p.alpha()).collect();
+    // The first pixel's window is [0, 0, 1], the second's [0, 1, 2], and the third's [1, 2, 2].
+    assert_eq!(alpha, [170, 85, 0]);
+}
+
+#[test]
+fn gaussian_radii() {
+    assert_eq!(gaussian_box_radii(0.0), [0, 0, 0]);
+    assert_eq!(gaussian_box_radii(-1.0), [0, 0, 0]);
+    assert_eq!(gaussian_box_radii(f32::NAN), [0, 0, 0]);
+    assert_eq!(gaussian_box_radii(1.0), [0, 0, 1]);
+    assert_eq!(gaussian_box_radii(5.0), [4, 4, 5]);
+    assert!(gaussian_box_radii(1e10).iter().all(|&r| r > 1 << 30));
+    assert!(gaussian_box_radii(f32::MAX).iter().all(|&r| r > 1 << 30));
+}
+
+#[test]
+fn huge_sigma_is_limited_to_pixmap_size() {
+    let mut pixmap = Pixmap::new(5, 1).unwrap();
+    pixmap.pixels_mut()[2] = PremultipliedColorU8::from_rgba(255, 0, 0, 255).unwrap();
+    let mut limited = pixmap.clone();
+    gaussian_blur(&mut pixmap.as_mut(), 1e10);
+    gaussian_blur(&mut limited.as_mut(), 5.0);
+    assert_eq!(pixmap, limited);
+}
+
+proptest! {
+    #[test]
+    fn zero_radius_does_nothing(original in pixmap(16)) {
+        let mut blurred = original.clone();
+        box_blur(&mut blurred.as_mut(), 0);
+        gaussian_blur(&mut blurred.as_mut(), 0.0);
+        prop_assert_eq!(blurred, original);
+    }
+
+    #[test]
+    fn stays_premultiplied(mut pixmap in pixmap(16), radius in 0_u32..20, sigma in 0.0_f32..10.0) {
+        box_blur(&mut pixmap.as_mut(), radius);
+        prop_assert!(is_premultiplied(&pixmap));
+        gaussian_blur(&mut pixmap.as_mut(), sigma);
+        prop_assert!(is_premultiplied(&pixmap));
+    }
+
+    #[test]
+    fn uniform_stays_uniform(
+        color in color(),
+        width in 1_u32..16,
+        height in 1_u32..16,
+        radius in 0_u32..20,
+    ) {
+        let mut pixmap = Pixmap::new(width, height).unwrap();
+        pixmap.pixels_mut().fill(color);
+        box_blur(&mut pixmap.as_mut(), radius);
+        prop_assert!(pixmap.pixels().iter().all(|&p| p == color));
+    }
+
+    #[test]
+    fn stays_within_range(mut pixmap in pixmap(16), radius in 0_u32..20) {
+        let range = |pixmap: &Pixmap| {
+            pixmap.data().chunks_exact(4).fold([(255, 0); 4], |mut range, pixel| {
+                for ((min, max), &c) in range.iter_mut().zip(pixel) {
+                    *min = c.min(*min);
+                    *max = c.max(*max);
+                }
+                range
+            })
+        };
+        let before = range(&pixmap);
+        box_blur(&mut pixmap.as_mut(), radius);
+        let after = range(&pixmap);
+        for ((min, max), (new_min, new_max)) in before.into_iter().zip(after) {
+            prop_assert!(min <= new_min && new_max <= max);
+        }
+    }
+
+    #[test]
+    fn huge_radius_averages_everything(mut pixmap in pixmap(8)) {
+        box_blur(&mut pixmap.as_mut(), u32::MAX);
+        let first = &pixmap.data()[..4];
+        for pixel in pixmap.data().chunks_exact(4) {
+            for (&c, &f) in pixel.iter().zip(first) {
+                prop_assert!(c.abs_diff(f) <= 2);
+            }
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! Operations on the colors of individual pixels.
+//!
+//! These work on unpremultiplied colors with components in the `0` to `1` range, so that a pixel's
+//! color is changed the same way no matter how transparent it is.
+
+use tiny_skia::PixmapMut;
+
+/// Weights of the red, green, and blue components in the luminance of a color. These are the
+/// Rec. 709 weights, rounded the same way SVG filters round them.
+const LUMINANCE: [f32; 3] = [0.213, 0.715, 0.072];
+
+/// A matrix transforming colors, laid out like the one in SVG's `feColorMatrix`.
+///
+/// Each row computes one of the red, green, blue, and alpha components of the resulting color.
+/// The first four columns are the weights of the input color's red, green, blue, and alpha
+/// components, and the fifth is a constant added to the result.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorMatrix(pub [[f32; 5]; 4]);
+
+impl ColorMatrix {
+    /// Leaves colors as they are.
+    pub const IDENTITY: Self = Self([
+        [1.0, 0.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0, 0.0],
+    ]);
+
+    /// Inverts the red, green, and blue components of colors.
+    pub const INVERT: Self = Self([
+        [-1.0, 0.0, 0.0, 0.0, 1.0],
+        [0.0, -1.0, 0.0, 0.0, 1.0],
+        [0.0, 0.0, -1.0, 0.0, 1.0],
+        [0.0, 0.0, 0.0, 1.0, 0.0],
+    ]);
+
+    /// Rotates the hue of colors by the given angle, in radians, keeping their luminance.
+    pub fn hue_rotate(angle: f32) -> Self {
+        let [lr, lg, lb] = LUMINANCE;
+        let (sin, cos) = (libm::sinf(angle), libm::cosf(angle));
+        Self([
+            [
+                lr + cos * (1.0 - lr) - sin * lr,
+                lg - cos * lg - sin * lg,
+                lb - cos * lb + sin * (1.0 - lb),
+                0.0,
+                0.0,
+            ],
+            [
+                lr - cos * lr + sin * 0.143,
+                lg + cos * (1.0 - lg) + sin * 0.140,
+                lb - cos * lb - sin * 0.283,
+                0.0,
+                0.0,
+            ],
+            [
+                lr - cos * lr - sin * (1.0 - lr),
+                lg - cos * lg + sin * lg,
+                lb + cos * (1.0 - lb) + sin * lb,
+                0.0,
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0, 0.0],
+        ])
+    }
+
+    /// Scales the saturation of colors. `0` makes them grayscale, and `1` leaves them as they are.
+    pub fn saturate(amount: f32) -> Self {
+        let [lr, lg, lb] = LUMINANCE;
+        let s = amount;
+        Self([
+            [lr + (1.0 - lr) * s, lg - lg * s, lb - lb * s, 0.0, 0.0],
+            [lr - lr * s, lg + (1.0 - lg) * s, lb - lb * s, 0.0, 0.0],
+            [lr - lr * s, lg - lg * s, lb + (1.0 - lb) * s, 0.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0, 0.0],
+        ])
+    }
+
+    /// Adds `brightness` to the red, green, and blue components of colors, after scaling their
+    /// distance from middle gray by `contrast`.
+    pub fn brightness_contrast(brightness: f32, contrast: f32) -> Self {
+        let offset = 0.5 - 0.5 * contrast + brightness;
+        Self([
+            [contrast, 0.0, 0.0, 0.0, offset],
+            [0.0, contrast, 0.0, 0.0, offset],
+            [0.0, 0.0, contrast, 0.0, offset],
+            [0.0, 0.0, 0.0, 1.0, 0.0],
+        ])
+    }
+
+    /// Returns a matrix which transforms colors by this matrix, and then by `next`.
+    pub fn then(&self, next: &Self) -> Self {
+        let mut result = [[0.0; 5]; 4];
+        for (row, next_row) in result.iter_mut().zip(&next.0) {
+            for (column, out) in row.iter_mut().enumerate() {
+                *out = (0..4).map(|i| next_row[i] * self.0[i][column]).sum();
+            }
+            row[4] += next_row[4];
+        }
+        Self(result)
+    }
+
+    /// Transforms an unpremultiplied color.
+    pub fn transform(&self, color: [f32; 4]) -> [f32; 4] {
+        self.0.map(|row| {
+            row[0] * color[0] + row[1] * color[1] + row[2] * color[2] + row[3] * color[3] + row[4]
+        })
+    }
+}
+
+/// Transform the colors of all pixels by a color matrix. Components of the resulting colors are
+/// clamped to the `0` to `1` range.
+pub fn color_matrix(pixmap: &mut PixmapMut, matrix: &ColorMatrix) {
+    map_colors(pixmap, |color| matrix.transform(color));
+}
+
+/// Make pixels whose luminance is at least `level` white, and the rest black, keeping their alpha.
+pub fn threshold(pixmap: &mut PixmapMut, level: f32) {
+    map_colors(pixmap, |[r, g, b, a]| {
+        let luminance = LUMINANCE[0] * r + LUMINANCE[1] * g + LUMINANCE[2] * b;
+        let c = if luminance >= level { 1.0 } else { 0.0 };
+        [c, c, c, a]
+    });
+}
+
+/// Reduce the red, green, and blue components of pixels to `levels` evenly spaced values each,
+/// rounding them to the nearest one. Fewer than 2 levels are treated as 2.
+pub fn posterize(pixmap: &mut PixmapMut, levels: u8) {
+    let steps = f32::from(levels.max(2) - 1);
+    let quantize = |c: f32| libm::roundf(c * steps) / steps;
+    map_colors(pixmap, |[r, g, b, a]| {
+        [quantize(r), quantize(g), quantize(b), a]
+    });
+}
+
+/// Replace the color of each pixel with `f` of its unpremultiplied color.
+fn map_colors(pixmap: &mut PixmapMut, f: impl Fn([f32; 4]) -> [f32; 4]) {
+    for pixel in pixmap.data_mut().chunks_exact_mut(4) {
+        let alpha = pixel[3];
+        let unpremultiply = if alpha == 0 {
+            0.0
+        } else {
+            1.0 / f32::from(alpha)
+        };
+        let color = [
+            f32::from(pixel[0]) * unpremultiply,
+            f32::from(pixel[1]) * unpremultiply,
+            f32::from(pixel[2]) * unpremultiply,
+            f32::from(alpha) / 255.0,
+        ];
+
+        let [r, g, b, a] = f(color).map(|c| c.clamp(0.0, 1.0));
+        // Premultiplying by the rounded alpha keeps the channels from exceeding it.
+        // NaNs are clamped to NaN, which is cast to 0.
+        let alpha = (a * 255.0 + 0.5) as u8;
+        let premultiply = f32::from(alpha);
+        pixel[0] = (r * premultiply + 0.5) as u8;
+        pixel[1] = (g * premultiply + 0.5) as u8;
+        pixel[2] = (b * premultiply + 0.5) as u8;
+        pixel[3] = alpha;
+    }
+}
+
+#[cfg(test)]
+mod tests;
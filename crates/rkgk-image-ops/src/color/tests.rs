@@ -0,0 +1,171 @@
+use core::f32::consts::PI;
+
+use proptest::prelude::*;
+use tiny_skia::{Pixmap, PremultipliedColorU8};
+
+use crate::strategy::{is_premultiplied, opaque_color, pixmap, pixmap_of};
+
+use super::{color_matrix, posterize, threshold, ColorMatrix};
+
+fn single_pixel(r: u8, g: u8, b: u8, a: u8) -> Pixmap {
+    let mut pixmap = Pixmap::new(1, 1).unwrap();
+    pixmap.pixels_mut()[0] = PremultipliedColorU8::from_rgba(r, g, b, a).unwrap();
+    pixmap
+}
+
+fn rgba(pixmap: &Pixmap) -> [u8; 4] {
+    let pixel = pixmap.pixels()[0];
+    [pixel.red(), pixel.green(), pixel.blue(), pixel.alpha()]
+}
+
+#[test]
+fn invert_keeps_alpha() {
+    let mut pixmap = single_pixel(0, 64, 128, 128);
+    color_matrix(&mut pixmap.as_mut(), &ColorMatrix::INVERT);
+    assert_eq!(rgba(&pixmap), [128, 64, 0, 128]);
+}
+
+#[test]
+fn transparent_pixels_can_become_visible() {
+    let mut pixmap = single_pixel(0, 0, 0, 0);
+    let mut matrix = ColorMatrix::IDENTITY;
+    matrix.0[0][4] = 1.0;
+    matrix.0[3][4] = 1.0;
+    color_matrix(&mut pixmap.as_mut(), &matrix);
+    assert_eq!(rgba(&pixmap), [255, 0, 0, 255]);
+}
+
+#[test]
+fn hue_rotate_half_turn() {
+    let mut pixmap = single_pixel(255, 0, 0, 255);
+    color_matrix(&mut pixmap.as_mut(), &ColorMatrix::hue_rotate(PI));
+    let [r, g, b, a] = rgba(&pixmap);
+    assert_eq!(a, 255);
+    assert!(
+        r < g && r < b,
+        "red should turn cyan-ish, got {:?}",
+        [r, g, b]
+    );
+}
+
+#[test]
+fn brightness_and_contrast() {
+    let mut pixmap = single_pixel(64, 128, 192, 255);
+    color_matrix(
+        &mut pixmap.as_mut(),
+        &ColorMatrix::brightness_contrast(0.0, 0.0),
+    );
+    assert_eq!(rgba(&pixmap), [128, 128, 128, 255]);
+
+    let mut pixmap = single_pixel(64, 128, 192, 255);
+    color_matrix(
+        &mut pixmap.as_mut(),
+        &ColorMatrix::brightness_contrast(1.0, 1.0),
+    );
+    assert_eq!(rgba(&pixmap), [255, 255, 255, 255]);
+}
+
+#[test]
+fn threshold_by_luminance() {
+    let mut pixmap = single_pixel(0, 200, 0, 255);
+    threshold(&mut pixmap.as_mut(), 0.5);
+    assert_eq!(rgba(&pixmap), [255, 255, 255, 255]);
+
+    let mut pixmap = single_pixel(0, 0, 200, 200);
+    threshold(&mut pixmap.as_mut(), 0.5);
+    assert_eq!(rgba(&pixmap), [0, 0, 0, 200]);
+}
+
+#[test]
+fn posterize_to_two_levels() {
+    let mut pixmap = single_pixel(100, 150, 255, 255);
+    posterize(&mut pixmap.as_mut(), 0);
+    assert_eq!(rgba(&pixmap), [0, 255, 255, 255]);
+}
+
+proptest! {
+    #[test]
+    fn identity_does_nothing(original in pixmap(8)) {
+        let mut pixmap = original.clone();
+        color_matrix(&mut pixmap.as_mut(), &ColorMatrix::IDENTITY);
+        prop_assert_eq!(pixmap, original);
+    }
+
+    #[test]
+    fn invert_twice_does_nothing(original in pixmap(8)) {
+        let mut pixmap = original.clone();
+        color_matrix(&mut pixmap.as_mut(), &ColorMatrix::INVERT);
+        color_matrix(&mut pixmap.as_mut(), &ColorMatrix::INVERT);
+        prop_assert_eq!(pixmap, original);
+    }
+
+    #[test]
+    fn then_composes(
+        original in pixmap(8),
+        angle in -PI..PI,
+        saturation in 0.0_f32..2.0,
+        brightness in -0.5_f32..0.5,
+        contrast in 0.0_f32..2.0,
+    ) {
+        let a = ColorMatrix::hue_rotate(angle);
+        let b = ColorMatrix::saturate(saturation)
+            .then(&ColorMatrix::brightness_contrast(brightness, contrast));
+        let color = [0.25, 0.5, 0.75, 1.0];
+        let separately = b.transform(a.transform(color));
+        let composed = a.then(&b).transform(color);
+        for (x, y) in separately.into_iter().zip(composed) {
+            prop_assert!((x - y).abs() < 1e-4);
+        }
+
+        let mut pixmap = original;
+        color_matrix(&mut pixmap.as_mut(), &a.then(&b));
+        prop_assert!(is_premultiplied(&pixmap));
+    }
+
+    #[test]
+    fn arbitrary_matrices_stay_premultiplied(
+        mut pixmap in pixmap(8),
+        matrix in prop::array::uniform4(prop::array::uniform5(-2.0_f32..2.0)),
+    ) {
+        color_matrix(&mut pixmap.as_mut(), &ColorMatrix(matrix));
+        prop_assert!(is_premultiplied(&pixmap));
+    }
+
+    #[test]
+    fn threshold_is_black_and_white(mut pixmap in pixmap(8), level in 0.0_f32..1.0) {
+        let alpha: Vec<_> = pixmap.pixels().iter().map(|p| p.alpha()).collect();
+        threshold(&mut pixmap.as_mut(), level);
+        for (pixel, a) in pixmap.pixels().iter().zip(alpha) {
+            prop_assert_eq!(pixel.alpha(), a);
+            prop_assert!(pixel.red() == 0 || pixel.red() == a);
+            prop_assert!(pixel.red() == pixel.green() && pixel.green() == pixel.blue());
+        }
+    }
+
+    #[test]
+    fn posterize_all_levels_does_nothing(original in pixmap_of(8, opaque_color())) {
+        let mut pixmap = original.clone();
+        posterize(&mut pixmap.as_mut(), 255);
+        // 255 levels are one short of all 8-bit values, so colors may move by one.
+        for (a, b) in pixmap.data().iter().zip(original.data()) {
+            prop_assert!(a.abs_diff(*b) <= 1);
+        }
+    }
+
+    #[test]
+    fn posterize_is_idempotent(mut pixmap in pixmap_of(8, opaque_color()), levels in 2_u8..=255) {
+        posterize(&mut pixmap.as_mut(), levels);
+        let once = pixmap.clone();
+        posterize(&mut pixmap.as_mut(), levels);
+        prop_assert_eq!(pixmap, once);
+    }
+
+    #[test]
+    fn posterize_limits_colors(mut pixmap in pixmap_of(8, opaque_color()), levels in 2_u8..=16) {
+        posterize(&mut pixmap.as_mut(), levels);
+        let mut values: Vec<_> = pixmap.pixels().iter().map(|p| p.red()).collect();
+        values.sort();
+        values.dedup();
+        prop_assert!(values.len() <= usize::from(levels));
+    }
+}
@@ -0,0 +1,139 @@
+//! Compositing pixmaps onto each other.
+//!
+//! The source pixmap is placed with its top-left corner at a given position within the
+//! destination pixmap. Any parts of it that fall outside the destination are clipped.
+
+use tiny_skia::{Mask, PixmapMut, PixmapRef};
+
+/// Replace the pixels of `dst` with the pixels of `src`.
+pub fn copy(dst: &mut PixmapMut, src: PixmapRef, x: i32, y: i32) {
+    composite(dst, src, x, y, |dst, src, _| dst.copy_from_slice(src));
+}
+
+/// Replace the pixels of `dst` with the pixels of `src`, but only as much as `mask` covers them.
+/// Where the mask is partially covered, the pixels are mixed together. The mask must be the same
+/// size as `dst`.
+pub fn copy_masked(dst: &mut PixmapMut, src: PixmapRef, x: i32, y: i32, mask: &Mask) {
+    assert!(
+        mask.width() == dst.width() && mask.height() == dst.height(),
+        "mask must be the same size as the destination pixmap"
+    );
+    let coverage = mask.data();
+    composite(dst, src, x, y, |dst, src, index| {
+        let coverage = coverage[index];
+        for (d, s) in dst.iter_mut().zip(src) {
+            *d = mul_div_255(*s, coverage) + mul_div_255(*d, 255 - coverage);
+        }
+    });
+}
+
+/// Draw `src` over `dst`, with source-over blending.
+pub fn alpha_over(dst: &mut PixmapMut, src: PixmapRef, x: i32, y: i32) {
+    composite(dst, src, x, y, |dst, src, _| {
+        let inverse_alpha = 255 - src[3];
+        for (d, s) in dst.iter_mut().zip(src) {
+            *d = s + mul_div_255(*d, inverse_alpha);
+        }
+    });
+}
+
+/// Erase `dst` wherever `src` is opaque, with destination-out blending. The alpha of each pixel of
+/// `src` is how much of the `dst` pixel under it is erased; its color doesn't matter.
+pub fn erase(dst: &mut PixmapMut, src: PixmapRef, x: i32, y: i32) {
+    composite(dst, src, x, y, |dst, src, _| {
+        let inverse_alpha = 255 - src[3];
+        for d in dst {
+            *d = mul_div_255(*d, inverse_alpha);
+        }
+    });
+}
+
+/// Call `blend` on each pair of overlapping pixels of `dst` and `src`, along with the index of the
+/// `dst` pixel.
+fn composite(
+    dst: &mut PixmapMut,
+    src: PixmapRef,
+    x: i32,
+    y: i32,
+    blend: impl Fn(&mut [u8], &[u8], usize),
+) {
+    let Some(overlap) = Overlap::new(dst.width(), dst.height(), src.width(), src.height(), x, y)
+    else {
+        return;
+    };
+
+    let dst_stride = dst.width() as usize * 4;
+    let src_stride = src.width() as usize * 4;
+    let dst_data = dst.data_mut();
+    let src_data = src.data();
+    for row in 0..overlap.height {
+        let dst_start = (overlap.dst_y + row) * dst_stride + overlap.dst_x * 4;
+        let src_start = (overlap.src_y + row) * src_stride + overlap.src_x * 4;
+        let len = overlap.width * 4;
+        let dst_row = &mut dst_data[dst_start..dst_start + len];
+        let src_row = &src_data[src_start..src_start + len];
+        let first_index = dst_start / 4;
+        for (i, (d, s)) in dst_row
+            .chunks_exact_mut(4)
+            .zip(src_row.chunks_exact(4))
+            .enumerate()
+        {
+            blend(d, s, first_index + i);
+        }
+    }
+}
+
+/// The rectangle where a source pixmap placed at some position overlaps the destination pixmap.
+struct Overlap {
+    dst_x: usize,
+    dst_y: usize,
+    src_x: usize,
+    src_y: usize,
+    width: usize,
+    height: usize,
+}
+
+impl Overlap {
+    fn new(
+        dst_width: u32,
+        dst_height: u32,
+        src_width: u32,
+        src_height: u32,
+        x: i32,
+        y: i32,
+    ) -> Option<Self> {
+        let (dst_x, src_x, width) = overlap_1d(dst_width, src_width, x)?;
+        let (dst_y, src_y, height) = overlap_1d(dst_height, src_height, y)?;
+        Some(Self {
+            dst_x,
+            dst_y,
+            src_x,
+            src_y,
+            width,
+            height,
+        })
+    }
+}
+
+/// Returns where the overlap starts in the destination and in the source, and how long it is.
+fn overlap_1d(dst_len: u32, src_len: u32, position: i32) -> Option<(usize, usize, usize)> {
+    let position = i64::from(position);
+    let start = position.max(0);
+    let end = (position + i64::from(src_len)).min(i64::from(dst_len));
+    (start < end).then(|| {
+        (
+            start as usize,
+            (start - position) as usize,
+            (end - start) as usize,
+        )
+    })
+}
+
+/// Multiplies two 8-bit values as if they were fractions of 255, rounding to the nearest value.
+fn mul_div_255(a: u8, b: u8) -> u8 {
+    let x = u32::from(a) * u32::from(b) + 128;
+    ((x + (x >> 8)) >> 8) as u8
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,172 @@
+use proptest::prelude::*;
+use tiny_skia::{FillRule, Mask, PathBuilder, Pixmap, PremultipliedColorU8, Rect, Transform};
+
+use crate::strategy::{is_premultiplied, opaque_color, pixmap, pixmap_of};
+
+use super::{alpha_over, copy, copy_masked, erase, mul_div_255};
+
+#[test]
+fn mul_div_255_rounds() {
+    for a in 0..=255 {
+        for b in 0..=255 {
+            let exact = f64::from(a) * f64::from(b) / 255.0;
+            assert_eq!(mul_div_255(a, b), exact.round() as u8, "{a} * {b}");
+        }
+    }
+}
+
+#[test]
+fn half_over_half() {
+    let half = PremultipliedColorU8::from_rgba(128, 0, 0, 128).unwrap();
+    let mut dst = Pixmap::new(1, 1).unwrap();
+    dst.pixels_mut()[0] = half;
+    let mut src = Pixmap::new(1, 1).unwrap();
+    src.pixels_mut()[0] = PremultipliedColorU8::from_rgba(0, 0, 128, 128).unwrap();
+    alpha_over(&mut dst.as_mut(), src.as_ref(), 0, 0);
+    let pixel = dst.pixels()[0];
+    assert_eq!(
+        [pixel.red(), pixel.green(), pixel.blue(), pixel.alpha()],
+        [64, 0, 128, 192]
+    );
+}
+
+#[test]
+fn clipped() {
+    let mut dst = Pixmap::new(4, 4).unwrap();
+    let mut src = Pixmap::new(3, 3).unwrap();
+    src.pixels_mut()
+        .fill(PremultipliedColorU8::from_rgba(0, 0, 0, 255).unwrap());
+    alpha_over(&mut dst.as_mut(), src.as_ref(), -2, 3);
+    let alpha: Vec<_> = dst.pixels().iter().map(|p| p.alpha()).collect();
+    #[rustfmt::skip]
+    assert_eq!(alpha, [
+        0, 0, 0, 0,
+        0, 0, 0, 0,
+        0, 0, 0, 0,
+        255, 0, 0, 0,
+    ]);
+
+    alpha_over(&mut dst.as_mut(), src.as_ref(), 4, 0);
+    alpha_over(&mut dst.as_mut(), src.as_ref(), i32::MIN, i32::MAX);
+    assert_eq!(dst.pixels().iter().filter(|p| p.alpha() != 0).count(), 1);
+}
+
+#[test]
+fn masked() {
+    let mut dst = Pixmap::new(4, 1).unwrap();
+    let mut src = Pixmap::new(4, 1).unwrap();
+    src.pixels_mut()
+        .fill(PremultipliedColorU8::from_rgba(0, 0, 0, 255).unwrap());
+    let mut mask = Mask::new(4, 1).unwrap();
+    let path = PathBuilder::from_rect(Rect::from_xywh(1.0, 0.0, 2.0, 1.0).unwrap());
+    mask.fill_path(&path, FillRule::Winding, false, Transform::identity());
+
+    copy_masked(&mut dst.as_mut(), src.as_ref(), 0, 0, &mask);
+    let alpha: Vec<_> = dst.pixels().iter().map(|p| p.alpha()).collect();
+    assert_eq!(alpha, [0, 255, 255, 0]);
+}
+
+fn offset() -> impl Strategy<Value = i32> {
+    -10..10
+}
+
+proptest! {
+    #[test]
+    fn transparent_over_does_nothing(original in pixmap(8), x in offset(), y in offset()) {
+        let mut dst = original.clone();
+        let src = Pixmap::new(8, 8).unwrap();
+        alpha_over(&mut dst.as_mut(), src.as_ref(), x, y);
+        erase(&mut dst.as_mut(), src.as_ref(), x, y);
+        prop_assert_eq!(dst, original);
+    }
+
+    #[test]
+    fn copy_replaces(mut dst in pixmap(8), src in pixmap(8), x in offset(), y in offset()) {
+        let original = dst.clone();
+        copy(&mut dst.as_mut(), src.as_ref(), x, y);
+        for dst_y in 0..dst.height() {
+            for dst_x in 0..dst.width() {
+                let (src_x, src_y) = (dst_x as i32 - x, dst_y as i32 - y);
+                let expected = if (0..src.width() as i32).contains(&src_x)
+                    && (0..src.height() as i32).contains(&src_y)
+                {
+                    src.pixel(src_x as u32, src_y as u32)
+                } else {
+                    original.pixel(dst_x, dst_y)
+                };
+                prop_assert_eq!(dst.pixel(dst_x, dst_y), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn masked_copy_stays_premultiplied(
+        mut dst in pixmap(8),
+        src in pixmap(8),
+        coverage in prop::collection::vec(any::<u8>(), 64),
+        x in offset(),
+        y in offset(),
+    ) {
+        let mut mask = Mask::new(dst.width(), dst.height()).unwrap();
+        let len = mask.data().len();
+        mask.data_mut().copy_from_slice(&coverage[..len]);
+        copy_masked(&mut dst.as_mut(), src.as_ref(), x, y, &mask);
+        prop_assert!(is_premultiplied(&dst));
+    }
+
+    #[test]
+    fn opaque_over_replaces(mut dst in pixmap(8), src in pixmap_of(8, opaque_color())) {
+        alpha_over(&mut dst.as_mut(), src.as_ref(), 0, 0);
+        for y in 0..dst.height().min(src.height()) {
+            for x in 0..dst.width().min(src.width()) {
+                prop_assert_eq!(dst.pixel(x, y), src.pixel(x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn over_transparent_copies(src in pixmap(8)) {
+        let mut dst = Pixmap::new(src.width(), src.height()).unwrap();
+        alpha_over(&mut dst.as_mut(), src.as_ref(), 0, 0);
+        prop_assert_eq!(dst, src);
+    }
+
+    #[test]
+    fn over_stays_premultiplied(
+        mut dst in pixmap(8),
+        src in pixmap(8),
+        x in offset(),
+        y in offset(),
+    ) {
+        alpha_over(&mut dst.as_mut(), src.as_ref(), x, y);
+        prop_assert!(is_premultiplied(&dst));
+    }
+
+    #[test]
+    fn over_never_lowers_alpha(original in pixmap(8), src in pixmap(8)) {
+        let mut dst = original.clone();
+        alpha_over(&mut dst.as_mut(), src.as_ref(), 0, 0);
+        for (after, before) in dst.pixels().iter().zip(original.pixels()) {
+            prop_assert!(after.alpha() >= before.alpha());
+        }
+    }
+
+    #[test]
+    fn opaque_erase_clears(mut dst in pixmap(8), color in opaque_color()) {
+        let mut src = Pixmap::new(dst.width(), dst.height()).unwrap();
+        src.pixels_mut().fill(color);
+        erase(&mut dst.as_mut(), src.as_ref(), 0, 0);
+        prop_assert!(dst.pixels().iter().all(|p| p.alpha() == 0));
+    }
+
+    #[test]
+    fn erase_stays_premultiplied(
+        mut dst in pixmap(8),
+        src in pixmap(8),
+        x in offset(),
+        y in offset(),
+    ) {
+        erase(&mut dst.as_mut(), src.as_ref(), x, y);
+        prop_assert!(is_premultiplied(&dst));
+    }
+}
@@ -0,0 +1,86 @@
+//! Flood fill.
+
+use alloc::{vec, vec::Vec};
+use tiny_skia::{PixmapMut, PremultipliedColorU8};
+
+/// Fill the area of similar colors around the pixel at (`x`, `y`) with `color`, like the paint
+/// bucket tool of an image editor does.
+///
+/// The area consists of all pixels that can be reached from the starting pixel by going up, down,
+/// left, or right, without crossing any pixel that isn't similar to the starting pixel. Pixels are
+/// similar if none of their premultiplied components differ by more than `tolerance`.
+///
+/// Returns the number of pixels filled, which is zero if the starting pixel is outside the pixmap.
+pub fn flood_fill(
+    pixmap: &mut PixmapMut,
+    x: u32,
+    y: u32,
+    color: PremultipliedColorU8,
+    tolerance: u8,
+) -> usize {
+    let width = pixmap.width() as usize;
+    let height = pixmap.height() as usize;
+    let (x, y) = (x as usize, y as usize);
+    if x >= width || y >= height {
+        return 0;
+    }
+
+    let pixels = pixmap.pixels_mut();
+    let seed = pixels[y * width + x];
+    let mut filled = vec![false; width * height];
+    let fillable = |pixels: &[PremultipliedColorU8], filled: &[bool], x: usize, y: usize| {
+        let i = y * width + x;
+        !filled[i] && similar(pixels[i], seed, tolerance)
+    };
+
+    // Fill horizontal spans of pixels at a time. Each span on the stack is represented by any one
+    // of its pixels.
+    let mut count = 0;
+    let mut stack: Vec<(usize, usize)> = vec![(x, y)];
+    while let Some((x, y)) = stack.pop() {
+        if !fillable(pixels, &filled, x, y) {
+            continue;
+        }
+
+        let mut left = x;
+        while left > 0 && fillable(pixels, &filled, left - 1, y) {
+            left -= 1;
+        }
+        let mut right = x;
+        while right + 1 < width && fillable(pixels, &filled, right + 1, y) {
+            right += 1;
+        }
+
+        for x in left..=right {
+            let i = y * width + x;
+            pixels[i] = color;
+            filled[i] = true;
+        }
+        count += right - left + 1;
+
+        let above = y.checked_sub(1);
+        let below = (y + 1 < height).then_some(y + 1);
+        for y in [above, below].into_iter().flatten() {
+            let mut in_span = false;
+            for x in left..=right {
+                let fillable = fillable(pixels, &filled, x, y);
+                if fillable && !in_span {
+                    stack.push((x, y));
+                }
+                in_span = fillable;
+            }
+        }
+    }
+
+    count
+}
+
+fn similar(a: PremultipliedColorU8, b: PremultipliedColorU8, tolerance: u8) -> bool {
+    a.red().abs_diff(b.red()) <= tolerance
+        && a.green().abs_diff(b.green()) <= tolerance
+        && a.blue().abs_diff(b.blue()) <= tolerance
+        && a.alpha().abs_diff(b.alpha()) <= tolerance
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,149 @@
+use proptest::prelude::*;
+use tiny_skia::{Pixmap, PremultipliedColorU8};
+
+use crate::strategy::{color, pixmap};
+
+use super::{flood_fill, similar};
+
+fn gray(value: u8) -> PremultipliedColorU8 {
+    PremultipliedColorU8::from_rgba(value, value, value, 255).unwrap()
+}
+
+/// Builds a pixmap from rows of characters, where `#` is black and anything else is white.
+fn from_ascii(rows: &[&str]) -> Pixmap {
+    let mut pixmap = Pixmap::new(rows[0].len() as u32, rows.len() as u32).unwrap();
+    for (pixel, c) in pixmap.pixels_mut().iter_mut().zip(rows.concat().chars()) {
+        *pixel = if c == '#' { gray(0) } else { gray(255) };
+    }
+    pixmap
+}
+
+fn to_ascii(pixmap: &Pixmap) -> Vec<String> {
+    pixmap
+        .pixels()
+        .chunks(pixmap.width() as usize)
+        .map(|row| {
+            row.iter()
+                .map(|&p| match p {
+                    p if p == gray(0) => '#',
+                    p if p == gray(255) => '.',
+                    _ => 'o',
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn fills_enclosed_area() {
+    let mut pixmap = from_ascii(&[
+        "..#....", //
+        ".#.#...", //
+        "#...#..", //
+        ".#.#...", //
+        "..#....", //
+    ]);
+    let count = flood_fill(&mut pixmap.as_mut(), 2, 2, gray(128), 0);
+    assert_eq!(count, 5);
+    assert_eq!(
+        to_ascii(&pixmap),
+        [
+            "..#....", //
+            ".#o#...", //
+            "#ooo#..", //
+            ".#o#...", //
+            "..#....", //
+        ]
+    );
+}
+
+#[test]
+fn does_not_leak_diagonally() {
+    let mut pixmap = from_ascii(&[
+        ".#.", //
+        "#..", //
+        "...", //
+    ]);
+    let count = flood_fill(&mut pixmap.as_mut(), 0, 0, gray(128), 0);
+    assert_eq!(count, 1);
+}
+
+#[test]
+fn fills_around_obstacles() {
+    let mut pixmap = from_ascii(&[
+        "......", //
+        ".####.", //
+        ".#..#.", //
+        ".####.", //
+        "......", //
+    ]);
+    let count = flood_fill(&mut pixmap.as_mut(), 0, 0, gray(128), 0);
+    assert_eq!(count, 18);
+    assert_eq!(
+        to_ascii(&pixmap),
+        [
+            "oooooo", //
+            "o####o", //
+            "o#..#o", //
+            "o####o", //
+            "oooooo", //
+        ]
+    );
+}
+
+#[test]
+fn outside_does_nothing() {
+    let mut pixmap = from_ascii(&["..", ".."]);
+    assert_eq!(flood_fill(&mut pixmap.as_mut(), 2, 0, gray(128), 0), 0);
+    assert_eq!(
+        flood_fill(&mut pixmap.as_mut(), 0, u32::MAX, gray(128), 0),
+        0
+    );
+    assert_eq!(to_ascii(&pixmap), ["..", ".."]);
+}
+
+proptest! {
+    #[test]
+    fn fill_count_is_pixels_changed(mut pixmap in pixmap(12), x in 0_u32..12, y in 0_u32..12, tolerance: u8) {
+        // A fill color that's not in the pixmap, so that all filled pixels can be counted.
+        let fill = PremultipliedColorU8::from_rgba(1, 2, 3, 4).unwrap();
+        pixmap.pixels_mut().iter_mut().filter(|p| **p == fill).for_each(|p| *p = gray(0));
+
+        let count = flood_fill(&mut pixmap.as_mut(), x, y, fill, tolerance);
+        prop_assert_eq!(count, pixmap.pixels().iter().filter(|&&p| p == fill).count());
+        prop_assert_eq!(count == 0, x >= pixmap.width() || y >= pixmap.height());
+    }
+
+    #[test]
+    fn dissimilar_pixels_are_kept(
+        original in pixmap(12),
+        x: u32,
+        y: u32,
+        color in color(),
+        tolerance: u8,
+    ) {
+        let (x, y) = (x % original.width(), y % original.height());
+        let seed = original.pixel(x, y).unwrap();
+        let mut pixmap = original.clone();
+        flood_fill(&mut pixmap.as_mut(), x, y, color, tolerance);
+        for (after, before) in pixmap.pixels().iter().zip(original.pixels()) {
+            if !similar(*before, seed, tolerance) {
+                prop_assert_eq!(after, before);
+            }
+        }
+    }
+
+    #[test]
+    fn full_tolerance_fills_everything(mut pixmap in pixmap(12), color in color()) {
+        let count = flood_fill(&mut pixmap.as_mut(), 0, 0, color, 255);
+        prop_assert_eq!(count, (pixmap.width() * pixmap.height()) as usize);
+        prop_assert!(pixmap.pixels().iter().all(|&p| p == color));
+    }
+
+    #[test]
+    fn fills_uniform_pixmap(color in color(), width in 1_u32..32, height in 1_u32..32, x: u32, y: u32) {
+        let mut pixmap = Pixmap::new(width, height).unwrap();
+        let count = flood_fill(&mut pixmap.as_mut(), x % width, y % height, color, 0);
+        prop_assert_eq!(count, (width * height) as usize);
+    }
+}
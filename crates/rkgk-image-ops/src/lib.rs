@@ -1,14 +1,24 @@
-pub fn add(left: u64, right: u64) -> u64 {
-    left + right
-}
+//! Image operations on tiny-skia pixmaps, shared by the server and haku.
+//!
+//! All operations work on premultiplied RGBA pixels, which is what pixmaps store, and keep them
+//! valid: no color channel of a pixel ever ends up larger than its alpha. Operations which work on
+//! colors rather than light, such as colour matrices, unpremultiply pixels first, and premultiply
+//! the results back.
+//!
+//! The crate is compiled with optimizations even in debug builds, since the operations are much
+//! too slow to be usable otherwise.
+
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+pub mod blur;
+pub mod color;
+pub mod composite;
+pub mod fill;
+pub mod resample;
+
+pub use tiny_skia;
 
 #[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
-    }
-}
+mod strategy;
@@ -0,0 +1,158 @@
+//! Resizing pixmaps, and other operations that move pixels around.
+
+use tiny_skia::{IntSize, Pixmap, PixmapMut, PixmapRef};
+
+/// How pixels are sampled when resizing a pixmap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Filter {
+    /// Take the pixel nearest to the sampled point. Keeps hard edges, and is what you want for
+    /// pixel art.
+    #[default]
+    Nearest,
+    /// Interpolate linearly between the four pixels nearest to the sampled point.
+    Bilinear,
+}
+
+/// Resize the pixmap to the given size, returning a new pixmap. Returns `None` if either of the
+/// dimensions is zero.
+///
+/// The pixmap is sampled at the centers of the new pixels, so that its content stays centered no
+/// matter how it's scaled.
+pub fn resample(src: PixmapRef, width: u32, height: u32, filter: Filter) -> Option<Pixmap> {
+    let mut dst = Pixmap::new(width, height)?;
+    match filter {
+        Filter::Nearest => nearest(src, &mut dst),
+        Filter::Bilinear => bilinear(src, &mut dst),
+    }
+    Some(dst)
+}
+
+fn nearest(src: PixmapRef, dst: &mut Pixmap) {
+    let (src_width, src_height) = (u64::from(src.width()), u64::from(src.height()));
+    let (dst_width, dst_height) = (u64::from(dst.width()), u64::from(dst.height()));
+    let src_pixels = src.pixels();
+
+    let width = dst.width() as usize;
+    for (y, row) in dst.pixels_mut().chunks_exact_mut(width).enumerate() {
+        // (y + 0.5) * src_height / dst_height, floored, without going through floats.
+        let src_y = (2 * y as u64 + 1) * src_height / (2 * dst_height);
+        let src_row = &src_pixels[(src_y * src_width) as usize..][..src_width as usize];
+        for (x, pixel) in row.iter_mut().enumerate() {
+            let src_x = (2 * x as u64 + 1) * src_width / (2 * dst_width);
+            *pixel = src_row[src_x as usize];
+        }
+    }
+}
+
+fn bilinear(src: PixmapRef, dst: &mut Pixmap) {
+    let x_scale = src.width() as f32 / dst.width() as f32;
+    let y_scale = src.height() as f32 / dst.height() as f32;
+
+    let width = dst.width() as usize;
+    for (y, row) in dst.data_mut().chunks_exact_mut(width * 4).enumerate() {
+        let src_y = (y as f32 + 0.5) * y_scale - 0.5;
+        for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+            let src_x = (x as f32 + 0.5) * x_scale - 0.5;
+            pixel.copy_from_slice(&sample_bilinear(src, src_x, src_y));
+        }
+    }
+}
+
+/// Replace each block of `block_size` by `block_size` pixels with the average of its pixels.
+/// Blocks start at the top-left corner of the pixmap, and blocks at the right and bottom edges may
+/// be cut short.
+pub fn pixelate(pixmap: &mut PixmapMut, block_size: u32) {
+    if block_size <= 1 {
+        return;
+    }
+
+    let width = pixmap.width() as usize;
+    let height = pixmap.height() as usize;
+    let block_size = block_size as usize;
+    let data = pixmap.data_mut();
+    for block_y in (0..height).step_by(block_size) {
+        for block_x in (0..width).step_by(block_size) {
+            let rows = block_y..(block_y + block_size).min(height);
+            let columns = block_x * 4..(block_x + block_size).min(width) * 4;
+            let row_range = |y: usize| y * width * 4 + columns.start..y * width * 4 + columns.end;
+
+            let mut sum = [0_u64; 4];
+            for y in rows.clone() {
+                for pixel in data[row_range(y)].chunks_exact(4) {
+                    for (s, &c) in sum.iter_mut().zip(pixel) {
+                        *s += u64::from(c);
+                    }
+                }
+            }
+
+            let count = (rows.len() * columns.len() / 4) as u64;
+            let average = sum.map(|s| ((s + count / 2) / count) as u8);
+            for y in rows {
+                for pixel in data[row_range(y)].chunks_exact_mut(4) {
+                    pixel.copy_from_slice(&average);
+                }
+            }
+        }
+    }
+}
+
+/// Move pixels around: each pixel is replaced with the pixel at `offset` of its position away from
+/// it, interpolated bilinearly. Positions outside the pixmap are clamped to its edges.
+pub fn displace(pixmap: &mut PixmapMut, offset: impl Fn(u32, u32) -> (f32, f32)) {
+    let src = Pixmap::from_vec(
+        pixmap.data_mut().to_vec(),
+        IntSize::from_wh(pixmap.width(), pixmap.height()).unwrap(),
+    )
+    .unwrap();
+
+    let width = pixmap.width() as usize;
+    for (y, row) in pixmap.data_mut().chunks_exact_mut(width * 4).enumerate() {
+        for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+            let (dx, dy) = offset(x as u32, y as u32);
+            let sample = sample_bilinear(src.as_ref(), x as f32 + dx, y as f32 + dy);
+            pixel.copy_from_slice(&sample);
+        }
+    }
+}
+
+/// Sample the pixmap at a point, where the centers of pixels lie at whole coordinates, by
+/// interpolating between the four pixels nearest to it.
+fn sample_bilinear(src: PixmapRef, x: f32, y: f32) -> [u8; 4] {
+    let (x0, x1, tx) = sample_position(x, src.width());
+    let (y0, y1, ty) = sample_position(y, src.height());
+    let data = src.data();
+    let width = src.width() as usize;
+    let at = |x: usize, y: usize| &data[(y * width + x) * 4..][..4];
+    let (p00, p10, p01, p11) = (at(x0, y0), at(x1, y0), at(x0, y1), at(x1, y1));
+
+    let mut pixel = [0; 4];
+    for (c, out) in pixel.iter_mut().enumerate() {
+        let top = lerp(f32::from(p00[c]), f32::from(p10[c]), tx);
+        let bottom = lerp(f32::from(p01[c]), f32::from(p11[c]), tx);
+        // All channels are interpolated with the same weights, so none of them can end up larger
+        // than alpha.
+        *out = (lerp(top, bottom, ty) + 0.5) as u8;
+    }
+    pixel
+}
+
+/// Returns the two pixels around a position along one axis, and how far between them the
+/// position lies. Positions outside the pixmap are clamped to its edges, and NaN is treated as 0.
+fn sample_position(position: f32, len: u32) -> (usize, usize, f32) {
+    let max = (len - 1) as f32;
+    let position = if position.is_nan() {
+        0.0
+    } else {
+        position.clamp(0.0, max)
+    };
+    let first = position as usize;
+    let second = (first + 1).min(len as usize - 1);
+    (first, second, position - first as f32)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,157 @@
+use proptest::prelude::*;
+use tiny_skia::{Pixmap, PremultipliedColorU8};
+
+use crate::strategy::{color, is_premultiplied, pixmap};
+
+use super::{displace, pixelate, resample, Filter};
+
+fn filter() -> impl Strategy<Value = Filter> {
+    prop_oneof![Just(Filter::Nearest), Just(Filter::Bilinear)]
+}
+
+fn alpha(pixmap: &Pixmap) -> Vec<u8> {
+    pixmap.pixels().iter().map(|p| p.alpha()).collect()
+}
+
+fn gradient() -> Pixmap {
+    let mut pixmap = Pixmap::new(2, 1).unwrap();
+    pixmap.pixels_mut()[1] = PremultipliedColorU8::from_rgba(0, 0, 0, 255).unwrap();
+    pixmap
+}
+
+#[test]
+fn zero_size() {
+    let src = gradient();
+    assert!(resample(src.as_ref(), 0, 1, Filter::Nearest).is_none());
+    assert!(resample(src.as_ref(), 1, 0, Filter::Bilinear).is_none());
+}
+
+#[test]
+fn nearest_upscale() {
+    let src = gradient();
+    let dst = resample(src.as_ref(), 4, 1, Filter::Nearest).unwrap();
+    assert_eq!(alpha(&dst), [0, 0, 255, 255]);
+}
+
+#[test]
+fn bilinear_upscale() {
+    let src = gradient();
+    let dst = resample(src.as_ref(), 4, 1, Filter::Bilinear).unwrap();
+    // Sampled at 0, 0.25, 0.75, and 1, with samples outside the pixmap clamped to its edges.
+    assert_eq!(alpha(&dst), [0, 64, 191, 255]);
+}
+
+#[test]
+fn downscale() {
+    let mut src = Pixmap::new(4, 1).unwrap();
+    for (i, pixel) in src.pixels_mut().iter_mut().enumerate() {
+        let a = i as u8 * 60;
+        *pixel = PremultipliedColorU8::from_rgba(0, 0, 0, a).unwrap();
+    }
+    let nearest = resample(src.as_ref(), 2, 1, Filter::Nearest).unwrap();
+    assert_eq!(alpha(&nearest), [60, 180]);
+    let bilinear = resample(src.as_ref(), 2, 1, Filter::Bilinear).unwrap();
+    assert_eq!(alpha(&bilinear), [30, 150]);
+}
+
+#[test]
+fn pixelate_blocks() {
+    let mut pixmap = Pixmap::new(3, 1).unwrap();
+    for (i, pixel) in pixmap.pixels_mut().iter_mut().enumerate() {
+        let a = i as u8 * 100;
+        *pixel = PremultipliedColorU8::from_rgba(0, 0, 0, a).unwrap();
+    }
+    pixelate(&mut pixmap.as_mut(), 2);
+    assert_eq!(alpha(&pixmap), [50, 50, 200]);
+}
+
+#[test]
+fn displace_by_half_a_pixel() {
+    let mut pixmap = gradient();
+    displace(&mut pixmap.as_mut(), |_, _| (0.5, 0.0));
+    assert_eq!(alpha(&pixmap), [128, 255]);
+
+    let mut pixmap = gradient();
+    displace(&mut pixmap.as_mut(), |_, _| (f32::NAN, f32::INFINITY));
+    assert_eq!(alpha(&pixmap), [0, 0]);
+}
+
+proptest! {
+    #[test]
+    fn same_size_does_nothing(src in pixmap(12), filter in filter()) {
+        let dst = resample(src.as_ref(), src.width(), src.height(), filter).unwrap();
+        prop_assert_eq!(dst, src);
+    }
+
+    #[test]
+    fn uniform_stays_uniform(
+        color in color(),
+        size in (1_u32..12, 1_u32..12, 1_u32..24, 1_u32..24),
+        filter in filter(),
+    ) {
+        let (src_width, src_height, width, height) = size;
+        let mut src = Pixmap::new(src_width, src_height).unwrap();
+        src.pixels_mut().fill(color);
+        let dst = resample(src.as_ref(), width, height, filter).unwrap();
+        prop_assert!(dst.pixels().iter().all(|&p| p == color));
+    }
+
+    #[test]
+    fn stays_premultiplied(
+        src in pixmap(12),
+        width in 1_u32..24,
+        height in 1_u32..24,
+        filter in filter(),
+    ) {
+        let dst = resample(src.as_ref(), width, height, filter).unwrap();
+        prop_assert!(is_premultiplied(&dst));
+    }
+
+    #[test]
+    fn pixelate_stays_premultiplied(mut pixmap in pixmap(12), block_size in 0_u32..16) {
+        pixelate(&mut pixmap.as_mut(), block_size);
+        prop_assert!(is_premultiplied(&pixmap));
+    }
+
+    #[test]
+    fn pixelate_makes_uniform_blocks(mut pixmap in pixmap(12), block_size in 1_u32..6) {
+        pixelate(&mut pixmap.as_mut(), block_size);
+        for y in 0..pixmap.height() {
+            for x in 0..pixmap.width() {
+                let corner = pixmap.pixel(x / block_size * block_size, y / block_size * block_size);
+                prop_assert_eq!(pixmap.pixel(x, y), corner);
+            }
+        }
+    }
+
+    #[test]
+    fn displace_by_whole_pixels_moves_pixels(original in pixmap(12), dx in -3_i32..3, dy in -3_i32..3) {
+        let mut pixmap = original.clone();
+        displace(&mut pixmap.as_mut(), |_, _| (dx as f32, dy as f32));
+        for y in 0..pixmap.height() {
+            for x in 0..pixmap.width() {
+                let src_x = (x as i32 + dx).clamp(0, original.width() as i32 - 1) as u32;
+                let src_y = (y as i32 + dy).clamp(0, original.height() as i32 - 1) as u32;
+                prop_assert_eq!(pixmap.pixel(x, y), original.pixel(src_x, src_y));
+            }
+        }
+    }
+
+    #[test]
+    fn displace_stays_premultiplied(mut pixmap in pixmap(12), offsets in prop::array::uniform2(-20.0_f32..20.0)) {
+        let [ox, oy] = offsets;
+        displace(&mut pixmap.as_mut(), |x, y| (ox * (x as f32).sin(), oy * (y as f32).cos()));
+        prop_assert!(is_premultiplied(&pixmap));
+    }
+
+    #[test]
+    fn nearest_integer_upscale_repeats_pixels(src in pixmap(8), scale in 1_u32..4) {
+        let dst = resample(src.as_ref(), src.width() * scale, src.height() * scale, Filter::Nearest)
+            .unwrap();
+        for y in 0..dst.height() {
+            for x in 0..dst.width() {
+                prop_assert_eq!(dst.pixel(x, y), src.pixel(x / scale, y / scale));
+            }
+        }
+    }
+}
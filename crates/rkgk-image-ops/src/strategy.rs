@@ -0,0 +1,43 @@
+//! Strategies for generating pixmaps in property tests.
+
+use proptest::prelude::*;
+use tiny_skia::{Pixmap, PremultipliedColorU8};
+
+/// Arbitrary valid premultiplied colors.
+pub fn color() -> impl Strategy<Value = PremultipliedColorU8> + Clone {
+    any::<[u8; 4]>().prop_map(|[r, g, b, a]| {
+        PremultipliedColorU8::from_rgba(r.min(a), g.min(a), b.min(a), a).unwrap()
+    })
+}
+
+/// Arbitrary opaque colors.
+pub fn opaque_color() -> impl Strategy<Value = PremultipliedColorU8> + Clone {
+    any::<[u8; 3]>().prop_map(|[r, g, b]| PremultipliedColorU8::from_rgba(r, g, b, 255).unwrap())
+}
+
+/// Pixmaps of up to `max_size` pixels on each side, with pixels generated by `color`.
+pub fn pixmap_of<S>(max_size: u32, color: S) -> impl Strategy<Value = Pixmap>
+where
+    S: Strategy<Value = PremultipliedColorU8> + Clone,
+{
+    (1..=max_size, 1..=max_size).prop_flat_map(move |(width, height)| {
+        prop::collection::vec(color.clone(), (width * height) as usize).prop_map(move |pixels| {
+            let mut pixmap = Pixmap::new(width, height).unwrap();
+            pixmap.pixels_mut().copy_from_slice(&pixels);
+            pixmap
+        })
+    })
+}
+
+/// Pixmaps of up to `max_size` pixels on each side, with arbitrary pixels.
+pub fn pixmap(max_size: u32) -> impl Strategy<Value = Pixmap> {
+    pixmap_of(max_size, color())
+}
+
+/// Returns whether no color channel of any pixel is larger than its alpha.
+pub fn is_premultiplied(pixmap: &Pixmap) -> bool {
+    pixmap
+        .data()
+        .chunks_exact(4)
+        .all(|p| p[0] <= p[3] && p[1] <= p[3] && p[2] <= p[3])
+}
@@ -9,13 +9,14 @@ use axum::{
 };
 use serde::{Deserialize, Serialize};
 
-use crate::{config::Config, Databases};
+use crate::{config::Config, haku::cache::BrushCache, Databases};
 
 mod wall;
 
 pub struct Api {
     pub config: Config,
     pub dbs: Arc<Databases>,
+    pub brush_cache: Arc<BrushCache>,
 }
 
 pub fn router<S>(api: Arc<Api>) -> Router<S> {
@@ -1,5 +1,5 @@
 use std::{
-    collections::{HashSet, VecDeque},
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     sync::Arc,
 };
 
@@ -12,7 +12,15 @@ use axum::{
 };
 use base64::Engine;
 use eyre::{bail, Context, OptionExt};
-use haku::value::Value;
+use haku::{
+    render::{
+        accumulator::{Accumulator, Dither},
+        tiny_skia::{Color, Pixmap},
+        RenderCost,
+    },
+    value::Value,
+};
+use rkgk_image_ops::composite;
 use schema::{
     ChunkInfo, Error, LoginRequest, LoginResponse, Notify, Online, Request, Version, WallInfo,
 };
@@ -21,15 +29,16 @@ use tokio::{
     select,
     sync::{mpsc, oneshot},
 };
-use tracing::{error, info, info_span, instrument};
+use tracing::{debug, error, info, info_span, instrument, Level};
 
 use crate::{
-    haku::{Haku, Limits},
+    haku::{cache::BrushCache, BrushParams, Haku, Limits},
     login::{self, database::LoginStatus},
     schema::Vec2,
     wall::{
         self, auto_save::AutoSave, chunk_images::ChunkImages, chunk_iterator::ChunkIterator,
-        database::ChunkDataPair, ChunkPosition, JoinError, SessionHandle, UserInit, Wall, WallId,
+        database::ChunkDataPair, Blending, ChunkPosition, JoinError, SessionHandle, UserInit, Wall,
+        WallId,
     },
 };
 
@@ -157,6 +166,7 @@ async fn fallible_websocket(api: Arc<Api>, ws: &mut WebSocket) -> eyre::Result<(
             cursor: online.cursor,
             init: UserInit {
                 brush: online.brush,
+                params: online.params,
             },
         })
     }
@@ -164,12 +174,14 @@ async fn fallible_websocket(api: Arc<Api>, ws: &mut WebSocket) -> eyre::Result<(
 
     ws.send(to_message(&LoginResponse::LoggedIn {
         wall: wall_id,
-        wall_info: WallInfo {
+        wall_info: Box::new(WallInfo {
             chunk_size: open_wall.wall.settings().chunk_size,
             paint_area: open_wall.wall.settings().paint_area,
+            blending: api.config.blending,
             online: users_online,
             haku_limits: api.config.haku.clone(),
-        },
+            haku_modules: api.config.haku_modules.clone(),
+        }),
         session_id: session_handle.session_id,
     }))
     .await?;
@@ -195,8 +207,8 @@ async fn fallible_websocket(api: Arc<Api>, ws: &mut WebSocket) -> eyre::Result<(
         open_wall.chunk_images,
         open_wall.auto_save,
         session_handle,
-        api.config.haku.clone(),
-        login_request.init.brush,
+        &api,
+        login_request.init,
     )
     .await?
     .event_loop(ws)
@@ -222,6 +234,7 @@ struct SessionLoop {
 enum RenderCommand {
     SetBrush {
         brush: String,
+        params: BrushParams,
     },
 
     Plot {
@@ -237,8 +250,8 @@ impl SessionLoop {
         chunk_images: Arc<ChunkImages>,
         auto_save: Arc<AutoSave>,
         handle: SessionHandle,
-        limits: Limits,
-        brush: String,
+        api: &Api,
+        init: UserInit,
     ) -> eyre::Result<Self> {
         // Limit how many commands may come in _pretty darn hard_ because these can be really
         // CPU-intensive.
@@ -246,7 +259,10 @@ impl SessionLoop {
         let (render_commands_tx, render_commands_rx) = mpsc::channel(1);
 
         render_commands_tx
-            .send(RenderCommand::SetBrush { brush })
+            .send(RenderCommand::SetBrush {
+                brush: init.brush,
+                params: init.params,
+            })
             .await
             .unwrap();
 
@@ -256,11 +272,22 @@ impl SessionLoop {
             .name(String::from("haku render thread"))
             .spawn({
                 let wall = Arc::clone(&wall);
+                let limits = api.config.haku.clone();
+                let host_modules = api.config.haku_modules.clone();
+                let brush_cache = Arc::clone(&api.brush_cache);
+                let blending = api.config.blending;
                 move || {
                     let _span =
                         info_span!("render_thread", %wall_id, session_id = ?handle.session_id)
                             .entered();
-                    Self::render_thread(wall, limits, render_commands_rx)
+                    Self::render_thread(
+                        wall,
+                        limits,
+                        host_modules,
+                        brush_cache,
+                        blending,
+                        render_commands_rx,
+                    )
                 }
             })
             .context("could not spawn render thread")?;
@@ -312,12 +339,13 @@ impl SessionLoop {
                     | wall::EventKind::Leave
                     | wall::EventKind::Cursor { .. } => (),
 
-                    wall::EventKind::SetBrush { brush } => {
+                    wall::EventKind::SetBrush { brush, params } => {
                         // SetBrush is not dropped because it is a very important event.
                         _ = self
                             .render_commands_tx
                             .send(RenderCommand::SetBrush {
                                 brush: brush.clone(),
+                                params: params.clone(),
                             })
                             .await;
                     }
@@ -435,26 +463,59 @@ impl SessionLoop {
         Ok(())
     }
 
-    fn render_thread(wall: Arc<Wall>, limits: Limits, mut commands: mpsc::Receiver<RenderCommand>) {
-        let mut haku = Haku::new(limits);
+    fn render_thread(
+        wall: Arc<Wall>,
+        limits: Limits,
+        host_modules: BTreeMap<String, String>,
+        brush_cache: Arc<BrushCache>,
+        blending: Blending,
+        mut commands: mpsc::Receiver<RenderCommand>,
+    ) {
+        let mut haku = Haku::new(limits, host_modules, brush_cache);
         let mut brush_ok = false;
 
         while let Some(command) = commands.blocking_recv() {
             match command {
-                RenderCommand::SetBrush { brush } => {
-                    brush_ok = haku.set_brush(&brush).is_ok();
+                RenderCommand::SetBrush { brush, params } => {
+                    brush_ok = haku.set_brush(&brush, params).is_ok();
+                    // Profiling evaluates the brush once more, so only do it if anyone's going to
+                    // read the result.
+                    if brush_ok && tracing::enabled!(Level::DEBUG) {
+                        let (_, profile) = haku.profile_brush();
+                        haku.reset_vm();
+                        debug!(
+                            fuel_used = profile.fuel_used,
+                            fuel_limit = profile.fuel_limit,
+                            peak_memory = profile.peak_memory,
+                            memory_limit = profile.memory_limit,
+                            peak_stack_depth = profile.peak_stack_depth,
+                            peak_call_depth = profile.peak_call_depth,
+                            "brush profile"
+                        );
+                    }
                 }
 
                 RenderCommand::Plot { points, done } => {
                     if brush_ok {
-                        if let Ok(value) = haku.eval_brush() {
-                            for point in points {
-                                // Ignore the result. It's better if we render _something_ rather
-                                // than nothing.
-                                _ = draw_to_chunks(&wall, &haku, value, point);
-                            }
-                            haku.reset_vm();
+                        if haku.begin_stroke().is_ok() {
+                            let cost = if blending.high_precision {
+                                let dither = if blending.dither {
+                                    Dither::Ordered
+                                } else {
+                                    Dither::None
+                                };
+                                draw_accumulated(&wall, &mut haku, &points, dither)
+                            } else {
+                                draw_direct(&wall, &mut haku, &points)
+                            };
+                            debug!(
+                                points = points.len(),
+                                pixels = cost.pixels,
+                                path_segments = cost.path_segments,
+                                "brush render cost"
+                            );
                         }
+                        haku.reset_vm();
                     }
                     _ = done.send(());
                 }
@@ -482,8 +543,164 @@ fn chunks_to_modify(wall: &Wall, points: &[Vec2]) -> HashSet<ChunkPosition> {
     chunks
 }
 
+/// Draw the brush at each point onto chunks directly.
+fn draw_direct(wall: &Wall, haku: &mut Haku, points: &[Vec2]) -> RenderCost {
+    let mut cost = RenderCost::default();
+    for &point in points {
+        // Ignore errors. It's better if we render _something_ rather than nothing.
+        let Ok(value) = haku.eval_point(point) else {
+            continue;
+        };
+        let result = if haku.has_filters(value) {
+            draw_filtered(wall, haku, value, point)
+        } else {
+            draw_to_chunks(wall, haku, value, point)
+        };
+        if let Ok(point_cost) = result {
+            cost += point_cost;
+        }
+    }
+    cost
+}
+
+/// Draw the brush at each point into high-precision accumulators, one per chunk, and composite
+/// them onto the chunks once all points are drawn.
+fn draw_accumulated(wall: &Wall, haku: &mut Haku, points: &[Vec2], dither: Dither) -> RenderCost {
+    let settings = wall.settings();
+    let chunk_size = settings.chunk_size;
+    let paint_area = settings.paint_area;
+    let half_paint_area = (paint_area / 2) as i32;
+
+    let mut splat = Pixmap::new(paint_area, paint_area).unwrap();
+    let mut accumulators: HashMap<ChunkPosition, Accumulator> = HashMap::new();
+    let mut cost = RenderCost::default();
+    for &point in points {
+        let Ok(value) = haku.eval_point(point) else {
+            continue;
+        };
+
+        if haku.has_filters(value) {
+            // Filters have to see everything drawn before them, so whatever was accumulated so
+            // far has to land on the wall first.
+            composite_accumulators(wall, &mut accumulators, dither);
+            if let Ok(point_cost) = draw_filtered(wall, haku, value, point) {
+                cost += point_cost;
+            }
+            continue;
+        }
+
+        // The splat covers the paint area, with the point in its center.
+        splat.fill(Color::TRANSPARENT);
+        let translation = Vec2::new(half_paint_area as f32, half_paint_area as f32);
+        // Ignore errors, like with direct drawing. Whatever was rendered before the error
+        // is still drawn.
+        if let Ok(point_cost) = haku.render_value(&mut splat, value, translation) {
+            cost += point_cost;
+        }
+
+        let left = point.x.floor() as i32 - half_paint_area;
+        let top = point.y.floor() as i32 - half_paint_area;
+        let left_chunk = settings.chunk_at_1d(left as f32);
+        let top_chunk = settings.chunk_at_1d(top as f32);
+        let right_chunk = settings.chunk_at_1d_ceil((left + paint_area as i32) as f32);
+        let bottom_chunk = settings.chunk_at_1d_ceil((top + paint_area as i32) as f32);
+        for chunk_y in top_chunk..bottom_chunk {
+            for chunk_x in left_chunk..right_chunk {
+                accumulators
+                    .entry(ChunkPosition::new(chunk_x, chunk_y))
+                    .or_insert_with(|| Accumulator::new(chunk_size, chunk_size))
+                    .blend(
+                        &splat,
+                        left - chunk_x * chunk_size as i32,
+                        top - chunk_y * chunk_size as i32,
+                    );
+            }
+        }
+    }
+
+    composite_accumulators(wall, &mut accumulators, dither);
+
+    cost
+}
+
+/// Composite accumulators onto their chunks, leaving no accumulators behind.
+fn composite_accumulators(
+    wall: &Wall,
+    accumulators: &mut HashMap<ChunkPosition, Accumulator>,
+    dither: Dither,
+) {
+    for (position, accumulator) in accumulators.drain() {
+        let chunk_ref = wall.get_or_create_chunk(position);
+        let mut chunk = chunk_ref.blocking_lock();
+        accumulator.composite(&mut chunk.pixmap, dither);
+    }
+}
+
+/// Draw a value which has filters in it. Filters change what's already on the wall, and may reach
+/// across chunk boundaries, so rather than being drawn onto each chunk separately, the value is
+/// drawn onto a copy of the paint area gathered from all chunks under it, which is then copied
+/// back onto the chunks.
+fn draw_filtered(wall: &Wall, haku: &Haku, value: Value, center: Vec2) -> eyre::Result<RenderCost> {
+    let settings = wall.settings();
+    let chunk_size = settings.chunk_size as i32;
+    let paint_area = settings.paint_area;
+    let half_paint_area = (paint_area / 2) as i32;
+
+    let left = center.x.floor() as i32 - half_paint_area;
+    let top = center.y.floor() as i32 - half_paint_area;
+    let left_chunk = settings.chunk_at_1d(left as f32);
+    let top_chunk = settings.chunk_at_1d(top as f32);
+    let right_chunk = settings.chunk_at_1d_ceil((left + paint_area as i32) as f32);
+    let bottom_chunk = settings.chunk_at_1d_ceil((top + paint_area as i32) as f32);
+
+    // All chunks are kept locked until the paint area is copied back, so that nothing drawn onto
+    // them in the meantime is lost. They're always locked in the same order, so drawing filters
+    // from multiple threads at once can't deadlock.
+    let mut chunks = Vec::new();
+    for chunk_y in top_chunk..bottom_chunk {
+        for chunk_x in left_chunk..right_chunk {
+            let chunk_ref = wall.get_or_create_chunk(ChunkPosition::new(chunk_x, chunk_y));
+            let x = chunk_x * chunk_size - left;
+            let y = chunk_y * chunk_size - top;
+            chunks.push((x, y, chunk_ref.blocking_lock_owned()));
+        }
+    }
+
+    let mut paint_area_pixmap = Pixmap::new(paint_area, paint_area).unwrap();
+    for (x, y, chunk) in &chunks {
+        composite::copy(
+            &mut paint_area_pixmap.as_mut(),
+            chunk.pixmap.as_ref(),
+            *x,
+            *y,
+        );
+    }
+
+    let translation = Vec2::new(half_paint_area as f32, half_paint_area as f32);
+    // Whatever was rendered before an error is still drawn, like with the other drawing modes.
+    let result = haku.render_value(&mut paint_area_pixmap, value, translation);
+
+    for (x, y, chunk) in &mut chunks {
+        composite::copy(
+            &mut chunk.pixmap.as_mut(),
+            paint_area_pixmap.as_ref(),
+            -*x,
+            -*y,
+        );
+    }
+
+    result
+}
+
 #[instrument(skip(wall, haku, value))]
-fn draw_to_chunks(wall: &Wall, haku: &Haku, value: Value, center: Vec2) -> eyre::Result<()> {
+/// Draw a value evaluated from the brush onto all chunks in the paint area around `center`, and
+/// return the total cost of rendering it.
+fn draw_to_chunks(
+    wall: &Wall,
+    haku: &Haku,
+    value: Value,
+    center: Vec2,
+) -> eyre::Result<RenderCost> {
     let settings = wall.settings();
 
     let chunk_size = settings.chunk_size as f32;
@@ -496,15 +713,16 @@ fn draw_to_chunks(wall: &Wall, haku: &Haku, value: Value, center: Vec2) -> eyre:
     let top_chunk = settings.chunk_at_1d(top);
     let right_chunk = settings.chunk_at_1d_ceil(left + paint_area);
     let bottom_chunk = settings.chunk_at_1d_ceil(top + paint_area);
+    let mut cost = RenderCost::default();
     for chunk_y in top_chunk..bottom_chunk {
         for chunk_x in left_chunk..right_chunk {
             let x = f32::floor(-chunk_x as f32 * chunk_size + center.x);
             let y = f32::floor(-chunk_y as f32 * chunk_size + center.y);
             let chunk_ref = wall.get_or_create_chunk(ChunkPosition::new(chunk_x, chunk_y));
             let mut chunk = chunk_ref.blocking_lock();
-            haku.render_value(&mut chunk.pixmap, value, Vec2 { x, y })?;
+            cost += haku.render_value(&mut chunk.pixmap, value, Vec2 { x, y })?;
         }
     }
 
-    Ok(())
+    Ok(cost)
 }
@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 
 use crate::{
@@ -18,7 +20,7 @@ pub struct Error {
     pub error: String,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct LoginRequest {
     pub user: UserId,
@@ -42,7 +44,10 @@ pub struct Online {
 pub struct WallInfo {
     pub chunk_size: u32,
     pub paint_area: u32,
+    pub blending: crate::wall::Blending,
     pub haku_limits: crate::haku::Limits,
+    /// Modules supplied by the server, which clients must make available to brushes as well.
+    pub haku_modules: BTreeMap<String, String>,
     pub online: Vec<Online>,
 }
 
@@ -55,7 +60,7 @@ pub struct WallInfo {
 pub enum LoginResponse {
     LoggedIn {
         wall: WallId,
-        wall_info: WallInfo,
+        wall_info: Box<WallInfo>,
         session_id: SessionId,
     },
     UserDoesNotExist,
@@ -1,4 +1,7 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
 
 use serde::{Deserialize, Serialize};
 
@@ -9,6 +12,13 @@ pub struct Config {
     pub build: BuildConfig,
     pub wall_broker: wall::broker::Settings,
     pub haku: crate::haku::Limits,
+    /// Modules brushes may import, in addition to the ones bundled with haku, keyed by name.
+    #[serde(default)]
+    pub haku_modules: BTreeMap<String, String>,
+    #[serde(default)]
+    pub brush_cache: crate::haku::cache::Settings,
+    #[serde(default)]
+    pub blending: wall::Blending,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
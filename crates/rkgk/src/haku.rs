@@ -3,25 +3,37 @@
 // TODO: This should be used as the basis for haku-wasm as well as haku tests in the future to
 // avoid duplicating code.
 
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use cache::{BrushCache, CompiledBrush};
 use eyre::{bail, Context, OptionExt};
 use haku::{
     ast::Ast,
-    bytecode::{Chunk, Defs, DefsImage},
-    compiler::{ClosureSpec, Compiler, Source},
+    bytecode::{Chunk, Defs, DefsImage, Namespace},
+    compiler::{incremental::DefCache, ClosureSpec, Compiler, Source},
     lexer::{lex, Lexer},
+    module::{self, Loader, Module, ModuleLimits, BUNDLED, PRELUDE},
+    param::{self, Param, ParamValue},
     parser::{self, Parser, ParserLimits},
-    render::{tiny_skia::Pixmap, Renderer, RendererLimits},
-    source::SourceCode,
+    render::{self, tiny_skia::Pixmap, RenderCost, Renderer, RendererLimits},
+    snapshot::{read_chunk, read_defs, write_chunk, write_defs},
+    source::{SourceCode, Span},
+    stroke::{self, EvalMode, Inputs},
     system::{ChunkId, System, SystemImage},
     token::Lexis,
-    value::{Closure, Ref, Value},
-    vm::{Vm, VmImage, VmLimits},
+    value::{self, Ref, Rgba, Value},
+    vm::{profile::Profile, Vm, VmImage, VmLimits},
 };
 use serde::{Deserialize, Serialize};
-use tracing::{info, instrument, Level};
+use tracing::{info, instrument, warn, Level};
 
 use crate::schema::Vec2;
 
+pub mod cache;
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 // NOTE: For serialization, this struct does _not_ have serde(rename_all = "camelCase") on it,
 // because we do some dynamic typing magic over on the JavaScript side to automatically call all
@@ -41,6 +53,65 @@ pub struct Limits {
     pub memory: usize,
     pub pixmap_stack_capacity: usize,
     pub transform_stack_capacity: usize,
+    #[serde(default = "default_max_pixels")]
+    pub max_pixels: usize,
+    #[serde(default = "default_max_path_segments")]
+    pub max_path_segments: usize,
+    #[serde(default)]
+    pub strict_math: bool,
+}
+
+// The render budget was added after the other limits, so configs written before it existed must
+// still load. These defaults match the ones in haku-wasm.
+
+fn default_max_pixels() -> usize {
+    4 * 1024 * 1024
+}
+
+fn default_max_path_segments() -> usize {
+    8192
+}
+
+impl Limits {
+    fn module_limits(&self) -> ModuleLimits {
+        ModuleLimits {
+            max_source_code_len: self.max_source_code_len,
+            max_tokens: self.max_tokens,
+            max_parser_events: self.max_parser_events,
+            ast_capacity: self.ast_capacity,
+            chunk_capacity: self.chunk_capacity,
+        }
+    }
+}
+
+/// The value of a brush parameter, as sent by clients.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum BrushParamValue {
+    Boolean(bool),
+    Number(f32),
+    Rgba([f32; 4]),
+}
+
+impl From<BrushParamValue> for ParamValue {
+    fn from(value: BrushParamValue) -> Self {
+        match value {
+            BrushParamValue::Boolean(b) => ParamValue::Boolean(b),
+            BrushParamValue::Number(x) => ParamValue::Number(x),
+            BrushParamValue::Rgba([r, g, b, a]) => ParamValue::Rgba(Rgba { r, g, b, a }),
+        }
+    }
+}
+
+/// Values of brush parameters set by the user, by parameter name.
+pub type BrushParams = HashMap<String, BrushParamValue>;
+
+/// State of a stroke started with [`Haku::begin_stroke`].
+enum Stroke {
+    /// The brush doesn't vary between points, so it evaluates to the same value at all of them.
+    Constant(Value),
+    /// The brush varies between points, so its per-point part is evaluated from this image.
+    PerPoint(VmImage),
 }
 
 pub struct Haku {
@@ -52,15 +123,43 @@ pub struct Haku {
     defs_image: DefsImage,
     vm: Vm,
     vm_image: VmImage,
+    prelude: Namespace,
+    inputs: Inputs,
+    /// Defs compiled from the last brush, so that recompiling it after an edit is fast.
+    def_cache: DefCache,
+    /// Modules supplied by the server, importable by brushes alongside the bundled ones.
+    host_modules: BTreeMap<String, String>,
+    brush_cache: Arc<BrushCache>,
+    /// Hasher with the limits and host modules already fed into it, for computing brush cache
+    /// keys.
+    brush_cache_hasher: blake3::Hasher,
 
     brush: Option<(ChunkId, ClosureSpec)>,
+    /// Modules imported by the brush.
+    modules: Vec<Module>,
+    params: Vec<Param>,
+    param_values: BrushParams,
+    stroke: Option<Stroke>,
 }
 
 impl Haku {
-    pub fn new(limits: Limits) -> Self {
-        let system = System::new(limits.max_chunks);
-        let defs = Defs::new(limits.max_defs);
-        let vm = Vm::new(
+    pub fn new(
+        limits: Limits,
+        host_modules: BTreeMap<String, String>,
+        brush_cache: Arc<BrushCache>,
+    ) -> Self {
+        let mut system = System::new(limits.max_chunks);
+        let mut defs = Defs::new(limits.max_defs);
+
+        // The prelude is the same for all brushes, so it's compiled and run only once, before the
+        // images are taken.
+        let prelude = Loader::new(BUNDLED, limits.module_limits(), None)
+            .load(&mut system, &mut defs, PRELUDE, Span::new(0, 0))
+            .expect("the prelude must compile");
+        let inputs =
+            Inputs::declare(&mut defs, prelude.namespace).expect("the prelude must declare inputs");
+
+        let mut vm = Vm::new(
             &defs,
             &VmLimits {
                 stack_capacity: limits.stack_capacity,
@@ -68,13 +167,24 @@ impl Haku {
                 ref_capacity: limits.ref_capacity,
                 fuel: limits.fuel,
                 memory: limits.memory,
+                strict_math: limits.strict_math,
             },
         );
+        module::init(&mut vm, &system, &[prelude]).expect("the prelude must run");
+        vm.set_fuel(limits.fuel);
 
         let system_image = system.image();
         let defs_image = defs.image();
         let vm_image = vm.image();
 
+        let mut brush_cache_hasher = blake3::Hasher::new();
+        brush_cache_hasher
+            .update(&serde_json::to_vec(&limits).expect("limits must be serializable to JSON"))
+            .update(
+                &serde_json::to_vec(&host_modules)
+                    .expect("host modules must be serializable to JSON"),
+            );
+
         Self {
             limits,
             system,
@@ -83,7 +193,17 @@ impl Haku {
             defs_image,
             vm,
             vm_image,
+            prelude: prelude.namespace,
+            inputs,
+            def_cache: DefCache::new(),
+            host_modules,
+            brush_cache,
+            brush_cache_hasher,
             brush: None,
+            modules: Vec::new(),
+            params: Vec::new(),
+            param_values: BrushParams::new(),
+            stroke: None,
         }
     }
 
@@ -92,11 +212,33 @@ impl Haku {
         self.defs.restore_image(&self.defs_image);
     }
 
-    #[instrument(skip(self, code), err)]
-    pub fn set_brush(&mut self, code: &str) -> eyre::Result<()> {
-        info!(?code);
+    #[instrument(skip(self, code, params), err)]
+    pub fn set_brush(&mut self, code: &str, params: BrushParams) -> eyre::Result<()> {
+        info!(?code, ?params);
 
         self.reset();
+        self.brush = None;
+        self.stroke = None;
+        self.params.clear();
+        self.param_values = params;
+
+        let key = self
+            .brush_cache_hasher
+            .clone()
+            .update(code.as_bytes())
+            .finalize();
+        if let Some(compiled) = self.brush_cache.get(&key) {
+            match self.load_compiled_brush(&compiled) {
+                Ok(()) => {
+                    info!("brush loaded from cache");
+                    return Ok(());
+                }
+                Err(error) => {
+                    warn!(%error, "cached brush could not be loaded, compiling it again");
+                    self.reset();
+                }
+            }
+        }
 
         let code = SourceCode::limited_len(code, self.limits.max_source_code_len)
             .ok_or_eyre("source code is too long")?;
@@ -114,6 +256,12 @@ impl Haku {
         let mut ast = Ast::new(self.limits.ast_capacity);
         let (root, parser_diagnostics) = parser.into_ast(&mut ast)?;
 
+        let resolver = (BUNDLED, &self.host_modules);
+        let mut loader = Loader::new(&resolver, self.limits.module_limits(), Some(self.prelude));
+        let import_diagnostics =
+            loader.import_all(&mut self.system, &mut self.defs, code, &ast, root);
+        self.modules = loader.modules().collect();
+
         let src = Source {
             code,
             ast: &ast,
@@ -123,37 +271,160 @@ impl Haku {
         let mut chunk = Chunk::new(self.limits.chunk_capacity)
             .expect("chunk capacity must be representable as a 16-bit number");
         let mut compiler = Compiler::new(&mut self.defs, &mut chunk);
+        compiler.def_cache = Some(&mut self.def_cache);
         haku::compiler::compile_expr(&mut compiler, &src, root)
             .context("failed to compile the chunk")?;
         let closure_spec = compiler.closure_spec();
+        let params = compiler.params;
 
-        if !lexer.diagnostics.is_empty()
-            || !parser_diagnostics.is_empty()
-            || !compiler.diagnostics.is_empty()
+        if lexer
+            .diagnostics
+            .iter()
+            .chain(&parser_diagnostics)
+            .chain(&import_diagnostics)
+            .chain(&compiler.diagnostics)
+            .any(|diagnostic| diagnostic.is_error())
         {
-            info!(?lexer.diagnostics, ?parser_diagnostics, ?compiler.diagnostics, "diagnostics were emitted");
+            info!(?lexer.diagnostics, ?parser_diagnostics, ?import_diagnostics, ?compiler.diagnostics, "diagnostics were emitted");
             bail!("diagnostics were emitted");
         }
 
-        let chunk_id = self.system.add_chunk(chunk).context("too many chunks")?;
+        let chunk_id = self
+            .system
+            .add_chunk(chunk, &self.defs, closure_spec)
+            .context("failed to add the chunk")?;
         self.brush = Some((chunk_id, closure_spec));
+        self.params = params;
+        self.brush_cache.insert(key, self.compiled_brush());
 
         info!("brush set successfully");
 
         Ok(())
     }
 
+    /// Take the currently set brush, along with the modules it imports, for caching.
+    fn compiled_brush(&self) -> CompiledBrush {
+        let brush = self.brush.expect("brush must be set");
+
+        let mut chunks: Vec<_> = self
+            .modules
+            .iter()
+            .map(|module| (module.chunk_id, module.closure_spec))
+            .chain([brush])
+            .collect();
+        chunks.sort_by_key(|&(chunk_id, _)| chunk_id);
+
+        let mut snapshot = Vec::new();
+        write_defs(&mut snapshot, &self.defs);
+        for (chunk_id, closure_spec) in chunks {
+            write_chunk(&mut snapshot, self.system.chunk(chunk_id), closure_spec);
+        }
+
+        CompiledBrush {
+            snapshot,
+            modules: self.modules.clone(),
+            brush,
+            params: self.params.clone(),
+        }
+    }
+
+    /// Set a brush from the cache. Its chunks are added to the system in the same order as when
+    /// it was compiled, so that they end up with the same IDs.
+    fn load_compiled_brush(&mut self, compiled: &CompiledBrush) -> eyre::Result<()> {
+        let mut input = &compiled.snapshot[..];
+        self.defs = read_defs(&mut input, self.limits.max_defs)?;
+        let mut last_chunk_id = None;
+        while !input.is_empty() {
+            let (chunk, closure_spec) = read_chunk(&mut input, self.limits.chunk_capacity)?;
+            last_chunk_id = Some(self.system.add_chunk(chunk, &self.defs, closure_spec)?);
+        }
+        // The brush is compiled after all of its imports, so its chunk must come out last.
+        if last_chunk_id != Some(compiled.brush.0) {
+            bail!("brush chunk ended up with a different ID than when it was compiled");
+        }
+
+        self.modules = compiled.modules.clone();
+        self.brush = Some(compiled.brush);
+        self.params = compiled.params.clone();
+        Ok(())
+    }
+
+    /// Evaluate all of the brush at once. Stroke inputs keep their default values.
     #[instrument(skip(self), err(level = Level::INFO))]
     pub fn eval_brush(&mut self) -> eyre::Result<Value> {
+        self.vm.apply_defs(&self.defs);
+        self.apply_params()?;
+        self.eval_toplevel(EvalMode::Full)
+    }
+
+    /// Evaluate the stroke-constant part of the brush, so that it can then be evaluated at each
+    /// point of a stroke with [`Haku::eval_point`].
+    #[instrument(skip(self), err(level = Level::INFO))]
+    pub fn begin_stroke(&mut self) -> eyre::Result<()> {
+        self.stroke = None;
+        self.vm.apply_defs(&self.defs);
+        self.apply_params()?;
+        let value = self.eval_toplevel(EvalMode::Stroke)?;
+
+        // If the brush itself doesn't vary, it doesn't use anything varying from its modules
+        // either, so they don't need to be evaluated per point.
+        let varies_per_point = self.brush.is_some_and(|(_, spec)| spec.varies_per_point());
+        self.stroke = Some(if varies_per_point {
+            Stroke::PerPoint(self.vm.image())
+        } else {
+            Stroke::Constant(value)
+        });
+        Ok(())
+    }
+
+    /// Evaluate the brush at a point of the stroke started with [`Haku::begin_stroke`].
+    /// The returned value is valid until the next call.
+    #[instrument(skip(self), err(level = Level::INFO))]
+    pub fn eval_point(&mut self, position: Vec2) -> eyre::Result<Value> {
+        match self.stroke.as_ref().ok_or_eyre("stroke was not begun")? {
+            &Stroke::Constant(value) => Ok(value),
+            Stroke::PerPoint(image) => {
+                self.vm.restore_image(image);
+                self.inputs
+                    .apply(
+                        &mut self.vm,
+                        value::Vec2 {
+                            x: position.x,
+                            y: position.y,
+                        },
+                    )
+                    .context("an exception occurred while setting stroke inputs")?;
+                self.eval_toplevel(EvalMode::Point)
+            }
+        }
+    }
+
+    fn apply_params(&mut self) -> eyre::Result<()> {
+        param::apply(&mut self.vm, &self.params, |param| {
+            self.param_values
+                .get(&param.name)
+                .map(|&value| value.into())
+        })
+        .context("an exception occurred while setting brush parameters")
+    }
+
+    /// Run the given part of the imported modules and the brush. Defs and parameters must already
+    /// be applied to the VM.
+    fn eval_toplevel(&mut self, mode: EvalMode) -> eyre::Result<Value> {
         let (chunk_id, closure_spec) = self
             .brush
             .ok_or_eyre("brush is not compiled and ready to be used")?;
 
-        self.vm.apply_defs(&self.defs);
+        self.vm.set_eval_mode(mode);
+        module::init(&mut self.vm, &self.system, &self.modules)
+            .context("an exception occurred while initializing imported modules")?;
 
+        let Some(closure) = stroke::toplevel(chunk_id, closure_spec, mode) else {
+            return Ok(Value::Nil);
+        };
         let closure_id = self
             .vm
-            .create_ref(Ref::Closure(Closure::chunk(chunk_id, closure_spec)))
+            .create_ref(Ref::Closure(closure))
             .context("not enough ref slots to create initial closure")?;
 
         let scribble = self
@@ -164,24 +435,47 @@ impl Haku {
         Ok(scribble)
     }
 
+    /// Evaluate the brush like [`Haku::eval_brush`], while recording the resources it uses.
+    /// The profile is returned even if evaluation fails, so that it can tell which part of the
+    /// brush ran out of its limits.
+    pub fn profile_brush(&mut self) -> (eyre::Result<Value>, Profile) {
+        self.vm.start_profiling();
+        let result = self.eval_brush();
+        let profile = self
+            .vm
+            .finish_profiling()
+            .expect("the VM must still be profiling");
+        (result, profile)
+    }
+
+    /// Render a value evaluated from the brush, and return how much work it took.
     #[instrument(skip(self, pixmap, value, translation), err(level = Level::INFO))]
     pub fn render_value(
         &self,
         pixmap: &mut Pixmap,
         value: Value,
         translation: Vec2,
-    ) -> eyre::Result<()> {
+    ) -> eyre::Result<RenderCost> {
         let mut renderer = Renderer::new(
             pixmap,
             &RendererLimits {
                 pixmap_stack_capacity: self.limits.pixmap_stack_capacity,
                 transform_stack_capacity: self.limits.transform_stack_capacity,
+                max_pixels: self.limits.max_pixels,
+                max_path_segments: self.limits.max_path_segments,
             },
         );
         renderer.translate(translation.x, translation.y);
         let result = renderer.render(&self.vm, value);
 
-        result.context("an exception occurred while rendering the scribble")
+        result.context("an exception occurred while rendering the scribble")?;
+        Ok(renderer.cost())
+    }
+
+    /// Returns whether a value evaluated from the brush has any filters in it. See
+    /// [`haku::render::has_filters`].
+    pub fn has_filters(&self, value: Value) -> bool {
+        render::has_filters(&self.vm, value)
     }
 
     pub fn reset_vm(&mut self) {
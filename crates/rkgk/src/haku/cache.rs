@@ -0,0 +1,118 @@
+//! Cache of compiled brushes, shared between all sessions on all walls.
+//!
+//! Many users tend to draw with the same few brushes, so there's no need to lex, parse, and
+//! compile each of them again every time someone picks it up.
+
+use std::sync::{Arc, Mutex};
+
+use haku::{compiler::ClosureSpec, module::Module, param::Param, system::ChunkId};
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Settings {
+    /// Maximum total size of the cached brushes, in bytes.
+    pub max_size: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            max_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// A brush compiled by [`Haku::set_brush`][super::Haku::set_brush].
+#[derive(Debug)]
+pub struct CompiledBrush {
+    /// Snapshot of the defs, followed by snapshots of the chunks of the brush and all the modules
+    /// it imports, in the order they were added to the system.
+    pub snapshot: Vec<u8>,
+    pub modules: Vec<Module>,
+    pub brush: (ChunkId, ClosureSpec),
+    pub params: Vec<Param>,
+}
+
+impl CompiledBrush {
+    /// Approximate amount of memory taken up by the brush.
+    fn size(&self) -> usize {
+        self.snapshot.len()
+            + self.modules.len() * size_of::<Module>()
+            + self
+                .params
+                .iter()
+                .map(|param| size_of::<Param>() + param.name.len())
+                .sum::<usize>()
+    }
+}
+
+/// Compiled brushes, keyed by a BLAKE3 hash of the limits, the host modules, and the brush's
+/// source code, which together determine how a brush compiles. Parameter values set by the user
+/// are not part of the key, since they're only applied when the brush is run.
+/// When the cache gets too big, the least recently used brushes are evicted.
+///
+/// Entries never need to be invalidated, since anything that would change the compiled brush
+/// changes its key instead; brushes compiled under old settings are simply never hit again, and
+/// get evicted as newer brushes take their place. The cache only lives in memory, so brushes
+/// compiled by older versions of haku don't outlive a server restart.
+pub struct BrushCache {
+    settings: Settings,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    /// Brushes along with their sizes, ordered from least to most recently used.
+    /// Both touching and evicting a brush is O(1), so the lock is never held for long.
+    brushes: LruCache<blake3::Hash, (Arc<CompiledBrush>, usize)>,
+    size: usize,
+}
+
+impl BrushCache {
+    pub fn new(settings: Settings) -> Self {
+        Self {
+            settings,
+            inner: Mutex::new(Inner {
+                brushes: LruCache::unbounded(),
+                size: 0,
+            }),
+        }
+    }
+
+    pub fn get(&self, key: &blake3::Hash) -> Option<Arc<CompiledBrush>> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.brushes.get(key).map(|(brush, _)| Arc::clone(brush))
+    }
+
+    /// Inserts a brush into the cache, evicting the least recently used brushes to make room for
+    /// it. Brushes larger than the entire cache are not cached at all.
+    pub fn insert(&self, key: blake3::Hash, brush: CompiledBrush) {
+        let size = brush.size();
+        if size > self.settings.max_size {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        if let Some((_, old_size)) = inner.brushes.pop(&key) {
+            inner.size -= old_size;
+        }
+        while inner.size + size > self.settings.max_size {
+            let (_, (_, evicted_size)) = inner
+                .brushes
+                .pop_lru()
+                .expect("the cache cannot be empty while its size is non-zero");
+            inner.size -= evicted_size;
+        }
+        inner.brushes.put(key, (Arc::new(brush), size));
+        inner.size += size;
+    }
+
+    /// Total size of the cached brushes, in bytes.
+    #[cfg(test)]
+    fn size(&self) -> usize {
+        self.inner.lock().unwrap().size
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,124 @@
+use haku::{
+    bytecode::{Chunk, Defs, Opcode},
+    compiler::Compiler,
+    system::System,
+};
+
+use super::{BrushCache, CompiledBrush, Settings};
+
+/// A brush taking up exactly `size` bytes in the cache.
+fn brush(size: usize) -> CompiledBrush {
+    let mut system = System::new(1);
+    let mut defs = Defs::new(1);
+    let mut chunk = Chunk::new(16).unwrap();
+    chunk.emit_opcode(Opcode::Nil).unwrap();
+    chunk.emit_opcode(Opcode::Return).unwrap();
+    let spec = Compiler::new(&mut defs, &mut chunk).closure_spec();
+    let chunk_id = system.add_chunk(chunk, &defs, spec).unwrap();
+    let brush = CompiledBrush {
+        snapshot: vec![0; size],
+        modules: Vec::new(),
+        brush: (chunk_id, spec),
+        params: Vec::new(),
+    };
+    assert_eq!(brush.size(), size);
+    brush
+}
+
+fn key(name: &str) -> blake3::Hash {
+    blake3::hash(name.as_bytes())
+}
+
+fn cache(max_size: usize) -> BrushCache {
+    BrushCache::new(Settings { max_size })
+}
+
+#[test]
+fn get_returns_inserted_brush() {
+    let cache = cache(100);
+    assert!(cache.get(&key("a")).is_none());
+    cache.insert(key("a"), brush(10));
+    assert_eq!(cache.get(&key("a")).unwrap().snapshot.len(), 10);
+    assert!(cache.get(&key("b")).is_none());
+}
+
+#[test]
+fn evicts_least_recently_inserted() {
+    let cache = cache(30);
+    cache.insert(key("a"), brush(10));
+    cache.insert(key("b"), brush(10));
+    cache.insert(key("c"), brush(10));
+    cache.insert(key("d"), brush(10));
+    assert!(cache.get(&key("a")).is_none());
+    assert!(cache.get(&key("b")).is_some());
+    assert!(cache.get(&key("c")).is_some());
+    assert!(cache.get(&key("d")).is_some());
+}
+
+#[test]
+fn get_marks_brush_as_recently_used() {
+    let cache = cache(30);
+    cache.insert(key("a"), brush(10));
+    cache.insert(key("b"), brush(10));
+    cache.insert(key("c"), brush(10));
+    cache.get(&key("a"));
+    cache.insert(key("d"), brush(10));
+    assert!(cache.get(&key("a")).is_some());
+    assert!(cache.get(&key("b")).is_none());
+}
+
+#[test]
+fn evicts_as_many_brushes_as_needed() {
+    let cache = cache(30);
+    cache.insert(key("a"), brush(10));
+    cache.insert(key("b"), brush(10));
+    cache.insert(key("c"), brush(10));
+    cache.insert(key("d"), brush(25));
+    assert!(cache.get(&key("a")).is_none());
+    assert!(cache.get(&key("b")).is_none());
+    assert!(cache.get(&key("c")).is_none());
+    assert!(cache.get(&key("d")).is_some());
+    assert_eq!(cache.size(), 25);
+}
+
+#[test]
+fn size_accounts_for_insertions_and_evictions() {
+    let cache = cache(30);
+    cache.insert(key("a"), brush(10));
+    cache.insert(key("b"), brush(15));
+    assert_eq!(cache.size(), 25);
+    cache.insert(key("c"), brush(5));
+    assert_eq!(cache.size(), 30);
+    cache.insert(key("d"), brush(8));
+    assert_eq!(cache.size(), 28);
+}
+
+#[test]
+fn reinserting_replaces_brush() {
+    let cache = cache(30);
+    cache.insert(key("a"), brush(10));
+    cache.insert(key("b"), brush(10));
+    cache.insert(key("a"), brush(20));
+    assert_eq!(cache.size(), 30);
+    assert_eq!(cache.get(&key("a")).unwrap().snapshot.len(), 20);
+    assert!(cache.get(&key("b")).is_some());
+}
+
+#[test]
+fn oversized_brush_is_not_cached() {
+    let cache = cache(30);
+    cache.insert(key("a"), brush(10));
+    cache.insert(key("b"), brush(31));
+    assert!(cache.get(&key("b")).is_none());
+    // Nothing is evicted to make room for a brush that would never fit.
+    assert!(cache.get(&key("a")).is_some());
+    assert_eq!(cache.size(), 10);
+}
+
+#[test]
+fn brush_filling_entire_cache_is_cached() {
+    let cache = cache(30);
+    cache.insert(key("a"), brush(30));
+    assert!(cache.get(&key("a")).is_some());
+    assert_eq!(cache.size(), 30);
+}
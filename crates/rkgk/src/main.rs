@@ -1,5 +1,6 @@
 use std::{fs::create_dir_all, net::Ipv4Addr, path::Path, sync::Arc};
 
+use crate::haku::cache::BrushCache;
 use api::Api;
 use config::Config;
 use eyre::Context;
@@ -70,7 +71,13 @@ async fn fallible_main() -> eyre::Result<()> {
     build::build(&paths, &config.build)?;
     let dbs = Arc::new(database(&config, &paths)?);
 
-    let api = Arc::new(Api { config, dbs });
+    let brush_cache = Arc::new(BrushCache::new(config.brush_cache.clone()));
+
+    let api = Arc::new(Api {
+        config,
+        dbs,
+        brush_cache,
+    });
     let app = router(&paths, api);
 
     let port: u16 = std::env::var("RKGK_PORT")
@@ -15,7 +15,9 @@ use serde::{Deserialize, Serialize};
 use tokio::sync::{broadcast, Mutex};
 use tracing::info;
 
-use crate::{id, login::UserId, schema::Vec2, serialization::DeserializeFromStr};
+use crate::{
+    haku::BrushParams, id, login::UserId, schema::Vec2, serialization::DeserializeFromStr,
+};
 
 pub mod auto_save;
 pub mod broker;
@@ -115,6 +117,21 @@ impl Chunk {
     }
 }
 
+/// How brushes are blended into chunks.
+/// This is sent to clients, since they have to draw exactly the same way as the server.
+///
+/// By default, every point is blended onto chunks separately, like before high-precision blending
+/// existed.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct Blending {
+    /// Accumulate each batch of points in high precision, and composite it onto chunks once,
+    /// instead of blending every point onto chunks separately.
+    pub high_precision: bool,
+    /// Dither high-precision batches when compositing them onto chunks.
+    pub dither: bool,
+}
+
 #[derive(Debug, Clone, Copy, Deserialize, Serialize)]
 pub struct Settings {
     pub max_chunks: usize,
@@ -155,17 +172,20 @@ pub struct Wall {
     event_sender: broadcast::Sender<Event>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct UserInit {
     // Provide a brush upon initialization, so that the user always has a valid brush set.
     pub brush: String,
+    #[serde(default)]
+    pub params: BrushParams,
 }
 
 pub struct Session {
     pub user_id: UserId,
     pub cursor: Option<Vec2>,
     pub brush: String,
+    pub params: BrushParams,
 }
 
 pub struct SessionHandle {
@@ -188,13 +208,24 @@ pub struct Event {
     rename_all_fields = "camelCase"
 )]
 pub enum EventKind {
-    Join { nickname: String, init: UserInit },
+    Join {
+        nickname: String,
+        init: UserInit,
+    },
     Leave,
 
-    Cursor { position: Vec2 },
+    Cursor {
+        position: Vec2,
+    },
 
-    SetBrush { brush: String },
-    Plot { points: Vec<Vec2> },
+    SetBrush {
+        brush: String,
+        #[serde(default)]
+        params: BrushParams,
+    },
+    Plot {
+        points: Vec<Vec2>,
+    },
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -204,6 +235,7 @@ pub struct Online {
     pub user_id: UserId,
     pub cursor: Option<Vec2>,
     pub brush: String,
+    pub params: BrushParams,
 }
 
 impl Wall {
@@ -259,6 +291,7 @@ impl Wall {
                 user_id: r.user_id,
                 cursor: r.value().cursor,
                 brush: r.value().brush.clone(),
+                params: r.value().params.clone(),
             })
             .collect()
     }
@@ -290,6 +323,7 @@ impl Session {
             user_id,
             cursor: None,
             brush: user_init.brush,
+            params: user_init.params,
         }
     }
 }
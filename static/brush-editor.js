@@ -19,6 +19,14 @@ export class BrushEditor extends HTMLElement {
 
         this.codeEditor = this.appendChild(
             new CodeEditor([
+                {
+                    className: "layer-debug-location",
+                    render: (code, element) => this.#renderDebugLocation(code, element),
+                },
+                {
+                    className: "layer-syntax",
+                    render: (code, element) => this.#renderSyntax(code, element),
+                },
                 {
                     className: "layer-error-squiggles",
                     render: (code, element) => this.#renderErrorSquiggles(code, element),
@@ -36,6 +44,21 @@ export class BrushEditor extends HTMLElement {
             );
         });
 
+        this.paramValues = JSON.parse(localStorage.getItem("rkgk.brushEditor.params") ?? "{}");
+        this.paramsArea = this.appendChild(document.createElement("div"));
+        this.paramsArea.classList.add("params");
+
+        this.formatButton = this.appendChild(document.createElement("button"));
+        this.formatButton.classList.add("format-button");
+        this.formatButton.textContent = "Format";
+        this.formatButton.addEventListener("click", () => {
+            this.dispatchEvent(new Event(".formatRequested"));
+        });
+
+        this.debugPanel = this.appendChild(document.createElement("div"));
+        this.debugPanel.classList.add("debug-panel");
+        this.#renderDebugControls();
+
         this.errorHeader = this.appendChild(document.createElement("h1"));
         this.errorHeader.classList.add("error-header");
 
@@ -47,6 +70,89 @@ export class BrushEditor extends HTMLElement {
         return this.codeEditor.code;
     }
 
+    // Values of brush parameters set by the user, by name.
+    // Values are kept around when a parameter is removed from the code, so that they aren't lost
+    // when the parameter is temporarily deleted while editing.
+    get params() {
+        return this.paramValues;
+    }
+
+    #setParam(name, value) {
+        if (value == null) {
+            delete this.paramValues[name];
+        } else {
+            this.paramValues[name] = value;
+        }
+        localStorage.setItem("rkgk.brushEditor.params", JSON.stringify(this.paramValues));
+        this.dispatchEvent(new Event(".paramsChanged"));
+    }
+
+    // Renders controls for the brush's parameters, as returned by `Haku.setBrush`.
+    renderParams(params) {
+        this.paramsArea.replaceChildren();
+        for (let param of params) {
+            let value = this.paramValues[param.name];
+            if (!paramValueMatchesKind(param, value)) {
+                value = param.defaultValue;
+            }
+
+            let label = this.paramsArea.appendChild(document.createElement("label"));
+            label.textContent = param.name;
+
+            let valueElement = this.paramsArea.appendChild(document.createElement("div"));
+            valueElement.classList.add("param-value");
+            let input = valueElement.appendChild(document.createElement("input"));
+            input.id = `brush-param-${param.name}`;
+            label.htmlFor = input.id;
+
+            if (param.kind == "number") {
+                input.type = param.range != null ? "range" : "number";
+                input.step = "any";
+                if (param.range != null) {
+                    input.min = param.range.min;
+                    input.max = param.range.max;
+                    let output = valueElement.appendChild(document.createElement("output"));
+                    output.textContent = value;
+                    input.addEventListener("input", () => (output.textContent = input.value));
+                }
+                input.value = value;
+                input.addEventListener("change", () => {
+                    let number = parseFloat(input.value);
+                    if (!Number.isNaN(number)) this.#setParam(param.name, number);
+                });
+            } else if (param.kind == "rgba") {
+                // Color inputs do not support alpha, so the alpha of the current value is kept.
+                input.type = "color";
+                input.value = rgbaToHex(value);
+                input.addEventListener("change", () => {
+                    this.#setParam(param.name, [...hexToRgb(input.value), value[3]]);
+                });
+            } else if (param.kind == "boolean") {
+                input.type = "checkbox";
+                input.checked = value;
+                input.addEventListener("change", () => this.#setParam(param.name, input.checked));
+            }
+
+            let resetButton = this.paramsArea.appendChild(document.createElement("button"));
+            resetButton.textContent = "Reset";
+            resetButton.title = "Reset to the default value from the code";
+            resetButton.disabled = this.paramValues[param.name] == null;
+            resetButton.addEventListener("click", () => this.#setParam(param.name, null));
+        }
+    }
+
+    // Replaces the code with its formatted version, in a way that can be undone.
+    setFormattedCode(formattedCode) {
+        if (formattedCode == this.code) return;
+        this.codeEditor.pushHistory({ allowMerge: false });
+        this.codeEditor.setCode(formattedCode);
+    }
+
+    setHighlighting(highlighting) {
+        this.highlighting = highlighting;
+        this.codeEditor.renderLayer("layer-syntax");
+    }
+
     resetErrors() {
         this.errorHeader.textContent = "";
         this.errorArea.textContent = "";
@@ -57,27 +163,17 @@ export class BrushEditor extends HTMLElement {
         this.errorSquiggles = null;
 
         if (result.status != "error") {
-            // We need to request a rebuild if there's no error to remove any squiggles that may be
-            // left over from the error state.
-            this.codeEditor.renderLayer("layer-error-squiggles");
+            // Successful compilation may still produce warnings.
+            // We need to request a rebuild even if there are none to remove any squiggles that may
+            // be left over from the error state.
+            this.#renderDiagnostics(result.diagnostics ?? []);
             return;
         }
 
         this.errorHeader.textContent = `${phase} failed`;
 
         if (result.errorKind == "diagnostics") {
-            this.codeEditor.rebuildLineMap();
-            this.errorSquiggles = this.#computeErrorSquiggles(
-                this.codeEditor.lineMap,
-                result.diagnostics,
-            );
-            this.codeEditor.renderLayer("layer-error-squiggles");
-
-            this.errorArea.textContent = result.diagnostics
-                .map(
-                    (diagnostic) => `${diagnostic.start}..${diagnostic.end}: ${diagnostic.message}`,
-                )
-                .join("\n");
+            this.#renderDiagnostics(result.diagnostics);
         } else if (result.errorKind == "plain") {
             this.errorHeader.textContent = result.message;
         } else if (result.errorKind == "exception") {
@@ -89,6 +185,228 @@ export class BrushEditor extends HTMLElement {
         }
     }
 
+    // Stepping through the brush.
+    // The brush editor only shows the controls and the state of the debugger; the brush is run
+    // by whoever listens to the `.debug*` events, which then calls `renderDebugState` or
+    // `stopDebugging`.
+
+    get isDebugging() {
+        return this.debugState != null;
+    }
+
+    #debugButton(text, title, onClick) {
+        let button = this.debugControls.appendChild(document.createElement("button"));
+        button.textContent = text;
+        button.title = title;
+        button.addEventListener("click", onClick);
+        return button;
+    }
+
+    #renderDebugControls() {
+        this.debugPanel.replaceChildren();
+        this.debugControls = this.debugPanel.appendChild(document.createElement("div"));
+        this.debugControls.classList.add("debug-controls");
+
+        if (!this.isDebugging) {
+            this.#debugButton(
+                "Step through",
+                "Run the brush one expression at a time, to see what it does",
+                () => this.dispatchEvent(new Event(".debugStart")),
+            );
+            this.#debugButton(
+                "Profile",
+                "Run the brush, and see how much of the wall's limits each of its functions uses",
+                () => this.dispatchEvent(new Event(".profileRequested")),
+            );
+            return;
+        }
+
+        this.#debugButton("Step", "Run until the next expression", () =>
+            this.dispatchEvent(new Event(".debugStep")),
+        );
+        this.#debugButton("Continue", "Run until the brush finishes", () =>
+            this.dispatchEvent(Object.assign(new Event(".debugContinue"), { breakpoints: [] })),
+        );
+        this.#debugButton(
+            "Run to cursor",
+            "Run until the expression under the text cursor is reached",
+            () =>
+                this.dispatchEvent(
+                    Object.assign(new Event(".debugContinue"), {
+                        breakpoints: [this.codeEditor.textArea.selectionStart],
+                    }),
+                ),
+        );
+        this.#debugButton("Stop", "Stop stepping through the brush", () =>
+            this.dispatchEvent(new Event(".debugStop")),
+        );
+    }
+
+    // Shows where the brush is paused, and the values of its variables. `state` is as returned by
+    // `Haku.debugState`.
+    renderDebugState(state) {
+        let wasDebugging = this.isDebugging;
+        this.debugState = state;
+        if (!wasDebugging) this.#renderDebugControls();
+
+        this.debugPanel.querySelector(".debug-variables")?.remove();
+        let variables = this.debugPanel.appendChild(document.createElement("pre"));
+        variables.classList.add("debug-variables");
+
+        let lines = [];
+        let code = this.code;
+        // Innermost call first, like in a stack trace.
+        for (let frame of state.frames.toReversed()) {
+            lines.push(`in: ${code.substring(frame.start, frame.end).split("\n")[0]}`);
+            for (let { name, value } of frame.variables) {
+                lines.push(`    ${name} = ${value}`);
+            }
+            if (frame.temporaries.length > 0) {
+                lines.push(`    (evaluated so far: ${frame.temporaries.join(", ")})`);
+            }
+        }
+        if (state.defs.length > 0) {
+            lines.push("defs:");
+            for (let { name, value } of state.defs) {
+                lines.push(`    ${name} = ${value}`);
+            }
+        }
+        variables.textContent = lines.join("\n");
+
+        this.codeEditor.renderLayer("layer-debug-location");
+    }
+
+    // Shows how much of each limit the brush used. `profile` is as returned by `Haku.profile`.
+    renderProfile(profile) {
+        this.debugPanel.querySelector(".debug-variables")?.remove();
+        let summary = this.debugPanel.appendChild(document.createElement("pre"));
+        summary.classList.add("debug-variables");
+
+        let usage = (used, limit) => `${used} / ${limit} (${Math.ceil((used / limit) * 100)}%)`;
+        let lines = [
+            `fuel:       ${usage(profile.fuelUsed, profile.fuelLimit)}`,
+            `memory:     ${usage(profile.peakMemory, profile.memoryLimit)} bytes`,
+            `stack:      ${usage(profile.peakStackDepth, profile.stackCapacity)} values`,
+            `call stack: ${usage(profile.peakCallDepth, profile.callStackCapacity)} calls`,
+        ];
+
+        let code = this.code;
+        // Most expensive functions first.
+        for (let f of profile.functions.toSorted((a, b) => b.fuel - a.fuel)) {
+            let name =
+                f.start != null
+                    ? code.substring(f.start, f.end).split("\n")[0]
+                    : "(outside the brush)";
+            lines.push(`in: ${name}`);
+            lines.push(
+                `    ${f.calls} calls, ${f.fuel} fuel, ${f.memory} bytes of memory, ${f.refs} values`,
+            );
+        }
+        summary.textContent = lines.join("\n");
+    }
+
+    stopDebugging() {
+        if (!this.isDebugging) return;
+        this.debugState = null;
+        this.#renderDebugControls();
+        this.codeEditor.renderLayer("layer-debug-location");
+    }
+
+    #renderDebugLocation(lines, element) {
+        let frame = this.debugState?.frames.at(-1);
+        if (frame == null) return;
+
+        for (let lineBounds of lines.lineBounds) {
+            let lineElement = element.appendChild(document.createElement("span"));
+            lineElement.classList.add("line");
+
+            let start = Math.max(frame.start, lineBounds.start);
+            let end = Math.min(frame.end, lineBounds.end);
+            if (start < end) {
+                lineElement.append(lines.string.substring(lineBounds.start, start));
+                let location = lineElement.appendChild(document.createElement("span"));
+                location.classList.add("debug-location");
+                location.textContent = lines.string.substring(start, end);
+            } else {
+                lineElement.textContent = lineBounds.substring;
+            }
+        }
+    }
+
+    #renderSyntax(lines, element) {
+        if (this.highlighting == null) return;
+
+        // Semantic classes are more specific than token classes, so they take priority.
+        let classes = new Array(lines.string.length).fill(null);
+        for (let token of this.highlighting.tokens) {
+            classes.fill(`token-${token.className}`, token.start, token.end);
+        }
+        for (let token of this.highlighting.semanticTokens) {
+            let className = `semantic-${token.className}`;
+            if (token.isDefinition) className += " definition";
+            classes.fill(className, token.start, token.end);
+        }
+
+        for (let lineBounds of lines.lineBounds) {
+            let lineElement = element.appendChild(document.createElement("span"));
+            lineElement.classList.add("line");
+
+            let runStart = lineBounds.start;
+            for (let i = lineBounds.start; i <= lineBounds.end; ++i) {
+                if (i == lineBounds.end || classes[i] != classes[runStart]) {
+                    let text = lines.string.substring(runStart, i);
+                    if (classes[runStart] == null) {
+                        lineElement.append(text);
+                    } else {
+                        let spanElement = lineElement.appendChild(document.createElement("span"));
+                        spanElement.className = classes[runStart];
+                        spanElement.textContent = text;
+                    }
+                    runStart = i;
+                }
+            }
+        }
+    }
+
+    #renderDiagnostics(diagnostics) {
+        this.codeEditor.rebuildLineMap();
+        this.errorSquiggles =
+            diagnostics.length > 0
+                ? this.#computeErrorSquiggles(this.codeEditor.lineMap, diagnostics)
+                : null;
+        this.codeEditor.renderLayer("layer-error-squiggles");
+
+        this.errorArea.replaceChildren();
+        for (let diagnostic of diagnostics) {
+            let diagnosticElement = this.errorArea.appendChild(document.createElement("div"));
+            diagnosticElement.classList.add("diagnostic");
+
+            let lines = [
+                `${diagnostic.start}..${diagnostic.end} ${diagnostic.severity}: ${diagnostic.message}`,
+            ];
+            for (let label of diagnostic.labels ?? []) {
+                lines.push(`    ${label.start}..${label.end}: ${label.message}`);
+            }
+            for (let note of diagnostic.notes ?? []) {
+                lines.push(`    note: ${note}`);
+            }
+            diagnosticElement.textContent = lines.join("\n");
+
+            for (let suggestion of diagnostic.suggestions ?? []) {
+                let button = diagnosticElement.appendChild(document.createElement("button"));
+                button.classList.add("quick-fix");
+                button.textContent = suggestion.message;
+                button.addEventListener("click", () => this.#applySuggestion(suggestion));
+            }
+        }
+    }
+
+    // Applies a fix suggested by a diagnostic, in a way that can be undone.
+    #applySuggestion(suggestion) {
+        this.codeEditor.pushHistory({ allowMerge: false });
+        this.codeEditor.replace(suggestion, suggestion.replacement);
+    }
+
     #computeErrorSquiggles(lineMap, diagnostics) {
         // This is an extremely inefficient algorithm.
         // If we had better control of drawing (I'm talking: letter per letter, with a shader!)
@@ -168,8 +486,12 @@ export class BrushEditor extends HTMLElement {
                     if (segment.diagnostics.length == 0) {
                         lineElement.append(text);
                     } else {
+                        // Show the most severe diagnostic if there is more than one.
+                        let severity = ["error", "warning", "hint"].find((severity) =>
+                            segment.diagnostics.some((d) => d.severity == severity),
+                        );
                         let spanElement = lineElement.appendChild(document.createElement("span"));
-                        spanElement.classList.add("squiggle", "squiggle-error");
+                        spanElement.classList.add("squiggle", `squiggle-${severity}`);
                         spanElement.textContent = text;
                     }
                 }
@@ -180,4 +502,28 @@ export class BrushEditor extends HTMLElement {
     }
 }
 
+function paramValueMatchesKind(param, value) {
+    switch (param.kind) {
+        case "number":
+            return typeof value == "number";
+        case "rgba":
+            return Array.isArray(value) && value.length == 4;
+        case "boolean":
+            return typeof value == "boolean";
+    }
+    return false;
+}
+
+function rgbaToHex([r, g, b]) {
+    let byte = (x) =>
+        Math.round(Math.min(Math.max(x, 0), 1) * 255)
+            .toString(16)
+            .padStart(2, "0");
+    return `#${byte(r)}${byte(g)}${byte(b)}`;
+}
+
+function hexToRgb(hex) {
+    return [1, 3, 5].map((i) => parseInt(hex.substring(i, i + 2), 16) / 255);
+}
+
 customElements.define("rkgk-brush-editor", BrushEditor);
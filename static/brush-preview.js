@@ -29,6 +29,10 @@ export class BrushPreview extends HTMLElement {
             return { status: "error", phase: "eval", result: evalResult };
         }
 
+        return this.#renderValueInner(haku);
+    }
+
+    #renderValueInner(haku) {
         this.pixmap.clear();
         let renderResult = haku.renderValue(
             this.pixmap,
@@ -53,6 +57,17 @@ export class BrushPreview extends HTMLElement {
         return result;
     }
 
+    // Renders the value the brush evaluated to, without evaluating it again.
+    // Used for showing the result of a brush that was stepped through.
+    renderValue(haku) {
+        this.unsetErrorFlag();
+        let result = this.#renderValueInner(haku);
+        if (result.status == "error") {
+            this.setErrorFlag();
+        }
+        return result;
+    }
+
     unsetErrorFlag() {
         this.classList.remove("error");
     }
@@ -65,6 +65,29 @@ function readCString(pCString) {
     return readString(size, pCString);
 }
 
+// haku works with UTF-8 byte offsets, whereas JavaScript strings are indexed by UTF-16 code units.
+// This returns an array mapping the former to the latter.
+function utf8ToUtf16Offsets(string) {
+    let offsets = [];
+    let utf16Offset = 0;
+    for (let char of string) {
+        let codePoint = char.codePointAt(0);
+        let utf8Length = codePoint < 0x80 ? 1 : codePoint < 0x800 ? 2 : codePoint < 0x10000 ? 3 : 4;
+        for (let i = 0; i < utf8Length; ++i) {
+            offsets.push(utf16Offset);
+        }
+        utf16Offset += char.length;
+    }
+    offsets.push(utf16Offset);
+    return offsets;
+}
+
+// The inverse of the mapping returned by `utf8ToUtf16Offsets`.
+function utf16ToUtf8Offset(offsets, utf16Offset) {
+    let utf8Offset = offsets.findIndex((offset) => offset >= utf16Offset);
+    return utf8Offset == -1 ? offsets.length - 1 : utf8Offset;
+}
+
 class Panic extends Error {
     name = "Panic";
 }
@@ -96,6 +119,12 @@ export class Pixmap {
         w.haku_pixmap_clear(this.#pPixmap, r, g, b, a);
     }
 
+    // Replaces pixels of this pixmap with the pixels of `src`, placed with its top-left corner at
+    // `x`, `y`. Any parts of `src` outside this pixmap are left out.
+    copy(src, x, y) {
+        w.haku_pixmap_copy(this.#pPixmap, src.ptr, x, y);
+    }
+
     get ptr() {
         return this.#pPixmap;
     }
@@ -113,12 +142,41 @@ export class Pixmap {
     }
 }
 
+// Blends many pixmaps together at a higher precision than pixmaps themselves have, so that lots of
+// faint splats add up to what they're supposed to, before being composited onto a pixmap at once.
+export class Accumulator {
+    #pAccumulator = 0;
+
+    constructor(width, height) {
+        this.#pAccumulator = w.haku_accumulator_new(width, height);
+        this.width = width;
+        this.height = height;
+    }
+
+    destroy() {
+        w.haku_accumulator_destroy(this.#pAccumulator);
+    }
+
+    blend(pixmap, x, y) {
+        w.haku_accumulator_blend(this.#pAccumulator, pixmap.ptr, x, y);
+    }
+
+    composite(pixmap, dither) {
+        w.haku_accumulator_composite(this.#pAccumulator, pixmap.ptr, dither);
+    }
+}
+
 export class Haku {
     #pInstance = 0;
     #pBrush = 0;
     #brushCode = null;
+    #brushCodeOffsets = [0];
 
-    constructor(limits) {
+    // Brushes can only be debugged if `debug` is set, because collecting the information needed
+    // for that makes compilation slower.
+    // `modules` maps names of modules supplied by the server to their source code, so that brushes
+    // can import them.
+    constructor(limits, { debug = false, modules = {} } = {}) {
         console.groupCollapsed("construct Haku");
 
         let pLimits = w.haku_limits_new();
@@ -128,6 +186,22 @@ export class Haku {
 
         this.#pInstance = w.haku_instance_new(pLimits);
         this.#pBrush = w.haku_brush_new();
+        if (debug) {
+            w.haku_enable_debug_info(this.#pInstance);
+        }
+        for (let [name, code] of Object.entries(modules)) {
+            let pName = allocString(name);
+            let pCode = allocString(code);
+            w.haku_instance_add_module(
+                this.#pInstance,
+                pName.length,
+                pName.ptr,
+                pCode.length,
+                pCode.ptr,
+            );
+            freeString(pName);
+            freeString(pCode);
+        }
 
         w.haku_limits_destroy(pLimits);
 
@@ -139,7 +213,10 @@ export class Haku {
         w.haku_instance_destroy(this.#pInstance);
     }
 
-    setBrush(code) {
+    // `params` holds the values of brush parameters by name. Values are numbers, `[r, g, b, a]`
+    // arrays, or booleans, depending on the parameter's kind. Parameters without a value (or with
+    // a value of the wrong kind) use their default.
+    setBrush(code, params = {}) {
         w.haku_reset(this.#pInstance);
         // NOTE: Brush is invalid at this point, because we reset removes all defs and registered chunks.
 
@@ -152,19 +229,66 @@ export class Haku {
             this.#brushCode.length,
             this.#brushCode.ptr,
         );
+        // Spans are converted to string offsets, so that suggestions can be applied to the code
+        // directly.
+        let offsets = utf8ToUtf16Offsets(code);
+        this.#brushCodeOffsets = offsets;
+        let diagnostics = [];
+        for (let i = 0; i < w.haku_num_diagnostics(this.#pBrush); ++i) {
+            let labels = [];
+            for (let j = 0; j < w.haku_diagnostic_num_labels(this.#pBrush, i); ++j) {
+                labels.push({
+                    start: offsets[w.haku_diagnostic_label_start(this.#pBrush, i, j)],
+                    end: offsets[w.haku_diagnostic_label_end(this.#pBrush, i, j)],
+                    message: readString(
+                        w.haku_diagnostic_label_message_len(this.#pBrush, i, j),
+                        w.haku_diagnostic_label_message(this.#pBrush, i, j),
+                    ),
+                });
+            }
+
+            let notes = [];
+            for (let j = 0; j < w.haku_diagnostic_num_notes(this.#pBrush, i); ++j) {
+                notes.push(
+                    readString(
+                        w.haku_diagnostic_note_len(this.#pBrush, i, j),
+                        w.haku_diagnostic_note(this.#pBrush, i, j),
+                    ),
+                );
+            }
+
+            let suggestions = [];
+            for (let j = 0; j < w.haku_diagnostic_num_suggestions(this.#pBrush, i); ++j) {
+                suggestions.push({
+                    start: offsets[w.haku_diagnostic_suggestion_start(this.#pBrush, i, j)],
+                    end: offsets[w.haku_diagnostic_suggestion_end(this.#pBrush, i, j)],
+                    replacement: readString(
+                        w.haku_diagnostic_suggestion_replacement_len(this.#pBrush, i, j),
+                        w.haku_diagnostic_suggestion_replacement(this.#pBrush, i, j),
+                    ),
+                    message: readString(
+                        w.haku_diagnostic_suggestion_message_len(this.#pBrush, i, j),
+                        w.haku_diagnostic_suggestion_message(this.#pBrush, i, j),
+                    ),
+                });
+            }
+
+            diagnostics.push({
+                start: offsets[w.haku_diagnostic_start(this.#pBrush, i)],
+                end: offsets[w.haku_diagnostic_end(this.#pBrush, i)],
+                severity: readCString(w.haku_diagnostic_severity(this.#pBrush, i)),
+                message: readString(
+                    w.haku_diagnostic_message_len(this.#pBrush, i),
+                    w.haku_diagnostic_message(this.#pBrush, i),
+                ),
+                labels,
+                notes,
+                suggestions,
+            });
+        }
+
         if (!w.haku_is_ok(statusCode)) {
             if (w.haku_is_diagnostics_emitted(statusCode)) {
-                let diagnostics = [];
-                for (let i = 0; i < w.haku_num_diagnostics(this.#pBrush); ++i) {
-                    diagnostics.push({
-                        start: w.haku_diagnostic_start(this.#pBrush, i),
-                        end: w.haku_diagnostic_end(this.#pBrush, i),
-                        message: readString(
-                            w.haku_diagnostic_message_len(this.#pBrush, i),
-                            w.haku_diagnostic_message(this.#pBrush, i),
-                        ),
-                    });
-                }
                 return {
                     status: "error",
                     errorKind: "diagnostics",
@@ -179,7 +303,108 @@ export class Haku {
             }
         }
 
-        return { status: "ok" };
+        let brushParams = this.#readParams();
+        for (let i = 0; i < brushParams.length; ++i) {
+            let param = brushParams[i];
+            let value = params[param.name];
+            if (value == null) continue;
+
+            if (param.kind == "number" && typeof value == "number") {
+                w.haku_param_set(this.#pBrush, i, value, 0, 0, 0);
+            } else if (param.kind == "rgba" && Array.isArray(value) && value.length == 4) {
+                w.haku_param_set(this.#pBrush, i, ...value);
+            } else if (param.kind == "boolean" && typeof value == "boolean") {
+                w.haku_param_set(this.#pBrush, i, value ? 1 : 0, 0, 0, 0);
+            }
+        }
+
+        // Warnings do not prevent the brush from compiling.
+        return { status: "ok", diagnostics, params: brushParams };
+    }
+
+    #readParams() {
+        let params = [];
+        for (let i = 0; i < w.haku_num_params(this.#pBrush); ++i) {
+            let kind = readCString(w.haku_param_kind(this.#pBrush, i));
+            let component = (c) => w.haku_param_default(this.#pBrush, i, c);
+            let defaultValue;
+            if (kind == "number") defaultValue = component(0);
+            else if (kind == "rgba") defaultValue = [0, 1, 2, 3].map(component);
+            else if (kind == "boolean") defaultValue = component(0) != 0;
+
+            params.push({
+                name: readString(
+                    w.haku_param_name_len(this.#pBrush, i),
+                    w.haku_param_name(this.#pBrush, i),
+                ),
+                kind,
+                defaultValue,
+                range: w.haku_param_has_range(this.#pBrush, i)
+                    ? { min: w.haku_param_min(this.#pBrush, i), max: w.haku_param_max(this.#pBrush, i) }
+                    : null,
+            });
+        }
+        return params;
+    }
+
+    // Returns the code formatted canonically, or null if it couldn't be formatted (most likely
+    // because it has syntax errors.)
+    format(code) {
+        let pCode = allocString(code);
+        let statusCode = w.haku_format(this.#pInstance, pCode.length, pCode.ptr);
+        freeString(pCode);
+
+        if (!w.haku_is_ok(statusCode)) {
+            console.info("cannot format code:", readCString(w.haku_status_string(statusCode)));
+            return null;
+        }
+        return readString(
+            w.haku_formatted_len(this.#pInstance),
+            w.haku_formatted(this.#pInstance),
+        );
+    }
+
+    // Returns the tokens of the code, and the semantic classes of identifiers and literals, as
+    // seen by the compiler. This works even if the code has errors.
+    highlight(code) {
+        let pCode = allocString(code);
+        let statusCode = w.haku_highlight(this.#pInstance, pCode.length, pCode.ptr);
+        freeString(pCode);
+
+        let offsets = utf8ToUtf16Offsets(code);
+
+        let tokens = [];
+        for (let i = 0; i < w.haku_num_tokens(this.#pInstance); ++i) {
+            tokens.push({
+                className: readCString(w.haku_token_class(this.#pInstance, i)),
+                start: offsets[w.haku_token_start(this.#pInstance, i)],
+                end: offsets[w.haku_token_end(this.#pInstance, i)],
+            });
+        }
+
+        let semanticTokens = [];
+        for (let i = 0; i < w.haku_num_semantic_tokens(this.#pInstance); ++i) {
+            semanticTokens.push({
+                className: readCString(w.haku_semantic_token_class(this.#pInstance, i)),
+                start: offsets[w.haku_semantic_token_start(this.#pInstance, i)],
+                end: offsets[w.haku_semantic_token_end(this.#pInstance, i)],
+                isDefinition: w.haku_semantic_token_is_definition(this.#pInstance, i),
+            });
+        }
+
+        let outline = semanticTokens
+            .filter((token) => token.className == "def" && token.isDefinition)
+            .map((token) => ({
+                name: code.substring(token.start, token.end),
+                start: token.start,
+                end: token.end,
+            }));
+
+        if (!w.haku_is_ok(statusCode)) {
+            console.info("cannot highlight code:", readCString(w.haku_status_string(statusCode)));
+        }
+
+        return { tokens, semanticTokens, outline };
     }
 
     #statusCodeToResultObject(statusCode) {
@@ -210,6 +435,25 @@ export class Haku {
         return this.#statusCodeToResultObject(w.haku_eval_brush(this.#pInstance, this.#pBrush));
     }
 
+    // Drawing strokes. The parts of the brush that are the same at every point of the stroke are
+    // evaluated once by `beginStroke`, and the rest is evaluated for each point by `evalPoint`,
+    // whose result can be rendered with `renderValue`.
+    beginStroke() {
+        return this.#statusCodeToResultObject(w.haku_begin_stroke(this.#pInstance, this.#pBrush));
+    }
+
+    evalPoint(x, y) {
+        return this.#statusCodeToResultObject(
+            w.haku_eval_point(this.#pInstance, this.#pBrush, x, y),
+        );
+    }
+
+    // Whether the value evaluated last has filters in it. Filters change what's already drawn, so
+    // they have to be rendered onto the wall itself.
+    hasFilters() {
+        return w.haku_has_filters(this.#pInstance);
+    }
+
     renderValue(pixmap, translationX, translationY) {
         return this.#statusCodeToResultObject(
             w.haku_render_value(this.#pInstance, pixmap.ptr, translationX, translationY),
@@ -219,4 +463,141 @@ export class Haku {
     resetVm() {
         w.haku_reset_vm(this.#pInstance);
     }
+
+    // Stepping through brushes. The brush starts out paused before its first expression, and
+    // can then be resumed either until it gets to a different expression, or until it reaches a
+    // breakpoint. Once it finishes running, its result can be rendered with `renderValue`.
+
+    debugStart() {
+        return this.#statusCodeToResultObject(w.haku_debug_start(this.#pInstance, this.#pBrush));
+    }
+
+    debugStep() {
+        return this.#statusCodeToResultObject(w.haku_debug_step(this.#pInstance, this.#pBrush));
+    }
+
+    debugContinue() {
+        return this.#statusCodeToResultObject(
+            w.haku_debug_continue(this.#pInstance, this.#pBrush),
+        );
+    }
+
+    debugStop() {
+        w.haku_debug_stop(this.#pInstance);
+    }
+
+    get isDebugPaused() {
+        return w.haku_debug_is_paused(this.#pInstance);
+    }
+
+    // Positions are string offsets into the brush's code. Breakpoints are placed on the innermost
+    // expression at each position.
+    setBreakpoints(positions) {
+        w.haku_debug_clear_breakpoints(this.#pInstance);
+        for (let position of positions) {
+            w.haku_debug_add_breakpoint(
+                this.#pInstance,
+                this.#pBrush,
+                utf16ToUtf8Offset(this.#brushCodeOffsets, position),
+            );
+        }
+    }
+
+    #readDebugText() {
+        return readString(
+            w.haku_debug_text_len(this.#pInstance),
+            w.haku_debug_text(this.#pInstance),
+        );
+    }
+
+    // Returns the call stack of the paused brush, outermost call first, along with the values of
+    // all of the brush's defs. Values are formatted as strings of haku code.
+    debugState() {
+        let offsets = this.#brushCodeOffsets;
+
+        let frames = [];
+        for (let i = 0; i < w.haku_debug_num_frames(this.#pInstance); ++i) {
+            let variables = [];
+            for (let j = 0; j < w.haku_debug_num_variables(this.#pInstance, this.#pBrush, i); ++j) {
+                w.haku_debug_format_variable(this.#pInstance, i, j);
+                variables.push({
+                    name: readString(
+                        w.haku_debug_variable_name_len(this.#pInstance, this.#pBrush, i, j),
+                        w.haku_debug_variable_name(this.#pInstance, this.#pBrush, i, j),
+                    ),
+                    value: this.#readDebugText(),
+                });
+            }
+
+            let temporaries = [];
+            for (let j = 0; j < w.haku_debug_num_temporaries(this.#pInstance, i); ++j) {
+                w.haku_debug_format_temporary(this.#pInstance, i, j);
+                temporaries.push(this.#readDebugText());
+            }
+
+            frames.push({
+                start: offsets[w.haku_debug_frame_start(this.#pInstance, this.#pBrush, i)],
+                end: offsets[w.haku_debug_frame_end(this.#pInstance, this.#pBrush, i)],
+                variables,
+                temporaries,
+            });
+        }
+
+        let defs = [];
+        for (let i = 0; i < w.haku_debug_num_defs(this.#pBrush); ++i) {
+            w.haku_debug_format_def(this.#pInstance, this.#pBrush, i);
+            defs.push({
+                name: readString(
+                    w.haku_debug_def_name_len(this.#pBrush, i),
+                    w.haku_debug_def_name(this.#pBrush, i),
+                ),
+                value: this.#readDebugText(),
+            });
+        }
+
+        return { paused: this.isDebugPaused, frames, defs };
+    }
+
+    // Evaluates the brush like `evalBrush`, while recording how much of each limit it uses.
+    // The profile can be read with `profile` afterwards, even if evaluation fails.
+    profileBrush() {
+        return this.#statusCodeToResultObject(w.haku_profile_brush(this.#pInstance, this.#pBrush));
+    }
+
+    // Returns the resources used by the last `profileBrush` call, in total and per function.
+    // Functions that don't come from the brush itself (such as ones from the prelude) don't have
+    // a `start` and `end`, and neither do any functions if the instance doesn't collect debug
+    // info.
+    profile() {
+        let offsets = this.#brushCodeOffsets;
+
+        let functions = [];
+        for (let i = 0; i < w.haku_profile_num_functions(this.#pInstance); ++i) {
+            let inBrush = w.haku_profile_function_in_brush(this.#pInstance, this.#pBrush, i);
+            functions.push({
+                start: inBrush
+                    ? offsets[w.haku_profile_function_start(this.#pInstance, this.#pBrush, i)]
+                    : null,
+                end: inBrush
+                    ? offsets[w.haku_profile_function_end(this.#pInstance, this.#pBrush, i)]
+                    : null,
+                calls: w.haku_profile_function_calls(this.#pInstance, i),
+                fuel: w.haku_profile_function_fuel(this.#pInstance, i),
+                memory: w.haku_profile_function_memory(this.#pInstance, i),
+                refs: w.haku_profile_function_refs(this.#pInstance, i),
+            });
+        }
+
+        return {
+            fuelUsed: w.haku_profile_fuel_used(this.#pInstance),
+            fuelLimit: w.haku_profile_fuel_limit(this.#pInstance),
+            peakMemory: w.haku_profile_peak_memory(this.#pInstance),
+            memoryLimit: w.haku_profile_memory_limit(this.#pInstance),
+            peakStackDepth: w.haku_profile_peak_stack_depth(this.#pInstance),
+            stackCapacity: w.haku_profile_stack_capacity(this.#pInstance),
+            peakCallDepth: w.haku_profile_peak_call_depth(this.#pInstance),
+            callStackCapacity: w.haku_profile_call_stack_capacity(this.#pInstance),
+            functions,
+        };
+    }
 }
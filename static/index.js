@@ -9,6 +9,7 @@ import {
 } from "rkgk/session.js";
 import { debounce } from "rkgk/framework.js";
 import { ReticleCursor } from "rkgk/reticle-renderer.js";
+import { Haku } from "rkgk/haku.js";
 
 const updateInterval = 1000 / 60;
 
@@ -84,6 +85,7 @@ function readUrl(urlString) {
         wallId: urlData.wallId ?? localStorage.getItem("rkgk.mostRecentWallId"),
         userInit: {
             brush: brushEditor.code,
+            params: brushEditor.params,
         },
 
         onError(error) {
@@ -133,6 +135,7 @@ function readUrl(urlString) {
         wall.onlineUsers.addUser(onlineUser.sessionId, {
             nickname: onlineUser.nickname,
             brush: onlineUser.init.brush,
+            params: onlineUser.init.params,
         });
     }
 
@@ -147,6 +150,7 @@ function readUrl(urlString) {
                 wall.onlineUsers.addUser(wallEvent.sessionId, {
                     nickname: wallEvent.kind.nickname,
                     brush: wallEvent.kind.init.brush,
+                    params: wallEvent.kind.init.params,
                 });
             }
 
@@ -176,13 +180,11 @@ function readUrl(urlString) {
             }
 
             if (wallEvent.kind.event == "setBrush") {
-                user.setBrush(wallEvent.kind.brush);
+                user.setBrush(wallEvent.kind.brush, wallEvent.kind.params);
             }
 
             if (wallEvent.kind.event == "plot") {
-                for (let { x, y } of wallEvent.kind.points) {
-                    user.renderBrushToChunks(wall, x, y);
-                }
+                user.renderBrushToChunks(wall, wallEvent.kind.points);
             }
         }
     });
@@ -230,11 +232,31 @@ function readUrl(urlString) {
         reportCursor(event.x, event.y);
     });
 
+    function renderOwnPoints(points) {
+        if (currentUser.isBrushOk) {
+            brushEditor.resetErrors();
+
+            let result = currentUser.renderBrushToChunks(wall, points);
+            if (result.status == "error") {
+                brushEditor.renderHakuResult(
+                    result.phase == "eval" ? "Evaluation" : "Rendering",
+                    result.result,
+                );
+            }
+        }
+    }
+
+    // With high-precision blending, the server blends each batch of points together before
+    // drawing it onto the wall, so our own points have to be drawn in the same batches for the
+    // result to look the same.
+    let { highPrecision } = session.wallInfo.blending;
+
     let plotQueue = [];
     async function flushPlotQueue() {
         let points = plotQueue.splice(0, plotQueue.length);
         if (points.length != 0) {
             session.sendPlot(points);
+            if (highPrecision) renderOwnPoints(points);
         }
     }
 
@@ -242,18 +264,7 @@ function readUrl(urlString) {
 
     canvasRenderer.addEventListener(".paint", async (event) => {
         plotQueue.push({ x: event.x, y: event.y });
-
-        if (currentUser.isBrushOk) {
-            brushEditor.resetErrors();
-
-            let result = currentUser.renderBrushToChunks(wall, event.x, event.y);
-            if (result.status == "error") {
-                brushEditor.renderHakuResult(
-                    result.phase == "eval" ? "Evaluation" : "Rendering",
-                    result.result,
-                );
-            }
-        }
+        if (!highPrecision) renderOwnPoints([event]);
     });
 
     canvasRenderer.addEventListener(".viewportUpdate", () => reticleRenderer.render());
@@ -262,8 +273,13 @@ function readUrl(urlString) {
     );
 
     function compileBrush() {
-        let compileResult = currentUser.setBrush(brushEditor.code);
+        brushEditor.setHighlighting(currentUser.haku.highlight(brushEditor.code));
+
+        let compileResult = currentUser.setBrush(brushEditor.code, brushEditor.params);
         brushEditor.renderHakuResult("Compilation", compileResult);
+        if (compileResult.status == "ok") {
+            brushEditor.renderParams(compileResult.params);
+        }
 
         if (compileResult.status != "ok") {
             brushPreview.setErrorFlag();
@@ -279,11 +295,93 @@ function readUrl(urlString) {
         }
     }
 
+    // Brushes are stepped through in an instance of their own, so that painting doesn't disturb
+    // the brush being debugged.
+    let debugHaku = new Haku(session.wallInfo.hakuLimits, {
+        debug: true,
+        modules: session.wallInfo.hakuModules,
+    });
+
+    function stopDebugging() {
+        if (!brushEditor.isDebugging) return;
+        debugHaku.debugStop();
+        brushEditor.stopDebugging();
+    }
+
+    function renderDebugResult(result) {
+        if (result.status != "ok") {
+            stopDebugging();
+            brushEditor.renderHakuResult("Evaluation", result);
+            brushPreview.setErrorFlag();
+            return;
+        }
+
+        if (debugHaku.isDebugPaused) {
+            brushEditor.renderDebugState(debugHaku.debugState());
+        } else {
+            // Once the brush finishes, show what it evaluated to.
+            brushEditor.stopDebugging();
+            let previewResult = brushPreview.renderValue(debugHaku);
+            if (previewResult.status == "error") {
+                brushEditor.renderHakuResult("Rendering", previewResult.result);
+            }
+        }
+    }
+
+    brushEditor.addEventListener(".debugStart", () => {
+        let compileResult = debugHaku.setBrush(brushEditor.code, brushEditor.params);
+        if (compileResult.status != "ok") {
+            brushEditor.renderHakuResult("Compilation", compileResult);
+            return;
+        }
+        brushEditor.resetErrors();
+        debugHaku.resetVm();
+        renderDebugResult(debugHaku.debugStart());
+    });
+    brushEditor.addEventListener(".debugStep", () => {
+        renderDebugResult(debugHaku.debugStep());
+    });
+    brushEditor.addEventListener(".debugContinue", (event) => {
+        debugHaku.setBreakpoints(event.breakpoints);
+        renderDebugResult(debugHaku.debugContinue());
+    });
+    brushEditor.addEventListener(".profileRequested", () => {
+        let compileResult = debugHaku.setBrush(brushEditor.code, brushEditor.params);
+        if (compileResult.status != "ok") {
+            brushEditor.renderHakuResult("Compilation", compileResult);
+            return;
+        }
+        brushEditor.resetErrors();
+        debugHaku.resetVm();
+        let result = debugHaku.profileBrush();
+        brushEditor.renderProfile(debugHaku.profile());
+        if (result.status != "ok") {
+            brushEditor.renderHakuResult("Evaluation", result);
+        }
+    });
+    brushEditor.addEventListener(".debugStop", () => {
+        stopDebugging();
+        compileBrush();
+    });
+
     compileBrush();
     brushEditor.addEventListener(".codeChanged", async () => {
         flushPlotQueue();
+        stopDebugging();
         compileBrush();
-        session.sendSetBrush(brushEditor.code);
+        session.sendSetBrush(brushEditor.code, brushEditor.params);
+    });
+    brushEditor.addEventListener(".paramsChanged", async () => {
+        flushPlotQueue();
+        stopDebugging();
+        compileBrush();
+        session.sendSetBrush(brushEditor.code, brushEditor.params);
+    });
+    brushEditor.addEventListener(".formatRequested", () => {
+        let formattedCode = currentUser.haku.format(brushEditor.code);
+        if (formattedCode != null) {
+            brushEditor.setFormattedCode(formattedCode);
+        }
     });
 
     session.eventLoop();
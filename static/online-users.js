@@ -11,17 +11,18 @@ export class User {
     constructor(wallInfo, nickname) {
         this.nickname = nickname;
 
-        this.haku = new Haku(wallInfo.hakuLimits);
-        this.painter = new Painter(wallInfo.paintArea);
+        this.haku = new Haku(wallInfo.hakuLimits, { modules: wallInfo.hakuModules });
+        this.painter = new Painter(wallInfo.paintArea, wallInfo.blending);
     }
 
     destroy() {
         this.haku.destroy();
+        this.painter.destroy();
     }
 
-    setBrush(brush) {
+    setBrush(brush, params) {
         console.groupCollapsed("setBrush", this.nickname);
-        let compileResult = this.haku.setBrush(brush);
+        let compileResult = this.haku.setBrush(brush, params);
         console.log("compiling brush complete", compileResult);
         console.groupEnd();
 
@@ -30,9 +31,9 @@ export class User {
         return compileResult;
     }
 
-    renderBrushToChunks(wall, x, y) {
+    renderBrushToChunks(wall, points) {
         console.groupCollapsed("renderBrushToChunks", this.nickname);
-        let result = this.painter.renderBrushToWall(this.haku, x, y, wall);
+        let result = this.painter.renderBrushToWall(this.haku, points, wall);
         console.log("rendering brush to chunks complete");
         console.groupEnd();
 
@@ -50,12 +51,12 @@ export class OnlineUsers extends EventTarget {
         this.#wallInfo = wallInfo;
     }
 
-    addUser(sessionId, { nickname, brush }) {
+    addUser(sessionId, { nickname, brush, params }) {
         if (!this.#users.has(sessionId)) {
             console.info("user added", sessionId, nickname);
 
             let user = new User(this.#wallInfo, nickname);
-            user.setBrush(brush);
+            user.setBrush(brush, params);
             this.#users.set(sessionId, user);
             return user;
         } else {
@@ -1,15 +1,171 @@
+import { Accumulator, Pixmap } from "rkgk/haku.js";
+
 export class Painter {
-    constructor(paintArea) {
+    #splat = null;
+
+    constructor(paintArea, blending) {
         this.paintArea = paintArea;
+        this.blending = blending;
+    }
+
+    destroy() {
+        this.#splat?.destroy();
     }
 
-    renderBrushToWall(haku, centerX, centerY, wall) {
+    renderBrushToWall(haku, points, wall) {
         haku.resetVm();
 
-        let evalResult = haku.evalBrush();
+        let strokeResult = haku.beginStroke();
+        if (strokeResult.status != "ok")
+            return { status: "error", phase: "eval", result: strokeResult };
+
+        if (this.blending.highPrecision) return this.#renderAccumulated(haku, points, wall);
+
+        // Keep going if a point fails, so that at least the rest of the stroke gets drawn.
+        let result = { status: "ok" };
+        for (let { x, y } of points) {
+            let pointResult = this.#renderPointToWall(haku, x, y, wall);
+            if (pointResult.status != "ok") result = pointResult;
+        }
+
+        return result;
+    }
+
+    #getSplat() {
+        if (this.#splat == null) this.#splat = new Pixmap(this.paintArea, this.paintArea);
+        return this.#splat;
+    }
+
+    // Draws the points the same way the server does with high-precision blending: each point is
+    // rendered into a splat, which is blended into accumulators covering the chunks it touches, and
+    // only once all points are drawn are the accumulators composited onto the chunks.
+    #renderAccumulated(haku, points, wall) {
+        let splat = this.#getSplat();
+        let halfPaintArea = Math.floor(this.paintArea / 2);
+
+        let accumulators = new Map();
+        let result = { status: "ok" };
+        for (let { x, y } of points) {
+            let evalResult = haku.evalPoint(x, y);
+            if (evalResult.status != "ok") {
+                result = { status: "error", phase: "eval", result: evalResult };
+                continue;
+            }
+
+            if (haku.hasFilters()) {
+                // Filters have to see everything drawn before them, so whatever was accumulated so
+                // far has to land on the wall first.
+                this.#compositeAccumulators(accumulators, wall);
+                let filteredResult = this.#renderFilteredToWall(haku, x, y, wall);
+                if (filteredResult.status != "ok") result = filteredResult;
+                continue;
+            }
+
+            // Whatever was rendered before an error is still drawn, like on the server.
+            splat.clear();
+            let renderResult = haku.renderValue(splat, halfPaintArea, halfPaintArea);
+            if (renderResult.status != "ok") {
+                result = { status: "error", phase: "render", result: renderResult };
+            }
+
+            let left = Math.floor(x) - halfPaintArea;
+            let top = Math.floor(y) - halfPaintArea;
+            let leftChunk = Math.floor(left / wall.chunkSize);
+            let topChunk = Math.floor(top / wall.chunkSize);
+            let rightChunk = Math.ceil((left + this.paintArea) / wall.chunkSize);
+            let bottomChunk = Math.ceil((top + this.paintArea) / wall.chunkSize);
+            for (let chunkY = topChunk; chunkY < bottomChunk; ++chunkY) {
+                for (let chunkX = leftChunk; chunkX < rightChunk; ++chunkX) {
+                    let key = `${chunkX},${chunkY}`;
+                    let entry = accumulators.get(key);
+                    if (entry == null) {
+                        entry = {
+                            chunkX,
+                            chunkY,
+                            accumulator: new Accumulator(wall.chunkSize, wall.chunkSize),
+                        };
+                        accumulators.set(key, entry);
+                    }
+                    entry.accumulator.blend(
+                        splat,
+                        left - chunkX * wall.chunkSize,
+                        top - chunkY * wall.chunkSize,
+                    );
+                }
+            }
+        }
+
+        this.#compositeAccumulators(accumulators, wall);
+
+        return result;
+    }
+
+    #compositeAccumulators(accumulators, wall) {
+        for (let { chunkX, chunkY, accumulator } of accumulators.values()) {
+            let chunk = wall.getOrCreateChunk(chunkX, chunkY);
+            chunk.markModified();
+            accumulator.composite(chunk.pixmap, this.blending.dither);
+            accumulator.destroy();
+            chunk.syncFromPixmap();
+        }
+        accumulators.clear();
+    }
+
+    // Filters change what's already on the wall, and may reach across chunk boundaries, so values
+    // with filters are drawn onto a copy of the paint area gathered from the chunks under it, which
+    // is then copied back onto the chunks. This is also how the server draws them.
+    #renderFilteredToWall(haku, x, y, wall) {
+        let splat = this.#getSplat();
+        let halfPaintArea = Math.floor(this.paintArea / 2);
+
+        let left = Math.floor(x) - halfPaintArea;
+        let top = Math.floor(y) - halfPaintArea;
+        let leftChunk = Math.floor(left / wall.chunkSize);
+        let topChunk = Math.floor(top / wall.chunkSize);
+        let rightChunk = Math.ceil((left + this.paintArea) / wall.chunkSize);
+        let bottomChunk = Math.ceil((top + this.paintArea) / wall.chunkSize);
+
+        splat.clear();
+        for (let chunkY = topChunk; chunkY < bottomChunk; ++chunkY) {
+            for (let chunkX = leftChunk; chunkX < rightChunk; ++chunkX) {
+                let chunk = wall.getChunk(chunkX, chunkY);
+                if (chunk == null) continue;
+                splat.copy(
+                    chunk.pixmap,
+                    chunkX * wall.chunkSize - left,
+                    chunkY * wall.chunkSize - top,
+                );
+            }
+        }
+
+        // Whatever was rendered before an error is still drawn, like on the server.
+        let renderResult = haku.renderValue(splat, halfPaintArea, halfPaintArea);
+
+        for (let chunkY = topChunk; chunkY < bottomChunk; ++chunkY) {
+            for (let chunkX = leftChunk; chunkX < rightChunk; ++chunkX) {
+                let chunk = wall.getOrCreateChunk(chunkX, chunkY);
+                chunk.markModified();
+                chunk.pixmap.copy(
+                    splat,
+                    left - chunkX * wall.chunkSize,
+                    top - chunkY * wall.chunkSize,
+                );
+                chunk.syncFromPixmap();
+            }
+        }
+
+        if (renderResult.status != "ok")
+            return { status: "error", phase: "render", result: renderResult };
+        return { status: "ok" };
+    }
+
+    #renderPointToWall(haku, centerX, centerY, wall) {
+        let evalResult = haku.evalPoint(centerX, centerY);
         if (evalResult.status != "ok")
             return { status: "error", phase: "eval", result: evalResult };
 
+        if (haku.hasFilters()) return this.#renderFilteredToWall(haku, centerX, centerY, wall);
+
         let left = centerX - this.paintArea / 2;
         let top = centerY - this.paintArea / 2;
 
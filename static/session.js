@@ -160,6 +160,7 @@ class Session extends EventTarget {
 
         let init = {
             brush: userInit.brush,
+            params: userInit.params,
         };
         if (this.wallId == null) {
             this.#sendJson({
@@ -277,12 +278,13 @@ class Session extends EventTarget {
         });
     }
 
-    sendSetBrush(brush) {
+    sendSetBrush(brush, params) {
         this.#sendJson({
             request: "wall",
             wallEvent: {
                 event: "setBrush",
                 brush,
+                params,
             },
         });
     }